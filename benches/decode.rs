@@ -0,0 +1,160 @@
+//! Benchmarks of the searches on representative workloads, with criterion.
+//!
+//! Run with `cargo bench`, and add `--features simd` or `--features rayon` (or use
+//! `--no-default-features --features std` to go without `fastexp`) to compare the features. The
+//! network outputs are synthetic, generated from fixed seeds, so every run and every version
+//! decodes exactly the same inputs.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fast_ctc_decode::duplex;
+use fast_ctc_decode::search::{beam_search, beam_search_batch, viterbi_search};
+use ndarray::{s, Array2, Array3};
+
+// the random number generator of the tests, so the benchmarks need no copy of their own
+#[path = "../src/testing.rs"]
+mod testing;
+
+use testing::Lcg;
+
+/// `n` random labels (not the blank, which is column 0) of an alphabet of `n_cols` labels.
+fn labels(rng: &mut Lcg, n: usize, n_cols: usize) -> Vec<usize> {
+    (0..n)
+        .map(|_| 1 + (rng.next_f32() * (n_cols - 1) as f32) as usize % (n_cols - 1))
+        .collect()
+}
+
+/// A network output giving `labels` as a peaky network does: each label lasts one to three rows,
+/// with one to four rows of blanks after it, and a little noise on every column.
+fn peaky_output(labels: &[usize], n_cols: usize, rng: &mut Lcg) -> Array2<f32> {
+    let mut rows = Vec::new();
+    for &label in labels {
+        let n_label = 1 + (rng.next_f32() * 3.0) as usize % 3;
+        let n_blank = 1 + (rng.next_f32() * 4.0) as usize % 4;
+        for column in std::iter::repeat(label)
+            .take(n_label)
+            .chain(std::iter::repeat(0).take(n_blank))
+        {
+            let mut row: Vec<f32> = (0..n_cols)
+                .map(|col| {
+                    rng.next_f32() * 0.5 / n_cols as f32 + if col == column { 1.0 } else { 0.0 }
+                })
+                .collect();
+            let total: f32 = row.iter().sum();
+            row.iter_mut().for_each(|x| *x /= total);
+            rows.extend(row);
+        }
+    }
+    Array2::from_shape_vec((rows.len() / n_cols, n_cols), rows).unwrap()
+}
+
+/// A peaky network output of exactly `n_rows` rows over an alphabet of `n_cols` labels.
+fn read(n_rows: usize, n_cols: usize, seed: u32) -> Array2<f32> {
+    let mut rng = Lcg(seed);
+    // every label takes at least two rows, so this is always enough
+    let labels = labels(&mut rng, n_rows / 2 + 1, n_cols);
+    peaky_output(&labels, n_cols, &mut rng)
+        .slice(s![..n_rows, ..])
+        .to_owned()
+}
+
+/// The alphabet of `n_cols` labels, with the blank first.
+fn alphabet(n_cols: usize) -> Vec<String> {
+    if n_cols == 5 {
+        "NACGT".chars().map(String::from).collect()
+    } else {
+        (0..n_cols).map(|label| format!("<{}>", label)).collect()
+    }
+}
+
+fn bench_single(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single");
+    let alphabet = alphabet(5);
+    let network_output = read(2000, 5, 1);
+    group.throughput(Throughput::Elements(network_output.nrows() as u64));
+    for &beam_size in &[5, 32] {
+        group.bench_with_input(
+            BenchmarkId::new("beam_search_2000x5", beam_size),
+            &beam_size,
+            |b, &beam_size| {
+                b.iter(|| beam_search(&network_output, &alphabet, beam_size, 0.0, true, 0).unwrap())
+            },
+        );
+    }
+    group.bench_function("viterbi_search_2000x5", |b| {
+        b.iter(|| viterbi_search(&network_output, &alphabet, false, 1.0, 0.0, true, 0).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_wide_alphabet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wide_alphabet");
+    let alphabet = alphabet(256);
+    let network_output = read(1000, 256, 2);
+    group.throughput(Throughput::Elements(network_output.nrows() as u64));
+    group.bench_function("beam_search_1000x256", |b| {
+        b.iter(|| beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_duplex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("duplex");
+    let alphabet = alphabet(5);
+    let mut rng = Lcg(3);
+    let labels = labels(&mut rng, 500, 5);
+    let read_1 = peaky_output(&labels, 5, &mut rng);
+    let read_2 = peaky_output(&labels, 5, &mut rng);
+    // 40 rows wider than `compute_envelope` gives, as from a coarser alignment
+    let mut envelope = duplex::compute_envelope(&read_1, &read_2, &alphabet).unwrap();
+    for mut bounds in envelope.outer_iter_mut() {
+        bounds[0] = bounds[0].saturating_sub(20);
+        bounds[1] = (bounds[1] + 20).min(read_2.nrows());
+    }
+    group.throughput(Throughput::Elements(read_1.nrows() as u64));
+    group.bench_function("beam_search_500_labels", |b| {
+        b.iter(|| {
+            duplex::beam_search(&read_1, &read_2, &alphabet, &envelope, 5, 0.0, true).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    let alphabet = alphabet(5);
+    let mut batch = Array3::zeros((16, 2000, 5));
+    for (seed, mut item) in batch.outer_iter_mut().enumerate() {
+        item.assign(&read(2000, 5, 100 + seed as u32));
+    }
+    group.throughput(Throughput::Elements(
+        (batch.shape()[0] * batch.shape()[1]) as u64,
+    ));
+    group.bench_function("beam_search_batch_16x2000x5", |b| {
+        b.iter(|| beam_search_batch(&batch, &alphabet, 5, 0.0, true, 0).unwrap())
+    });
+    // with the `rayon` feature, how the decode scales with the threads of the pool it runs in
+    #[cfg(feature = "rayon")]
+    for &threads in &[1, 2, 4, 8] {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("beam_search_batch_16x2000x5_threads", threads),
+            &threads,
+            |b, _| {
+                b.iter(|| pool.install(|| beam_search_batch(&batch, &alphabet, 5, 0.0, true, 0)))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_single,
+    bench_wide_alphabet,
+    bench_duplex,
+    bench_batch
+);
+criterion_main!(benches);
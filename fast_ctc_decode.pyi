@@ -0,0 +1,96 @@
+"""Type stubs for the fast_ctc_decode extension module.
+
+These mirror the signatures (and defaults) of the functions in src/python.rs, and must be kept in
+step with the ``#[pyfunction]`` and ``text_signature`` attributes there.
+"""
+
+from typing import Callable, Iterable, Iterator, List, Optional, Sequence, Tuple, Union
+
+import numpy as np
+
+Alphabet = Union[str, Sequence[str]]
+NetworkOutput = Union[np.ndarray, Sequence[Sequence[float]]]
+
+__version__: str
+features: List[str]
+
+def beam_search(
+    network_output: NetworkOutput,
+    alphabet: Alphabet,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+    collapse_repeats: bool = True,
+    apply_softmax: bool = False,
+    progress: Optional[Callable[[int, str], object]] = None,
+    should_stop: Optional[Callable[[], bool]] = None,
+    progress_interval: int = 10000,
+    time_axis: int = 0,
+) -> Union[Tuple[str, np.ndarray], Tuple[str, np.ndarray, bool]]: ...
+class DecodeResult:
+    sequence: str
+    timepoints: np.ndarray
+    probability: float
+    score: Optional[float]
+    qualities: Optional[np.ndarray]
+
+def beam_search_ex(
+    network_output: NetworkOutput,
+    alphabet: Alphabet,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+    collapse_repeats: bool = True,
+    apply_softmax: bool = False,
+    time_axis: int = 0,
+    qualities: bool = False,
+    score: bool = False,
+) -> DecodeResult: ...
+class DecodeAll(Iterator[Tuple[str, np.ndarray]]):
+    def __iter__(self) -> DecodeAll: ...
+    def __next__(self) -> Tuple[str, np.ndarray]: ...
+
+def decode_all(
+    reads: Iterable[np.ndarray],
+    alphabet: Alphabet,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+    collapse_repeats: bool = True,
+    apply_softmax: bool = False,
+    time_axis: int = 0,
+) -> DecodeAll: ...
+def beam_search_duplex(
+    network_output_1: np.ndarray,
+    network_output_2: np.ndarray,
+    alphabet: Alphabet,
+    envelope: Optional[np.ndarray] = None,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+    alphabet_2: Optional[Alphabet] = None,
+    mapping: Optional[Sequence[int]] = None,
+    weight_1: float = 1.0,
+    weight_2: float = 1.0,
+    refine_margin: Optional[int] = None,
+) -> str: ...
+def beam_search_duplex_with_timepoints(
+    network_output_1: np.ndarray,
+    network_output_2: np.ndarray,
+    alphabet: Alphabet,
+    envelope: Optional[np.ndarray] = None,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+) -> Tuple[str, np.ndarray, np.ndarray]: ...
+def beam_search_duplex_with_support(
+    network_output_1: np.ndarray,
+    network_output_2: np.ndarray,
+    alphabet: Alphabet,
+    envelope: Optional[np.ndarray] = None,
+    beam_size: int = 5,
+    beam_cut_threshold: float = 0.0,
+) -> Tuple[str, np.ndarray]: ...
+def viterbi_search(
+    network_output: NetworkOutput,
+    alphabet: Alphabet,
+    qstring: bool = False,
+    qscale: float = 1.0,
+    qbias: float = 0.0,
+    collapse_repeats: bool = True,
+) -> Tuple[str, np.ndarray]: ...
@@ -0,0 +1,48 @@
+//! Decode arbitrary network outputs, which must give a `SearchError` rather than panic.
+//!
+//! The first four bytes of the input set the alphabet size and the options, and the rest are
+//! the values of the network output, four bytes (one `f32`) each, so that every bit pattern
+//! (subnormals, NaNs, infinities, negative values and values above 1.0) can turn up.
+
+#![no_main]
+use fast_ctc_decode::search::{self, BeamSearchOptions};
+use libfuzzer_sys::fuzz_target;
+use ndarray::Array2;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let (header, values) = data.split_at(4);
+    let n_labels = 1 + header[0] as usize % 8;
+    // a beam size of 0 and a blank past the end of the alphabet are errors worth reaching too
+    let options = BeamSearchOptions::new()
+        .beam_size(header[1] as usize % 64)
+        .beam_cut_threshold(header[2] as f32 / 1024.0)
+        .collapse_repeats(header[3] & 1 == 0)
+        .blank_id((header[3] >> 2) as usize % 9);
+    let apply_softmax = header[3] & 2 != 0;
+
+    let values: Vec<f32> = values
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+    let n_rows = values.len() / n_labels;
+    let mut network_output =
+        Array2::from_shape_vec((n_rows, n_labels), values[..n_rows * n_labels].to_vec()).unwrap();
+    if apply_softmax {
+        // as the Python module does for logits
+        search::softmax_rows(&mut network_output);
+    }
+    let alphabet: Vec<String> = "NACGTUXY".chars().take(n_labels).map(String::from).collect();
+
+    if let Ok((sequence, path, _)) = search::beam_search_with(&network_output, &alphabet, &options)
+    {
+        assert_eq!(sequence.chars().count(), path.len());
+        assert!(path.iter().all(|&time| time < n_rows));
+    }
+    let _ = search::beam_search_with_spans(&network_output, &alphabet, &options);
+    let _ = search::beam_search_with_segments(&network_output, &alphabet, &options);
+    let _ = search::beam_search_qualities_with(&network_output, &alphabet, &options);
+    let _ = search::viterbi_search(&network_output, &alphabet, true, 1.0, 0.0, true, 0);
+});
@@ -0,0 +1,106 @@
+#ifndef FAST_CTC_DECODE_H
+#define FAST_CTC_DECODE_H
+
+/* Generated by cbindgen from src/capi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of a call to the C API.
+ *
+ * Each `SearchError` has a code of its own, numbered from 1 in the order of its variants. The
+ * arguments that could not be converted from C have the fixed codes from 100, so they keep their
+ * values as errors are added.
+ */
+typedef enum CtcStatus {
+  CTC_STATUS_OK = 0,
+  CTC_STATUS_RAN_OUT_OF_BEAM,
+  CTC_STATUS_INCOMPARABLE_VALUES,
+  CTC_STATUS_INVALID_ENVELOPE,
+  CTC_STATUS_ALPHABET_MISMATCH,
+  CTC_STATUS_ENVELOPE_COUNT_MISMATCH,
+  CTC_STATUS_INVALID_BEAM_SIZE,
+  CTC_STATUS_INVALID_MAX_SYMBOLS_PER_FRAME,
+  CTC_STATUS_INVALID_BLANK_BIAS,
+  CTC_STATUS_INVALID_BEAM_CUT_RELATIVE,
+  CTC_STATUS_INVALID_BEAM_CUT_THRESHOLD,
+  CTC_STATUS_INVALID_DELTA,
+  CTC_STATUS_INVALID_INTERVAL,
+  CTC_STATUS_INVALID_BLANK_ID,
+  CTC_STATUS_INVALID_SCALE,
+  CTC_STATUS_INVALID_SEPARATOR,
+  CTC_STATUS_INVALID_TRANSITIONS,
+  CTC_STATUS_INVALID_LENGTH_RANGE,
+  CTC_STATUS_INVALID_WEIGHT,
+  CTC_STATUS_INVALID_REPEAT_LABEL_FLOOR,
+  CTC_STATUS_INVALID_TIME_AXIS,
+  CTC_STATUS_INVALID_TARGET,
+  CTC_STATUS_UNALIGNABLE_TARGET,
+  CTC_STATUS_INVALID_ALPHABET_MAPPING,
+  CTC_STATUS_ROW_SUM_MISMATCH,
+  CTC_STATUS_INVALID_INIT_STATE,
+  CTC_STATUS_CAPACITY_EXCEEDED,
+  CTC_STATUS_INVALID_ANCHOR,
+  CTC_STATUS_INVALID_LABEL_PRIOR,
+  /**
+   * A pointer argument was NULL (other than `data` for an empty network output).
+   */
+  CTC_STATUS_NULL_POINTER = 100,
+  /**
+   * A label of the alphabet is not valid UTF-8.
+   */
+  CTC_STATUS_INVALID_UTF8 = 101,
+} CtcStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Perform a CTC beam search decode on an RNN output.
+ *
+ * `data` is the network output as a row-major `rows` by `cols` matrix of probabilities, with
+ * time on the outer axis, and `alphabet` is the `cols` NUL-terminated labels, with the blank
+ * label first. Repeated labels are collapsed. See `search::beam_search` for `beam_size` and
+ * `threshold` (the beam cut threshold).
+ *
+ * On success, `*out_str` is the decoded sequence (NUL-terminated), `*out_timepoints` the
+ * timepoint of each label and `*out_len` the number of labels (and so of timepoints). These must
+ * be freed with `ctc_free_result`. On failure, the out pointers are left unchanged.
+ *
+ * # Safety
+ *
+ * `data` must point to `rows * cols` floats (or may be NULL if that is 0), `alphabet` to `cols`
+ * valid C strings, and the out pointers must be valid for writes.
+ */
+CtcStatus ctc_beam_search(const float *data,
+                          size_t rows,
+                          size_t cols,
+                          const char *const *alphabet,
+                          size_t beam_size,
+                          float threshold,
+                          char **out_str,
+                          size_t **out_timepoints,
+                          size_t *out_len);
+
+/**
+ * Free the sequence and timepoints returned by `ctc_beam_search`.
+ *
+ * `len` must be the number of timepoints returned with them. Either pointer may be NULL.
+ *
+ * # Safety
+ *
+ * The pointers must have come from a successful call to `ctc_beam_search`, and not have been
+ * freed already.
+ */
+void ctc_free_result(char *str, size_t *timepoints, size_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* FAST_CTC_DECODE_H */
@@ -0,0 +1,136 @@
+//! An alphabet that is checked once and reused for many decodes.
+//!
+//! The searches take the alphabet as a slice of labels, and an `Alphabet` dereferences to one, so
+//! `&alphabet` can be passed to any of them. Building one checks the things that do not depend on
+//! the network output (that there is a label, and that the blank is one of them) and builds a
+//! lookup from each label to its column, so none of that is repeated for each decode.
+
+use crate::SearchError;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+/// The labels of the columns of a network output, with the index of the blank.
+#[derive(Clone, Debug)]
+pub struct Alphabet {
+    labels: Vec<String>,
+    blank_id: usize,
+    columns: BTreeMap<String, usize>,
+}
+
+impl Alphabet {
+    /// An alphabet of `labels`, with the blank in column `blank_id`.
+    ///
+    /// Fails with `SearchError::AlphabetMismatch` if there are no labels, and
+    /// `SearchError::InvalidBlankId` if `blank_id` is not one of them.
+    pub fn new<S: Into<String>, I: IntoIterator<Item = S>>(
+        labels: I,
+        blank_id: usize,
+    ) -> Result<Self, SearchError> {
+        let labels: Vec<String> = labels.into_iter().map(Into::into).collect();
+        if labels.is_empty() {
+            return Err(SearchError::AlphabetMismatch);
+        }
+        if blank_id >= labels.len() {
+            return Err(SearchError::InvalidBlankId);
+        }
+        let mut columns = BTreeMap::new();
+        for (column, label) in labels.iter().enumerate() {
+            // the first column with a label wins
+            columns.entry(label.clone()).or_insert(column);
+        }
+        Ok(Alphabet {
+            labels,
+            blank_id,
+            columns,
+        })
+    }
+
+    /// An alphabet with one label for each character of `labels`, and the blank first.
+    pub fn from_chars(labels: &str) -> Result<Self, SearchError> {
+        Alphabet::new(labels.chars().map(String::from), 0)
+    }
+
+    /// The labels, in column order.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The column of the blank label.
+    ///
+    /// This is not passed on to the searches, which take the blank from their options, so
+    /// `BeamSearchOptions::blank_id` should be set to it if it is not 0.
+    pub fn blank_id(&self) -> usize {
+        self.blank_id
+    }
+
+    /// The column of `label`, or the first column if several have it.
+    pub fn column(&self, label: &str) -> Option<usize> {
+        self.columns.get(label).copied()
+    }
+}
+
+impl Deref for Alphabet {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        &self.labels
+    }
+}
+
+impl AsRef<[String]> for Alphabet {
+    fn as_ref(&self) -> &[String] {
+        &self.labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{beam_search, beam_search_with, BeamSearchOptions, Decoder};
+
+    #[test]
+    fn test_alphabet() {
+        let alphabet = Alphabet::from_chars("NAG").unwrap();
+        assert_eq!(alphabet.len(), 3);
+        assert_eq!(alphabet.labels(), ["N", "A", "G"]);
+        assert_eq!(alphabet.blank_id(), 0);
+        assert_eq!(alphabet.column("G"), Some(2));
+        assert_eq!(alphabet.column("T"), None);
+
+        let alphabet = Alphabet::new(vec!["A", "N", "A"], 1).unwrap();
+        assert_eq!(alphabet.column("A"), Some(0));
+
+        assert!(matches!(
+            Alphabet::new(Vec::<String>::new(), 0),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert!(matches!(
+            Alphabet::new(vec!["N", "A"], 2),
+            Err(SearchError::InvalidBlankId)
+        ));
+    }
+
+    #[test]
+    fn test_alphabet_search() {
+        let alphabet = Alphabet::from_chars("NAG").unwrap();
+        let labels: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.7, 0.2, 0.1], [0.1, 0.1, 0.8]];
+        let expected = beam_search(&network_output, &labels, 5, 0.0, true, 0).unwrap();
+        assert_eq!(
+            beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap(),
+            expected
+        );
+        let options = BeamSearchOptions::new().blank_id(alphabet.blank_id());
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options).unwrap(),
+            expected
+        );
+        let mut decoder = Decoder::new(options);
+        assert_eq!(
+            decoder.decode(&network_output, &alphabet).unwrap(),
+            expected
+        );
+    }
+}
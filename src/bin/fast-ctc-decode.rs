@@ -0,0 +1,254 @@
+//! Decode a network output saved as a `.npy` file, built with the `cli` feature.
+//!
+//! ```text
+//! fast-ctc-decode --alphabet NACGT --beam-size 32 input.npy
+//! ```
+//!
+//! The input is a 2D array of probabilities (`float32` or `float64`), with time on the outer axis
+//! and the labels of `--alphabet` (one per character, blank first) on the inner axis. It is read
+//! from stdin if no file (or `-`) is given. The decoded sequence and the timepoint of each label
+//! are printed as one line of tab-separated values, with the timepoints separated by commas.
+
+use fast_ctc_decode::search::{beam_search_with, BeamSearchOptions};
+use ndarray::{Array2, ShapeBuilder};
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &str = "\
+usage: fast-ctc-decode --alphabet ALPHABET [--beam-size N] [--beam-cut-threshold X] [INPUT]
+
+Decode a 2D .npy array of probabilities (time by label) with a CTC beam search, printing the
+sequence and the comma-separated timepoints of its labels as tab-separated values.
+
+  --alphabet ALPHABET       the labels, one per character, with the blank first (required)
+  --beam-size N             how many labellings to keep at each step (default 5)
+  --beam-cut-threshold X    ignore probabilities below this (default 0.0)
+  INPUT                     the .npy file to decode, or - for stdin (the default)
+  --version                 print the version and the features it was built with";
+
+/// The command line arguments.
+struct Args {
+    alphabet: Vec<String>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    input: Option<String>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut alphabet = None;
+    let mut beam_size = 5;
+    let mut beam_cut_threshold = 0.0;
+    let mut input = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--alphabet" => {
+                alphabet = Some(value("--alphabet")?.chars().map(String::from).collect());
+            }
+            "--beam-size" => {
+                beam_size = value("--beam-size")?
+                    .parse()
+                    .map_err(|_| String::from("--beam-size must be a whole number"))?;
+            }
+            "--beam-cut-threshold" => {
+                beam_cut_threshold = value("--beam-cut-threshold")?
+                    .parse()
+                    .map_err(|_| String::from("--beam-cut-threshold must be a number"))?;
+            }
+            "-" => input = None,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if input.is_some() => return Err(String::from("only one input can be given")),
+            _ => input = Some(arg),
+        }
+    }
+    Ok(Args {
+        alphabet: alphabet.ok_or_else(|| String::from("--alphabet is required"))?,
+        beam_size,
+        beam_cut_threshold,
+        input,
+    })
+}
+
+/// Read a 2D array of little-endian `float32` or `float64` values from the contents of a `.npy`
+/// file (format version 1, 2 or 3), in either C or Fortran order.
+fn read_npy(bytes: &[u8]) -> Result<Array2<f32>, String> {
+    const MAGIC: &[u8] = b"\x93NUMPY";
+    if bytes.len() < 10 || !bytes.starts_with(MAGIC) {
+        return Err(String::from("not a .npy file"));
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (usize::from(u16::from_le_bytes([bytes[8], bytes[9]])), 10),
+        2 | 3 if bytes.len() >= 12 => {
+            let len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+            (len as usize, 12)
+        }
+        version => return Err(format!("unsupported .npy version {}", version)),
+    };
+    let data = bytes
+        .get(header_start + header_len..)
+        .ok_or_else(|| String::from("the .npy header is truncated"))?;
+    let header = std::str::from_utf8(&bytes[header_start..header_start + header_len])
+        .map_err(|_| String::from("the .npy header is not text"))?;
+
+    let descr = header_value(header, "descr")?;
+    let fortran_order = header_value(header, "fortran_order")? == "True";
+    let shape = header_value(header, "shape")?;
+    let shape = shape
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| format!("bad shape {}", shape))?;
+    let (rows, cols) = match shape[..] {
+        [rows, cols] => (rows, cols),
+        _ => return Err(format!("expected a 2D array, not one of shape {:?}", shape)),
+    };
+
+    let values: Vec<f32> = match descr.trim_matches('\'') {
+        "<f4" => data
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .collect(),
+        "<f8" => data
+            .chunks_exact(8)
+            .map(|x| f64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]]) as f32)
+            .collect(),
+        other => return Err(format!("expected float32 or float64 values, not {}", other)),
+    };
+    if rows.checked_mul(cols) != Some(values.len()) {
+        return Err(format!(
+            "found {} values for an array of shape ({}, {})",
+            values.len(),
+            rows,
+            cols
+        ));
+    }
+    Array2::from_shape_vec((rows, cols).set_f(fortran_order), values).map_err(|e| e.to_string())
+}
+
+/// The value of `key` in a `.npy` header, such as `{'descr': '<f4', 'fortran_order': False,
+/// 'shape': (3, 5), }`.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str, String> {
+    let missing = || format!("the .npy header has no {}", key);
+    let start = header.find(&format!("'{}':", key)).ok_or_else(missing)? + key.len() + 3;
+    let rest = header[start..].trim_start();
+    // the shape is the only value with commas in it
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|end| end + 1)
+    } else {
+        rest.find(|c| c == ',' || c == '}')
+    };
+    Ok(rest[..end.ok_or_else(missing)?].trim())
+}
+
+fn run(args: Args) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    match &args.input {
+        Some(path) => File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+        None => io::stdin().read_to_end(&mut bytes),
+    }
+    .map_err(|e| e.to_string())?;
+    let network_output = read_npy(&bytes)?;
+
+    let options = BeamSearchOptions::new()
+        .beam_size(args.beam_size)
+        .beam_cut_threshold(args.beam_cut_threshold);
+    let (sequence, path, _) =
+        beam_search_with(&network_output, &args.alphabet, &options).map_err(|e| e.to_string())?;
+    let path: Vec<String> = path.iter().map(usize::to_string).collect();
+    Ok(format!("{}\t{}", sequence, path.join(",")))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.iter().any(|arg| arg == "--version") {
+        println!(
+            "fast-ctc-decode {} (features: {})",
+            fast_ctc_decode::VERSION,
+            fast_ctc_decode::features().join(", ")
+        );
+        return;
+    }
+    let args = match parse_args(args.into_iter()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(args) {
+        Ok(line) => println!("{}", line),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    /// The `.npy` file for a float32 array, as numpy writes it.
+    fn npy_f32(shape: &str, fortran_order: bool, values: &[f32]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': {}, 'shape': {}, }}",
+            if fortran_order { "True" } else { "False" },
+            shape
+        );
+        // the header is padded with spaces to a multiple of 64 bytes, ending with a newline
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        for x in values {
+            bytes.extend(x.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read_npy() {
+        let values = [0.1f32, 0.8, 0.1, 0.7, 0.2, 0.1];
+        let expected = array![[0.1f32, 0.8, 0.1], [0.7, 0.2, 0.1]];
+        assert_eq!(
+            read_npy(&npy_f32("(2, 3)", false, &values)).unwrap(),
+            expected
+        );
+        let values = [0.1f32, 0.7, 0.8, 0.2, 0.1, 0.1];
+        assert_eq!(
+            read_npy(&npy_f32("(2, 3)", true, &values)).unwrap(),
+            expected
+        );
+
+        assert!(read_npy(&npy_f32("(6,)", false, &values)).is_err());
+        assert!(read_npy(&npy_f32("(2, 2)", false, &values)).is_err());
+        assert!(read_npy(b"not numpy").is_err());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|x| x.to_string()));
+        let parsed = args(&["--alphabet", "NAG", "--beam-size", "32", "input.npy"]).unwrap();
+        assert_eq!(parsed.alphabet, vec!["N", "A", "G"]);
+        assert_eq!(parsed.beam_size, 32);
+        assert_eq!(parsed.input.as_deref(), Some("input.npy"));
+        assert_eq!(args(&["--alphabet", "NAG", "-"]).unwrap().input, None);
+
+        assert!(args(&["input.npy"]).is_err());
+        assert!(args(&["--alphabet"]).is_err());
+        assert!(args(&["--alphabet", "NAG", "--beam-size", "many"]).is_err());
+        assert!(args(&["--alphabet", "NAG", "a.npy", "b.npy"]).is_err());
+    }
+}
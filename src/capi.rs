@@ -0,0 +1,260 @@
+//! The C API, built with the `capi` feature.
+//!
+//! This is a thin `extern "C"` wrapper around `search::beam_search`, so that C and C++ programs
+//! can link the library directly. The declarations are in `include/fast_ctc_decode.h`, which is
+//! generated from this module by cbindgen (see `cbindgen.toml`), so regenerate it whenever the
+//! functions or types here change:
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --crate fast_ctc_decode --output include/fast_ctc_decode.h
+//! ```
+//!
+//! The strings and arrays returned through the out pointers are allocated by Rust, and must be
+//! given back to `ctc_free_result` rather than to `free`.
+
+use crate::search;
+use crate::SearchError;
+use ndarray::ArrayView2;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+/// The result of a call to the C API.
+///
+/// Each `SearchError` has a code of its own, numbered from 1 in the order of its variants. The
+/// arguments that could not be converted from C have the fixed codes from 100, so they keep their
+/// values as errors are added.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CtcStatus {
+    Ok = 0,
+    RanOutOfBeam,
+    IncomparableValues,
+    InvalidEnvelope,
+    AlphabetMismatch,
+    EnvelopeCountMismatch,
+    InvalidBeamSize,
+    InvalidMaxSymbolsPerFrame,
+    InvalidBlankBias,
+    InvalidBeamCutRelative,
+    InvalidBeamCutThreshold,
+    InvalidDelta,
+    InvalidInterval,
+    InvalidBlankId,
+    InvalidScale,
+    InvalidSeparator,
+    InvalidTransitions,
+    InvalidLengthRange,
+    InvalidWeight,
+    InvalidRepeatLabelFloor,
+    InvalidTimeAxis,
+    InvalidTarget,
+    UnalignableTarget,
+    InvalidAlphabetMapping,
+    RowSumMismatch,
+    InvalidInitState,
+    CapacityExceeded,
+    InvalidAnchor,
+    InvalidLabelPrior,
+    /// A pointer argument was NULL (other than `data` for an empty network output).
+    NullPointer = 100,
+    /// A label of the alphabet is not valid UTF-8.
+    InvalidUtf8 = 101,
+}
+
+impl From<SearchError> for CtcStatus {
+    fn from(err: SearchError) -> Self {
+        match err {
+            SearchError::RanOutOfBeam { .. } => CtcStatus::RanOutOfBeam,
+            SearchError::IncomparableValues { .. } => CtcStatus::IncomparableValues,
+            SearchError::InvalidEnvelope { .. } => CtcStatus::InvalidEnvelope,
+            SearchError::AlphabetMismatch => CtcStatus::AlphabetMismatch,
+            SearchError::EnvelopeCountMismatch => CtcStatus::EnvelopeCountMismatch,
+            SearchError::InvalidBeamSize => CtcStatus::InvalidBeamSize,
+            SearchError::InvalidMaxSymbolsPerFrame => CtcStatus::InvalidMaxSymbolsPerFrame,
+            SearchError::InvalidBlankBias => CtcStatus::InvalidBlankBias,
+            SearchError::InvalidBeamCutRelative => CtcStatus::InvalidBeamCutRelative,
+            SearchError::InvalidBeamCutThreshold => CtcStatus::InvalidBeamCutThreshold,
+            SearchError::InvalidDelta => CtcStatus::InvalidDelta,
+            SearchError::InvalidInterval => CtcStatus::InvalidInterval,
+            SearchError::InvalidBlankId => CtcStatus::InvalidBlankId,
+            SearchError::InvalidScale => CtcStatus::InvalidScale,
+            SearchError::InvalidSeparator => CtcStatus::InvalidSeparator,
+            SearchError::InvalidTransitions => CtcStatus::InvalidTransitions,
+            SearchError::InvalidLengthRange => CtcStatus::InvalidLengthRange,
+            SearchError::InvalidWeight => CtcStatus::InvalidWeight,
+            SearchError::InvalidRepeatLabelFloor => CtcStatus::InvalidRepeatLabelFloor,
+            SearchError::InvalidTimeAxis => CtcStatus::InvalidTimeAxis,
+            SearchError::InvalidTarget => CtcStatus::InvalidTarget,
+            SearchError::UnalignableTarget => CtcStatus::UnalignableTarget,
+            SearchError::InvalidAlphabetMapping => CtcStatus::InvalidAlphabetMapping,
+            SearchError::RowSumMismatch { .. } => CtcStatus::RowSumMismatch,
+            SearchError::InvalidInitState => CtcStatus::InvalidInitState,
+            SearchError::CapacityExceeded => CtcStatus::CapacityExceeded,
+            SearchError::InvalidAnchor => CtcStatus::InvalidAnchor,
+            SearchError::InvalidLabelPrior => CtcStatus::InvalidLabelPrior,
+        }
+    }
+}
+
+/// Perform a CTC beam search decode on an RNN output.
+///
+/// `data` is the network output as a row-major `rows` by `cols` matrix of probabilities, with
+/// time on the outer axis, and `alphabet` is the `cols` NUL-terminated labels, with the blank
+/// label first. Repeated labels are collapsed. See `search::beam_search` for `beam_size` and
+/// `threshold` (the beam cut threshold).
+///
+/// On success, `*out_str` is the decoded sequence (NUL-terminated), `*out_timepoints` the
+/// timepoint of each label and `*out_len` the number of labels (and so of timepoints). These must
+/// be freed with `ctc_free_result`. On failure, the out pointers are left unchanged.
+///
+/// # Safety
+///
+/// `data` must point to `rows * cols` floats (or may be NULL if that is 0), `alphabet` to `cols`
+/// valid C strings, and the out pointers must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ctc_beam_search(
+    data: *const f32,
+    rows: usize,
+    cols: usize,
+    alphabet: *const *const c_char,
+    beam_size: usize,
+    threshold: f32,
+    out_str: *mut *mut c_char,
+    out_timepoints: *mut *mut usize,
+    out_len: *mut usize,
+) -> CtcStatus {
+    if out_str.is_null() || out_timepoints.is_null() || out_len.is_null() {
+        return CtcStatus::NullPointer;
+    }
+    let n_values = match rows.checked_mul(cols) {
+        Some(n_values) => n_values,
+        None => return CtcStatus::AlphabetMismatch,
+    };
+    let data = if n_values == 0 {
+        &[]
+    } else if data.is_null() {
+        return CtcStatus::NullPointer;
+    } else {
+        std::slice::from_raw_parts(data, n_values)
+    };
+    let network_output = match ArrayView2::from_shape((rows, cols), data) {
+        Ok(network_output) => network_output,
+        Err(_) => return CtcStatus::AlphabetMismatch,
+    };
+    let labels = if cols == 0 {
+        &[]
+    } else if alphabet.is_null() {
+        return CtcStatus::NullPointer;
+    } else {
+        std::slice::from_raw_parts(alphabet, cols)
+    };
+    let mut alphabet = Vec::with_capacity(labels.len());
+    for &label in labels {
+        if label.is_null() {
+            return CtcStatus::NullPointer;
+        }
+        match CStr::from_ptr(label).to_str() {
+            Ok(label) => alphabet.push(label.to_string()),
+            Err(_) => return CtcStatus::InvalidUtf8,
+        }
+    }
+
+    let (sequence, path, _) =
+        match search::beam_search(&network_output, &alphabet, beam_size, threshold, true, 0) {
+            Ok(result) => result,
+            Err(err) => return err.into(),
+        };
+    // the labels came from C strings, so the sequence has no NULs in it
+    let sequence = match CString::new(sequence) {
+        Ok(sequence) => sequence,
+        Err(_) => return CtcStatus::InvalidUtf8,
+    };
+    *out_len = path.len();
+    *out_str = sequence.into_raw();
+    *out_timepoints = Box::into_raw(path.into_boxed_slice()) as *mut usize;
+    CtcStatus::Ok
+}
+
+/// Free the sequence and timepoints returned by `ctc_beam_search`.
+///
+/// `len` must be the number of timepoints returned with them. Either pointer may be NULL.
+///
+/// # Safety
+///
+/// The pointers must have come from a successful call to `ctc_beam_search`, and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn ctc_free_result(str: *mut c_char, timepoints: *mut usize, len: usize) {
+    if !str.is_null() {
+        drop(CString::from_raw(str));
+    }
+    if !timepoints.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            timepoints, len,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctc_beam_search() {
+        let labels: Vec<CString> = "NAG"
+            .chars()
+            .map(|x| CString::new(x.to_string()).unwrap())
+            .collect();
+        let alphabet: Vec<*const c_char> = labels.iter().map(|x| x.as_ptr()).collect();
+        let data = [0.1f32, 0.8, 0.1, 0.7, 0.2, 0.1, 0.1, 0.1, 0.8];
+        let (mut out_str, mut out_timepoints, mut out_len) = (ptr::null_mut(), ptr::null_mut(), 0);
+        unsafe {
+            let status = ctc_beam_search(
+                data.as_ptr(),
+                3,
+                3,
+                alphabet.as_ptr(),
+                5,
+                0.0,
+                &mut out_str,
+                &mut out_timepoints,
+                &mut out_len,
+            );
+            assert_eq!(status, CtcStatus::Ok);
+            assert_eq!(CStr::from_ptr(out_str).to_str().unwrap(), "AG");
+            assert_eq!(std::slice::from_raw_parts(out_timepoints, out_len), &[0, 1]);
+            ctc_free_result(out_str, out_timepoints, out_len);
+
+            let status = ctc_beam_search(
+                data.as_ptr(),
+                3,
+                3,
+                alphabet.as_ptr(),
+                0,
+                0.0,
+                &mut out_str,
+                &mut out_timepoints,
+                &mut out_len,
+            );
+            assert_eq!(status, CtcStatus::InvalidBeamSize);
+            let status = ctc_beam_search(
+                data.as_ptr(),
+                3,
+                3,
+                ptr::null(),
+                5,
+                0.0,
+                &mut out_str,
+                &mut out_timepoints,
+                &mut out_len,
+            );
+            assert_eq!(status, CtcStatus::NullPointer);
+        }
+        // the codes C code sees
+        assert_eq!(CtcStatus::InvalidBeamSize as i32, 6);
+        assert_eq!(CtcStatus::NullPointer as i32, 100);
+        assert_eq!(CtcStatus::InvalidUtf8 as i32, 101);
+    }
+}
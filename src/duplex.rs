@@ -1,83 +1,13 @@
-use super::SearchError;
+use super::{EnvelopeError, SearchError};
+use crate::logspace::LogSpace;
+use crate::search::{self, BeamSearchOptions};
+use crate::simd;
 use crate::tree::{SuffixTree, ROOT_NODE};
-use logspace::LogSpace;
-use ndarray::{ArrayBase, Axis, Data, Ix1, Ix2, Ix3};
-use ndarray_stats::QuantileExt;
-
-mod logspace {
-    use std::ops::{Add, AddAssign, Mul, MulAssign};
-
-    #[cfg(feature = "fastexp")]
-    fn exp(a: f32) -> f32 {
-        use crate::fastexp::FastExp;
-        a.fastexp()
-    }
-    #[cfg(not(feature = "fastexp"))]
-    fn exp(a: f32) -> f32 {
-        a.exp()
-    }
-
-    #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
-    pub struct LogSpace(f32);
-
-    impl LogSpace {
-        pub fn new(val: f32) -> Self {
-            LogSpace(val.ln())
-        }
-        pub fn zero() -> Self {
-            LogSpace(std::f32::NEG_INFINITY)
-        }
-        pub fn one() -> Self {
-            LogSpace(0.0)
-        }
-        pub fn max(self, other: Self) -> Self {
-            if self.0 < other.0 {
-                other
-            } else {
-                self
-            }
-        }
-    }
-
-    impl Add for LogSpace {
-        type Output = Self;
-        fn add(self, other: Self) -> Self {
-            fn add_internal(big: f32, small: f32) -> f32 {
-                if small == std::f32::NEG_INFINITY {
-                    // -inf is the additive unit (it represents zero probability)
-                    big
-                } else {
-                    big + exp(small - big).ln_1p()
-                }
-            }
-            // order operands by magnitude to ensure a+b produces the same answer as b+a
-            // NB: the comparison is done such that if there is a NaN, it will end up being the
-            // first argument to add_internal(), and thus be propagated properly (fastexp() doesn't
-            // propagate NaNs).
-            if self.0 <= other.0 {
-                LogSpace(add_internal(other.0, self.0))
-            } else {
-                LogSpace(add_internal(self.0, other.0))
-            }
-        }
-    }
-    impl AddAssign for LogSpace {
-        fn add_assign(&mut self, other: Self) {
-            *self = self.add(other);
-        }
-    }
-    impl Mul for LogSpace {
-        type Output = Self;
-        fn mul(self, other: Self) -> Self {
-            LogSpace(self.0 + other.0)
-        }
-    }
-    impl MulAssign for LogSpace {
-        fn mul_assign(&mut self, other: Self) {
-            *self = self.mul(other);
-        }
-    }
-}
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use ndarray::{Array2, ArrayBase, Axis, Data, Ix1, Ix2, Ix3};
 
 /// The probabilities for a node in the search tree.
 #[derive(Clone, Copy, Debug)]
@@ -120,7 +50,7 @@ impl ProbPair {
     }
 }
 
-impl std::ops::AddAssign for ProbPair {
+impl core::ops::AddAssign for ProbPair {
     fn add_assign(&mut self, other: Self) {
         self.label += other.label;
         self.gap += other.gap;
@@ -137,7 +67,8 @@ struct SearchPoint {
     /// The probability information for the paths in network 1 discovered so far that could produce
     /// the labelling indicated by `node`.
     prob_1: ProbPair,
-    /// The maximum probability found in network 2 for the labelling indicated by `node`.
+    /// The maximum probability found in network 2 for the labelling indicated by `node` (or, for
+    /// a consensus of several networks, the product of the maximum found in each of the others).
     prob_2_max: LogSpace,
 }
 
@@ -171,7 +102,7 @@ impl SecondaryProbs {
         } else {
             let index = index as usize;
             if index < self.probs.len() {
-                self.probs[index as usize]
+                self.probs[index]
             } else {
                 ProbPair::zero()
             }
@@ -203,48 +134,132 @@ impl SecondaryProbs {
         self.max_prob = max_prob;
     }
 
+    /// The row from `lower_bound` to `upper_bound` (exclusive) with the highest probability, or
+    /// `None` if every row there has a probability of zero.
+    fn best_row(&self, lower_bound: isize, upper_bound: isize) -> Option<isize> {
+        let len = self.probs.len() as isize;
+        let begin = (lower_bound - self.offset).clamp(0, len) as usize;
+        let end = (upper_bound - self.offset).clamp(begin as isize, len) as usize;
+        let mut best = None;
+        let mut max_prob = LogSpace::zero();
+        for (index, prob) in self.probs[begin..end].iter().enumerate() {
+            if prob.probability() > max_prob {
+                max_prob = prob.probability();
+                best = Some(self.offset + (begin + index) as isize);
+            }
+        }
+        best
+    }
+
     fn end(&self) -> isize {
-        assert!(self.probs.len() <= (std::isize::MAX as usize));
+        assert!(self.probs.len() <= (isize::MAX as usize));
         self.offset + (self.probs.len() as isize)
     }
 }
 
-fn build_secondary_probs<D: Data<Elem = LogSpace>>(
-    network_output: &ArrayBase<D, Ix2>,
-    parent_probs: &SecondaryProbs,
+/// A labelling to find the probabilities of in a secondary output: the labelling of `parent`
+/// extended by `label`.
+#[derive(Clone, Copy)]
+struct Extension<'a> {
+    parent: &'a SecondaryProbs,
     label: usize,
+    /// Whether `label` is the same as the last label of the parent's labelling, with repeats
+    /// collapsed, so it can only follow the parent's paths that end in a blank.
     is_repeat: bool,
-    lower_bound: usize,
-    upper_bound: usize,
-) -> SecondaryProbs {
-    assert!(lower_bound < upper_bound);
-    assert!(upper_bound <= (std::isize::MAX as usize));
-    assert!(upper_bound <= network_output.shape()[0]);
-    assert!(label + 1 < network_output.shape()[1]);
+}
 
-    let mut probs = SecondaryProbs::with_offset(lower_bound as isize);
-    probs.probs.reserve(upper_bound - lower_bound);
-    let mut last_probs = ProbPair::zero();
+/// Append the probabilities of each of `extensions` to the matching entry of `probs`, for the
+/// rows from `start` to `end` of `network_output` (exclusive). Each of `probs` must end at
+/// `start`.
+///
+/// The extensions are stepped through the rows together, so the sums for a row are done for all
+/// of them at once (with SIMD, see `simd::log_add`).
+fn append_secondary_probs<D: Data<Elem = LogSpace>>(
+    probs: &mut [SecondaryProbs],
+    network_output: &ArrayBase<D, Ix2>,
+    extensions: &[Extension<'_>],
+    start: usize,
+    end: usize,
+) {
+    assert_eq!(probs.len(), extensions.len());
+    assert!(start <= end);
+    assert!(end <= (isize::MAX as usize));
+    assert!(end <= network_output.shape()[0]);
+    assert!(extensions
+        .iter()
+        .all(|extension| extension.label + 1 < network_output.shape()[1]));
+    let n = extensions.len();
+    if n == 0 {
+        return;
+    }
 
-    for (idx, labelling_probs) in (lower_bound..upper_bound).zip(
-        network_output
-            .slice(s![lower_bound..upper_bound, ..])
-            .outer_iter(),
-    ) {
-        let gap_prob = last_probs.probability() * labelling_probs[0];
-        let prev_parent_probs = parent_probs.get((idx as isize) - 1);
-        let label_prob = if is_repeat {
-            labelling_probs[label + 1] * (last_probs.label + prev_parent_probs.gap)
-        } else {
-            labelling_probs[label + 1] * (last_probs.label + prev_parent_probs.probability())
-        };
-        last_probs = ProbPair {
-            label: label_prob,
-            gap: gap_prob,
-        };
-        probs.probs.push(last_probs);
-        probs.max_prob = probs.max_prob.max(last_probs.probability());
+    // the probabilities of each labelling and its parent at the last row, as natural logs, and
+    // room for sums
+    let mut last_label = Vec::with_capacity(n);
+    let mut last_gap = Vec::with_capacity(n);
+    for probs in probs.iter_mut() {
+        assert_eq!(probs.end(), start as isize);
+        let last_probs = probs.probs.last().copied().unwrap_or(ProbPair::zero());
+        last_label.push(last_probs.label.ln());
+        last_gap.push(last_probs.gap.ln());
+        probs.probs.reserve(end - start);
     }
+    let (mut parent_label, mut parent_gap, mut parent) = (vec![0.0; n], vec![0.0; n], vec![0.0; n]);
+    let mut sum = vec![0.0; n];
+
+    for (idx, labelling_probs) in
+        (start..end).zip(network_output.slice(s![start..end, ..]).outer_iter())
+    {
+        for (i, extension) in extensions.iter().enumerate() {
+            let prev_parent_probs = extension.parent.get((idx as isize) - 1);
+            parent_label[i] = prev_parent_probs.label.ln();
+            parent_gap[i] = prev_parent_probs.gap.ln();
+        }
+        simd::log_add(&parent_label, &parent_gap, &mut parent);
+        for (i, extension) in extensions.iter().enumerate() {
+            if extension.is_repeat {
+                parent[i] = parent_gap[i];
+            }
+        }
+        simd::log_add(&last_label, &last_gap, &mut sum);
+        for (gap_prob, &sum) in last_gap.iter_mut().zip(&sum) {
+            *gap_prob = sum + labelling_probs[0].ln();
+        }
+        simd::log_add(&last_label, &parent, &mut sum);
+        for ((label_prob, &sum), extension) in last_label.iter_mut().zip(&sum).zip(extensions) {
+            *label_prob = labelling_probs[extension.label + 1].ln() + sum;
+        }
+        simd::log_add(&last_label, &last_gap, &mut sum);
+        for (i, probs) in probs.iter_mut().enumerate() {
+            probs.probs.push(ProbPair {
+                label: LogSpace::from_ln(last_label[i]),
+                gap: LogSpace::from_ln(last_gap[i]),
+            });
+            probs.max_prob = probs.max_prob.max(LogSpace::from_ln(sum[i]));
+        }
+    }
+}
+
+/// The probabilities of each of `extensions`, from `lower_bound` to `upper_bound` (exclusive).
+/// See `append_secondary_probs`.
+fn build_secondary_probs<D: Data<Elem = LogSpace>>(
+    network_output: &ArrayBase<D, Ix2>,
+    extensions: &[Extension<'_>],
+    lower_bound: usize,
+    upper_bound: usize,
+) -> Vec<SecondaryProbs> {
+    assert!(lower_bound < upper_bound);
+    let mut probs: Vec<_> = extensions
+        .iter()
+        .map(|_| SecondaryProbs::with_offset(lower_bound as isize))
+        .collect();
+    append_secondary_probs(
+        &mut probs,
+        network_output,
+        extensions,
+        lower_bound,
+        upper_bound,
+    );
     probs
 }
 
@@ -257,7 +272,7 @@ fn crf_build_secondary_probs<D: Data<Elem = LogSpace>>(
     upper_bound: usize,
 ) -> SecondaryProbs {
     assert!(lower_bound < upper_bound);
-    assert!(upper_bound <= (std::isize::MAX as usize));
+    assert!(upper_bound <= (isize::MAX as usize));
     assert!(upper_bound <= network_output.shape()[0]);
     assert!(label + 1 < network_output.shape()[2]);
 
@@ -296,7 +311,7 @@ fn crf_extend_secondary_probs<D: Data<Elem = LogSpace>>(
     lower_bound: usize,
     upper_bound: usize,
 ) {
-    assert!(upper_bound <= (std::isize::MAX as usize));
+    assert!(upper_bound <= (isize::MAX as usize));
     assert!(lower_bound <= upper_bound);
 
     // discard everything below lower_bound-1, and make sure max_prob only covers from lower_bound
@@ -344,7 +359,7 @@ fn extend_secondary_probs<D: Data<Elem = LogSpace>>(
     lower_bound: usize,
     upper_bound: usize,
 ) {
-    assert!(upper_bound <= (std::isize::MAX as usize));
+    assert!(upper_bound <= (isize::MAX as usize));
     assert!(lower_bound <= upper_bound);
 
     // discard everything below lower_bound-1, and make sure max_prob only covers from lower_bound
@@ -363,27 +378,18 @@ fn extend_secondary_probs<D: Data<Elem = LogSpace>>(
     let current_end = current_end as usize;
     assert!(current_end < upper_bound);
 
-    let mut last_probs = probs.probs.last().copied().unwrap_or(ProbPair::zero());
-    probs.probs.reserve(upper_bound - current_end);
-    for (idx, labelling_probs) in (current_end..upper_bound).zip(
-        network_output
-            .slice(s![current_end..upper_bound, ..])
-            .outer_iter(),
-    ) {
-        let gap_prob = last_probs.probability() * labelling_probs[0];
-        let prev_parent_probs = parent_probs.get((idx as isize) - 1);
-        let label_prob = if is_repeat {
-            labelling_probs[label + 1] * (last_probs.label + prev_parent_probs.gap)
-        } else {
-            labelling_probs[label + 1] * (last_probs.label + prev_parent_probs.probability())
-        };
-        last_probs = ProbPair {
-            label: label_prob,
-            gap: gap_prob,
-        };
-        probs.probs.push(last_probs);
-        probs.max_prob = probs.max_prob.max(last_probs.probability());
-    }
+    let extension = Extension {
+        parent: parent_probs,
+        label,
+        is_repeat,
+    };
+    append_secondary_probs(
+        core::slice::from_mut(probs),
+        network_output,
+        &[extension],
+        current_end,
+        upper_bound,
+    );
 }
 
 fn root_probs<D: Data<Elem = LogSpace>>(
@@ -432,7 +438,7 @@ fn crf_root_probs<D: Data<Elem = LogSpace>>(
         .slice(s![0..upper_bound, .., ..])
         .axis_iter(Axis(0))
     {
-        cur_prob *= pr[[state as usize, 0]];
+        cur_prob *= pr[[state, 0]];
         probs.probs.push(ProbPair::with_gap(cur_prob));
         state = (state * n_base) % n_state;
     }
@@ -440,28 +446,680 @@ fn crf_root_probs<D: Data<Elem = LogSpace>>(
     probs
 }
 
+/// Check `envelope` meets the constraints described on `EnvelopeError`.
+fn check_envelope<E: Data<Elem = usize>>(
+    envelope: &ArrayBase<E, Ix2>,
+    len_1: usize,
+    len_2: usize,
+) -> Result<(), SearchError> {
+    if envelope.shape() != [len_1, 2] {
+        return Err(SearchError::InvalidEnvelope {
+            row: 0,
+            reason: EnvelopeError::WrongShape,
+        });
+    }
+    let (mut last_start, mut last_end) = (0, 0);
+    for (row, bounds) in envelope.outer_iter().enumerate() {
+        let (start, end) = (bounds[0], bounds[1]);
+        let reason = if start >= end {
+            Some(EnvelopeError::EmptyRange)
+        } else if end > len_2 {
+            Some(EnvelopeError::EndOutOfBounds)
+        } else if start < last_start {
+            Some(EnvelopeError::DecreasingStart)
+        } else if start > last_end {
+            Some(EnvelopeError::Disconnected)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(SearchError::InvalidEnvelope { row, reason });
+        }
+        last_start = start;
+        last_end = end;
+    }
+    Ok(())
+}
+
+/// The labels on the best path through `network_output`, with the timepoint each was emitted.
+///
+/// Repeats are collapsed and blanks removed, as for a greedy decode.
+fn best_path_labels<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+) -> Vec<(usize, usize)> {
+    let mut labels = Vec::new();
+    let mut last_label = 0;
+    for (idx, pr) in network_output.outer_iter().enumerate() {
+        // treat rows we can't order (NaNs) as blanks; they just won't provide an anchor
+        let label = search::max_index(pr).map_or(0, |(label, _)| label);
+        if label != 0 && label != last_label {
+            labels.push((label, idx));
+        }
+        last_label = label;
+    }
+    labels
+}
+
+/// Timepoint pairs where the best paths through the two network outputs agree on a label.
+///
+/// This finds a longest common subsequence of the two labellings, only considering matches close
+/// to the diagonal (scaled for the lengths of the labellings) to keep the cost down. The pairs are
+/// strictly increasing in both timepoints.
+fn anchor_points(path_1: &[(usize, usize)], path_2: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let (len_1, len_2) = (path_1.len(), path_2.len());
+    if len_1 == 0 || len_2 == 0 {
+        return Vec::new();
+    }
+
+    let len_diff = if len_1 > len_2 {
+        len_1 - len_2
+    } else {
+        len_2 - len_1
+    };
+    let band = (len_diff + len_1.max(len_2) / 10 + 8) as isize;
+    let width = (2 * band + 1) as usize;
+    let band_start = |i: usize| ((i * len_2 / len_1) as isize) - band;
+
+    // lcs[i][j - band_start(i)] is the LCS length of path_1[..i] and path_2[..j]; anything
+    // outside the band is treated as zero
+    let mut lcs = vec![0u32; (len_1 + 1) * width];
+    let get = |lcs: &[u32], i: usize, j: usize| -> u32 {
+        let offset = (j as isize) - band_start(i);
+        if offset >= 0 && (offset as usize) < width {
+            lcs[i * width + offset as usize]
+        } else {
+            0
+        }
+    };
+    for i in 1..=len_1 {
+        let start = band_start(i).max(1);
+        let end = (band_start(i) + width as isize).min(len_2 as isize + 1);
+        for j in (start..end).map(|j| j as usize) {
+            let value = if path_1[i - 1].0 == path_2[j - 1].0 {
+                get(&lcs, i - 1, j - 1) + 1
+            } else {
+                get(&lcs, i - 1, j).max(get(&lcs, i, j - 1))
+            };
+            lcs[i * width + (j as isize - band_start(i)) as usize] = value;
+        }
+    }
+
+    let mut anchors = Vec::new();
+    let (mut i, mut j) = (len_1, len_2);
+    while i > 0 && j > 0 {
+        let value = get(&lcs, i, j);
+        if path_1[i - 1].0 == path_2[j - 1].0 && value == get(&lcs, i - 1, j - 1) + 1 {
+            anchors.push((path_1[i - 1].1, path_2[j - 1].1));
+            i -= 1;
+            j -= 1;
+        } else if get(&lcs, i - 1, j) >= get(&lcs, i, j - 1) {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// The part of `bounds` (a row of an envelope) to search, when refining the envelope with
+/// `margin`.
+///
+/// `last` is the part searched for the row before, `aligned` the lowest and highest rows the
+/// labellings in the beam are best aligned with (if any), and `next_start` the start of the next
+/// row of the envelope. The result starts and ends no earlier than `last`, and ends after
+/// `next_start`, so that the refined rows stay connected to each other as those of an envelope
+/// must.
+fn refine_bounds(
+    bounds: (usize, usize),
+    last: (usize, usize),
+    aligned: Option<(usize, usize)>,
+    margin: usize,
+    next_start: usize,
+) -> (usize, usize) {
+    let (lower, upper) = match aligned {
+        Some((lowest, highest)) => (
+            lowest.saturating_sub(margin),
+            highest.saturating_add(margin).saturating_add(1),
+        ),
+        None => bounds,
+    };
+    let upper = upper.max(last.1).max(next_start + 1).min(bounds.1);
+    let lower = lower.max(last.0).max(bounds.0).min(upper - 1);
+    (lower, upper)
+}
+
+/// Calculate an envelope for aligning `network_output_2` to `network_output_1`.
+///
+/// This anchors the two outputs together at the labels their best paths agree on. Each row of
+/// `network_output_1` between two anchors is then given the range of `network_output_2` from the
+/// anchor before the previous one to the anchor after the next one, so that a missed or spurious
+/// anchor does not cut off the true alignment. The start and end of the two outputs are always
+/// anchored to each other.
+///
+/// The result is an Nx2 array (where N is the outer axis length of `network_output_1`) that can be
+/// passed as the `envelope` to `beam_search`. If either output does not have the same inner axis
+/// length as `alphabet`, this returns `SearchError::AlphabetMismatch`. If `network_output_2` is
+/// empty, every range is empty (there is nothing to align with), which the searches accept as they
+/// do not use the envelope of an empty output.
+///
+/// For outputs that are known to line up, `band_envelope` gives a band around the diagonal
+/// instead, without looking at them.
+pub fn compute_envelope<D: Data<Elem = f32>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+) -> Result<Array2<usize>, SearchError> {
+    if network_output_1.shape()[1] != alphabet.len()
+        || network_output_2.shape()[1] != alphabet.len()
+    {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let len_1 = network_output_1.shape()[0];
+    let len_2 = network_output_2.shape()[0];
+    if len_2 == 0 {
+        return Ok(Array2::zeros((len_1, 2)));
+    }
+
+    let mut anchors = vec![(0, 0)];
+    for anchor in anchor_points(
+        &best_path_labels(network_output_1),
+        &best_path_labels(network_output_2),
+    ) {
+        // the initial anchor may coincide with a real one
+        if anchor.0 > 0 && anchor.1 > 0 {
+            anchors.push(anchor);
+        }
+    }
+    anchors.push((len_1, len_2));
+
+    let mut envelope = Array2::zeros((len_1, 2));
+    let mut k = 0;
+    for (i, mut bounds) in envelope.outer_iter_mut().enumerate() {
+        // anchors[k] is the last anchor at or before row i
+        while anchors[k + 1].0 <= i {
+            k += 1;
+        }
+        let lower = anchors[k.saturating_sub(1)].1;
+        let upper = anchors[(k + 2).min(anchors.len() - 1)].1 + 1;
+        bounds[0] = lower.min(len_2 - 1);
+        bounds[1] = upper.min(len_2);
+    }
+    Ok(envelope)
+}
+
+/// An envelope for aligning a network output of `len_2` rows to one of `len_1` rows that keeps
+/// within `band_width` rows of the diagonal.
+///
+/// The diagonal is where the rows would line up if the two outputs were stretched to the same
+/// length: row `i` of the first output covers the rows of the second from `i * len_2 / len_1`
+/// (rounded down) up to `(i + 1) * len_2 / len_1` (rounded up). That is the one row `i` when the
+/// outputs are the same length, and several rows (or part of one) for each row when they are not.
+/// The envelope widens each row's part of the diagonal by `band_width` rows on each side, cut off
+/// at the start and end of the second output, so with outputs of the same length row `i` gets
+/// rows `i - band_width` to `i + band_width` (inclusive).
+///
+/// Unlike `compute_envelope`, this does not look at the outputs, so it suits reads that are known
+/// to line up, such as two runs over the same signal. The result always meets the constraints on
+/// an envelope, whatever `band_width` is: the parts of the diagonal of neighbouring rows meet, so
+/// the rows stay connected, and the last row reaches the end of the second output. As for
+/// `compute_envelope`, if `len_2` is 0 every range is empty.
+pub fn band_envelope(len_1: usize, len_2: usize, band_width: usize) -> Array2<usize> {
+    let mut envelope = Array2::zeros((len_1, 2));
+    if len_2 == 0 {
+        return envelope;
+    }
+    // in 64 bits, so the products cannot overflow on 32-bit targets
+    let (rows_1, rows_2) = (len_1 as u64, len_2 as u64);
+    for (i, mut bounds) in envelope.outer_iter_mut().enumerate() {
+        let i = i as u64;
+        let diagonal_start = (i * rows_2 / rows_1) as usize;
+        let diagonal_end = ((i + 1) * rows_2 + rows_1 - 1) / rows_1;
+        bounds[0] = diagonal_start.saturating_sub(band_width);
+        bounds[1] = (diagonal_end as usize)
+            .saturating_add(band_width)
+            .min(len_2);
+    }
+    envelope
+}
+
+/// The concatenated tokens of `labels` (which leave out the blank).
+fn labels_to_string(labels: &[usize], alphabet: &[String]) -> String {
+    labels
+        .iter()
+        .map(|&label| alphabet[label + 1].as_str())
+        .collect()
+}
+
+/// Re-index `network_output`, whose inner axis is the labels of `alphabet`, onto the labels of
+/// `shared_alphabet`.
+///
+/// Column `j` of `network_output` stands for label `mapping[j]` of `shared_alphabet`. Columns
+/// that map to the same label (such as a modified and an unmodified base) have their
+/// probabilities added up, and labels that nothing maps to get probability 0. The mapping must
+/// be total: it needs an entry for every label of `alphabet`, each less than
+/// `len(shared_alphabet)`, and the blank (column 0) must map to the blank and nothing else may.
+pub fn map_columns<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    shared_alphabet: &[String],
+    mapping: &[usize],
+) -> Result<Array2<f32>, SearchError> {
+    if network_output.shape()[1] != alphabet.len() {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let valid_mapping = mapping.len() == alphabet.len()
+        && mapping.first() == Some(&0)
+        && mapping[1..]
+            .iter()
+            .all(|&label| label != 0 && label < shared_alphabet.len());
+    if !valid_mapping {
+        return Err(SearchError::InvalidAlphabetMapping);
+    }
+    let mut mapped = Array2::zeros((network_output.shape()[0], shared_alphabet.len()));
+    for (mut mapped_row, row) in mapped.outer_iter_mut().zip(network_output.outer_iter()) {
+        for (&label, &prob) in mapping.iter().zip(row) {
+            mapped_row[label] += prob;
+        }
+    }
+    Ok(mapped)
+}
+
+/// Perform the same search as `beam_search`, where the two network outputs have different
+/// alphabets.
+///
+/// The consensus is found over `alphabet_1`, after re-indexing `network_output_2` (whose inner
+/// axis is the labels of `alphabet_2`) onto it with `map_columns`, so `mapping[j]` is the label of
+/// `alphabet_1` that label `j` of `alphabet_2` stands for. The envelope relates the rows of the
+/// two outputs as for `beam_search`; `compute_envelope` can make one from `network_output_1` and
+/// the output of `map_columns`.
+#[allow(clippy::too_many_arguments)]
+pub fn beam_search_mapped<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    alphabet_1: &[String],
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet_2: &[String],
+    mapping: &[usize],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+) -> Result<String, SearchError> {
+    if alphabet_1.is_empty() || network_output_1.shape()[1] != alphabet_1.len() {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let network_output_2 = map_columns(network_output_2, alphabet_2, alphabet_1, mapping)?;
+    beam_search(
+        &network_output_1.view(),
+        &network_output_2.view(),
+        alphabet_1,
+        envelope,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )
+}
+
+/// Perform a CTC beam search decode on two RNN outputs that describe the same sequence.
+///
+/// `envelope` gives, for each row of `network_output_1`, the range of rows of `network_output_2`
+/// to align it with (see `EnvelopeError` for what makes a valid one, and `compute_envelope`). If
+/// either output has no rows, there is nothing for them to agree on, and the result is the empty
+/// string whatever the envelope.
+///
+/// The entries of both outputs are probabilities, and so is `beam_cut_threshold` (at least 0.0
+/// and less than `1/len(alphabet)`), even though the search adds them up in log space. This holds
+/// for all the duplex searches.
+///
+/// Labellings with exactly the same score are ordered lexicographically by their columns (as
+/// `TieBreak::Lexicographic` does for the single-output searches), so when the beam is cut down
+/// to `beam_size`, which of them survive is always the same. The CRF searches break ties the
+/// same way.
 pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
-    network_output_1_real: &ArrayBase<D, Ix2>,
-    network_output_2_real: &ArrayBase<D, Ix2>,
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
     alphabet: &[String],
     envelope: &ArrayBase<E, Ix2>,
     beam_size: usize,
-    beam_cut_threshold_real: f32,
+    beam_cut_threshold: f32,
     collapse_repeats: bool,
 ) -> Result<String, SearchError> {
-    let network_output_1 = network_output_1_real.map(|&x| LogSpace::new(x));
-    let network_output_2 = network_output_2_real.map(|&x| LogSpace::new(x));
+    beam_search_weighted(
+        network_output_1,
+        network_output_2,
+        alphabet,
+        envelope,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+        1.0,
+        1.0,
+    )
+}
+
+/// Perform the same search as `beam_search`, trusting one network output more than the other.
+///
+/// A labelling is scored by `weight_1` times its log probability in `network_output_1`, plus
+/// `weight_2` times its best log probability in `network_output_2` (within the envelope). Only
+/// the ratio of the weights changes the result, and weights of 1.0 and 1.0 give the same search as
+/// `beam_search`. Both must be finite and greater than 0.0.
+///
+/// The weights only change how labellings are scored against each other. The envelope still
+/// limits the rows of `network_output_2` each row of `network_output_1` is aligned with, and
+/// `beam_cut_threshold` is still compared with the unweighted entries of `network_output_1`.
+#[allow(clippy::too_many_arguments)]
+pub fn beam_search_weighted<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    weight_1: f32,
+    weight_2: f32,
+) -> Result<String, SearchError> {
+    let labels = consensus_labels(
+        network_output_1,
+        &[network_output_2],
+        alphabet,
+        &[envelope],
+        &[weight_1, weight_2],
+        None,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )?;
+    Ok(labels_to_string(&labels, alphabet))
+}
+
+/// Perform the same search as `beam_search_weighted`, narrowing the envelope as it goes.
+///
+/// A wide envelope makes the search track each labelling over many rows of `network_output_2`
+/// that it is nowhere near. With refinement, after each row of `network_output_1` the search
+/// finds the rows of `network_output_2` that the labellings left in the beam are best aligned
+/// with, and for the next row only searches the part of the envelope from `refine_margin` rows
+/// before the lowest of these to `refine_margin` rows after the highest. The part searched never
+/// moves backwards, and never leaves the envelope.
+///
+/// Wherever the envelope is within `refine_margin` rows of the alignments of the beam, nothing is
+/// cut from it, so for an envelope no wider than `refine_margin` (such as a tight one from
+/// `compute_envelope`, given a margin of its width) the result is the same as that of
+/// `beam_search_weighted`. A smaller margin does less work, but may cut off the true alignment of
+/// a labelling that is not yet in the beam; a few times the width of the labels in
+/// `network_output_2` is usually plenty.
+#[allow(clippy::too_many_arguments)]
+pub fn beam_search_refined<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    weight_1: f32,
+    weight_2: f32,
+    refine_margin: usize,
+) -> Result<String, SearchError> {
+    let labels = consensus_labels(
+        network_output_1,
+        &[network_output_2],
+        alphabet,
+        &[envelope],
+        &[weight_1, weight_2],
+        Some(refine_margin),
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )?;
+    Ok(labels_to_string(&labels, alphabet))
+}
+
+/// Perform the same search as `beam_search`, also returning the timepoints of each label in both
+/// network outputs.
+///
+/// The first `Vec` gives the final timepoint of each label as a row of `network_output_1`, and
+/// the second as a row of `network_output_2`. The search does not follow a single path through
+/// either output, so each output is aligned with the decoded labelling afterwards, and the
+/// timepoint of a label is the last row it covers in the most probable path through that output
+/// that gives the labelling (as for `search::beam_search_with_spans`). The alignments are not
+/// limited to the envelope.
+///
+/// If the labelling is impossible in one of the outputs (which needs some of its probabilities
+/// to be zero), the labels off the path in that output are given the timepoint of the label
+/// before them, or 0.
+pub fn beam_search_with_timepoints<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+) -> Result<(String, Vec<usize>, Vec<usize>), SearchError> {
+    let labels = search_labels(
+        network_output_1,
+        network_output_2,
+        alphabet,
+        envelope,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )?;
+    let columns: Vec<usize> = labels.iter().map(|&label| label + 1).collect();
+    let options = BeamSearchOptions::new().collapse_repeats(collapse_repeats);
+    let timepoints = |network_output: &ArrayBase<D, Ix2>| {
+        let mut last = 0;
+        search::label_spans(network_output, &columns, &options)
+            .into_iter()
+            .map(|span| {
+                last = span.map_or(last, |(_, end)| end);
+                last
+            })
+            .collect()
+    };
+    Ok((
+        labels_to_string(&labels, alphabet),
+        timepoints(network_output_1),
+        timepoints(network_output_2),
+    ))
+}
+
+/// Perform the same search as `beam_search`, also returning how strongly each network output
+/// supports each label.
+///
+/// Each output is aligned with the decoded labelling as for `beam_search_with_timepoints`. The
+/// support of a label in an output is the highest probability the output gives that label over
+/// the rows it covers in the alignment, or 0.0 if the labelling is impossible in that output. There
+/// is one pair of supports (from `network_output_1`, then `network_output_2`) for each label.
+///
+/// A label that is well supported by one output but not the other is one the outputs disagree
+/// on, and the consensus took from the output that supports it. A run of such labels that favour
+/// the same output can point to a bias in one of the networks.
+pub fn beam_search_with_support<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+) -> Result<(String, Vec<(f32, f32)>), SearchError> {
+    let labels = search_labels(
+        network_output_1,
+        network_output_2,
+        alphabet,
+        envelope,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )?;
+    let columns: Vec<usize> = labels.iter().map(|&label| label + 1).collect();
+    let options = BeamSearchOptions::new().collapse_repeats(collapse_repeats);
+    let support = |network_output: &ArrayBase<D, Ix2>| -> Vec<f32> {
+        search::label_spans(network_output, &columns, &options)
+            .into_iter()
+            .zip(&columns)
+            .map(|(span, &column)| match span {
+                Some((start, end)) => network_output
+                    .slice(s![start..=end, column])
+                    .fold(0.0, |max: f32, &x| max.max(x)),
+                None => 0.0,
+            })
+            .collect()
+    };
+    Ok((
+        labels_to_string(&labels, alphabet),
+        support(network_output_1)
+            .into_iter()
+            .zip(support(network_output_2))
+            .collect(),
+    ))
+}
+
+/// Perform a CTC beam search decode on any number of RNN outputs that describe the same sequence.
+///
+/// This extends `beam_search` from two outputs to several, such as reads of the same molecule.
+/// `network_outputs[0]` is the reference, which the search steps through a row at a time. Each of
+/// the others is aligned with it by an envelope: `envelopes[i]` gives, for each row of the
+/// reference, the rows of `network_outputs[i + 1]` to consider (as the `envelope` of
+/// `beam_search`, so `compute_envelope` can make one). A labelling is scored by its probability in
+/// the reference times, for each other output, the highest probability of the labelling ending at
+/// a row within its envelope. The outputs are only aligned with the reference, not with each
+/// other. With two outputs, this is the same search as `beam_search`.
+///
+/// The cost grows linearly with the number of outputs. Each labelling the search reaches tracks
+/// its probabilities over the envelope of each output other than the reference, so with `N`
+/// outputs, envelopes `W` rows wide on average and a reference of `T` rows, the search takes time
+/// proportional to `T * beam_size * len(alphabet) * (1 + (N - 1) * W)`, and memory proportional to
+/// the number of labellings reached times `(N - 1) * W`.
+///
+/// All the outputs must have the same inner axis length as `alphabet`, and there must be at least
+/// one output, and one envelope for each output after the first.
+pub fn beam_search_consensus<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_outputs: &[ArrayBase<D, Ix2>],
+    alphabet: &[String],
+    envelopes: &[ArrayBase<E, Ix2>],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+) -> Result<String, SearchError> {
+    let (primary, secondaries) = match network_outputs.split_first() {
+        Some(split) if split.1.len() == envelopes.len() => split,
+        _ => return Err(SearchError::EnvelopeCountMismatch),
+    };
+    if alphabet.is_empty()
+        || network_outputs
+            .iter()
+            .any(|output| output.shape()[1] != alphabet.len())
+    {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let secondaries: Vec<_> = secondaries.iter().collect();
+    let envelopes: Vec<_> = envelopes.iter().collect();
+    let labels = consensus_labels(
+        primary,
+        &secondaries,
+        alphabet,
+        &envelopes,
+        &vec![1.0; network_outputs.len()],
+        None,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )?;
+    Ok(labels_to_string(&labels, alphabet))
+}
+
+/// The labels (leaving out the blank) of the labelling found by `beam_search`.
+fn search_labels<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    network_output_1: &ArrayBase<D, Ix2>,
+    network_output_2: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    envelope: &ArrayBase<E, Ix2>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+) -> Result<Vec<usize>, SearchError> {
+    consensus_labels(
+        network_output_1,
+        &[network_output_2],
+        alphabet,
+        &[envelope],
+        &[1.0, 1.0],
+        None,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+    )
+}
+
+/// The labels (leaving out the blank) of the consensus of `primary_real` and each of
+/// `secondaries_real`, where `envelopes[i]` aligns `secondaries_real[i]` to `primary_real`.
+///
+/// The search steps through `primary_real` a row at a time. For each labelling, it also tracks
+/// the probabilities of the labelling ending at each row of every secondary output, within that
+/// output's envelope, and scores the labelling with the best of those for each of them.
+///
+/// `weights` has the weight of each output's log probability in the score, starting with
+/// `primary_real`. Scaling them all by the same amount does not change the order of the
+/// labellings, so the secondary probabilities are raised to the power of their weight over that
+/// of the primary, and the primary probabilities are left as they are.
+///
+/// With a `refine_margin`, each envelope is narrowed as the search goes (see
+/// `beam_search_refined`).
+#[allow(clippy::too_many_arguments)]
+fn consensus_labels<D: Data<Elem = f32>, E: Data<Elem = usize>>(
+    primary_real: &ArrayBase<D, Ix2>,
+    secondaries_real: &[&ArrayBase<D, Ix2>],
+    alphabet: &[String],
+    envelopes: &[&ArrayBase<E, Ix2>],
+    weights: &[f32],
+    refine_margin: Option<usize>,
+    beam_size: usize,
+    beam_cut_threshold_real: f32,
+    collapse_repeats: bool,
+) -> Result<Vec<usize>, SearchError> {
+    assert_eq!(secondaries_real.len(), envelopes.len());
+    assert_eq!(secondaries_real.len() + 1, weights.len());
+    search::check_beam_size_and_threshold(beam_size, beam_cut_threshold_real, alphabet.len())?;
+    if weights
+        .iter()
+        .any(|&weight| !weight.is_finite() || weight <= 0.0)
+    {
+        return Err(SearchError::InvalidWeight);
+    }
+    assert_eq!(primary_real.shape()[1], alphabet.len());
+    // an empty read has nothing to agree on, and no envelope can align anything to it
+    if primary_real.shape()[0] == 0
+        || secondaries_real
+            .iter()
+            .any(|secondary| secondary.shape()[0] == 0)
+    {
+        return Ok(Vec::new());
+    }
+    let exponents: Vec<f32> = weights[1..]
+        .iter()
+        .map(|&weight| weight / weights[0])
+        .collect();
+    let network_output_1 = primary_real.map(|&x| LogSpace::new(x));
+    let secondaries: Vec<_> = secondaries_real
+        .iter()
+        .map(|output| output.map(|&x| LogSpace::new(x)))
+        .collect();
     let beam_cut_threshold = LogSpace::new(beam_cut_threshold_real);
 
-    assert_eq!(network_output_1.shape()[1], network_output_2.shape()[1]);
-    assert_eq!(network_output_1.shape()[0], envelope.shape()[0]);
-    assert_eq!(envelope.shape()[1], 2);
     assert_eq!(network_output_1.shape()[1], alphabet.len());
+    for (secondary, envelope) in secondaries.iter().zip(envelopes) {
+        assert_eq!(network_output_1.shape()[1], secondary.shape()[1]);
+        check_envelope(envelope, network_output_1.shape()[0], secondary.shape()[0])?;
+    }
 
     // alphabet size minus the blank label
     let alphabet_size = alphabet.len() - 1;
 
-    let mut suffix_tree = SuffixTree::new(alphabet_size);
+    // each node holds its probabilities in each of the secondary outputs
+    let mut suffix_tree: SuffixTree<Vec<SecondaryProbs>> = SuffixTree::new(alphabet_size);
     let mut beam = vec![SearchPoint {
         node: ROOT_NODE,
         state: 0,
@@ -473,55 +1131,136 @@ pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
     }];
     let mut next_beam = Vec::new();
 
-    let root_secondary_probs =
-        root_probs(&network_output_2.index_axis(Axis(1), 0), envelope[(0, 1)]);
+    let root_secondary_probs: Vec<SecondaryProbs> = secondaries
+        .iter()
+        .zip(envelopes)
+        .map(|(secondary, envelope)| {
+            root_probs(&secondary.index_axis(Axis(1), 0), envelope[(0, 1)])
+        })
+        .collect();
+    // the probabilities in each of the secondary outputs of the labelling of each `(node, label,
+    // is_repeat)` of `new_labels`, extended by `label`
+    let new_secondary_probs = |suffix_tree: &SuffixTree<Vec<SecondaryProbs>>,
+                               new_labels: &[(i32, usize, bool)],
+                               bounds: &[(usize, usize)]| {
+        let mut outputs: Vec<_> = secondaries
+            .iter()
+            .zip(bounds)
+            .enumerate()
+            .map(|(i, (secondary, &(lower_t, upper_t)))| {
+                let extensions: Vec<_> = new_labels
+                    .iter()
+                    .map(|&(node, label, is_repeat)| Extension {
+                        parent: &suffix_tree
+                            .get_data_ref(node)
+                            .unwrap_or(&root_secondary_probs)[i],
+                        label,
+                        is_repeat,
+                    })
+                    .collect();
+                build_secondary_probs(secondary, &extensions, lower_t, upper_t).into_iter()
+            })
+            .collect();
+        new_labels
+            .iter()
+            .map(|_| {
+                outputs
+                    .iter_mut()
+                    .map(|probs| probs.next().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut new_labels = Vec::new();
 
-    let network_2_len = network_output_2.shape()[0];
-    let mut last_upper_bound = 0;
+    let mut last_upper_bounds = vec![0; secondaries.len()];
+    let mut bounds = Vec::with_capacity(secondaries.len());
+    // for refining the envelopes: the bounds searched for the last row, and the lowest and
+    // highest rows the beam is best aligned with in each secondary output
+    let mut last_bounds = Vec::with_capacity(secondaries.len());
+    let mut aligned = vec![None; secondaries.len()];
 
-    for (labelling_probs, bounds) in network_output_1.outer_iter().zip(envelope.outer_iter()) {
+    for (idx, labelling_probs) in network_output_1.outer_iter().enumerate() {
         next_beam.clear();
 
-        let (lower_t, upper_t) = (bounds[0].max(0), bounds[1].min(network_2_len));
-        if lower_t >= upper_t || lower_t > last_upper_bound {
-            return Err(SearchError::InvalidEnvelope);
+        bounds.clear();
+        bounds.extend(
+            envelopes
+                .iter()
+                .map(|envelope| (envelope[(idx, 0)], envelope[(idx, 1)])),
+        );
+        if let Some(margin) = refine_margin {
+            if idx > 0 {
+                for (i, bound) in bounds.iter_mut().enumerate() {
+                    let envelope = envelopes[i];
+                    let next_start = if idx + 1 < envelope.nrows() {
+                        envelope[(idx + 1, 0)]
+                    } else {
+                        0
+                    };
+                    *bound = refine_bounds(*bound, last_bounds[i], aligned[i], margin, next_start);
+                }
+            }
+            last_bounds.clone_from(&bounds);
         }
 
-        if upper_t > last_upper_bound {
+        if bounds
+            .iter()
+            .zip(&last_upper_bounds)
+            .any(|(&(_, upper_t), &last_upper_bound)| upper_t > last_upper_bound)
+        {
             // need to extend secondary probs for anything still in the search beam
 
             beam.sort_by_key(|x| x.node); // parents before children
-            let mut placeholder = SecondaryProbs::with_offset(0);
             for &SearchPoint { node, .. } in &beam {
                 if let Some(info) = suffix_tree.info(node) {
-                    // we need to swap the data out before editing to satisfy Rust's borrowing rules
-                    let mut has_data = false;
-                    if let Some(data) = suffix_tree.get_data_ref_mut(node) {
-                        std::mem::swap(data, &mut placeholder);
-                        has_data = true;
-                    }
-                    if has_data {
-                        extend_secondary_probs(
-                            &mut placeholder,
-                            &network_output_2,
-                            suffix_tree
-                                .get_data_ref(info.parent)
-                                .unwrap_or_else(|| &root_secondary_probs),
-                            info.label,
-                            suffix_tree.label(info.parent) == Some(info.label),
-                            lower_t,
-                            upper_t,
-                        );
-                        std::mem::swap(
-                            suffix_tree.get_data_ref_mut(node).unwrap(),
-                            &mut placeholder,
-                        );
+                    // we need to take the data out before editing to satisfy Rust's borrowing
+                    // rules
+                    let mut probs = core::mem::take(suffix_tree.get_data_ref_mut(node).unwrap());
+                    let parent_probs = suffix_tree
+                        .get_data_ref(info.parent)
+                        .unwrap_or(&root_secondary_probs);
+                    let is_repeat = suffix_tree.label(info.parent) == Some(info.label);
+                    for (i, probs) in probs.iter_mut().enumerate() {
+                        let (lower_t, upper_t) = bounds[i];
+                        if upper_t > last_upper_bounds[i] {
+                            extend_secondary_probs(
+                                probs,
+                                &secondaries[i],
+                                &parent_probs[i],
+                                info.label,
+                                is_repeat,
+                                lower_t,
+                                upper_t,
+                            );
+                        }
                     }
+                    *suffix_tree.get_data_ref_mut(node).unwrap() = probs;
                 }
             }
         }
 
-        last_upper_bound = upper_t;
+        for (last_upper_bound, &(_, upper_t)) in last_upper_bounds.iter_mut().zip(&bounds) {
+            *last_upper_bound = upper_t;
+        }
+
+        // the labellings the beam reaches that are not in the tree yet, whose secondary
+        // probabilities are found together, and added to the tree in order below
+        new_labels.clear();
+        for tip in &beam {
+            let repeat = suffix_tree.label(tip.node).filter(|_| collapse_repeats);
+            for (label, &prob) in labelling_probs.iter().skip(1).enumerate() {
+                if prob < beam_cut_threshold {
+                    continue;
+                }
+                if suffix_tree.get_child(tip.node, label).is_none()
+                    && (Some(label) != repeat || tip.prob_1.gap > LogSpace::zero())
+                {
+                    new_labels.push((tip.node, label, Some(label) == repeat));
+                }
+            }
+        }
+        let mut new_probs = new_secondary_probs(&suffix_tree, &new_labels, &bounds).into_iter();
 
         for &tip in &beam {
             let tip_label = suffix_tree.label(tip.node);
@@ -544,16 +1283,7 @@ pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
                     });
                     let new_node_idx = suffix_tree.get_child(tip.node, label).or_else(|| {
                         if tip.prob_1.gap > LogSpace::zero() {
-                            let secondary_probs = build_secondary_probs(
-                                &network_output_2,
-                                suffix_tree
-                                    .get_data_ref(tip.node)
-                                    .unwrap_or_else(|| &root_secondary_probs),
-                                label,
-                                true,
-                                lower_t,
-                                upper_t,
-                            );
+                            let secondary_probs = new_probs.next().unwrap();
                             Some(suffix_tree.add_node(tip.node, label, secondary_probs))
                         } else {
                             None
@@ -570,17 +1300,7 @@ pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
                 } else {
                     let new_node_idx =
                         suffix_tree.get_child(tip.node, label).unwrap_or_else(|| {
-                            let secondary_probs = build_secondary_probs(
-                                &network_output_2,
-                                suffix_tree
-                                    .get_data_ref(tip.node)
-                                    .unwrap_or_else(|| &root_secondary_probs),
-                                label,
-                                false,
-                                lower_t,
-                                upper_t,
-                            );
-                            suffix_tree.add_node(tip.node, label, secondary_probs)
+                            suffix_tree.add_node(tip.node, label, new_probs.next().unwrap())
                         });
 
                     next_beam.push(SearchPoint {
@@ -592,9 +1312,9 @@ pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
             }
         }
 
-        std::mem::swap(&mut beam, &mut next_beam);
+        core::mem::swap(&mut beam, &mut next_beam);
 
-        const DELETE_MARKER: i32 = i32::min_value();
+        const DELETE_MARKER: i32 = i32::MIN;
         beam.sort_by_key(|x| x.node);
         let mut last_key: i32 = DELETE_MARKER;
         let mut last_key_pos = 0;
@@ -613,39 +1333,65 @@ pub fn beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
         for beam_item in &mut beam {
             let node = beam_item.node;
             if let Some(data) = suffix_tree.get_data_ref(node) {
-                beam_item.prob_2_max = data.max_prob;
+                let mut prob_2_max = LogSpace::one();
+                for (probs, &exponent) in data.iter().zip(&exponents) {
+                    prob_2_max *= probs.max_prob.powf(exponent);
+                }
+                beam_item.prob_2_max = prob_2_max;
             }
         }
         let mut has_nans = false;
-        beam.sort_unstable_by(|a, b| {
-            (b.probability())
-                .partial_cmp(&(a.probability()))
-                .unwrap_or_else(|| {
-                    has_nans = true;
-                    std::cmp::Ordering::Equal // don't really care
-                })
+        beam.sort_unstable_by(|a, b| match b.probability().partial_cmp(&a.probability()) {
+            Some(Ordering::Equal) => suffix_tree.compare_labellings(a.node, b.node),
+            Some(ordering) => ordering,
+            None => {
+                has_nans = true;
+                search::nan_order(&a.probability(), &b.probability())
+            }
         });
         if has_nans {
-            return Err(SearchError::IncomparableValues);
+            return Err(SearchError::incomparable_row(
+                idx,
+                primary_real.index_axis(Axis(0), idx).iter(),
+            ));
         }
         beam.truncate(beam_size);
         if beam.is_empty() {
             // we've run out of beam (probably the threshold is too high)
-            return Err(SearchError::RanOutOfBeam);
+            return Err(SearchError::RanOutOfBeam { at_timestep: idx });
         }
-    }
 
-    let mut sequence = String::new();
-
-    if beam[0].node != ROOT_NODE {
-        for label in suffix_tree.iter_from_no_data(beam[0].node) {
-            sequence.push_str(&alphabet[label + 1]);
+        if refine_margin.is_some() {
+            for (i, aligned) in aligned.iter_mut().enumerate() {
+                let (lower_t, upper_t) = bounds[i];
+                *aligned = None;
+                for &SearchPoint { node, .. } in &beam {
+                    let probs = suffix_tree
+                        .get_data_ref(node)
+                        .unwrap_or(&root_secondary_probs);
+                    if let Some(row) = probs[i].best_row(lower_t as isize, upper_t as isize) {
+                        let row = row as usize;
+                        *aligned = Some(
+                            aligned.map_or((row, row), |(lowest, highest): (usize, usize)| {
+                                (lowest.min(row), highest.max(row))
+                            }),
+                        );
+                    }
+                }
+            }
         }
     }
 
-    Ok(sequence.chars().rev().collect())
+    // the tree gives the labels from the last to the first
+    let mut labels = Vec::new();
+    if beam[0].node != ROOT_NODE {
+        labels.extend(suffix_tree.iter_from_no_data(beam[0].node));
+    }
+    labels.reverse();
+    Ok(labels)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
     network_output_1_real: &ArrayBase<D, Ix3>,
     init_state_1: &ArrayBase<D, Ix1>,
@@ -656,16 +1402,25 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
     beam_size: usize,
     beam_cut_threshold_real: f32,
 ) -> Result<String, SearchError> {
+    // the search point states index both outputs, so they must have the same states
+    if network_output_1_real.shape()[1] != network_output_2_real.shape()[1] {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let (init_1, _) = search::check_crf_args(network_output_1_real, init_state_1, alphabet)?;
+    let (init_2, _) = search::check_crf_args(network_output_2_real, init_state_2, alphabet)?;
+    search::check_beam_size_and_threshold(beam_size, beam_cut_threshold_real, alphabet.len())?;
+    if network_output_1_real.shape()[0] == 0 || network_output_2_real.shape()[0] == 0 {
+        return Ok(String::new());
+    }
     let network_output_1 = network_output_1_real.map(|&x| LogSpace::new(x));
     let network_output_2 = network_output_2_real.map(|&x| LogSpace::new(x));
     let beam_cut_threshold = LogSpace::new(beam_cut_threshold_real);
 
-    assert_eq!(network_output_1.shape()[1], network_output_2.shape()[1]);
-    assert_eq!(network_output_1.shape()[2], network_output_2.shape()[2]);
-    assert_eq!(network_output_1.shape()[2], alphabet.len());
-    assert_eq!(network_output_2.shape()[2], alphabet.len());
-    assert_eq!(network_output_1.shape()[0], envelope.shape()[0]);
-    assert_eq!(envelope.shape()[1], 2);
+    check_envelope(
+        envelope,
+        network_output_1.shape()[0],
+        network_output_2.shape()[0],
+    )?;
 
     let n_state = network_output_1.shape()[1];
     let n_base = network_output_1.shape()[2] - 1;
@@ -673,7 +1428,7 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
     let mut suffix_tree = SuffixTree::new(n_base);
     let mut beam = vec![SearchPoint {
         node: ROOT_NODE,
-        state: init_state_1.argmax().unwrap(),
+        state: init_1,
         prob_1: ProbPair {
             label: LogSpace::zero(),
             gap: LogSpace::one(),
@@ -683,22 +1438,18 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
 
     let mut next_beam = Vec::new();
 
-    let root_secondary_probs = crf_root_probs(
-        &network_output_2.view(),
-        init_state_2.argmax().unwrap(),
-        envelope[(0, 1)],
-    );
+    let root_secondary_probs = crf_root_probs(&network_output_2.view(), init_2, envelope[(0, 1)]);
 
-    let network_2_len = network_output_2.shape()[0];
     let mut last_upper_bound = 0;
 
-    for (probs, bounds) in network_output_1.outer_iter().zip(envelope.outer_iter()) {
+    for (idx, (probs, bounds)) in network_output_1
+        .outer_iter()
+        .zip(envelope.outer_iter())
+        .enumerate()
+    {
         next_beam.clear();
 
-        let (lower_t, upper_t) = (bounds[0].max(0), bounds[1].min(network_2_len));
-        if lower_t >= upper_t || lower_t > last_upper_bound {
-            return Err(SearchError::InvalidEnvelope);
-        }
+        let (lower_t, upper_t) = (bounds[0], bounds[1]);
 
         if upper_t > last_upper_bound {
             // need to extend secondary probs for anything still in the search beam
@@ -710,7 +1461,7 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
                     let mut has_data = false;
 
                     if let Some(data) = suffix_tree.get_data_ref_mut(node) {
-                        std::mem::swap(data, &mut placeholder);
+                        core::mem::swap(data, &mut placeholder);
                         has_data = true;
                     }
 
@@ -720,13 +1471,13 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
                             &mut placeholder,
                             suffix_tree
                                 .get_data_ref(info.parent)
-                                .unwrap_or_else(|| &root_secondary_probs),
+                                .unwrap_or(&root_secondary_probs),
                             info.label,
                             state,
                             lower_t,
                             upper_t,
                         );
-                        std::mem::swap(
+                        core::mem::swap(
                             suffix_tree.get_data_ref_mut(node).unwrap(),
                             &mut placeholder,
                         );
@@ -758,7 +1509,7 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
                         &network_output_2,
                         suffix_tree
                             .get_data_ref(tip.node)
-                            .unwrap_or_else(|| &root_secondary_probs),
+                            .unwrap_or(&root_secondary_probs),
                         label,
                         tip.state,
                         lower_t,
@@ -776,9 +1527,9 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
             }
         }
 
-        std::mem::swap(&mut beam, &mut next_beam);
+        core::mem::swap(&mut beam, &mut next_beam);
 
-        const DELETE_MARKER: i32 = i32::min_value();
+        const DELETE_MARKER: i32 = i32::MIN;
         beam.sort_by_key(|x| x.node);
         let mut last_key: i32 = DELETE_MARKER;
         let mut last_key_pos = 0;
@@ -801,40 +1552,747 @@ pub fn crf_beam_search<D: Data<Elem = f32>, E: Data<Elem = usize>>(
             }
         }
         let mut has_nans = false;
-        beam.sort_unstable_by(|a, b| {
-            (b.probability())
-                .partial_cmp(&(a.probability()))
-                .unwrap_or_else(|| {
-                    has_nans = true;
-                    std::cmp::Ordering::Equal // don't really care
-                })
+        beam.sort_unstable_by(|a, b| match b.probability().partial_cmp(&a.probability()) {
+            Some(Ordering::Equal) => suffix_tree.compare_labellings(a.node, b.node),
+            Some(ordering) => ordering,
+            None => {
+                has_nans = true;
+                search::nan_order(&a.probability(), &b.probability())
+            }
         });
         if has_nans {
-            return Err(SearchError::IncomparableValues);
+            return Err(SearchError::incomparable_row(
+                idx,
+                network_output_1_real.index_axis(Axis(0), idx).iter(),
+            ));
         }
         beam.truncate(beam_size);
         if beam.is_empty() {
             // we've run out of beam (probably the threshold is too high)
-            return Err(SearchError::RanOutOfBeam);
+            return Err(SearchError::RanOutOfBeam { at_timestep: idx });
         }
     }
 
-    let mut sequence = String::new();
-
+    // the tree gives the labels from the last to the first
+    let mut tokens = Vec::new();
     if beam[0].node != ROOT_NODE {
         for label in suffix_tree.iter_from_no_data(beam[0].node) {
-            sequence.push_str(&alphabet[label + 1]);
+            tokens.push(alphabet[label + 1].as_str());
         }
     }
 
-    Ok(sequence.chars().rev().collect())
+    Ok(tokens.iter().rev().copied().collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::Lcg;
     use std::cmp::PartialEq;
 
+    #[test]
+    fn test_compute_envelope() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output_1 = array![
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // N
+        ];
+        let network_output_2 = array![
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // N
+        ];
+
+        // anchors are (0, 0), (1, 2), (4, 6) and (6, 9)
+        let envelope = compute_envelope(&network_output_1, &network_output_2, &alphabet).unwrap();
+        assert_eq!(
+            envelope,
+            array![[0, 7], [0, 9], [0, 9], [0, 9], [2, 9], [2, 9]]
+        );
+        assert!(check_envelope(&envelope, 6, 9).is_ok());
+        assert!(matches!(
+            compute_envelope(&network_output_1, &network_output_2, &alphabet[..2]),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert!(matches!(
+            compute_envelope(
+                &network_output_1.slice(ndarray::s![.., ..2]),
+                &network_output_2.view(),
+                &alphabet[..2]
+            ),
+            Err(SearchError::AlphabetMismatch)
+        ));
+
+        let seq = beam_search(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(seq, "AG");
+    }
+
+    #[test]
+    fn test_band_envelope() {
+        assert_eq!(
+            band_envelope(5, 5, 1),
+            array![[0, 2], [0, 3], [1, 4], [2, 5], [3, 5]]
+        );
+        assert_eq!(band_envelope(3, 5, 0), array![[0, 2], [1, 4], [3, 5]]);
+        assert_eq!(
+            band_envelope(4, 2, 0),
+            array![[0, 1], [0, 1], [1, 2], [1, 2]]
+        );
+        assert_eq!(band_envelope(3, 0, 2), Array2::<usize>::zeros((3, 2)));
+        for &(len_1, len_2) in &[(1, 1), (1, 9), (9, 1), (10, 100), (100, 10), (37, 41)] {
+            for &band_width in &[0, 1, 3, 1000] {
+                let envelope = band_envelope(len_1, len_2, band_width);
+                assert!(check_envelope(&envelope, len_1, len_2).is_ok());
+                assert_eq!(envelope[(len_1 - 1, 1)], len_2);
+            }
+        }
+
+        // the reads of `test_compute_envelope` are close enough to line up
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output_1 = array![
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // N
+        ];
+        let network_output_2 = array![
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // N
+        ];
+        let envelope = band_envelope(6, 9, 1);
+        let seq = beam_search(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(seq, "AG");
+    }
+
+    fn envelope_error<E: Data<Elem = usize>>(
+        envelope: &ArrayBase<E, Ix2>,
+        len_1: usize,
+        len_2: usize,
+    ) -> Option<(usize, EnvelopeError)> {
+        match check_envelope(envelope, len_1, len_2) {
+            Err(SearchError::InvalidEnvelope { row, reason }) => Some((row, reason)),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(()) => None,
+        }
+    }
+
+    #[test]
+    fn test_check_envelope() {
+        assert_eq!(envelope_error(&array![[0, 2], [1, 3], [1, 4]], 3, 4), None);
+
+        assert_eq!(
+            envelope_error(&array![[0, 2], [1, 3]], 3, 4),
+            Some((0, EnvelopeError::WrongShape))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 2, 3], [1, 3, 4], [1, 4, 4]], 3, 4),
+            Some((0, EnvelopeError::WrongShape))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 2], [2, 2], [2, 4]], 3, 4),
+            Some((1, EnvelopeError::EmptyRange))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 2], [1, 3], [3, 1]], 3, 4),
+            Some((2, EnvelopeError::EmptyRange))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 2], [1, 3], [1, 5]], 3, 4),
+            Some((2, EnvelopeError::EndOutOfBounds))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 2], [1, 3], [0, 4]], 3, 4),
+            Some((2, EnvelopeError::DecreasingStart))
+        );
+        assert_eq!(
+            envelope_error(&array![[1, 2], [1, 3], [1, 4]], 3, 4),
+            Some((0, EnvelopeError::Disconnected))
+        );
+        assert_eq!(
+            envelope_error(&array![[0, 1], [2, 3], [2, 4]], 3, 4),
+            Some((1, EnvelopeError::Disconnected))
+        );
+    }
+
+    #[test]
+    fn test_beam_search_empty() {
+        let alphabet: Vec<String> = "NAG".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.7, 0.2, 0.1], [0.1, 0.1, 0.8]];
+        let empty = Array2::<f32>::zeros((0, 3));
+
+        let envelope = compute_envelope(&empty, &network_output, &alphabet).unwrap();
+        assert_eq!(envelope.shape(), [0, 2]);
+        let result = beam_search(&empty, &network_output, &alphabet, &envelope, 5, 0.0, true);
+        assert_eq!(result.unwrap(), "");
+
+        let envelope = compute_envelope(&network_output, &empty, &alphabet).unwrap();
+        assert_eq!(envelope, Array2::<usize>::zeros((3, 2)));
+        let result = beam_search(&network_output, &empty, &alphabet, &envelope, 5, 0.0, true);
+        assert_eq!(result.unwrap(), "");
+        let (sequence, timepoints_1, timepoints_2) = beam_search_with_timepoints(
+            &network_output,
+            &empty,
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(sequence, "");
+        assert!(timepoints_1.is_empty() && timepoints_2.is_empty());
+
+        // the arguments are still checked
+        assert!(matches!(
+            beam_search(&empty, &empty, &alphabet, &envelope, 0, 0.0, true),
+            Err(SearchError::InvalidBeamSize)
+        ));
+
+        let blank_only = vec![String::from("N")];
+        let network_output = Array2::<f32>::ones((4, 1));
+        let envelope = compute_envelope(&network_output, &network_output, &blank_only).unwrap();
+        let result = beam_search(
+            &network_output,
+            &network_output,
+            &blank_only,
+            &envelope,
+            5,
+            0.0,
+            true,
+        );
+        assert_eq!(result.unwrap(), "");
+    }
+
+    #[test]
+    fn test_beam_search_ties() {
+        // A, AG, G and GA are equally likely in both outputs, so the tie break picks the
+        // labelling whatever the beam size
+        let network_output = array![[0.0f32, 0.5, 0.5], [0.0f32, 0.5, 0.5]];
+        let envelope = array![[0usize, 2], [0, 2]];
+        for (labels, expected) in [("NAG", "A"), ("NGA", "G")] {
+            let alphabet: Vec<String> = labels.chars().map(|x| x.to_string()).collect();
+            for beam_size in 1..6 {
+                let result = beam_search(
+                    &network_output,
+                    &network_output,
+                    &alphabet,
+                    &envelope,
+                    beam_size,
+                    0.0,
+                    true,
+                );
+                assert_eq!(result.unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_beam_search_multi_character_tokens() {
+        let alphabet: Vec<String> = ["", "AC", "T", "GGA"].iter().map(|&x| x.into()).collect();
+        let network_output = array![
+            [0.1f32, 0.7, 0.1, 0.1], // AC
+            [0.7f32, 0.1, 0.1, 0.1], // blank
+            [0.1f32, 0.7, 0.1, 0.1], // AC
+            [0.1f32, 0.1, 0.1, 0.7], // GGA
+            [0.1f32, 0.1, 0.7, 0.1], // T
+        ];
+        let envelope = compute_envelope(&network_output, &network_output, &alphabet).unwrap();
+        assert_eq!(
+            beam_search(
+                &network_output,
+                &network_output,
+                &alphabet,
+                &envelope,
+                5,
+                0.0,
+                true
+            )
+            .unwrap(),
+            "ACACGGAT"
+        );
+    }
+
+    #[test]
+    fn test_beam_search_with_timepoints() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output_1 = array![
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // blank
+        ];
+        // the same labelling, more slowly
+        let network_output_2 = array![
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // blank
+        ];
+        let envelope = compute_envelope(&network_output_1, &network_output_2, &alphabet).unwrap();
+        let (seq, timepoints_1, timepoints_2) = beam_search_with_timepoints(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            seq,
+            beam_search(
+                &network_output_1,
+                &network_output_2,
+                &alphabet,
+                &envelope,
+                5,
+                0.0,
+                true
+            )
+            .unwrap()
+        );
+        assert_eq!(seq, "AG");
+        assert_eq!(timepoints_1, vec![0, 3]);
+        assert_eq!(timepoints_2, vec![2, 5]);
+    }
+
+    #[test]
+    fn test_beam_search_with_support() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output_1 = array![
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // blank
+        ];
+        // G is only weakly supported here
+        let network_output_2 = array![
+            [0.1f32, 0.9, 0.0], // A
+            [0.1f32, 0.7, 0.2], // A (or G)
+            [0.6f32, 0.0, 0.4], // blank (or G)
+        ];
+        let envelope = array![[0, 2], [0, 3], [1, 3], [1, 3]];
+        let (seq, support) = beam_search_with_support(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(seq, "AG");
+        assert_eq!(support, vec![(0.8, 0.9), (0.8, 0.4)]);
+    }
+
+    #[test]
+    fn test_beam_search_consensus() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        // on its own, this is more likely to be AAG than AG
+        let reference = array![
+            [0.1f32, 0.8, 0.1],   // A
+            [0.7f32, 0.25, 0.05], // blank (or A)
+            [0.1f32, 0.8, 0.1],   // A
+            [0.1f32, 0.1, 0.8],   // G
+            [0.8f32, 0.1, 0.1],   // blank
+        ];
+        let read_1 = array![
+            [0.1f32, 0.8, 0.1],   // A
+            [0.05f32, 0.9, 0.05], // A
+            [0.1f32, 0.1, 0.8],   // G
+            [0.8f32, 0.1, 0.1],   // blank
+        ];
+        let read_2 = array![
+            [0.8f32, 0.1, 0.1],   // blank
+            [0.1f32, 0.8, 0.1],   // A
+            [0.05f32, 0.9, 0.05], // A
+            [0.05f32, 0.9, 0.05], // A
+            [0.1f32, 0.1, 0.8],   // G
+            [0.1f32, 0.1, 0.8],   // G
+        ];
+        let search = |outputs: &[Array2<f32>]| {
+            let envelopes: Vec<_> = outputs[1..]
+                .iter()
+                .map(|output| compute_envelope(&outputs[0], output, &alphabet).unwrap())
+                .collect();
+            beam_search_consensus(outputs, &alphabet, &envelopes, 5, 0.0, true)
+        };
+        assert_eq!(search(std::slice::from_ref(&reference)).unwrap(), "AAG");
+        assert_eq!(
+            search(&[reference.clone(), read_1.clone(), read_2.clone()]).unwrap(),
+            "AG"
+        );
+
+        // with two outputs, this is the duplex search
+        let envelope = compute_envelope(&reference, &read_1, &alphabet).unwrap();
+        assert_eq!(
+            search(&[reference.clone(), read_1.clone()]).unwrap(),
+            beam_search(&reference, &read_1, &alphabet, &envelope, 5, 0.0, true).unwrap()
+        );
+
+        let no_outputs: &[Array2<f32>] = &[];
+        let no_envelopes: &[Array2<usize>] = &[];
+        assert!(matches!(
+            beam_search_consensus(
+                &[reference.clone(), read_1],
+                &alphabet,
+                no_envelopes,
+                5,
+                0.0,
+                true
+            ),
+            Err(SearchError::EnvelopeCountMismatch)
+        ));
+        assert!(matches!(
+            beam_search_consensus(no_outputs, &alphabet, no_envelopes, 5, 0.0, true),
+            Err(SearchError::EnvelopeCountMismatch)
+        ));
+        assert!(matches!(
+            beam_search_consensus(&[reference], &alphabet[..2], no_envelopes, 5, 0.0, true),
+            Err(SearchError::AlphabetMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_weighted() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        // on its own, this is more likely to be AAG than AG
+        let network_output_1 = array![
+            [0.1f32, 0.8, 0.1],   // A
+            [0.7f32, 0.25, 0.05], // blank (or A)
+            [0.1f32, 0.8, 0.1],   // A
+            [0.1f32, 0.1, 0.8],   // G
+            [0.8f32, 0.1, 0.1],   // blank
+        ];
+        let network_output_2 = array![
+            [0.1f32, 0.8, 0.1],   // A
+            [0.05f32, 0.9, 0.05], // A
+            [0.1f32, 0.1, 0.8],   // G
+            [0.8f32, 0.1, 0.1],   // blank
+        ];
+        let envelope = compute_envelope(&network_output_1, &network_output_2, &alphabet).unwrap();
+        let search = |weight_1, weight_2| {
+            beam_search_weighted(
+                &network_output_1,
+                &network_output_2,
+                &alphabet,
+                &envelope,
+                5,
+                0.0,
+                true,
+                weight_1,
+                weight_2,
+            )
+        };
+        assert_eq!(
+            search(1.0, 1.0).unwrap(),
+            beam_search(
+                &network_output_1,
+                &network_output_2,
+                &alphabet,
+                &envelope,
+                5,
+                0.0,
+                true
+            )
+            .unwrap()
+        );
+        assert_eq!(search(1.0, 1.0).unwrap(), "AG");
+        assert_eq!(search(100.0, 1.0).unwrap(), "AAG");
+        // only the ratio matters
+        assert_eq!(search(200.0, 2.0).unwrap(), "AAG");
+
+        assert!(matches!(search(0.0, 1.0), Err(SearchError::InvalidWeight)));
+        assert!(matches!(
+            search(1.0, f32::INFINITY),
+            Err(SearchError::InvalidWeight)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_refined() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let outputs_1 = random_outputs(10, 40, 3);
+        let outputs_2 = random_outputs(10, 40, 3)
+            .into_iter()
+            .zip(random_outputs(10, 40, 4))
+            .map(|(x, noise)| (x * 3.0 + noise) / 4.0)
+            .collect::<Vec<_>>();
+        for (output_1, output_2) in outputs_1.iter().zip(&outputs_2) {
+            let tight = compute_envelope(output_1, output_2, &alphabet).unwrap();
+            let expected =
+                beam_search(output_1, output_2, &alphabet, &tight, 5, 0.0, true).unwrap();
+            // a margin as wide as the envelope cuts nothing from it
+            let refined = beam_search_refined(
+                output_1, output_2, &alphabet, &tight, 5, 0.0, true, 1.0, 1.0, 40,
+            );
+            assert_eq!(refined.unwrap(), expected);
+
+            // a band covering all of output_2 is narrowed down to the alignments, which here
+            // does not change the result
+            let mut wide = Array2::zeros((output_1.nrows(), 2));
+            wide.column_mut(1).fill(output_2.nrows());
+            let refined = beam_search_refined(
+                output_1, output_2, &alphabet, &wide, 5, 0.0, true, 1.0, 1.0, 4,
+            );
+            assert_eq!(
+                refined.unwrap(),
+                beam_search(output_1, output_2, &alphabet, &wide, 5, 0.0, true).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_refine_bounds() {
+        // narrowed to the alignments plus the margin
+        assert_eq!(refine_bounds((0, 20), (0, 10), Some((5, 7)), 2, 0), (3, 10));
+        // never moving backwards, and staying connected to the next row
+        assert_eq!(refine_bounds((0, 20), (4, 6), Some((5, 7)), 2, 0), (4, 10));
+        assert_eq!(
+            refine_bounds((0, 20), (0, 10), Some((5, 7)), 2, 14),
+            (3, 15)
+        );
+        // never leaving the envelope
+        assert_eq!(refine_bounds((6, 8), (0, 10), Some((5, 7)), 2, 0), (6, 8));
+        assert_eq!(refine_bounds((0, 20), (3, 10), None, 2, 0), (3, 20));
+    }
+
+    #[test]
+    fn test_beam_search_mapped() {
+        let alphabet_1: Vec<String> = "NACGT".chars().map(String::from).collect();
+        // a different order, and a modified C
+        let alphabet_2: Vec<String> = "NTGCAm".chars().map(String::from).collect();
+        let mapping = [0, 4, 3, 2, 1, 2];
+        let output_1 = random_outputs(1, 30, 1).pop().unwrap();
+        let noise = random_outputs(1, 30, 2).pop().unwrap();
+        let same = (&output_1 * 3.0 + noise) / 4.0;
+        // split C between C and m
+        let output_2 = Array2::from_shape_fn((30, 6), |(t, j)| match j {
+            0 => same[(t, 0)],
+            3 => same[(t, 2)] * 0.75,
+            5 => same[(t, 2)] * 0.25,
+            _ => same[(t, 5 - j)],
+        });
+
+        let mapped = map_columns(&output_2, &alphabet_2, &alphabet_1, &mapping).unwrap();
+        for (x, y) in mapped.iter().zip(&same) {
+            assert!((x - y).abs() < 1e-6);
+        }
+        let envelope = compute_envelope(&output_1, &mapped, &alphabet_1).unwrap();
+        assert_eq!(
+            beam_search_mapped(
+                &output_1,
+                &alphabet_1,
+                &output_2,
+                &alphabet_2,
+                &mapping,
+                &envelope,
+                5,
+                0.0,
+                true
+            )
+            .unwrap(),
+            beam_search(&output_1, &mapped, &alphabet_1, &envelope, 5, 0.0, true).unwrap()
+        );
+
+        let search = |alphabet_2: &[String], mapping: &[usize]| {
+            beam_search_mapped(
+                &output_1,
+                &alphabet_1,
+                &output_2,
+                alphabet_2,
+                mapping,
+                &envelope,
+                5,
+                0.0,
+                true,
+            )
+        };
+        // missing an entry, past the end of the shared alphabet, blank to a label, label to blank
+        for mapping in &[
+            &[0, 4, 3, 2, 1][..],
+            &[0, 4, 3, 2, 1, 5],
+            &[1, 4, 3, 2, 1, 2],
+            &[0, 4, 3, 0, 1, 2],
+        ] {
+            assert!(matches!(
+                search(&alphabet_2, mapping),
+                Err(SearchError::InvalidAlphabetMapping)
+            ));
+        }
+        assert!(matches!(
+            search(&alphabet_2[..5], &mapping[..5]),
+            Err(SearchError::AlphabetMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_memory_order() {
+        use ndarray::ShapeBuilder;
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let mut outputs = random_outputs(2, 30, 3);
+        let output_2 = outputs.pop().unwrap();
+        let output_1 = outputs.pop().unwrap();
+        let envelope = compute_envelope(&output_1, &output_2, &alphabet).unwrap();
+        let expected = beam_search(
+            &output_1.view(),
+            &output_2.view(),
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+            true,
+        )
+        .unwrap();
+
+        let fortran = |output: &Array2<f32>| {
+            let mut f_order = Array2::zeros(output.dim().f());
+            f_order.assign(output);
+            f_order
+        };
+        let (f_order_1, f_order_2) = (fortran(&output_1), fortran(&output_2));
+        assert_eq!(
+            compute_envelope(&f_order_1, &f_order_2, &alphabet).unwrap(),
+            envelope
+        );
+        // the rows of the second output in reverse order, viewed the right way round
+        let mut reversed_2 = Array2::zeros(output_2.dim());
+        reversed_2.assign(&output_2.slice(s![..;-1, ..]));
+        for output_2 in &[f_order_2.view(), reversed_2.slice(s![..;-1, ..])] {
+            assert_eq!(
+                beam_search(
+                    &f_order_1.view(),
+                    output_2,
+                    &alphabet,
+                    &envelope,
+                    5,
+                    0.0,
+                    true
+                )
+                .unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_beam_arg_boundaries() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let output = array![[0.1f32, 0.3, 0.6], [0.7f32, 0.2, 0.1], [0.2f32, 0.6, 0.2]];
+        let envelope = compute_envelope(&output, &output, &alphabet).unwrap();
+        for &(beam_size, threshold, expected) in &[
+            (0, 0.0, Err("InvalidBeamSize")),
+            (1, 0.0, Ok(())),
+            (5, 0.33, Ok(())),
+            (5, -0.1, Err("InvalidBeamCutThreshold")),
+            (5, 1.0 / 3.0, Err("InvalidBeamCutThreshold")),
+            (5, f32::NAN, Err("InvalidBeamCutThreshold")),
+        ] {
+            let result = beam_search(
+                &output, &output, &alphabet, &envelope, beam_size, threshold, true,
+            );
+            assert_eq!(
+                result.map(|_| ()).map_err(|err| format!("{:?}", err)),
+                expected.map_err(String::from),
+                "beam_size {}, threshold {}",
+                beam_size,
+                threshold
+            );
+        }
+    }
+
+    #[test]
+    fn test_crf_beam_search_invalid_input() {
+        use ndarray::Array3;
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let output = Array3::from_elem((2, 4, 3), 1.0f32 / 3.0);
+        let init = array![1.0f32, 0.0, 0.0, 0.0];
+        let envelope = array![[0usize, 2], [0, 2]];
+        assert!(
+            crf_beam_search(&output, &init, &output, &init, &alphabet, &envelope, 5, 0.0).is_ok()
+        );
+
+        let other_states = Array3::from_elem((2, 2, 3), 1.0f32 / 3.0);
+        let result = crf_beam_search(
+            &output,
+            &init,
+            &other_states,
+            &array![1.0f32, 0.0],
+            &alphabet,
+            &envelope,
+            5,
+            0.0,
+        );
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+        let result = crf_beam_search(
+            &output,
+            &init,
+            &output,
+            &init,
+            &alphabet[..2],
+            &envelope,
+            5,
+            0.0,
+        );
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+
+        let out_of_range = array![0.0f32, 0.0, 0.0, 0.0, 1.0];
+        for (init_1, init_2) in [(&out_of_range, &init), (&init, &out_of_range)].iter() {
+            let result = crf_beam_search(
+                &output, init_1, &output, init_2, &alphabet, &envelope, 5, 0.0,
+            );
+            assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        }
+        let nan_init = array![f32::NAN, 0.0, 0.0, 0.0];
+        let result = crf_beam_search(
+            &output, &nan_init, &output, &init, &alphabet, &envelope, 5, 0.0,
+        );
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+    }
+
     #[test]
     fn test_secondary_probs_get() {
         let mut p = SecondaryProbs {
@@ -914,7 +2372,7 @@ mod tests {
         assert!(p.max_prob.eq(&LogSpace::zero()));
 
         p.max_prob = LogSpace::one();
-        p.update_max(std::isize::MIN, std::isize::MAX);
+        p.update_max(isize::MIN, isize::MAX);
         assert!(p.max_prob.eq(&LogSpace::zero()));
     }
 
@@ -988,4 +2446,110 @@ mod tests {
         p.update_max(8, 10);
         assert!(p.max_prob.eq(&LogSpace::zero()));
     }
+
+    /// A noisy network output over the alphabet NACGT for each of `n_reads` random reads.
+    fn random_outputs(n_reads: usize, n_rows: usize, seed: u32) -> Vec<Array2<f32>> {
+        let mut rng = Lcg(seed);
+        let mut next = move || rng.next_f32();
+        (0..n_reads)
+            .map(|_| {
+                let mut output = Array2::zeros((n_rows, 5));
+                for mut row in output.outer_iter_mut() {
+                    // mostly one peaked label, with some noise on the others
+                    let peak = (next() * 5.0) as usize % 5;
+                    for (col, x) in row.iter_mut().enumerate() {
+                        *x = next() * 0.3 + if col == peak { 1.0 } else { 0.0 };
+                    }
+                    let total = row.sum();
+                    row /= total;
+                }
+                output
+            })
+            .collect()
+    }
+
+    /// A duplex pair of reads of the same `n_bases` long random sequence (over ACGT), each base
+    /// lasting one to three rows with blanks between, as a peaky network sees it. Returns the
+    /// sequence and the network outputs over NACGT.
+    fn duplex_pair(n_bases: usize, seed: u32) -> (String, Array2<f32>, Array2<f32>) {
+        let mut rng = Lcg(seed);
+        let mut next = move || rng.next_f32();
+        let bases: Vec<usize> = (0..n_bases)
+            .map(|_| 1 + (next() * 4.0) as usize % 4)
+            .collect();
+        let mut read = || {
+            let mut rows = Vec::new();
+            for &base in &bases {
+                for column in std::iter::repeat(base)
+                    .take(1 + (next() * 2.0) as usize)
+                    .chain(std::iter::repeat(0).take(1 + (next() * 3.0) as usize))
+                {
+                    let mut row = [0.0f32; 5];
+                    for (col, x) in row.iter_mut().enumerate() {
+                        *x = next() * 0.2 + if col == column { 1.0 } else { 0.0 };
+                    }
+                    let total: f32 = row.iter().sum();
+                    for x in row.iter_mut() {
+                        *x /= total;
+                    }
+                    rows.push(row);
+                }
+            }
+            Array2::from(rows)
+        };
+        let (read_1, read_2) = (read(), read());
+        let sequence = bases.iter().map(|&base| &"NACGT"[base..=base]).collect();
+        (sequence, read_1, read_2)
+    }
+
+    /// A duplex decode of a realistic pair of reads, with an envelope 40 rows wider than
+    /// `compute_envelope` gives (as from a coarser alignment).
+    #[test]
+    fn test_duplex_realistic_pair() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let (sequence, read_1, read_2) = duplex_pair(1000, 7);
+        let mut envelope = compute_envelope(&read_1, &read_2, &alphabet).unwrap();
+        for mut bounds in envelope.outer_iter_mut() {
+            bounds[0] = bounds[0].saturating_sub(20);
+            bounds[1] = (bounds[1] + 20).min(read_2.nrows());
+        }
+        let result = beam_search(&read_1, &read_2, &alphabet, &envelope, 5, 0.0, true).unwrap();
+        assert_eq!(result, sequence);
+    }
+
+    #[test]
+    #[cfg(feature = "fastexp")]
+    fn test_fastexp_matches_exact() {
+        use crate::logspace::{with_exact_exp, ADD_TOLERANCE};
+
+        let exact = |a: f32, b: f32| (a.exp() + b.exp()).ln();
+        for i in -400..=0 {
+            for &b in &[0.0, -1.0, -20.0] {
+                let a = i as f32 * 0.25 + b;
+                let sum: LogSpace = LogSpace::from_ln(a) + LogSpace::from_ln(b);
+                let sum = sum.ln();
+                assert!(
+                    (sum - exact(a, b)).abs() <= ADD_TOLERANCE,
+                    "{} + {}: {} != {}",
+                    a,
+                    b,
+                    sum,
+                    exact(a, b)
+                );
+            }
+        }
+
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let outputs_1 = random_outputs(20, 40, 1);
+        let outputs_2 = random_outputs(20, 40, 1)
+            .into_iter()
+            .zip(random_outputs(20, 40, 2))
+            .map(|(x, noise)| (x * 3.0 + noise) / 4.0)
+            .collect::<Vec<_>>();
+        for (output_1, output_2) in outputs_1.iter().zip(&outputs_2) {
+            let envelope = compute_envelope(output_1, output_2, &alphabet).unwrap();
+            let search = || beam_search(output_1, output_2, &alphabet, &envelope, 5, 0.0, true);
+            assert_eq!(search().unwrap(), with_exact_exp(search).unwrap());
+        }
+    }
 }
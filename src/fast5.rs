@@ -0,0 +1,83 @@
+//! Decoding network outputs stored in HDF5 files, built with the `hdf5` feature.
+//!
+//! This does not parse FAST5 files: it takes an open dataset holding a 3D block of network
+//! outputs, with the reads on the outer axis, then time, then labels (the layout of a batch for
+//! `beam_search_batch`). Finding the dataset, and the id of each read in it, is up to the caller.
+
+use crate::search::beam_search_batch;
+use crate::SearchError;
+use ndarray::{Array3, Axis, Ix3};
+use std::fmt;
+
+/// The ways decoding an HDF5 dataset can fail.
+#[derive(Debug)]
+pub enum Fast5Error {
+    /// The dataset could not be read as a 3D block of floats.
+    Hdf5(::hdf5::Error),
+    /// There is not one read id for each read (the outer axis) of the dataset.
+    ReadIdMismatch,
+    /// One of the reads failed to decode.
+    Search(SearchError),
+}
+
+impl fmt::Display for Fast5Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fast5Error::Hdf5(err) => write!(f, "Failed to read the dataset: {}", err),
+            Fast5Error::ReadIdMismatch => write!(
+                f,
+                "The number of read ids does not match the outer axis of the dataset"
+            ),
+            Fast5Error::Search(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Fast5Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Fast5Error::Hdf5(err) => Some(err),
+            Fast5Error::ReadIdMismatch => None,
+            Fast5Error::Search(err) => Some(err),
+        }
+    }
+}
+
+impl From<SearchError> for Fast5Error {
+    fn from(err: SearchError) -> Self {
+        Fast5Error::Search(err)
+    }
+}
+
+/// Perform a CTC beam search decode on each read of an HDF5 dataset.
+///
+/// `dataset` is read into memory as a whole, and decoded with `beam_search_batch` (so in parallel
+/// with the `rayon` feature). `read_ids` gives the id of each read, in the order of the outer
+/// axis, and the results are the id and decoded sequence of each read, in the same order.
+pub fn beam_search_dataset(
+    dataset: &::hdf5::Dataset,
+    read_ids: &[String],
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<Vec<(String, String)>, Fast5Error> {
+    let block: Array3<f32> = dataset.read::<f32, Ix3>().map_err(Fast5Error::Hdf5)?;
+    if block.len_of(Axis(0)) != read_ids.len() {
+        return Err(Fast5Error::ReadIdMismatch);
+    }
+    let results = beam_search_batch(
+        &block,
+        alphabet,
+        beam_size,
+        beam_cut_threshold,
+        collapse_repeats,
+        blank_id,
+    )?;
+    Ok(read_ids
+        .iter()
+        .cloned()
+        .zip(results.into_iter().map(|(sequence, _, _)| sequence))
+        .collect())
+}
@@ -7,13 +7,21 @@
 
 //! This module provides a trait adding a fast approximation of the exponential function to f32.
 //! This can be very useful if the exact value is not too important.
+//!
+//! For inputs between -87 and 0 (the range used when adding probabilities in log space), the
+//! relative error is at most 8.9e-6. Below that the results are subnormal, so they lose precision
+//! (to about 33% relative error by -103), and below -500 the result is 0. Positive inputs have a
+//! relative error of up to 3.5e-3. `search::compare_fastexp` measures the effect on the decoded
+//! labellings.
 
-const COEFF_0: f32 = 1.0;
-const COEFF_1: f32 = 4.831794110;
-const COEFF_2: f32 = 0.143440676;
-const COEFF_3: f32 = 0.019890581;
-const COEFF_4: f32 = 0.006935931;
-const ONEBYLOG2: f32 = 1.442695041;
+#![allow(clippy::excessive_precision, clippy::approx_constant)]
+
+const COEFF_0: f64 = 1.0;
+const COEFF_1: f64 = 4.831794110;
+const COEFF_2: f64 = 0.143440676;
+const COEFF_3: f64 = 0.019890581;
+const COEFF_4: f64 = 0.006935931;
+const ONEBYLOG2: f64 = 1.442695041;
 const OFFSET_F64: i64 = 1023;
 const FRACTION_F64: u32 = 52;
 const MIN_VAL: f32 = -500.0;
@@ -28,16 +36,17 @@ impl FastExp<f32> for f32 {
     /// https://eldorado.tu-dortmund.de/bitstream/2003/36203/1/Dissertation_Kopczynski.pdf
     fn fastexp(&self) -> f32 {
         if *self > MIN_VAL {
-            let mut x = ONEBYLOG2 * self;
+            // the bit manipulation below builds an f64, so the calculation is done at that width
+            let mut x = ONEBYLOG2 * f64::from(*self);
 
             #[repr(C)]
             union F1 {
                 i: i64,
-                f: f32,
+                f: f64,
             }
             let mut f1 = F1 { i: x as i64 };
 
-            x -= unsafe { f1.i } as f32;
+            x -= unsafe { f1.i } as f64;
             let mut f2 = x;
             let mut x_tmp = x;
 
@@ -55,7 +64,7 @@ impl FastExp<f32> for f32 {
             f2 *= x_tmp;
             f2 += COEFF_0;
 
-            unsafe { f1.f * f2 }
+            (unsafe { f1.f } * f2) as f32
         } else {
             0.0
         }
@@ -1,152 +1,452 @@
+//! Without the `std` feature (which is on by default), the crate is `no_std`, and only needs
+//! `alloc`. The searches, the labelling tree and the language models are all still there, but
+//! the parts that need the standard library are left out: the `std::error::Error`
+//! implementations and `DecodeError`, and loading language models from files. The `python`,
+//! `capi`, `cli` and `rayon` features turn `std` on, and `serde` and `hdf5` need it too.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 #[macro_use(s)]
 #[cfg_attr(test, macro_use(array))]
 extern crate ndarray;
 
-use std::fmt;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::io;
 
+pub mod alphabet;
 pub mod duplex;
+#[cfg(all(feature = "hdf5", feature = "std"))]
+pub mod fast5;
+pub mod lm;
 pub mod search;
 pub mod tree;
 pub mod vec2d;
 
+#[cfg(feature = "capi")]
+mod capi;
 #[cfg(feature = "fastexp")]
 mod fastexp;
+mod logspace;
+#[cfg(feature = "python")]
+mod python;
+mod simd;
+#[cfg(test)]
+mod testing;
 
 pub mod prelude {
-    pub use crate::duplex::*;
+    pub use crate::alphabet::*;
+    pub use crate::duplex::{self, *};
+    pub use crate::lm::*;
     pub use crate::search::*;
+    // `duplex` has searches of these names too, which stay reachable as `duplex::beam_search`
+    // and `duplex::crf_beam_search`
+    pub use crate::search::{beam_search, crf_beam_search};
     pub use crate::tree::*;
     pub use crate::vec2d::*;
 }
 
+/// The version of the crate, from its `Cargo.toml`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The optional features the crate was built with, to say which build is in use in bug reports.
+///
+/// `simd` is only listed if the vectorised code was compiled in, which also needs an x86_64
+/// target with SSE2 (see the `simd` module).
+pub fn features() -> Vec<&'static str> {
+    let features = [
+        ("std", cfg!(feature = "std")),
+        ("fastexp", cfg!(feature = "fastexp")),
+        (
+            "simd",
+            cfg!(all(
+                feature = "simd",
+                target_arch = "x86_64",
+                target_feature = "sse2"
+            )),
+        ),
+        ("rayon", cfg!(feature = "rayon")),
+        ("serde", cfg!(feature = "serde")),
+        ("hdf5", cfg!(feature = "hdf5")),
+        ("python", cfg!(feature = "python")),
+        ("capi", cfg!(feature = "capi")),
+        ("cli", cfg!(feature = "cli")),
+    ];
+    features
+        .iter()
+        .filter(|&&(_, enabled)| enabled)
+        .map(|&(name, _)| name)
+        .collect()
+}
+
+/// The ways a duplex envelope can be invalid.
+///
+/// Row `i` of an envelope is the half-open range `[start, end)` of rows of `network_output_2` that
+/// row `i` of `network_output_1` may be aligned with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// The envelope is not Nx2, where N is the outer axis length of `network_output_1`.
+    WrongShape,
+    /// The row's range is empty (`start >= end`).
+    EmptyRange,
+    /// The row's range goes past the end of `network_output_2`.
+    EndOutOfBounds,
+    /// The row starts before the previous row does.
+    DecreasingStart,
+    /// The row starts after the previous row ends (or after 0, for the first row), so no path can
+    /// reach it.
+    Disconnected,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::WrongShape => write!(
+                f,
+                "envelope must be Nx2, where N is the length of network_output_1"
+            ),
+            EnvelopeError::EmptyRange => write!(f, "start must be less than end"),
+            EnvelopeError::EndOutOfBounds => {
+                write!(f, "end must be at most the length of network_output_2")
+            }
+            EnvelopeError::DecreasingStart => {
+                write!(f, "start must not be less than the previous start")
+            }
+            EnvelopeError::Disconnected => {
+                write!(f, "start must not be more than the previous end")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for EnvelopeError {}
+
+/// A line of an ARPA language model file that could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpaError {
+    /// The line number, counting from 1.
+    pub line: usize,
+}
+
+impl fmt::Display for ArpaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to parse line {} of ARPA file", self.line)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ArpaError {}
+
+/// The ways the searches themselves can fail.
+///
+/// These are the errors of the algorithms, which do no IO. See `DecodeError` for an error that
+/// also covers loading their inputs.
 #[derive(Clone, Copy, Debug)]
 pub enum SearchError {
-    RanOutOfBeam,
-    IncomparableValues,
-    InvalidEnvelope,
+    /// Every search point was pruned at `at_timestep`.
+    RanOutOfBeam {
+        at_timestep: usize,
+    },
+    /// The search could not order the probabilities at `row`. `col` is the first value of that
+    /// row of the network output that is not finite, or 0 if they all are. For CRF outputs, where
+    /// each row is a matrix of states and labels, `col` indexes the flattened matrix.
+    IncomparableValues {
+        row: usize,
+        col: usize,
+    },
+    InvalidEnvelope {
+        row: usize,
+        reason: EnvelopeError,
+    },
+    AlphabetMismatch,
+    /// A consensus search was not given at least one network output, and one envelope for each
+    /// network output after the first.
+    EnvelopeCountMismatch,
+    InvalidBeamSize,
+    InvalidMaxSymbolsPerFrame,
+    InvalidBlankBias,
+    InvalidBeamCutRelative,
+    InvalidBeamCutThreshold,
+    InvalidDelta,
+    InvalidInterval,
+    InvalidBlankId,
+    /// The `scale` of a quantized network output is not finite and greater than 0.0.
+    InvalidScale,
+    InvalidSeparator,
+    /// `allowed_transitions` is not `len(alphabet)` by `len(alphabet)`.
+    InvalidTransitions,
+    InvalidLengthRange,
+    InvalidWeight,
+    InvalidRepeatLabelFloor,
+    InvalidTimeAxis,
+    /// A target labelling cannot be split into the (non-blank) labels of the alphabet.
+    InvalidTarget,
+    /// No path through the network output gives a target labelling.
+    UnalignableTarget,
+    /// An alphabet mapping does not have one entry for each label, or maps a label past the end
+    /// of the shared alphabet, or does not map the blank (and only the blank) to the blank.
+    InvalidAlphabetMapping,
+    /// Row `row` of the network output sums to `sum`, which is too far from 1.0.
+    RowSumMismatch {
+        row: usize,
+        sum: f32,
+    },
+    /// The `init_state` of a CRF search is empty or has NaNs, or its most likely state is not one
+    /// of the states of the network output.
+    InvalidInitState,
+    /// A decoder made with `Decoder::with_capacity` was given a network output with more rows,
+    /// or a different alphabet length, than it has storage for.
+    CapacityExceeded,
+    /// An anchor's window of timesteps is empty, or its column is past the end of the alphabet or
+    /// a blank.
+    InvalidAnchor,
+    /// `label_prior` does not have an entry for each label, or the entry of a label that is not a
+    /// blank is not finite and greater than 0.0, or `label_prior_weight` is not finite.
+    InvalidLabelPrior,
 }
 
 impl fmt::Display for SearchError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SearchError::RanOutOfBeam => {
-                write!(f, "Ran out of search space (beam_cut_threshold too high)")
+            SearchError::RanOutOfBeam { at_timestep } => write!(
+                f,
+                "Ran out of search space at timestep {} (beam_cut_threshold too high)",
+                at_timestep
+            ),
+            SearchError::IncomparableValues { row, col } => write!(
+                f,
+                "Failed to compare values at row {}, column {} (NaNs in input?)",
+                row, col
+            ),
+            // the reason is the source of the error, so it is not repeated here
+            SearchError::InvalidEnvelope { row, .. } => {
+                write!(f, "Invalid envelope at row {}", row)
+            }
+            SearchError::AlphabetMismatch => {
+                write!(
+                    f,
+                    "Alphabet size does not match probability matrix dimensions"
+                )
+            }
+            SearchError::EnvelopeCountMismatch => write!(
+                f,
+                "There must be at least one network output, and one envelope for each network \
+                 output after the first"
+            ),
+            SearchError::InvalidBeamSize => write!(f, "beam_size cannot be 0"),
+            SearchError::InvalidMaxSymbolsPerFrame => {
+                write!(f, "max_symbols_per_frame cannot be 0")
+            }
+            SearchError::InvalidBlankBias => write!(f, "blank_bias must be finite"),
+            SearchError::InvalidBeamCutRelative => {
+                write!(f, "beam_cut_relative must be at least 0.0")
             }
-            SearchError::IncomparableValues => {
-                write!(f, "Failed to compare values (NaNs in input?)")
+            SearchError::InvalidBeamCutThreshold => write!(
+                f,
+                "beam_cut_threshold must be at least 0.0 and less than 1/len(alphabet)"
+            ),
+            SearchError::InvalidDelta => write!(f, "delta must be at least 0.0"),
+            SearchError::InvalidInterval => write!(f, "every must be at least 1"),
+            SearchError::InvalidBlankId => write!(f, "blank_id must be less than len(alphabet)"),
+            SearchError::InvalidScale => write!(f, "scale must be finite and greater than 0.0"),
+            SearchError::InvalidSeparator => write!(
+                f,
+                "separator must be less than len(alphabet) and not the same as blank_id"
+            ),
+            SearchError::InvalidTransitions => write!(
+                f,
+                "allowed_transitions must be a len(alphabet) by len(alphabet) matrix"
+            ),
+            SearchError::InvalidLengthRange => {
+                write!(f, "max_length must be at least min_length")
             }
-            // TODO: document envelope constraints
-            SearchError::InvalidEnvelope => write!(f, "Invalid envelope values"),
+            SearchError::InvalidWeight => {
+                write!(f, "weights must be finite and greater than 0.0")
+            }
+            SearchError::InvalidRepeatLabelFloor => {
+                write!(f, "repeat_label_floor must be at least 0.0 and at most 1.0")
+            }
+            SearchError::InvalidTimeAxis => write!(f, "time_axis must be 0 or 1"),
+            SearchError::UnalignableTarget => write!(
+                f,
+                "target cannot be aligned with the network output (it may be too long)"
+            ),
+            SearchError::InvalidTarget => {
+                write!(
+                    f,
+                    "target must be made up of the non-blank labels of alphabet"
+                )
+            }
+            SearchError::InvalidAlphabetMapping => write!(
+                f,
+                "mapping must have an entry for each label, each less than the length of the \
+                 shared alphabet, and map the blank and only the blank to the blank"
+            ),
+            SearchError::RowSumMismatch { row, sum } => write!(
+                f,
+                "Row {} of the network output sums to {} rather than 1.0",
+                row, sum
+            ),
+            SearchError::InvalidInitState => write!(
+                f,
+                "init_state must not be empty or have NaNs, and its largest value must be at a \
+                 state of network_output"
+            ),
+            SearchError::CapacityExceeded => write!(
+                f,
+                "The network output is longer, or has a different alphabet size, than the decoder \
+                 has storage for"
+            ),
+            SearchError::InvalidAnchor => write!(
+                f,
+                "anchors must have a non-empty window of timesteps and a column that is one of the \
+                 (non-blank) labels of alphabet"
+            ),
+            SearchError::InvalidLabelPrior => write!(
+                f,
+                "label_prior must have an entry for each label of alphabet, each finite and greater \
+                 than 0.0 (other than those of blanks), and label_prior_weight must be finite"
+            ),
+        }
+    }
+}
+
+impl SearchError {
+    /// An `IncomparableValues` error for `row`, pointing at the first value that is not finite.
+    pub(crate) fn incomparable_row<'a, I: IntoIterator<Item = &'a f32>>(
+        row: usize,
+        values: I,
+    ) -> Self {
+        let col = values.into_iter().position(|x| !x.is_finite()).unwrap_or(0);
+        SearchError::IncomparableValues { row, col }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::InvalidEnvelope { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Any of the ways decoding can fail, from loading the inputs to the search itself.
+///
+/// Every error of the crate converts into this with `?`, so application code can use it as the
+/// one error type for a decode. Its message only says which step failed: the error it wraps is
+/// its `source`, which has the details.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The search failed.
+    Search(SearchError),
+    /// A file (such as a language model) could not be read.
+    Io(io::Error),
+    /// An ARPA language model could not be parsed.
+    Arpa(ArpaError),
+    /// An array could not be given the shape of a network output (or other input).
+    Shape(ndarray::ShapeError),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Search(_) => write!(f, "Search failed"),
+            DecodeError::Io(_) => write!(f, "I/O error"),
+            DecodeError::Arpa(_) => write!(f, "Failed to load language model"),
+            DecodeError::Shape(_) => write!(f, "Invalid shape"),
         }
     }
 }
 
-impl std::error::Error for SearchError {}
-
-
-// / Perform a CTC beam search decode on an RNN output.
-// /
-// / This function does a beam search variant of the prefix search decoding mentioned (and described
-// / in fairly vague terms) in the original CTC paper (Graves et al, 2006, section 3.2).
-// /
-// / The paper mentioned above provides recursive equations that give an efficient way to find the
-// / probability for a specific labelling. A tree of possible labelling suffixes, together with
-// / their probabilities, can be built up by starting at one end and trying every possible label at
-// / each stage. The "beam" part of the search is how we keep the search space managable - at each
-// / step, we ignore all but the most-probable tree leaves (like searching with a torch beam). This
-// / means we may not actually find the most likely labelling, but it often works very well.
-// /
-// / See the module-level documentation for general requirements on `network_output` and `alphabet`.
-// /
-// / Args:
-// /     network_output (numpy.ndarray): The 2D array output of the neural network.
-// /     alphabet (sequence): The labels (including the blank label, which must be first) in the
-// /         order given on the inner axis of `network_output`.
-// /     beam_size (int): How many search points should be kept at each step. Higher numbers are
-// /         less likely to discard the true labelling, but also make it slower and more memory
-// /         intensive. Must be at least 1.
-// /     beam_cut_threshold (float): Ignore any entries in `network_output` below this value. Must
-// /         be at least 0.0, and less than ``1/len(alphabet)``.
-// /
-// / Returns:
-// /     tuple of (str, numpy.ndarray): The decoded sequence and an array of the final
-// /         timepoints of each label (as indices into the outer axis of `network_output`).
-// /
-// / Raises:
-// /     PyValueError: The constraints on the arguments have not been met.
-
-
-// / Perform a CTC beam search decode on two RNN outputs that describe the same sequence.
-// /
-// / This is a variation of `beam_search` that attempts to find a common labelling for two RNN
-// / outputs. This could be the same network run over two different samplings of the same sequence,
-// / or two different networks run over the same input, for example.
-// /
-// / It is an implementation of the algorithm developed by Silvestre-Ryan and Holmes
-// / (https://doi.org/10.1101/2020.02.25.956771).
-// /
-// / If no envelope is provided, a default one will be used. For now, that will just search the
-// / whole of `network_output_2` at every step, but in future it may calculate a more constrained
-// / envelope. For consistent results, you should provide an envelope.
-// /
-// / Args:
-// /     network_output_1 (numpy.ndarray): The 2D array output of the first neural network.
-// /     network_output_2 (numpy.ndarray): The 2D array output of the second neural network. Note
-// /         that while the inner axis size must match that of `network_output_1`, the outer axis
-// /         can be a different size.
-// /     alphabet (str): The labels (including the blank label, which must be first) in the order
-// /         given on the inner axis of `network_output_1` and `network_output_2`.
-// /     envelope (numpy.ndarray, optional): An Nx2 array, where N is the outer axis length of
-// /         `network_output_1`. For each row of `network_output_1`, this gives the starting and
-// /         ending rows of `network_output_2` to consider for alignment.
-// /     beam_size (int): How many suffix_tree should be kept at each step. Higher numbers are less
-// /         likely to discard the true labelling, but also make it slower and more memory
-// /         intensive. Must be at least 1.
-// /     beam_cut_threshold (float): Ignore any entries in `network_output` below this value. Must
-// /         be at least 0.0, and less than ``1/len(alphabet)``.
-// /
-// / Returns:
-// /     str: The decoded sequence.
-// /
-// / Raises:
-// /     PyValueError: The constraints on the arguments have not been met.
-
-
-
-// / Methods for labelling RNN results using CTC decoding.
-// /
-// / The methods in this module implement the last step of labelling input data. In the case of
-// / nanopore sequencing data, we're taking the electrical current samples, and labelling them with
-// / what we think the DNA/RNA base is at any given time.
-// /
-// / CTC decoding (and hence the funtions in this module) takes as input the result of a neural
-// / network that has figured out, for each sample and each label, the probability that the sample
-// / corresponds to that label. The network also outputs a probability for the data point
-// / corresponding to an extra "blank" label (a sort of "none of the above" option). This is
-// / represented as a 2D matrix of size ``N x (L+1)``, where ``N`` is the number of samples and
-// / ``L`` is the number of labels we're interested in (the ``+1`` is to account for the blank
-// / label).
-// /
-// / A _path_ through the matrix is an assignment of a label or blank to each sample. The
-// / probability that the path is correct is the product of the selected entries in the matrix. Each
-// / path produces a labelling: first collapse all duplicate labels or blanks, then remove the
-// / remaining blanks - AAAGGbGGbbbC would become AGbGbC, and then AGGC. The probability that the
-// / labelling is correct is the sum of the probabilities of the paths that produce it. We want the
-// / most likely labelling.
-// /
-// / This problem is, in general, intractable. This module provides heuristic functions that attempt
-// / to find the most likely labelling (but may produce a suboptimal labelling).
-// /
-// / All functions take outputs from one or more neural networks, plus an alphabet to use for the
-// / labelling.
-// /
-// / The network outpus are 2D arrays produced by a softmax layer of a neural network, with values
-// / between 0.0 and 1.0 representing probabilities. The outer axis (rows) is time, and the inner
-// / axis (columns) is labels. The first entry on the label axis is assumed to be the blank label.
-// / It's also worth noting that the values in each row should sum to 1.0.
-// /
-// / The alphabet can be a str or any sequence of str (eg: a list or tuple of str). Each element (or
-// / character in the case of str) provides the labelling for one element of the inner axis of the
-// / network output(s) - therefore, len(alphabet) must be the size of that inner axis. Using a list
-// / or tuple allows multi-character labels to be specified. Note that the first label is not
-// / actually used by any of the functions in this module, so the value does not matter.
+#[cfg(feature = "std")]
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Search(err) => Some(err),
+            DecodeError::Io(err) => Some(err),
+            DecodeError::Arpa(err) => Some(err),
+            DecodeError::Shape(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SearchError> for DecodeError {
+    fn from(err: SearchError) -> Self {
+        DecodeError::Search(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        DecodeError::Io(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ArpaError> for DecodeError {
+    fn from(err: ArpaError) -> Self {
+        DecodeError::Arpa(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ndarray::ShapeError> for DecodeError {
+    fn from(err: ndarray::ShapeError) -> Self {
+        DecodeError::Shape(err)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        assert_eq!(VERSION, env!("CARGO_PKG_VERSION"));
+        let features = features();
+        assert!(features.contains(&"std"));
+        assert_eq!(features.contains(&"fastexp"), cfg!(feature = "fastexp"));
+        assert_eq!(features.contains(&"rayon"), cfg!(feature = "rayon"));
+    }
+
+    #[test]
+    fn test_error_sources() {
+        let err = SearchError::InvalidEnvelope {
+            row: 3,
+            reason: EnvelopeError::EmptyRange,
+        };
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            EnvelopeError::EmptyRange.to_string()
+        );
+        assert!(SearchError::InvalidBeamSize.source().is_none());
+
+        // the chain goes from a DecodeError down to the envelope, and each error has a message
+        // of its own
+        let err = DecodeError::from(err);
+        assert_ne!(err.to_string(), err.source().unwrap().to_string());
+        assert!(!err
+            .source()
+            .unwrap()
+            .to_string()
+            .contains(&EnvelopeError::EmptyRange.to_string()));
+        let envelope = err.source().and_then(Error::source).unwrap();
+        assert_eq!(envelope.to_string(), EnvelopeError::EmptyRange.to_string());
+
+        let err = DecodeError::from(
+            ndarray::Array2::<f32>::from_shape_vec((2, 2), vec![0.0]).unwrap_err(),
+        );
+        assert!(matches!(err, DecodeError::Shape(_)));
+    }
+}
@@ -0,0 +1,248 @@
+//! Language models for biasing the beam search towards likely labellings.
+//!
+//! A language model gives the probability of the next label of a labelling, given the labels
+//! before it. `search::beam_search_lm` combines these with the network output by shallow fusion:
+//! each time a path emits a label, the model's log probability for that label, scaled by a
+//! weight, is added to the log probability of the path.
+
+use super::ArpaError;
+#[cfg(feature = "std")]
+use super::DecodeError;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// A model of which label is likely to come next in a labelling.
+///
+/// Labels are given as indices into the alphabet of the search (so, the same as the columns of
+/// the network output).
+pub trait LanguageModel {
+    /// The natural log of the probability that `next` is the label after `context`.
+    ///
+    /// `context` ends with the label just before `next`. It starts at the start of the labelling,
+    /// unless that is more than `max_context` labels back.
+    fn score(&self, context: &[usize], next: usize) -> f32;
+
+    /// The most labels of context `score` needs, if there is a limit.
+    ///
+    /// The search does not pass more than this many labels of context to `score`.
+    fn max_context(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A stand-in for the start of the labelling in n-grams.
+const START: usize = usize::MAX;
+
+/// The score given to labels the model knows nothing about, as a log10 probability.
+const UNKNOWN_LOG10_PROB: f32 = -99.0;
+
+/// An n-gram language model with backoff.
+#[derive(Clone, Debug)]
+pub struct NgramModel {
+    order: usize,
+    // the log10 probability and backoff weight of each n-gram
+    ngrams: BTreeMap<Vec<usize>, (f32, f32)>,
+    unknown: f32,
+}
+
+impl NgramModel {
+    /// Load a model in the ARPA text format.
+    ///
+    /// Each token of the model must be one of the labels of `alphabet`, except for `<s>` (which
+    /// marks the start of a labelling) and `<unk>` (whose probability is used for labels that
+    /// have no unigram). N-grams containing any other token, such as `</s>`, are ignored.
+    pub fn from_arpa(arpa: &str, alphabet: &[String]) -> Result<Self, ArpaError> {
+        let mut order = 0;
+        let mut ngrams = BTreeMap::new();
+        let mut unknown = UNKNOWN_LOG10_PROB;
+        // the length of the n-grams in the current section, if it is an n-gram section
+        let mut section = None;
+        for (idx, line) in arpa.lines().enumerate() {
+            let error = ArpaError { line: idx + 1 };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('\\') {
+                section = match line {
+                    "\\data\\" | "\\end\\" => None,
+                    _ => {
+                        let n = line
+                            .strip_prefix('\\')
+                            .and_then(|x| x.strip_suffix("-grams:"))
+                            .and_then(|x| x.parse::<usize>().ok())
+                            .filter(|&n| n > 0)
+                            .ok_or(error)?;
+                        order = order.max(n);
+                        Some(n)
+                    }
+                };
+                continue;
+            }
+            let n = match section {
+                Some(n) => n,
+                // the counts in the \data\ section are not needed
+                None => continue,
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != n + 1 && fields.len() != n + 2 {
+                return Err(error);
+            }
+            let log_prob: f32 = fields[0].parse().map_err(|_| error)?;
+            let backoff: f32 = match fields.get(n + 1) {
+                Some(x) => x.parse().map_err(|_| error)?,
+                None => 0.0,
+            };
+            if n == 1 && fields[1] == "<unk>" {
+                unknown = log_prob;
+                continue;
+            }
+            let ngram: Option<Vec<usize>> = fields[1..=n]
+                .iter()
+                .map(|&token| {
+                    if token == "<s>" {
+                        Some(START)
+                    } else {
+                        alphabet.iter().position(|x| x == token)
+                    }
+                })
+                .collect();
+            if let Some(ngram) = ngram {
+                ngrams.insert(ngram, (log_prob, backoff));
+            }
+        }
+        Ok(NgramModel {
+            order,
+            ngrams,
+            unknown,
+        })
+    }
+
+    /// Load a model from an ARPA file (see `from_arpa`).
+    #[cfg(feature = "std")]
+    pub fn from_arpa_file<P: AsRef<Path>>(
+        path: P,
+        alphabet: &[String],
+    ) -> Result<Self, DecodeError> {
+        let arpa = fs::read_to_string(path)?;
+        Ok(NgramModel::from_arpa(&arpa, alphabet)?)
+    }
+
+    /// The length of the longest n-grams in the model.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// The log10 probability of the last label of `ngram` following the rest.
+    fn log10_prob(&self, ngram: &[usize]) -> f32 {
+        if let Some(&(log_prob, _)) = self.ngrams.get(ngram) {
+            return log_prob;
+        }
+        if ngram.len() <= 1 {
+            return self.unknown;
+        }
+        let context = &ngram[..ngram.len() - 1];
+        let backoff = self
+            .ngrams
+            .get(context)
+            .map_or(0.0, |&(_, backoff)| backoff);
+        backoff + self.log10_prob(&ngram[1..])
+    }
+}
+
+impl LanguageModel for NgramModel {
+    fn score(&self, context: &[usize], next: usize) -> f32 {
+        let n_context = self.order.saturating_sub(1);
+        let mut ngram = Vec::with_capacity(n_context + 1);
+        if context.len() < n_context {
+            ngram.push(START);
+        }
+        ngram.extend_from_slice(&context[context.len().saturating_sub(n_context)..]);
+        ngram.push(next);
+        self.log10_prob(&ngram) * core::f32::consts::LN_10
+    }
+
+    fn max_context(&self) -> Option<usize> {
+        Some(self.order.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARPA: &str = "
+\\data\\
+ngram 1=5
+ngram 2=3
+
+\\1-grams:
+-1.0 <s> -0.5
+-0.5 A -0.25
+-0.7 G
+-99 </s>
+-2.0 <unk>
+
+\\2-grams:
+-0.1 <s> G
+-0.2 A G
+-0.3 G </s>
+
+\\end\\
+";
+
+    #[test]
+    fn test_ngram_model() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let model = NgramModel::from_arpa(ARPA, &alphabet).unwrap();
+        assert_eq!(model.order(), 2);
+        assert_eq!(model.max_context(), Some(1));
+
+        let ln = |x: f32| x * std::f32::consts::LN_10;
+        // at the start of the labelling
+        assert_eq!(model.score(&[], 2), ln(-0.1));
+        assert_eq!(model.score(&[], 1), ln(-0.5 - 0.5));
+        // a bigram
+        assert_eq!(model.score(&[1], 2), ln(-0.2));
+        assert_eq!(model.score(&[2, 2, 1], 2), ln(-0.2));
+        // backing off to a unigram
+        assert_eq!(model.score(&[1], 1), ln(-0.25 - 0.5));
+        assert_eq!(model.score(&[2], 1), ln(-0.5));
+        // not in the model
+        assert_eq!(model.score(&[1], 0), ln(-0.25 - 2.0));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_ngram_model_errors() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        assert_eq!(
+            NgramModel::from_arpa("\\1-grams:\n-1.0\n", &alphabet).unwrap_err(),
+            ArpaError { line: 2 }
+        );
+        assert_eq!(
+            NgramModel::from_arpa("\\1-grams:\n-1.0 A\nx A\n", &alphabet).unwrap_err(),
+            ArpaError { line: 3 }
+        );
+        assert_eq!(
+            NgramModel::from_arpa("\\data\\\n\\grams:\n", &alphabet).unwrap_err(),
+            ArpaError { line: 2 }
+        );
+
+        let path = std::env::temp_dir().join("fast_ctc_decode_test_ngram_model_errors.arpa");
+        fs::write(&path, "\\1-grams:\n-1.0\n").unwrap();
+        let err = NgramModel::from_arpa_file(&path, &alphabet).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(err, DecodeError::Arpa(ArpaError { line: 2 })));
+        assert!(matches!(
+            NgramModel::from_arpa_file(path, &alphabet),
+            Err(DecodeError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+//! Probabilities stored as natural logarithms, to avoid underflow on long sequences.
+//!
+//! Addition and multiplication of `LogSpace` values behave like addition and multiplication of
+//! the probabilities they represent.
+//!
+//! With the `fastexp` feature, addition uses the fast approximation of exp, so everything built
+//! on `LogSpace` (including the duplex search) gets the speedup. The sum of two values is then
+//! within 2e-6 (`ADD_TOLERANCE`) of the exact sum, as a natural log.
+
+#[cfg(all(test, feature = "fastexp"))]
+use core::cell::Cell;
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::ops::{Add, AddAssign, Mul, MulAssign};
+use num_traits::Float;
+
+/// The most the natural log of a sum can differ from the exact value when using `fastexp`.
+#[cfg(all(test, feature = "fastexp"))]
+pub const ADD_TOLERANCE: f32 = 2e-6;
+
+#[cfg(all(test, feature = "fastexp"))]
+thread_local! {
+    static EXACT_EXP: Cell<bool> = Cell::new(false);
+}
+
+/// Run `f` with `LogSpace` addition on this thread using the standard library's exp instead of
+/// `fastexp`, so tests can compare the two.
+#[cfg(all(test, feature = "fastexp"))]
+pub fn with_exact_exp<T>(f: impl FnOnce() -> T) -> T {
+    EXACT_EXP.with(|x| x.set(true));
+    let result = f();
+    EXACT_EXP.with(|x| x.set(false));
+    result
+}
+
+/// A way of calculating exp for `LogSpace` addition.
+pub trait Exp: Copy {
+    fn exp(a: f32) -> f32;
+}
+
+/// The fast approximation of exp with the `fastexp` feature, and `StdExp` otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct DefaultExp;
+
+/// The standard library's exp (libm's, without the `std` feature), whatever the other features.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct StdExp;
+
+impl Exp for DefaultExp {
+    #[cfg(feature = "fastexp")]
+    fn exp(a: f32) -> f32 {
+        use crate::fastexp::FastExp;
+        #[cfg(test)]
+        {
+            if EXACT_EXP.with(|x| x.get()) {
+                return Float::exp(a);
+            }
+        }
+        a.fastexp()
+    }
+    #[cfg(not(feature = "fastexp"))]
+    fn exp(a: f32) -> f32 {
+        Float::exp(a)
+    }
+}
+
+impl Exp for StdExp {
+    fn exp(a: f32) -> f32 {
+        Float::exp(a)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LogSpace<X = DefaultExp>(f32, PhantomData<X>);
+
+impl<X> PartialEq for LogSpace<X> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<X> PartialOrd for LogSpace<X> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<X: Exp> LogSpace<X> {
+    pub fn new(val: f32) -> Self {
+        LogSpace(Float::ln(val), PhantomData)
+    }
+    pub fn zero() -> Self {
+        LogSpace(f32::NEG_INFINITY, PhantomData)
+    }
+    pub fn one() -> Self {
+        LogSpace(0.0, PhantomData)
+    }
+    /// Wrap a value that is already a natural-log probability.
+    pub fn from_ln(val: f32) -> Self {
+        LogSpace(val, PhantomData)
+    }
+    /// The natural-log probability this represents.
+    pub fn ln(self) -> f32 {
+        self.0
+    }
+    /// Raise the probability this represents to the power `n`.
+    pub fn powf(self, n: f32) -> Self {
+        LogSpace(self.0 * n, PhantomData)
+    }
+    pub fn max(self, other: Self) -> Self {
+        if self.0 < other.0 {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<X: Exp> Add for LogSpace<X> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        fn add_internal<X: Exp>(big: f32, small: f32) -> f32 {
+            if small == f32::NEG_INFINITY {
+                // -inf is the additive unit (it represents zero probability)
+                big
+            } else {
+                big + Float::ln_1p(X::exp(small - big))
+            }
+        }
+        // order operands by magnitude to ensure a+b produces the same answer as b+a
+        // NB: the comparison is done such that if there is a NaN, it will end up being the
+        // first argument to add_internal(), and thus be propagated properly (fastexp() doesn't
+        // propagate NaNs).
+        if self.0 <= other.0 {
+            LogSpace(add_internal::<X>(other.0, self.0), PhantomData)
+        } else {
+            LogSpace(add_internal::<X>(self.0, other.0), PhantomData)
+        }
+    }
+}
+impl<X: Exp> AddAssign for LogSpace<X> {
+    fn add_assign(&mut self, other: Self) {
+        *self = self.add(other);
+    }
+}
+impl<X: Exp> Mul for LogSpace<X> {
+    type Output = Self;
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn mul(self, other: Self) -> Self {
+        LogSpace(self.0 + other.0, PhantomData)
+    }
+}
+impl<X: Exp> MulAssign for LogSpace<X> {
+    fn mul_assign(&mut self, other: Self) {
+        *self = self.mul(other);
+    }
+}
@@ -0,0 +1,915 @@
+//! The Python module, built with the `python` feature.
+//!
+//! These are thin wrappers around the searches in `search` and `duplex`, converting between numpy
+//! arrays and `ndarray`, and raising `ValueError` for a `SearchError`.
+//!
+//! Everything that takes time proportional to the size of the inputs (the searches, and
+//! computing an envelope or mapping an alphabet for the duplex search) runs with the GIL
+//! released, so several Python threads can decode at once. The input arrays are borrowed rather
+//! than copied: `PyReadonlyArray2` marks each array as not writeable until the function returns,
+//! so other threads cannot change it part way through a decode.
+//!
+//! The arrays can have any memory order (C or Fortran order, or a strided or transposed view of
+//! a larger array). The searches step through them with `ndarray`'s strided indexing, so they are
+//! never copied to make them contiguous, and give the same results as for a C-ordered copy.
+//! `beam_search`, `beam_search_ex` and `viterbi_search` also take a network output as a list of
+//! lists of floats (or any other sequence of sequences), which is copied into an array, so quick
+//! scripts do not need numpy to call them.
+//!
+//! Each function has a `text_signature` giving its arguments and their defaults (the same as in
+//! its `#[pyfunction]` attribute), which is what `help()` and `inspect.signature` show. The type
+//! stubs in `fast_ctc_decode.pyi`, which maturin installs alongside the module, repeat these
+//! signatures with types, so all three need to be changed together.
+
+use crate::duplex;
+use crate::search::{self, BeamSearchOptions};
+use crate::SearchError;
+use ndarray::{Array2, ArrayView2, CowArray, Ix2};
+use numpy::npyffi::PyArray_Check;
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyIterator, PySequence};
+use pyo3::wrap_pyfunction;
+use pyo3::AsPyPointer;
+use pyo3::PyIterProtocol;
+use std::error::Error;
+
+/// A network output passed from Python: a 2D numpy array of float32, borrowed as it is, or any
+/// other sequence of sequences of floats, copied into an array.
+enum NetworkOutput<'a> {
+    Array(PyReadonlyArray2<'a, f32>),
+    Rows(Array2<f32>),
+}
+
+impl<'a> FromPyObject<'a> for NetworkOutput<'a> {
+    fn extract(obj: &'a PyAny) -> PyResult<Self> {
+        // a numpy array of another dtype or shape is an error, as it is for the functions that
+        // only take arrays, rather than something to convert row by row
+        if unsafe { PyArray_Check(obj.as_ptr()) } != 0 {
+            return Ok(NetworkOutput::Array(obj.extract()?));
+        }
+        let rows: Vec<Vec<f32>> = obj.extract()?;
+        let n_cols = rows.first().map_or(0, Vec::len);
+        if let Some(ragged) = rows.iter().position(|row| row.len() != n_cols) {
+            return Err(PyValueError::new_err(format!(
+                "network_output is ragged: row {} has {} values, but row 0 has {}",
+                ragged,
+                rows[ragged].len(),
+                n_cols
+            )));
+        }
+        let n_rows = rows.len();
+        let values = rows.into_iter().flatten().collect();
+        // the rows all have `n_cols` values, so the shape matches
+        Ok(NetworkOutput::Rows(
+            Array2::from_shape_vec((n_rows, n_cols), values).unwrap(),
+        ))
+    }
+}
+
+impl NetworkOutput<'_> {
+    fn as_array(&self) -> ArrayView2<'_, f32> {
+        match self {
+            NetworkOutput::Array(array) => array.as_array(),
+            NetworkOutput::Rows(array) => array.view(),
+        }
+    }
+}
+
+fn to_py_err(err: SearchError) -> PyErr {
+    // the message of each error the search error comes from is added to it, as Python only shows
+    // the one message
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    PyValueError::new_err(message)
+}
+
+/// The labels of a str (one per character) or a sequence of str.
+fn seq_to_vec(seq: &PySequence) -> PyResult<Vec<String>> {
+    seq.iter()?.map(|label| label?.extract()).collect()
+}
+
+/// `network_output` as probabilities: put through a softmax along the label axis (the outer one
+/// for a `time_axis` of 1) if `apply_softmax`, and otherwise borrowed as it is.
+fn probabilities<'a>(
+    network_output: ArrayView2<'a, f32>,
+    apply_softmax: bool,
+    time_axis: usize,
+) -> CowArray<'a, f32, Ix2> {
+    if apply_softmax {
+        let mut probs = network_output.to_owned();
+        let mut labels_inner = probs.view_mut();
+        if time_axis == 1 {
+            labels_inner = labels_inner.reversed_axes();
+        }
+        search::softmax_rows(&mut labels_inner);
+        CowArray::from(probs)
+    } else {
+        CowArray::from(network_output)
+    }
+}
+
+/// Perform a CTC beam search decode on an RNN output.
+///
+/// This function does a beam search variant of the prefix search decoding mentioned (and described
+/// in fairly vague terms) in the original CTC paper (Graves et al, 2006, section 3.2).
+///
+/// The paper mentioned above provides recursive equations that give an efficient way to find the
+/// probability for a specific labelling. A tree of possible labelling suffixes, together with
+/// their probabilities, can be built up by starting at one end and trying every possible label at
+/// each stage. The "beam" part of the search is how we keep the search space managable - at each
+/// step, we ignore all but the most-probable tree leaves (like searching with a torch beam). This
+/// means we may not actually find the most likely labelling, but it often works very well.
+///
+/// See the module-level documentation for general requirements on `network_output` and `alphabet`.
+///
+/// A `network_output` with no rows, or an alphabet of only the blank label, decodes to an empty
+/// sequence with no timepoints.
+///
+/// Args:
+///     network_output (numpy.ndarray or sequence): The 2D array output of the neural network,
+///         or a list of lists of floats (one list per row), which must all be the same length.
+///     alphabet (sequence): The labels (including the blank label, which must be first) in the
+///         order given on the inner axis of `network_output`.
+///     beam_size (int): How many search points should be kept at each step. Higher numbers are
+///         less likely to discard the true labelling, but also make it slower and more memory
+///         intensive. Must be at least 1.
+///     beam_cut_threshold (float): Ignore any entries in `network_output` below this value. Must
+///         be at least 0.0, and less than ``1/len(alphabet)``. This is a probability, not a log,
+///         and with `apply_softmax` it is compared with the output of the softmax.
+///     collapse_repeats (bool): Whether repeated labels with no blank between them are collapsed
+///         into one.
+///     apply_softmax (bool): Whether `network_output` is logits, which should be put through a
+///         softmax (along the label axis) before decoding. `network_output` itself is not changed.
+///     progress (callable, optional): Called as ``progress(timestep, sequence)`` after every
+///         `progress_interval` rows of `network_output` (and after the last row), with the number
+///         of rows searched so far and the best sequence at that point. If it raises an exception,
+///         the search stops and the exception is raised from `beam_search`.
+///     should_stop (callable, optional): Called with no arguments after every `progress_interval`
+///         rows of `network_output`. If it returns a true value (for example, because a deadline
+///         has passed), the search stops there and returns the best sequence of the rows searched
+///         so far.
+///     progress_interval (int): How many rows to search between calls to `progress` and
+///         `should_stop`. Each call to `progress` builds the best sequence so far, so small values
+///         slow down long decodes. Must be at least 1.
+///     time_axis (int): Which axis of `network_output` is time: 0 for time on the outer axis and
+///         labels on the inner axis, or 1 for the reverse. Either way, `network_output` is not
+///         copied (unless `apply_softmax` is true), and "rows" above means timesteps. Must be 0
+///         or 1.
+///
+/// Returns:
+///     tuple of (str, numpy.ndarray): The decoded sequence and an array of the timepoints of each
+///         label (as indices into the outer axis of `network_output`). If `should_stop` is given,
+///         the tuple has a third element, a bool saying whether the search was stopped early.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(
+    beam_size = "5",
+    beam_cut_threshold = "0.0",
+    collapse_repeats = "true",
+    apply_softmax = "false",
+    progress = "None",
+    should_stop = "None",
+    progress_interval = "10000",
+    time_axis = "0"
+)]
+#[pyo3(
+    text_signature = "(network_output, alphabet, beam_size=5, beam_cut_threshold=0.0, \
+                         collapse_repeats=True, apply_softmax=False, progress=None, \
+                         should_stop=None, progress_interval=10000, time_axis=0)"
+)]
+#[allow(clippy::too_many_arguments)]
+fn beam_search<'py>(
+    py: Python<'py>,
+    network_output: NetworkOutput,
+    alphabet: &PySequence,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    apply_softmax: bool,
+    progress: Option<PyObject>,
+    should_stop: Option<PyObject>,
+    progress_interval: usize,
+    time_axis: usize,
+) -> PyResult<PyObject> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output = network_output.as_array();
+    let options = BeamSearchOptions::new()
+        .beam_size(beam_size)
+        .beam_cut_threshold(beam_cut_threshold)
+        .collapse_repeats(collapse_repeats)
+        .time_axis(time_axis);
+    let mut callback_err = None;
+    let result = py.allow_threads(|| {
+        let network_output = probabilities(network_output, apply_softmax, time_axis);
+        if progress.is_none() && should_stop.is_none() {
+            let (sequence, path, _) =
+                search::beam_search_with(&network_output, &alphabet, &options)?;
+            return Ok((sequence, path, true));
+        }
+        let (state, complete) = search::search_in_steps(
+            &network_output,
+            &alphabet,
+            &options,
+            progress_interval,
+            |state| {
+                let called = Python::with_gil(|py| {
+                    if let Some(progress) = &progress {
+                        let (sequence, _, _) = state.result(&alphabet, &options);
+                        progress.call1(py, (state.timestep(), sequence))?;
+                    }
+                    match &should_stop {
+                        Some(should_stop) => should_stop.call0(py)?.as_ref(py).is_true(),
+                        None => Ok(false),
+                    }
+                });
+                // stop at the first exception, and raise it once the GIL is back
+                match called {
+                    Ok(stop) => !stop,
+                    Err(err) => {
+                        callback_err = Some(err);
+                        false
+                    }
+                }
+            },
+        )?;
+        let (sequence, path, _) = state.result(&alphabet, &options);
+        Ok((sequence, path, complete))
+    });
+    if let Some(err) = callback_err {
+        return Err(err);
+    }
+    let (sequence, path, complete) = result.map_err(to_py_err)?;
+    let path = path.into_pyarray(py);
+    Ok(if should_stop.is_some() {
+        (sequence, path, !complete).into_py(py)
+    } else {
+        (sequence, path).into_py(py)
+    })
+}
+
+/// The result of `beam_search_ex`.
+///
+/// The optional outputs are None unless they were asked for.
+///
+/// Attributes:
+///     sequence (str): The decoded sequence.
+///     timepoints (numpy.ndarray): The timepoint of each label (as indices into the time axis of
+///         `network_output`).
+///     probability (float): The probability of the decoded labelling, relative to the others
+///         remaining in the beam at the end of the search.
+///     score (float, optional): The natural log of the probability `network_output` gives the
+///         decoded labelling, summed over every path that gives it.
+///     qualities (numpy.ndarray, optional): The probability `network_output` gives each
+///         character of the sequence, at the timepoint of its label.
+#[pyclass(module = "fast_ctc_decode")]
+struct DecodeResult {
+    #[pyo3(get)]
+    sequence: String,
+    #[pyo3(get)]
+    timepoints: Py<PyArray1<usize>>,
+    #[pyo3(get)]
+    probability: f32,
+    #[pyo3(get)]
+    score: Option<f32>,
+    #[pyo3(get)]
+    qualities: Option<Py<PyArray1<f32>>>,
+}
+
+#[pymethods]
+impl DecodeResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "DecodeResult(sequence={:?}, probability={}, score={}, qualities={})",
+            self.sequence,
+            self.probability,
+            self.score.map_or(String::from("None"), |x| x.to_string()),
+            if self.qualities.is_some() {
+                "[...]"
+            } else {
+                "None"
+            },
+        )
+    }
+}
+
+/// Perform a CTC beam search decode on an RNN output, returning a `DecodeResult`.
+///
+/// This is the same search as `beam_search`, with the same arguments for it (other than the
+/// callbacks), but the result has a named attribute for each output, so more can be added without
+/// changing how it is unpacked.
+///
+/// Args:
+///     network_output (numpy.ndarray or sequence): As for `beam_search`.
+///     alphabet (sequence): As for `beam_search`.
+///     beam_size (int): As for `beam_search`.
+///     beam_cut_threshold (float): As for `beam_search`.
+///     collapse_repeats (bool): As for `beam_search`.
+///     apply_softmax (bool): As for `beam_search`. The qualities and score are then from the
+///         output of the softmax.
+///     time_axis (int): As for `beam_search`.
+///     qualities (bool): Whether to fill in the `qualities` of the result.
+///     score (bool): Whether to fill in the `score` of the result. This runs the CTC forward
+///         algorithm over the decoded labelling, splitting the sequence back into labels by
+///         matching the longest label of `alphabet` at each point, so with multi-character labels
+///         it may score a different split of the same sequence.
+///
+/// Returns:
+///     DecodeResult: The decoded sequence, and the rest of the outputs.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(
+    beam_size = "5",
+    beam_cut_threshold = "0.0",
+    collapse_repeats = "true",
+    apply_softmax = "false",
+    time_axis = "0",
+    qualities = "false",
+    score = "false"
+)]
+#[pyo3(
+    text_signature = "(network_output, alphabet, beam_size=5, beam_cut_threshold=0.0, \
+                         collapse_repeats=True, apply_softmax=False, time_axis=0, \
+                         qualities=False, score=False)"
+)]
+#[allow(clippy::too_many_arguments)]
+fn beam_search_ex(
+    py: Python<'_>,
+    network_output: NetworkOutput,
+    alphabet: &PySequence,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    apply_softmax: bool,
+    time_axis: usize,
+    qualities: bool,
+    score: bool,
+) -> PyResult<DecodeResult> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output = network_output.as_array();
+    let options = BeamSearchOptions::new()
+        .beam_size(beam_size)
+        .beam_cut_threshold(beam_cut_threshold)
+        .collapse_repeats(collapse_repeats)
+        .time_axis(time_axis);
+    let (sequence, path, probability, quals, log_prob) = py
+        .allow_threads(|| {
+            let network_output = probabilities(network_output, apply_softmax, time_axis);
+            let (sequence, path, probability, quals) = if qualities {
+                let (sequence, path, probability, quals) =
+                    search::beam_search_qualities_with(&network_output, &alphabet, &options)?;
+                (sequence, path, probability, Some(quals))
+            } else {
+                let (sequence, path, probability) =
+                    search::beam_search_with(&network_output, &alphabet, &options)?;
+                (sequence, path, probability, None)
+            };
+            let log_prob = if score {
+                Some(search::score_labelling(
+                    &network_output,
+                    &alphabet,
+                    &sequence,
+                    &options,
+                )?)
+            } else {
+                None
+            };
+            Ok((sequence, path, probability, quals, log_prob))
+        })
+        .map_err(to_py_err)?;
+    Ok(DecodeResult {
+        sequence,
+        timepoints: path.into_pyarray(py).to_owned(),
+        probability,
+        score: log_prob,
+        qualities: quals.map(|quals| quals.into_pyarray(py).to_owned()),
+    })
+}
+
+/// The iterator returned by `decode_all`.
+///
+/// Each step takes the next network output from the iterable it was given and decodes it.
+#[pyclass(module = "fast_ctc_decode")]
+struct DecodeAll {
+    /// An iterator over the network outputs.
+    reads: PyObject,
+    alphabet: Vec<String>,
+    decoder: search::Decoder,
+    apply_softmax: bool,
+    time_axis: usize,
+}
+
+#[pyproto]
+impl PyIterProtocol for DecodeAll {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<PyObject>> {
+        // the GIL is held already, but `slf.py()` would keep `slf` borrowed
+        Python::with_gil(|py| {
+            // the iterator of an iterator is itself
+            let read = match PyIterator::from_object(py, slf.reads.as_ref(py))?.next() {
+                Some(read) => read?,
+                None => return Ok(None),
+            };
+            let network_output: PyReadonlyArray2<f32> = read.extract()?;
+            let network_output = network_output.as_array();
+            let DecodeAll {
+                alphabet,
+                decoder,
+                apply_softmax,
+                time_axis,
+                ..
+            } = &mut *slf;
+            let (sequence, path, _) = py
+                .allow_threads(|| {
+                    let network_output = probabilities(network_output, *apply_softmax, *time_axis);
+                    decoder.decode(&network_output, alphabet)
+                })
+                .map_err(to_py_err)?;
+            Ok(Some((sequence, path.into_pyarray(py)).into_py(py)))
+        })
+    }
+}
+
+/// Perform a CTC beam search decode on each of an iterable of RNN outputs, as they are needed.
+///
+/// The network outputs are taken from `reads` one at a time, as the returned iterator is advanced,
+/// so `reads` can be a generator, and only the network output being decoded needs to be held in
+/// memory. Every decode reuses the same search storage.
+///
+/// Args:
+///     reads (iterable): The network outputs, each as for `beam_search`.
+///     alphabet (sequence): As for `beam_search`.
+///     beam_size (int): As for `beam_search`.
+///     beam_cut_threshold (float): As for `beam_search`.
+///     collapse_repeats (bool): As for `beam_search`.
+///     apply_softmax (bool): As for `beam_search`.
+///     time_axis (int): As for `beam_search`, for every network output.
+///
+/// Returns:
+///     iterator of tuple of (str, numpy.ndarray): The decoded sequence and timepoints of each
+///         network output, in order, as `beam_search` returns them.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met. The constraints on each
+///         network output are checked as the iterator reaches it, and raise from `next`.
+#[pyfunction(
+    beam_size = "5",
+    beam_cut_threshold = "0.0",
+    collapse_repeats = "true",
+    apply_softmax = "false",
+    time_axis = "0"
+)]
+#[pyo3(
+    text_signature = "(reads, alphabet, beam_size=5, beam_cut_threshold=0.0, \
+                         collapse_repeats=True, apply_softmax=False, time_axis=0)"
+)]
+#[allow(clippy::too_many_arguments)]
+fn decode_all(
+    py: Python<'_>,
+    reads: &PyAny,
+    alphabet: &PySequence,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    apply_softmax: bool,
+    time_axis: usize,
+) -> PyResult<DecodeAll> {
+    let options = BeamSearchOptions::new()
+        .beam_size(beam_size)
+        .beam_cut_threshold(beam_cut_threshold)
+        .collapse_repeats(collapse_repeats)
+        .time_axis(time_axis);
+    Ok(DecodeAll {
+        reads: reads.iter()?.to_object(py),
+        alphabet: seq_to_vec(alphabet)?,
+        decoder: search::Decoder::new(options),
+        apply_softmax,
+        time_axis,
+    })
+}
+
+/// Check the network outputs of a duplex search, and get the envelope to use.
+fn duplex_envelope<'a>(
+    py: Python<'_>,
+    network_output_1: &ArrayView2<'a, f32>,
+    network_output_2: &ArrayView2<'a, f32>,
+    alphabet: &[String],
+    envelope: Option<PyReadonlyArray2<'_, usize>>,
+) -> PyResult<Array2<usize>> {
+    // the searches assume these, so check them here rather than panicking
+    if network_output_1.shape()[1] != alphabet.len()
+        || network_output_2.shape()[1] != alphabet.len()
+    {
+        return Err(to_py_err(SearchError::AlphabetMismatch));
+    }
+    match envelope {
+        Some(envelope) => Ok(envelope.as_array().to_owned()),
+        None => py
+            .allow_threads(|| {
+                duplex::compute_envelope(network_output_1, network_output_2, alphabet)
+            })
+            .map_err(to_py_err),
+    }
+}
+
+/// Perform a CTC beam search decode on two RNN outputs that describe the same sequence.
+///
+/// This is a variation of `beam_search` that attempts to find a common labelling for two RNN
+/// outputs. This could be the same network run over two different samplings of the same sequence,
+/// or two different networks run over the same input, for example.
+///
+/// It is an implementation of the algorithm developed by Silvestre-Ryan and Holmes
+/// (https://doi.org/10.1101/2020.02.25.956771).
+///
+/// If no envelope is provided, one is calculated by anchoring the two outputs together at the
+/// labels their best paths agree on. For consistent results, you should provide an envelope.
+///
+/// If either network output has no rows, there is nothing for the two to agree on, and the
+/// result is an empty sequence (the envelope is not checked). So is the result for an alphabet
+/// of only the blank label.
+///
+/// Args:
+///     network_output_1 (numpy.ndarray): The 2D array output of the first neural network.
+///     network_output_2 (numpy.ndarray): The 2D array output of the second neural network. Note
+///         that while the inner axis size must match that of `network_output_1`, the outer axis
+///         can be a different size.
+///     alphabet (str): The labels (including the blank label, which must be first) in the order
+///         given on the inner axis of `network_output_1` and `network_output_2`.
+///     envelope (numpy.ndarray, optional): An Nx2 array, where N is the outer axis length of
+///         `network_output_1`. For each row of `network_output_1`, this gives the starting and
+///         ending rows of `network_output_2` to consider for alignment.
+///     beam_size (int): How many search points should be kept at each step. Higher numbers are
+///         less likely to discard the true labelling, but also make it slower and more memory
+///         intensive. Must be at least 1.
+///     beam_cut_threshold (float): Ignore any entries in `network_output` below this value. Must
+///         be at least 0.0, and less than ``1/len(alphabet)``. This is a probability, not a log,
+///         although the search works in log space.
+///     alphabet_2 (sequence, optional): The labels on the inner axis of `network_output_2`, if
+///         they are not those of `alphabet`. The search is then done over `alphabet`, with the
+///         probabilities of `network_output_2` re-indexed onto it through `mapping`.
+///     mapping (sequence of int, optional): For each label of `alphabet_2`, the index of the label
+///         of `alphabet` it stands for. Labels that map to the same label have their probabilities
+///         added up. The blank must map to the blank, and nothing else may. Defaults to mapping
+///         each label to the label with the same index, which needs the alphabets to be the same
+///         length.
+///     weight_1 (float): How much to trust `network_output_1`: the weight of a labelling's log
+///         probability in it, in the score the search ranks labellings by. Must be greater than
+///         0.0.
+///     weight_2 (float): The same, for `network_output_2`. Only the ratio of the weights matters.
+///         They do not change the envelope, and `beam_cut_threshold` is compared with the
+///         unweighted entries of `network_output_1`.
+///     refine_margin (int, optional): Narrow the envelope as the search goes: after each row of
+///         `network_output_1`, only search the part of the envelope from `refine_margin` rows
+///         before to `refine_margin` rows after the rows of `network_output_2` that the labellings
+///         in the beam are best aligned with. This saves work for a wide envelope. Nothing is cut
+///         from an envelope no wider than the margin, so the result is then the same as without
+///         it. Defaults to searching all of the envelope.
+///
+/// Returns:
+///     str: The decoded sequence.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(
+    envelope = "None",
+    beam_size = "5",
+    beam_cut_threshold = "0.0",
+    alphabet_2 = "None",
+    mapping = "None",
+    weight_1 = "1.0",
+    weight_2 = "1.0",
+    refine_margin = "None"
+)]
+#[pyo3(
+    text_signature = "(network_output_1, network_output_2, alphabet, envelope=None, \
+                         beam_size=5, beam_cut_threshold=0.0, alphabet_2=None, mapping=None, \
+                         weight_1=1.0, weight_2=1.0, refine_margin=None)"
+)]
+#[allow(clippy::too_many_arguments)]
+fn beam_search_duplex(
+    py: Python<'_>,
+    network_output_1: PyReadonlyArray2<f32>,
+    network_output_2: PyReadonlyArray2<f32>,
+    alphabet: &PySequence,
+    envelope: Option<PyReadonlyArray2<usize>>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    alphabet_2: Option<PyObject>,
+    mapping: Option<Vec<usize>>,
+    weight_1: f32,
+    weight_2: f32,
+    refine_margin: Option<usize>,
+) -> PyResult<String> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output_1 = network_output_1.as_array();
+    let network_output_2 = network_output_2.as_array();
+    let mapped;
+    let network_output_2 = match (alphabet_2, mapping) {
+        (None, None) => network_output_2,
+        (None, Some(_)) => {
+            return Err(PyValueError::new_err(
+                "mapping can only be given with alphabet_2",
+            ))
+        }
+        (Some(alphabet_2), mapping) => {
+            let alphabet_2 = seq_to_vec(alphabet_2.as_ref(py).downcast()?)?;
+            let mapping = mapping.unwrap_or_else(|| (0..alphabet_2.len()).collect());
+            mapped = py
+                .allow_threads(|| {
+                    duplex::map_columns(&network_output_2, &alphabet_2, &alphabet, &mapping)
+                })
+                .map_err(to_py_err)?;
+            mapped.view()
+        }
+    };
+    let envelope = duplex_envelope(
+        py,
+        &network_output_1,
+        &network_output_2,
+        &alphabet,
+        envelope,
+    )?;
+    py.allow_threads(|| match refine_margin {
+        Some(refine_margin) => duplex::beam_search_refined(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            beam_size,
+            beam_cut_threshold,
+            true,
+            weight_1,
+            weight_2,
+            refine_margin,
+        ),
+        None => duplex::beam_search_weighted(
+            &network_output_1,
+            &network_output_2,
+            &alphabet,
+            &envelope,
+            beam_size,
+            beam_cut_threshold,
+            true,
+            weight_1,
+            weight_2,
+        ),
+    })
+    .map_err(to_py_err)
+}
+
+/// Perform a duplex CTC beam search decode, also returning the timepoints of each label in both RNN
+/// outputs.
+///
+/// This is the same search as `beam_search_duplex`. Each output is then aligned with the decoded
+/// sequence, to find where each label ends in it.
+///
+/// Args:
+///     network_output_1 (numpy.ndarray): As for `beam_search_duplex`.
+///     network_output_2 (numpy.ndarray): As for `beam_search_duplex`.
+///     alphabet (sequence): As for `beam_search_duplex`.
+///     envelope (numpy.ndarray, optional): As for `beam_search_duplex`.
+///     beam_size (int): As for `beam_search_duplex`.
+///     beam_cut_threshold (float): As for `beam_search_duplex`.
+///
+/// Returns:
+///     tuple of (str, numpy.ndarray, numpy.ndarray): The decoded sequence, and arrays of the final
+///         timepoint of each label as indices into the outer axes of `network_output_1` and
+///         `network_output_2` respectively.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(envelope = "None", beam_size = "5", beam_cut_threshold = "0.0")]
+#[pyo3(
+    text_signature = "(network_output_1, network_output_2, alphabet, envelope=None, \
+                         beam_size=5, beam_cut_threshold=0.0)"
+)]
+fn beam_search_duplex_with_timepoints<'py>(
+    py: Python<'py>,
+    network_output_1: PyReadonlyArray2<f32>,
+    network_output_2: PyReadonlyArray2<f32>,
+    alphabet: &PySequence,
+    envelope: Option<PyReadonlyArray2<usize>>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+) -> PyResult<(String, &'py PyArray1<usize>, &'py PyArray1<usize>)> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output_1 = network_output_1.as_array();
+    let network_output_2 = network_output_2.as_array();
+    let envelope = duplex_envelope(
+        py,
+        &network_output_1,
+        &network_output_2,
+        &alphabet,
+        envelope,
+    )?;
+    let (sequence, timepoints_1, timepoints_2) = py
+        .allow_threads(|| {
+            duplex::beam_search_with_timepoints(
+                &network_output_1,
+                &network_output_2,
+                &alphabet,
+                &envelope,
+                beam_size,
+                beam_cut_threshold,
+                true,
+            )
+        })
+        .map_err(to_py_err)?;
+    Ok((
+        sequence,
+        timepoints_1.into_pyarray(py),
+        timepoints_2.into_pyarray(py),
+    ))
+}
+
+/// Perform a duplex CTC beam search decode, also returning how strongly each RNN output supports
+/// each label.
+///
+/// This is the same search as `beam_search_duplex`. Each output is then aligned with the decoded
+/// sequence, and the support of a label in an output is the highest probability the output gives
+/// it over the rows it covers in that alignment (or 0.0 if the sequence is impossible in that
+/// output). Labels that one output supports much more strongly than the other show where the two
+/// disagree.
+///
+/// Args:
+///     network_output_1 (numpy.ndarray): As for `beam_search_duplex`.
+///     network_output_2 (numpy.ndarray): As for `beam_search_duplex`.
+///     alphabet (sequence): As for `beam_search_duplex`.
+///     envelope (numpy.ndarray, optional): As for `beam_search_duplex`.
+///     beam_size (int): As for `beam_search_duplex`.
+///     beam_cut_threshold (float): As for `beam_search_duplex`.
+///
+/// Returns:
+///     tuple of (str, numpy.ndarray): The decoded sequence, and an Nx2 array of the support of
+///         each of its N labels in `network_output_1` and `network_output_2` respectively.
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(envelope = "None", beam_size = "5", beam_cut_threshold = "0.0")]
+#[pyo3(
+    text_signature = "(network_output_1, network_output_2, alphabet, envelope=None, \
+                         beam_size=5, beam_cut_threshold=0.0)"
+)]
+fn beam_search_duplex_with_support<'py>(
+    py: Python<'py>,
+    network_output_1: PyReadonlyArray2<f32>,
+    network_output_2: PyReadonlyArray2<f32>,
+    alphabet: &PySequence,
+    envelope: Option<PyReadonlyArray2<usize>>,
+    beam_size: usize,
+    beam_cut_threshold: f32,
+) -> PyResult<(String, &'py PyArray2<f32>)> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output_1 = network_output_1.as_array();
+    let network_output_2 = network_output_2.as_array();
+    let envelope = duplex_envelope(
+        py,
+        &network_output_1,
+        &network_output_2,
+        &alphabet,
+        envelope,
+    )?;
+    let (sequence, support) = py
+        .allow_threads(|| {
+            duplex::beam_search_with_support(
+                &network_output_1,
+                &network_output_2,
+                &alphabet,
+                &envelope,
+                beam_size,
+                beam_cut_threshold,
+                true,
+            )
+        })
+        .map_err(to_py_err)?;
+    let support: Vec<[f32; 2]> = support.iter().map(|&(x_1, x_2)| [x_1, x_2]).collect();
+    let support = Array2::from(support);
+    Ok((sequence, support.into_pyarray(py)))
+}
+
+/// Perform a Viterbi (best path) decode on an RNN output.
+///
+/// This takes the most probable label at each timepoint, collapses repeats and removes blanks.
+///
+/// Args:
+///     network_output (numpy.ndarray or sequence): As for `beam_search`.
+///     alphabet (sequence): The labels (including the blank label, which must be first) in the
+///         order given on the inner axis of `network_output`.
+///     qstring (bool): Whether to append a quality string (of phred scores) to the sequence.
+///     qscale (float): The scale of the phred scores.
+///     qbias (float): The offset of the phred scores.
+///     collapse_repeats (bool): Whether repeated labels with no blank between them are collapsed
+///         into one.
+///
+/// Returns:
+///     tuple of (str, numpy.ndarray): The decoded sequence and an array of the first timepoint of
+///         each label (as indices into the outer axis of `network_output`).
+///
+/// Raises:
+///     ValueError: The constraints on the arguments have not been met.
+#[pyfunction(
+    qstring = "false",
+    qscale = "1.0",
+    qbias = "0.0",
+    collapse_repeats = "true"
+)]
+#[pyo3(
+    text_signature = "(network_output, alphabet, qstring=False, qscale=1.0, qbias=0.0, \
+                         collapse_repeats=True)"
+)]
+fn viterbi_search<'py>(
+    py: Python<'py>,
+    network_output: NetworkOutput,
+    alphabet: &PySequence,
+    qstring: bool,
+    qscale: f32,
+    qbias: f32,
+    collapse_repeats: bool,
+) -> PyResult<(String, &'py PyArray1<usize>)> {
+    let alphabet = seq_to_vec(alphabet)?;
+    let network_output = network_output.as_array();
+    if alphabet.is_empty() || network_output.shape()[1] != alphabet.len() {
+        return Err(to_py_err(SearchError::AlphabetMismatch));
+    }
+    let (sequence, path) = py
+        .allow_threads(|| {
+            search::viterbi_search(
+                &network_output,
+                &alphabet,
+                qstring,
+                qscale,
+                qbias,
+                collapse_repeats,
+                0,
+            )
+        })
+        .map_err(to_py_err)?;
+    Ok((sequence, path.into_pyarray(py)))
+}
+
+/// Methods for labelling RNN results using CTC decoding.
+///
+/// The methods in this module implement the last step of labelling input data. In the case of
+/// nanopore sequencing data, we're taking the electrical current samples, and labelling them with
+/// what we think the DNA/RNA base is at any given time.
+///
+/// CTC decoding (and hence the funtions in this module) takes as input the result of a neural
+/// network that has figured out, for each sample and each label, the probability that the sample
+/// corresponds to that label. The network also outputs a probability for the data point
+/// corresponding to an extra "blank" label (a sort of "none of the above" option). This is
+/// represented as a 2D matrix of size ``N x (L+1)``, where ``N`` is the number of samples and
+/// ``L`` is the number of labels we're interested in (the ``+1`` is to account for the blank
+/// label).
+///
+/// A _path_ through the matrix is an assignment of a label or blank to each sample. The
+/// probability that the path is correct is the product of the selected entries in the matrix. Each
+/// path produces a labelling: first collapse all duplicate labels or blanks, then remove the
+/// remaining blanks - AAAGGbGGbbbC would become AGbGbC, and then AGGC. The probability that the
+/// labelling is correct is the sum of the probabilities of the paths that produce it. We want the
+/// most likely labelling.
+///
+/// This problem is, in general, intractable. This module provides heuristic functions that attempt
+/// to find the most likely labelling (but may produce a suboptimal labelling).
+///
+/// All functions take outputs from one or more neural networks, plus an alphabet to use for the
+/// labelling.
+///
+/// The network outpus are 2D arrays produced by a softmax layer of a neural network, with values
+/// between 0.0 and 1.0 representing probabilities. The outer axis (rows) is time, and the inner
+/// axis (columns) is labels. The first entry on the label axis is assumed to be the blank label.
+/// It's also worth noting that the values in each row should sum to 1.0.
+///
+/// The alphabet can be a str or any sequence of str (eg: a list or tuple of str). Each element (or
+/// character in the case of str) provides the labelling for one element of the inner axis of the
+/// network output(s) - therefore, len(alphabet) must be the size of that inner axis. Using a list
+/// or tuple allows multi-character labels to be specified. Note that the first label is not
+/// actually used by any of the functions in this module, so the value does not matter.
+///
+/// ``__version__`` is the version of the module, and ``features`` lists the optional features it
+/// was built with (such as ``fastexp`` and ``simd``), which are worth including in bug reports.
+#[pymodule]
+fn fast_ctc_decode(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(beam_search, m)?)?;
+    m.add_function(wrap_pyfunction!(beam_search_ex, m)?)?;
+    m.add_class::<DecodeResult>()?;
+    m.add_function(wrap_pyfunction!(decode_all, m)?)?;
+    m.add_class::<DecodeAll>()?;
+    m.add("__version__", crate::VERSION)?;
+    m.add("features", crate::features())?;
+    m.add_function(wrap_pyfunction!(beam_search_duplex, m)?)?;
+    m.add_function(wrap_pyfunction!(beam_search_duplex_with_timepoints, m)?)?;
+    m.add_function(wrap_pyfunction!(beam_search_duplex_with_support, m)?)?;
+    m.add_function(wrap_pyfunction!(viterbi_search, m)?)?;
+    Ok(())
+}
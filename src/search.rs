@@ -1,38 +1,230 @@
 use super::SearchError;
-use crate::tree::{SuffixTree, ROOT_NODE};
-use ndarray::{ArrayBase, Axis, Data, FoldWhile, Ix1, Ix2, Ix3, Zip};
-use ndarray_stats::QuantileExt;
+use crate::lm::LanguageModel;
+use crate::logspace::{DefaultExp, Exp, LogSpace, StdExp};
+use crate::simd;
+use crate::tree::{Growth, Lexicon, SuffixTree, ROOT_NODE};
+use crate::vec2d::Vec2D;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::RefCell;
+use core::cmp::{Ordering, Reverse};
+use core::iter::Rev;
+use core::ops::{Add, AddAssign, Mul, Range};
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use ndarray::{
+    Array2, ArrayBase, ArrayView1, ArrayView2, Axis, Data, DataMut, FoldWhile, Ix1, Ix2, Ix3, Zip,
+};
+use num_traits::Float;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The operations the beam search needs from a representation of probabilities.
+trait Probability: Copy + PartialOrd + Add<Output = Self> + AddAssign + Mul<Output = Self> {
+    fn zero() -> Self;
+    fn one() -> Self;
+    /// The probability whose natural log is `ln`.
+    fn from_ln(ln: f32) -> Self;
+    /// The probability as an `f64` (not in log space).
+    fn to_f64(self) -> f64;
+    /// The probability `x` (not in log space).
+    fn from_f64(x: f64) -> Self;
+
+    /// Push the index of each of `probs` that is not less than `threshold` onto `columns`, in
+    /// increasing order.
+    fn columns_at_least(probs: &[Self], threshold: Self, columns: &mut Vec<usize>) {
+        for (column, pr) in probs.iter().enumerate() {
+            if pr.partial_cmp(&threshold) != Some(Ordering::Less) {
+                columns.push(column);
+            }
+        }
+    }
+}
+
+impl Probability for f32 {
+    fn zero() -> Self {
+        0.0
+    }
+    fn one() -> Self {
+        1.0
+    }
+    fn from_ln(ln: f32) -> Self {
+        ln.exp()
+    }
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+    fn columns_at_least(probs: &[Self], threshold: Self, columns: &mut Vec<usize>) {
+        simd::columns_at_least(probs, threshold, columns)
+    }
+}
+
+impl Probability for f64 {
+    fn zero() -> Self {
+        0.0
+    }
+    fn one() -> Self {
+        1.0
+    }
+    fn from_ln(ln: f32) -> Self {
+        f64::from(ln).exp()
+    }
+    fn to_f64(self) -> f64 {
+        self
+    }
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+}
+
+impl<X: Exp> Probability for LogSpace<X> {
+    fn zero() -> Self {
+        LogSpace::zero()
+    }
+    fn one() -> Self {
+        LogSpace::one()
+    }
+    fn from_ln(ln: f32) -> Self {
+        LogSpace::from_ln(ln)
+    }
+    fn to_f64(self) -> f64 {
+        f64::from(self.ln()).exp()
+    }
+    fn from_f64(x: f64) -> Self {
+        LogSpace::from_ln(x.ln() as f32)
+    }
+}
+
+/// The types of value a network output can be given in.
+trait Input: Copy {
+    fn is_finite(self) -> bool;
+}
+
+impl Input for f32 {
+    fn is_finite(self) -> bool {
+        f32::is_finite(self)
+    }
+}
+
+impl Input for f64 {
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+}
+
+impl Input for u8 {
+    fn is_finite(self) -> bool {
+        true
+    }
+}
+
+/// The index of the first value that is not finite, if there is one.
+fn first_non_finite<'a, E: 'a + Input, I: IntoIterator<Item = &'a E>>(values: I) -> Option<usize> {
+    values.into_iter().position(|x| !x.is_finite())
+}
+
+/// The index and value of the first largest of `values`, or `None` if there are none, or they
+/// cannot all be ordered (because one is NaN).
+pub(crate) fn max_index<'a, I: IntoIterator<Item = &'a f32>>(values: I) -> Option<(usize, f32)> {
+    let mut values = values.into_iter().copied().enumerate();
+    let mut max = values.next()?;
+    max.1.partial_cmp(&max.1)?;
+    for (idx, x) in values {
+        if x.partial_cmp(&max.1)? == Ordering::Greater {
+            max = (idx, x);
+        }
+    }
+    Some(max)
+}
+
+/// Check that a CRF `network_output` matches `alphabet`, and find the state a CRF search starts
+/// from: the most likely state of `init_state`, and its probability.
+///
+/// Each row of a CRF network output is a matrix of states by labels. A label moves the search on
+/// to a state worked out from the current one, which is only sure to be a row of the matrix when
+/// the number of states is a multiple of the number of labels other than the blank.
+pub(crate) fn check_crf_args<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix3>,
+    init_state: &ArrayBase<D, Ix1>,
+    alphabet: &[String],
+) -> Result<(usize, f32), SearchError> {
+    let (n_state, n_labels) = (network_output.shape()[1], network_output.shape()[2]);
+    if alphabet.is_empty()
+        || n_labels != alphabet.len()
+        || (n_labels > 1 && n_state % (n_labels - 1) != 0)
+    {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    match max_index(init_state) {
+        Some((state, prob)) if state < n_state => Ok((state, prob)),
+        _ => Err(SearchError::InvalidInitState),
+    }
+}
 
 /// A node in the labelling tree to build from.
 #[derive(Clone, Copy, Debug)]
-struct SearchPoint {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct SearchPoint<P = f32> {
     /// The node search should progress from.
     node: i32,
-    /// The transition state for crf.
+    /// The transition state for crf, or the lexicon node of the current word for lexicon
+    /// searches.
     state: usize,
     /// The cumulative probability of the labelling so far for paths without any leading blank
     /// labels.
-    label_prob: f32,
+    label_prob: P,
     /// The cumulative probability of the labelling so far for paths with one or more leading
     /// blank labels.
-    gap_prob: f32,
+    gap_prob: P,
+    /// The fewest labels any of the paths in `label_prob` has emitted since its last blank.
+    run_length: usize,
+    /// The number of labels in the labelling of `node`.
+    length: usize,
 }
 
-impl SearchPoint {
+impl<P: Probability> SearchPoint<P> {
     /// The total probability of the labelling so far.
     ///
     /// This sums the probabilities of the paths with and without leading blank labels.
-    fn probability(&self) -> f32 {
+    fn probability(&self) -> P {
         self.label_prob + self.gap_prob
     }
 }
 
 /// Convert probability into an ASCII encoded phred quality score between 0 and 40.
+///
+/// A `qscale` or `qbias` that takes the score outside of that is clamped to between 0 and 93
+/// (`~`), the range Phred+33 can encode.
 pub fn phred(prob: f32, qscale: f32, qbias: f32) -> char {
     let max = 1e-4;
     let p = if 1.0 - prob < max { max } else { 1.0 - prob };
     let q = -10.0 * p.log10() * qscale + qbias;
-    std::char::from_u32(q.round() as u32 + 33).unwrap()
+    // the cast turns a NaN score into 0
+    char::from(q.round().clamp(0.0, 93.0) as u8 + 33)
+}
+
+/// Format a sequence and its per-character quality scores as a FASTQ record.
+///
+/// Each entry of `quals` is the probability that the character of `seq` at the same position is
+/// right (as returned by `beam_search_with_qualities`). It is written as the Phred+33 character
+/// for `-10 * log10(1 - p)`, rounded and capped at 40 (so a probability of 1.0 gives the maximum
+/// quality, `I`), which is `phred(p, 1.0, 0.0)`.
+///
+/// # Panics
+///
+/// If `quals` does not have one entry for each character of `seq`.
+pub fn to_fastq(read_id: &str, seq: &str, quals: &[f32]) -> String {
+    assert_eq!(
+        seq.chars().count(),
+        quals.len(),
+        "there must be one quality score for each character of the sequence"
+    );
+    let qual: String = quals.iter().map(|&p| phred(p, 1.0, 0.0)).collect();
+    format!("@{}\n{}\n+\n{}\n", read_id, seq, qual)
 }
 
 pub fn crf_beam_search<D: Data<Elem = f32>>(
@@ -42,19 +234,19 @@ pub fn crf_beam_search<D: Data<Elem = f32>>(
     beam_size: usize,
     beam_cut_threshold: f32,
 ) -> Result<(String, Vec<usize>), SearchError> {
-    assert!(!alphabet.is_empty());
-    assert!(!network_output.is_empty());
-    assert_eq!(network_output.ndim(), 3);
-    assert_eq!(network_output.shape()[2], alphabet.len());
+    let (init, init_prob) = check_crf_args(network_output, init_state, alphabet)?;
+    check_beam_size_and_threshold(beam_size, beam_cut_threshold, alphabet.len())?;
     let n_state = network_output.shape()[1];
     let n_base = network_output.shape()[2] - 1;
 
     let mut suffix_tree = SuffixTree::new(n_base);
     let mut beam = vec![SearchPoint {
         node: ROOT_NODE,
-        label_prob: *init_state.max().unwrap(),
+        label_prob: init_prob,
         gap_prob: init_state[0],
-        state: init_state.argmax().unwrap(),
+        state: init,
+        run_length: 0,
+        length: 0,
     }];
     let mut next_beam = Vec::new();
 
@@ -66,6 +258,7 @@ pub fn crf_beam_search<D: Data<Elem = f32>>(
             state,
             label_prob,
             gap_prob,
+            ..
         } in &beam
         {
             let pr = probs.slice(s![state, ..]);
@@ -73,10 +266,12 @@ pub fn crf_beam_search<D: Data<Elem = f32>>(
             // add N to beam
             if pr[0] > beam_cut_threshold {
                 next_beam.push(SearchPoint {
-                    node: node,
-                    state: state,
+                    node,
+                    state,
                     label_prob: 0.0,
                     gap_prob: (label_prob + gap_prob) * pr[0],
+                    run_length: 0,
+                    length: 0,
                 });
             }
 
@@ -94,13 +289,15 @@ pub fn crf_beam_search<D: Data<Elem = f32>>(
                     gap_prob: 0.0,
                     label_prob: (label_prob + gap_prob) * pr_b,
                     state: (state * n_base) % n_state + (label),
+                    run_length: 0,
+                    length: 0,
                 });
             }
         }
 
-        std::mem::swap(&mut beam, &mut next_beam);
+        core::mem::swap(&mut beam, &mut next_beam);
 
-        const DELETE_MARKER: i32 = i32::min_value();
+        const DELETE_MARKER: i32 = i32::MIN;
         beam.sort_by_key(|x| x.node);
         let mut last_key = DELETE_MARKER;
         let mut last_key_pos = 0;
@@ -118,423 +315,7011 @@ pub fn crf_beam_search<D: Data<Elem = f32>>(
 
         beam.retain(|x| x.node != DELETE_MARKER);
         let mut has_nans = false;
-        beam.sort_unstable_by(|a, b| {
-            (b.probability())
-                .partial_cmp(&(a.probability()))
-                .unwrap_or_else(|| {
-                    has_nans = true;
-                    std::cmp::Ordering::Equal // don't really care
-                })
+        beam.sort_unstable_by(|a, b| match b.probability().partial_cmp(&a.probability()) {
+            Some(Ordering::Equal) => suffix_tree.compare_labellings(a.node, b.node),
+            Some(ordering) => ordering,
+            None => {
+                has_nans = true;
+                nan_order(&a.probability(), &b.probability())
+            }
         });
         if has_nans {
-            return Err(SearchError::IncomparableValues);
+            return Err(SearchError::incomparable_row(idx, probs.iter()));
         }
         beam.truncate(beam_size);
         if beam.is_empty() {
             // we've run out of beam (probably the threshold is too high)
-            return Err(SearchError::RanOutOfBeam);
+            return Err(SearchError::RanOutOfBeam { at_timestep: idx });
         }
         let top = beam[0].probability();
-        for mut x in &mut beam {
+        for x in &mut beam {
             x.label_prob /= top;
             x.gap_prob /= top;
         }
     }
 
+    // the tree gives the labels from the last to the first
     let mut path = Vec::new();
-    let mut sequence = String::new();
-
+    let mut tokens = Vec::new();
     if beam[0].node != ROOT_NODE {
         for (label, &time) in suffix_tree.iter_from(beam[0].node) {
             path.push(time);
-            sequence.push_str(&alphabet[label + 1].chars().rev().collect::<String>());
+            tokens.push(alphabet[label + 1].as_str());
         }
     }
 
     path.reverse();
-    Ok((sequence.chars().rev().collect::<String>(), path))
+    Ok((tokens.iter().rev().copied().collect(), path))
 }
 
-pub fn beam_search<D: Data<Elem = f32>>(
+/// Check that every value in `network_output` is finite.
+///
+/// The searches only notice NaNs when they fail to compare two search points, which may be a long
+/// way into the search. This scans the whole of `network_output` first, and reports the first
+/// value that is NaN or infinite as `SearchError::IncomparableValues`.
+pub fn check_finite<E: Float, D: Data<Elem = E>>(
+    network_output: &ArrayBase<D, Ix2>,
+) -> Result<(), SearchError> {
+    for (row, pr) in network_output.outer_iter().enumerate() {
+        if let Some(col) = pr.iter().position(|x| !x.is_finite()) {
+            return Err(SearchError::IncomparableValues { row, col });
+        }
+    }
+    Ok(())
+}
+
+/// How far the sum of a row of network output can be from 1.0 for `validate_inputs` (and
+/// `BeamSearchOptions::check_normalized`) to accept it.
+pub const ROW_SUM_TOLERANCE: f32 = 1e-3;
+
+/// Check the arguments of a `beam_search` without decoding.
+///
+/// This runs the same checks on the arguments as `beam_search` (that `network_output` matches
+/// `alphabet`, `beam_size` is at least 1 and `beam_cut_threshold` is in range), and also scans
+/// `network_output` to check that every value is finite (as `check_finite` does) and that each
+/// row sums to within `ROW_SUM_TOLERANCE` of 1.0. It returns the first problem found. The scans
+/// take time proportional to the size of `network_output`, so `beam_search` leaves them out: NaNs
+/// are only reported there when the search trips over them, and rows are not checked at all.
+///
+/// This uses the other default options of `beam_search` (such as a `blank_id` of 0).
+pub fn validate_inputs<D: Data<Elem = f32>>(
     network_output: &ArrayBase<D, Ix2>,
     alphabet: &[String],
     beam_size: usize,
     beam_cut_threshold: f32,
-    collapse_repeats: bool,
-) -> Result<(String, Vec<usize>, f32), SearchError> {
-    // alphabet size minus the blank label
-    let alphabet_size = alphabet.len() - 1;
-
-    let mut suffix_tree = SuffixTree::new(alphabet_size);
-    let mut beam = vec![SearchPoint {
-        node: ROOT_NODE,
-        state: 0,
-        gap_prob: 1.0,
-        label_prob: 0.0,
-    }];
-    let mut next_beam = Vec::new();
-    for (idx, pr) in network_output.outer_iter().enumerate() {
-        next_beam.clear();
-
-        for &SearchPoint {
-            node,
-            label_prob,
-            gap_prob,
-            state,
-        } in &beam
-        {
-            let tip_label = suffix_tree.label(node);
-            // add N to beam
-            if pr[0] > beam_cut_threshold {
-                next_beam.push(SearchPoint {
-                    node: node,
-                    state: state,
-                    label_prob: 0.0,
-                    gap_prob: (label_prob + gap_prob) * pr[0],
-                });
-            }
-
-            for (label, &pr_b) in pr.iter().skip(1).enumerate() {
-                if pr_b < beam_cut_threshold {
-                    continue;
-                }
-
-                if collapse_repeats && Some(label) == tip_label {
-                    next_beam.push(SearchPoint {
-                        node: node,
-                        label_prob: label_prob * pr_b,
-                        gap_prob: 0.0,
-                        state: state,
-                    });
-                    let new_node_idx = suffix_tree.get_child(node, label).or_else(|| {
-                        if gap_prob > 0.0 {
-                            Some(suffix_tree.add_node(node, label, idx))
-                        } else {
-                            None
-                        }
-                    });
-
-                    if let Some(idx) = new_node_idx {
-                        next_beam.push(SearchPoint {
-                            node: idx,
-                            state: state,
-                            label_prob: gap_prob * pr_b,
-                            gap_prob: 0.0,
-                        });
-                    }
-                } else {
-                    let new_node_idx = suffix_tree
-                        .get_child(node, label)
-                        .unwrap_or_else(|| suffix_tree.add_node(node, label, idx));
-
-                    next_beam.push(SearchPoint {
-                        node: new_node_idx,
-                        state: state,
-                        label_prob: (label_prob + gap_prob) * pr_b,
-                        gap_prob: 0.0,
-                    });
-                }
-            }
+) -> Result<(), SearchError> {
+    let options = BeamSearchOptions::new()
+        .beam_size(beam_size)
+        .beam_cut_threshold(beam_cut_threshold)
+        .check_finite(true);
+    check_beam_args(
+        network_output,
+        alphabet,
+        &options,
+        beam_cut_threshold,
+        false,
+    )?;
+    for (row, pr) in network_output.outer_iter().enumerate() {
+        let sum = pr.iter().fold(0.0, |acc, x| acc + x);
+        if (sum - 1.0).abs() > ROW_SUM_TOLERANCE {
+            return Err(SearchError::RowSumMismatch { row, sum });
         }
-        std::mem::swap(&mut beam, &mut next_beam);
+    }
+    Ok(())
+}
 
-        const DELETE_MARKER: i32 = i32::min_value();
-        beam.sort_by_key(|x| x.node);
-        let mut last_key = DELETE_MARKER;
-        let mut last_key_pos = 0;
-        for i in 0..beam.len() {
-            let beam_item = beam[i];
-            if beam_item.node == last_key {
-                beam[last_key_pos].label_prob += beam_item.label_prob;
-                beam[last_key_pos].gap_prob += beam_item.gap_prob;
-                beam[i].node = DELETE_MARKER;
+/// Replace each row of `network_output` with its softmax, for networks that output logits.
+///
+/// The row's maximum is subtracted before taking the `exp` of each value, so large logits do not
+/// overflow. With the `fastexp` feature, the fast approximation of `exp` is used (its arguments
+/// are all at most 0 here, where it is most accurate). Values of negative infinity become 0, but a
+/// row with a NaN or positive infinity in it (or that is all negative infinity) becomes all NaN,
+/// which the searches report as `SearchError::IncomparableValues`.
+pub fn softmax_rows<D: DataMut<Elem = f32>>(network_output: &mut ArrayBase<D, Ix2>) {
+    for mut row in network_output.outer_iter_mut() {
+        // unlike `f32::max`, this keeps a NaN, and `fastexp` would turn its NaN result into 0
+        let max = row.iter().fold(f32::NEG_INFINITY, |acc, &x| {
+            if x > acc || x.is_nan() {
+                x
             } else {
-                last_key_pos = i;
-                last_key = beam_item.node;
+                acc
             }
-        }
-
-        beam.retain(|x| x.node != DELETE_MARKER);
-        let mut has_nans = false;
-        beam.sort_unstable_by(|a, b| {
-            (b.probability())
-                .partial_cmp(&(a.probability()))
-                .unwrap_or_else(|| {
-                    has_nans = true;
-                    std::cmp::Ordering::Equal // don't really care
-                })
         });
-        if has_nans {
-            return Err(SearchError::IncomparableValues);
+        if !max.is_finite() {
+            row.fill(f32::NAN);
+            continue;
         }
-        beam.truncate(beam_size);
-        if beam.is_empty() {
-            // we've run out of beam (probably the threshold is too high)
-            return Err(SearchError::RanOutOfBeam);
-        }
-
-        // Probability normalisation is removed because we want to 
-        // output raw probability scores.
-        // NOTE: this provides a risk of float underflow as we are
-        // multiplying several numbers < 0. 
-        // We can get round this by using logs and adding log(p) to scores
-        // but that would be slower. 
-
-        // let top = beam[0].probability();
-        // for mut x in &mut beam {
-        //     x.label_prob /= top;
-        //     x.gap_prob /= top;
-        // }
-    }
-    // The probabilities of the beam paths are summed (using normalize_denominator) 
-    // and used to divide the best path's probability to normalize the outputted 
-    // probabilities to range within [0,1]. This fixes the problem of outputting
-    // very low probability values (like 1e-5) which is not easily interpretable
-    // as confidence scores, which is one of the main use cases of this value.
-    let mut normalize_denominator :f32 = 0.00;
-    for mut x in &mut beam  {
-        normalize_denominator += x.probability()
-    }
-    let mut path = Vec::new();
-    let mut sequence = String::new();
-    if beam[0].node != ROOT_NODE {
-        for (label, &time) in suffix_tree.iter_from(beam[0].node) {
-            path.push(time);
-            sequence.push_str(&alphabet[label + 1].chars().rev().collect::<String>());
+        let mut sum = 0.0;
+        for x in row.iter_mut() {
+            *x = DefaultExp::exp(*x - max);
+            sum += *x;
         }
+        row.mapv_inplace(|x| x / sum);
     }
-    path.reverse();
-    Ok((sequence.chars().rev().collect::<String>(), path, beam[0].probability() / normalize_denominator))
 }
 
-fn find_max(
-    acc: Option<(usize, f32)>,
-    elem_idx: usize,
-    elem_val: &f32,
-) -> FoldWhile<Option<(usize, f32)>> {
-    match acc {
-        Some((_, val)) => {
-            if *elem_val > val {
-                FoldWhile::Continue(Some((elem_idx, *elem_val)))
-            } else {
-                FoldWhile::Continue(acc)
-            }
-        }
-        None => FoldWhile::Continue(Some((elem_idx, *elem_val))),
+/// The index on the label axis of `network_output` for a (non-blank) label in the suffix tree.
+fn label_column(label: usize, blank_id: usize) -> usize {
+    if label < blank_id {
+        label
+    } else {
+        label + 1
     }
 }
 
-pub fn viterbi_search<D: Data<Elem = f32>>(
-    network_output: &ArrayBase<D, Ix2>,
-    alphabet: &[String],
-    qstring: bool,
-    qscale: f32,
-    qbias: f32,
-    collapse_repeats: bool,
-) -> Result<(String, Vec<usize>), SearchError> {
-    assert!(!alphabet.is_empty());
-    assert!(!network_output.is_empty());
-    assert_eq!(network_output.ndim(), 2);
-    assert_eq!(alphabet.len(), network_output.shape()[1]);
-
-    let mut path = Vec::new();
-    let mut quality = String::new();
-    let mut sequence = String::new();
-
-    let mut last_label = None;
-    let mut label_prob_count = 0;
-    let mut label_prob_total = 0.0;
-
-    for (idx, pr) in network_output.outer_iter().enumerate() {
-        let (label, prob) = Zip::indexed(pr)
-            .fold_while(None, find_max)
-            .into_inner()
-            .unwrap(); // only an empty network_output could give us None
+/// How the beam search decides whether a label at consecutive timesteps is one label or several.
+///
+/// The search only consults this for a label straight after another, with no blank between them.
+/// A label after a blank is always a new label of the labelling, and blanks are always removed.
+/// To see the path itself, with nothing removed, use `greedy_frames` or the path of
+/// `beam_search_with_path` with `frame_tokens`.
+#[derive(Clone, Copy, Debug)]
+pub enum Collapse {
+    /// A label straight after the same label continues it, so repeats are collapsed into one.
+    /// This is the standard CTC rule, and the default.
+    Repeats,
+    /// Every label is a new label of the labelling, so only blanks are removed.
+    Never,
+    /// `merges(last, next)` says whether the label in column `next` of `network_output`, straight
+    /// after a labelling whose last label is in column `last`, continues that label rather than
+    /// being a new one. Labels that continue the last label are not added to the labelling.
+    ///
+    /// The search keeps one entry for each labelling, adding up the probabilities of all the paths
+    /// that give it, so for its merging to be correct, `merges` must:
+    ///
+    /// - Depend only on its arguments, and give the same answer every time it is called with them.
+    /// - Be read as comparing `next` with the last label of the labelling, not with the label at
+    ///   the timestep before: after `b` continues `a`, the next label is compared with `a` again.
+    ///
+    /// Blank columns are never passed to it. With `merges(a, a)` false, a label lasting several
+    /// timesteps is that many labels, as with `Never`.
+    Custom(fn(usize, usize) -> bool),
+}
 
-        if label != 0 && (!collapse_repeats || last_label != Some(label)) {
-            if label_prob_count > 0 {
-                quality.push(phred(
-                    label_prob_total / (label_prob_count as f32),
-                    qscale,
-                    qbias,
-                ));
-                label_prob_total = 0.0;
-                label_prob_count = 0;
-            }
+impl Default for Collapse {
+    fn default() -> Self {
+        Collapse::Repeats
+    }
+}
 
-            sequence.push_str(&alphabet[label]);
-            path.push(idx);
+impl Collapse {
+    /// Whether the label in column `next`, straight after a label in column `last`, continues it.
+    fn merges(&self, last: usize, next: usize) -> bool {
+        match self {
+            Collapse::Repeats => last == next,
+            Collapse::Never => false,
+            Collapse::Custom(merges) => merges(last, next),
         }
+    }
+}
 
-        if label != 0 {
-            label_prob_total += prob;
-            label_prob_count += 1;
-        }
+/// How the beam search orders labellings with exactly the same probability.
+///
+/// Labellings are compared as sequences of columns of `network_output` (indices into the
+/// alphabet), from the first label to the last. Because ties are always broken the same way, the
+/// labellings a search keeps, and the one it returns, only depend on the probabilities it
+/// computes.
+#[derive(Clone, Copy, Debug)]
+pub enum TieBreak {
+    /// The lexicographically smaller labelling comes first, so a labelling comes before any
+    /// labelling it is a prefix of. This is the default.
+    Lexicographic,
+    /// `cmp(a, b)` returning `Ordering::Less` puts labelling `a` before labelling `b`. Labellings
+    /// it finds equal are ordered lexicographically.
+    ///
+    /// `cmp` must be a total order, as `Ord::cmp` is: consistent with itself, antisymmetric and
+    /// transitive. The beam is sorted with `sort_unstable_by`, which may panic (from Rust 1.81)
+    /// if it is not, and otherwise leaves the beam in an unspecified order.
+    Custom(fn(&[usize], &[usize]) -> Ordering),
+}
 
-        last_label = Some(label);
+impl Default for TieBreak {
+    fn default() -> Self {
+        TieBreak::Lexicographic
     }
+}
 
-    if label_prob_count > 0 {
-        quality.push(phred(
-            label_prob_total / (label_prob_count as f32),
-            qscale,
-            qbias,
-        ));
+impl TieBreak {
+    fn compare(&self, a: &[usize], b: &[usize]) -> Ordering {
+        match self {
+            TieBreak::Lexicographic => a.cmp(b),
+            TieBreak::Custom(cmp) => cmp(a, b).then_with(|| a.cmp(b)),
+        }
     }
+}
 
-    if qstring {
-        sequence.push_str(&quality);
+/// Replace `columns` with the columns of the labels of the labelling ending at `node`, from the
+/// first label to the last.
+fn labelling_columns(
+    suffix_tree: &SuffixTree<usize>,
+    node: i32,
+    blank_id: usize,
+    columns: &mut Vec<usize>,
+) {
+    columns.clear();
+    if node != ROOT_NODE {
+        columns.extend(
+            suffix_tree
+                .iter_from_no_data(node)
+                .map(|label| label_column(label, blank_id)),
+        );
     }
-
-    Ok((sequence, path))
+    columns.reverse();
 }
 
-pub fn crf_greedy_search<D: Data<Elem = f32>>(
-    network_output: &ArrayBase<D, Ix3>,
-    init_state: &ArrayBase<D, Ix1>,
-    alphabet: &[String],
-    qstring: bool,
-    qscale: f32,
-    qbias: f32,
-) -> Result<(String, Vec<usize>), SearchError> {
-    assert!(!alphabet.is_empty());
-    assert!(!network_output.is_empty());
-    assert_eq!(network_output.ndim(), 3);
-    assert_eq!(network_output.shape()[2], alphabet.len());
+/// Order the probabilities `a` and `b` of two search points when they cannot be compared, because
+/// one or both is NaN.
+///
+/// The sorts of the beams stop at the first NaN, but still need a total order to sort with (the
+/// standard library's sorts may panic without one). This puts NaNs after every other probability,
+/// and treats them as equal to each other.
+pub(crate) fn nan_order<P: PartialOrd>(a: &P, b: &P) -> Ordering {
+    let is_nan = |x: &P| x.partial_cmp(x).is_none();
+    is_nan(a).cmp(&is_nan(b))
+}
 
-    let n_state = network_output.shape()[1] as i32;
-    let n_base = network_output.shape()[2] as i32 - 1;
+/// The parameters of a beam search decode.
+///
+/// Start from `BeamSearchOptions::new()` (or `Default::default()`) and set the parameters that
+/// differ from their defaults:
+///
+/// ```
+/// use fast_ctc_decode::search::BeamSearchOptions;
+///
+/// let options = BeamSearchOptions::new()
+///     .beam_size(32)
+///     .beam_cut_threshold(0.1)
+///     .blank_id(0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BeamSearchOptions {
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse: Collapse,
+    blank_id: usize,
+    extra_blank_ids: Vec<usize>,
+    check_finite: bool,
+    check_normalized: bool,
+    normalize_rows: bool,
+    max_symbols_per_frame: Option<usize>,
+    blank_bias: f32,
+    beam_cut_relative: Option<f32>,
+    keep_best_on_empty: bool,
+    tie_break: TieBreak,
+    allowed_transitions: Option<Array2<bool>>,
+    min_length: usize,
+    max_length: Option<usize>,
+    repeat_label_floor: f32,
+    time_axis: usize,
+    merge_across_blank: bool,
+    emit_at_first_row: bool,
+    max_tree_nodes: Option<usize>,
+    label_prior: Option<Vec<f32>>,
+    label_prior_weight: f32,
+}
 
-    let mut path = Vec::new();
-    let mut quality = String::new();
-    let mut sequence = String::new();
-    let mut state = init_state.argmax().unwrap() as i32;
+impl Default for BeamSearchOptions {
+    fn default() -> Self {
+        BeamSearchOptions {
+            beam_size: 5,
+            beam_cut_threshold: 0.0,
+            collapse: Collapse::Repeats,
+            blank_id: 0,
+            extra_blank_ids: Vec::new(),
+            check_finite: false,
+            check_normalized: false,
+            normalize_rows: false,
+            max_symbols_per_frame: None,
+            blank_bias: 0.0,
+            beam_cut_relative: None,
+            keep_best_on_empty: false,
+            tie_break: TieBreak::Lexicographic,
+            allowed_transitions: None,
+            min_length: 0,
+            max_length: None,
+            repeat_label_floor: 0.0,
+            time_axis: 0,
+            merge_across_blank: false,
+            emit_at_first_row: true,
+            max_tree_nodes: None,
+            label_prior: None,
+            label_prior_weight: 1.0,
+        }
+    }
+}
 
-    for (idx, pr) in network_output.axis_iter(Axis(0)).enumerate() {
-        let label = pr.slice(s![state, ..]).argmax().unwrap();
+impl BeamSearchOptions {
+    /// The default options: a beam of 5, no beam cut threshold, repeats collapsed and the blank
+    /// label in column 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        if label > 0 {
-            path.push(idx);
-            sequence.push_str(&alphabet[label]);
-            let prob = *pr.slice(s![state, ..]).max().unwrap();
-            quality.push(phred(prob, qscale, qbias));
-            state = (state * n_base) % n_state + (label as i32 - 1);
-        }
+    /// How many search points should be kept at each step. Higher numbers are less likely to
+    /// discard the true labelling, but also make it slower and more memory intensive. Must be at
+    /// least 1.
+    ///
+    /// When more labellings than this are left after a step, the ones kept are the most probable,
+    /// and where several have exactly the probability of the last one kept, the first of them in
+    /// the order of `tie_break`. The labellings are fully sorted this way (not just partitioned),
+    /// so which ones survive never depends on the order they were added in.
+    pub fn beam_size(mut self, beam_size: usize) -> Self {
+        self.beam_size = beam_size;
+        self
     }
 
-    if qstring {
-        sequence.push_str(&quality);
+    /// Ignore any entries in `network_output` below this value. Must be at least 0.0, and less
+    /// than `1/len(alphabet)`.
+    ///
+    /// This is a probability for every search that takes options, including
+    /// `beam_search_log_with`: see `beam_cut_threshold_log` to give it as a natural log instead.
+    pub fn beam_cut_threshold(mut self, beam_cut_threshold: f32) -> Self {
+        self.beam_cut_threshold = beam_cut_threshold;
+        self
     }
 
-    Ok((sequence, path))
-}
+    /// Set `beam_cut_threshold` from its natural log, for network outputs in log space.
+    ///
+    /// The threshold is stored as `exp(ln_beam_cut_threshold)`, so this must be less than
+    /// `ln(1/len(alphabet))` (and `f32::NEG_INFINITY` is no threshold, as is anything below about
+    /// -103, where the `exp` is 0.0). `beam_search_log_with` takes the log of it again, so it cuts
+    /// the log space entries of `network_output` below `ln_beam_cut_threshold` (to within a unit
+    /// in the last place).
+    pub fn beam_cut_threshold_log(self, ln_beam_cut_threshold: f32) -> Self {
+        self.beam_cut_threshold(ln_beam_cut_threshold.exp())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    //use test::Bencher;
+    /// Whether repeated labels with no blank between them are collapsed into one (the default).
+    ///
+    /// Turn this off for models trained without the repeat rule: only blanks are then removed, so
+    /// a label at consecutive timesteps is that many labels of the labelling. Labellings that
+    /// differ only in repeats are kept apart in the beam, rather than merged.
+    ///
+    /// This is a shorthand for `collapse` with `Collapse::Repeats` or `Collapse::Never`.
+    pub fn collapse_repeats(mut self, collapse_repeats: bool) -> Self {
+        self.collapse = if collapse_repeats {
+            Collapse::Repeats
+        } else {
+            Collapse::Never
+        };
+        self
+    }
+
+    /// How labels at consecutive timesteps are collapsed into the labelling. Defaults to
+    /// `Collapse::Repeats`.
+    ///
+    /// With `Collapse::Custom`, the alignments of `beam_search_with_path` and
+    /// `beam_search_with_spans` follow the rule for a label straight after itself (whether it can
+    /// last more than one timestep, and whether two of the same label need a blank between them),
+    /// but only use each label's own column for the timesteps it covers.
+    pub fn collapse(mut self, collapse: Collapse) -> Self {
+        self.collapse = collapse;
+        self
+    }
+
+    /// Whether a blank between two labels that `collapse` merges still merges them (default
+    /// false).
+    ///
+    /// The standard CTC rule keeps `AbA` (with `b` the blank) as `AA`, since the blank separates
+    /// the repeats. With this set, the search treats it as `A`, as it does `AA`: a label that would
+    /// continue the last label of a labelling straight after it also continues it after blanks, so
+    /// `AbbA` and `AAbAA` read as `A` too. Such labels can then only be added to a labelling after a
+    /// different label. It has no effect with `Collapse::Never`.
+    ///
+    /// This only changes which labellings the beam search treats as the same. The alignments of
+    /// `beam_search_with_path` and `beam_search_with_spans`, `score_labelling` and
+    /// `forced_alignment` still follow the standard rule.
+    pub fn merge_across_blank(mut self, merge_across_blank: bool) -> Self {
+        self.merge_across_blank = merge_across_blank;
+        self
+    }
+
+    /// Whether a path can emit a label at the first row of `network_output` (default true).
+    ///
+    /// The search starts from the empty labelling, with the probability it would have after a
+    /// blank (so a label there is never a repeat), and so by default a label in the first row is
+    /// emitted straight away, with a timepoint of 0, as in the standard CTC rule. With this set to
+    /// false, every path starts with a blank: the first row only adds to the probability of the
+    /// empty labelling, and the first label can be emitted at the second row at the earliest.
+    /// This is for networks whose first output row comes before any signal, where a label there
+    /// is an artefact of the padding.
+    ///
+    /// The alignments of `beam_search_with_path` and `beam_search_with_spans`, `score_labelling`
+    /// and `forced_alignment` follow the same rule. A streaming search only applies it to the
+    /// first row of the whole network output, not of each piece.
+    pub fn emit_at_first_row(mut self, emit_at_first_row: bool) -> Self {
+        self.emit_at_first_row = emit_at_first_row;
+        self
+    }
+
+    /// The index of the blank label on the inner axis of `network_output`.
+    pub fn blank_id(mut self, blank_id: usize) -> Self {
+        self.blank_id = blank_id;
+        self
+    }
+
+    /// Other columns of `network_output` to treat as blanks, as well as `blank_id` (such as a
+    /// padding label).
+    ///
+    /// At each timestep, the probabilities of all the blank columns are added up and used as the
+    /// probability of a blank, so a search gives the same labelling as it would if those columns
+    /// were merged into the `blank_id` column beforehand. Moving between blank columns does not
+    /// emit anything, and a label on each side of them is not collapsed. Paths through any of
+    /// them are reported as `blank_id`. Each must be less than `len(alphabet)`; the default
+    /// is none.
+    pub fn extra_blank_ids(mut self, extra_blank_ids: &[usize]) -> Self {
+        self.extra_blank_ids = extra_blank_ids.to_vec();
+        self.extra_blank_ids.sort_unstable();
+        self.extra_blank_ids.dedup();
+        self
+    }
+
+    /// Whether to run `check_finite` on `network_output` before decoding, so that NaNs and
+    /// infinities are reported even where the search would not trip over them.
+    pub fn check_finite(mut self, check_finite: bool) -> Self {
+        self.check_finite = check_finite;
+        self
+    }
+
+    /// Whether to check that each row of `network_output` sums to within `ROW_SUM_TOLERANCE` of
+    /// 1.0, failing with `SearchError::RowSumMismatch` for the first that does not.
+    ///
+    /// The check is made on the probabilities the search uses, so for example log space inputs
+    /// are exponentiated and quantized inputs dequantized first. It catches inputs such as logits
+    /// that have not been through a softmax, which otherwise give poor results without any error.
+    /// It adds a pass over each row, so it is off by default.
+    pub fn check_normalized(mut self, check_normalized: bool) -> Self {
+        self.check_normalized = check_normalized;
+        self
+    }
+
+    /// Whether to divide each row of `network_output` by its sum before searching it, for
+    /// inputs that are scores that are not negative but do not sum to 1.0.
+    ///
+    /// Unlike `softmax_rows`, this does not take the `exp` of the values, and it is done as each
+    /// row is searched, without a copy of `network_output`. The `beam_cut_threshold` (and the
+    /// rest of the options) apply to the divided values, and so do the qualities of
+    /// `beam_search_qualities_with` and the score of `score_labelling`. `check_normalized` checks
+    /// the rows as they are given, so it should be left off. A row that sums to 0.0 cannot be
+    /// divided, and fails with `SearchError::RowSumMismatch`.
+    pub fn normalize_rows(mut self, normalize_rows: bool) -> Self {
+        self.normalize_rows = normalize_rows;
+        self
+    }
+
+    /// The factor `normalize_rows` multiplies the values of row `row` (`pr`) by, which is 1.0 if
+    /// it is not set.
+    fn row_scale(&self, row: usize, pr: ArrayView1<'_, f32>) -> Result<f32, SearchError> {
+        if !self.normalize_rows {
+            return Ok(1.0);
+        }
+        let sum = pr.iter().fold(0.0, |acc, x| acc + x);
+        if sum == 0.0 {
+            return Err(SearchError::RowSumMismatch { row, sum });
+        }
+        Ok(1.0 / sum)
+    }
+
+    /// Limit how many labels a path can emit in a row, with no blank between them.
+    ///
+    /// Each timestep emits at most one label, so this is the number of consecutive timesteps
+    /// that can each emit a new label. Ways of extending a labelling that would go over the limit
+    /// are dropped before the beam is cut down to `beam_size`. Repeats of the last label that are
+    /// collapsed into it do not count as new labels. Must be at least 1 if set; the default of
+    /// `None` has no limit.
+    ///
+    /// The search keeps one count for all the paths that give the same labelling, using the
+    /// lowest count among them, so some paths may get past the limit before they are dropped.
+    pub fn max_symbols_per_frame(mut self, max_symbols_per_frame: Option<usize>) -> Self {
+        self.max_symbols_per_frame = max_symbols_per_frame;
+        self
+    }
+
+    /// Add this to the natural log of the blank label's probability at every timestep.
+    ///
+    /// This multiplies the probability of every blank by `exp(blank_bias)`, so negative values
+    /// make blanks less likely (and labels more likely to be emitted), and positive values make
+    /// them more likely. The beam cut threshold is compared with the biased blank probability.
+    /// Must be finite; defaults to 0.0, which leaves the probabilities unchanged.
+    pub fn blank_bias(mut self, blank_bias: f32) -> Self {
+        self.blank_bias = blank_bias;
+        self
+    }
+
+    /// A prior over the labels, such as the expected base composition of a genome, with an entry
+    /// for each column of `network_output`.
+    ///
+    /// Each time a path emits a label (not a blank or a collapsed repeat),
+    /// `label_prior_weight * ln(label_prior[column])` is added to its log probability, in the
+    /// same way as a language model score (see `beam_search_lm`), but with no context. With a
+    /// language model as well, both are added, so the prior can make up for a model trained on
+    /// text of a different composition. `blank_bias` works the other way round: it scales the
+    /// blank at every timestep of every path, where this scales each label emitted, so it
+    /// changes how many labels are emitted rather than which. Neither affects which extensions
+    /// the beam cut threshold prunes, which only looks at the network output.
+    ///
+    /// The entries for blanks are ignored. The rest must be finite and greater than 0.0, but need
+    /// not add up to 1.0: multiplying them all by `c` multiplies the probability of every
+    /// labelling of `n` labels by `c^(label_prior_weight * n)`, which favours longer labellings
+    /// for `c` above 1.0, and shorter ones below, so a prior that does add up to 1.0 (over the
+    /// labels that are not blanks) also makes every labelling less likely the longer it is. The
+    /// default of `None` has no prior.
+    pub fn label_prior(mut self, label_prior: Option<Vec<f32>>) -> Self {
+        self.label_prior = label_prior;
+        self
+    }
+
+    /// The weight of `label_prior`, which must be finite. Defaults to 1.0; 0.0 ignores the prior.
+    pub fn label_prior_weight(mut self, label_prior_weight: f32) -> Self {
+        self.label_prior_weight = label_prior_weight;
+        self
+    }
+
+    /// After each timestep, also drop labellings whose log probability is more than this much
+    /// below that of the most probable one.
+    ///
+    /// This applies as well as `beam_size`, so the beam is cut down to at most `beam_size`
+    /// labellings, and fewer if some are less than `exp(-beam_cut_relative)` times as likely as
+    /// the best. Must be at least 0.0 if set; the default of `None` keeps `beam_size` labellings.
+    pub fn beam_cut_relative(mut self, beam_cut_relative: Option<f32>) -> Self {
+        self.beam_cut_relative = beam_cut_relative;
+        self
+    }
+
+    /// When `beam_cut_threshold` would prune every extension of the beam at a timestep, keep the
+    /// single most probable one and carry on, rather than failing with
+    /// `SearchError::RanOutOfBeam`.
+    ///
+    /// This keeps one bad read from aborting a batch, but the labellings of the reads it rescues
+    /// are likely to be of lower quality. Off by default.
+    pub fn keep_best_on_empty(mut self, keep_best_on_empty: bool) -> Self {
+        self.keep_best_on_empty = keep_best_on_empty;
+        self
+    }
+
+    /// How labellings with exactly the same probability are ordered, both when the beam is cut
+    /// down to size and when choosing the labelling to return. Defaults to
+    /// `TieBreak::Lexicographic`.
+    pub fn tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Only let the search emit a label if it is allowed to follow the label before it.
+    ///
+    /// `allowed[(prev, next)]` says whether the label in column `next` of `network_output` can
+    /// follow the label in column `prev`. The row of `blank_id` says which labels a labelling can
+    /// start with, and the rest of the blank row and column are ignored: a blank can always
+    /// follow, since it emits nothing. So, for example, clearing the column of a label keeps it
+    /// out of the labelling altogether.
+    ///
+    /// Extensions of the beam that would break these rules are dropped before the beam is cut
+    /// down to `beam_size`. With `collapse_repeats`, a repeat that is collapsed into the last
+    /// label emits nothing, so is always allowed, but the same label after a blank needs
+    /// `allowed[(label, label)]`. Nothing constrains how a labelling ends. Must be
+    /// `len(alphabet)` by `len(alphabet)`; the default of `None` allows every transition.
+    pub fn allowed_transitions(mut self, allowed_transitions: Option<Array2<bool>>) -> Self {
+        self.allowed_transitions = allowed_transitions;
+        self
+    }
+
+    /// Prefer labellings of at least this many labels.
+    ///
+    /// This does not prune anything during the search. At the end, the labelling returned is the
+    /// most probable one in the beam with at least `min_length` labels, or if there are none, the
+    /// most probable one of any length. Searches that return several labellings list the ones
+    /// that are long enough first. Defaults to 0, which has no effect.
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Drop any extension of the beam that would give a labelling more than this many labels.
+    ///
+    /// Labellings that have reached `max_length` can still be extended with blanks (and collapsed
+    /// repeats), so this never empties the beam. Must be at least `min_length` if set; the
+    /// default of `None` has no limit.
+    pub fn max_length(mut self, max_length: Option<usize>) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// The least probability a timestep gives to a labelling that stays on its last label.
+    ///
+    /// Staying on the last label (rather than emitting it again after a blank) uses the larger of
+    /// this and the probability `network_output` gives the label. Very peaky models put almost
+    /// all of a label's probability on a single timestep with blanks around it, so a label the
+    /// model spreads over two nearby spikes reads as two, lengthening homopolymers. A floor lets
+    /// a labelling stay on the label between the spikes, reading them as one.
+    ///
+    /// Only labels above the beam cut threshold are floored. Must be at least 0.0 and at most
+    /// 1.0; the default of 0.0 has no effect.
+    pub fn repeat_label_floor(mut self, repeat_label_floor: f32) -> Self {
+        self.repeat_label_floor = repeat_label_floor;
+        self
+    }
+
+    /// Which axis of `network_output` is time: 0 (the default) for an output with time on the
+    /// outer axis and labels on the inner axis, or 1 for one with labels on the outer axis.
+    ///
+    /// Outputs with labels on the outer axis are searched in place, stepping through each
+    /// timestep with a stride, so there is no need to transpose (and copy) them first. Timepoints
+    /// are always indices along the time axis, and the arrays returned by the searches (such as
+    /// the marginals of `beam_search_with_marginals`) always have time on the outer axis.
+    /// Rows given to `StreamingDecoder::push_row` are single timesteps either way.
+    pub fn time_axis(mut self, time_axis: usize) -> Self {
+        self.time_axis = time_axis;
+        self
+    }
+
+    /// Cap the number of nodes in the labelling tree (not counting the root), for a hard bound
+    /// on the memory a search can use.
+    ///
+    /// Before each row, if extending every entry of the beam with every label above the beam cut
+    /// threshold could take the tree past the cap, the nodes that the beam no longer needs are
+    /// evicted: those of the extensions that were cut from the beam at earlier rows. If that does
+    /// not leave enough room, the search fails with `SearchError::RanOutOfBeam`. The beam itself
+    /// is never cut, so the labelling and its probability are the same as without a cap. Its
+    /// timepoints can differ, though: a labelling that drops out of the beam and comes back later
+    /// gets a new node, with the timepoint of its return.
+    ///
+    /// The tree needs a node for each label of the labellings in the beam (which mostly share
+    /// their prefixes), so the cap has to allow for those, plus up to
+    /// `beam_size * (len(alphabet) - 1)` new nodes at each row. Evicting takes time proportional
+    /// to the size of the tree, so the more room there is beyond that, the less often it is done.
+    /// The nodes of a lattice's states (see `beam_search_lattice`) are never evicted. The default
+    /// of `None` has no cap.
+    pub fn max_tree_nodes(mut self, max_tree_nodes: Option<usize>) -> Self {
+        self.max_tree_nodes = max_tree_nodes;
+        self
+    }
+
+    /// The options given by the positional arguments of the `beam_search` functions.
+    fn from_args(
+        beam_size: usize,
+        beam_cut_threshold: f32,
+        collapse_repeats: bool,
+        blank_id: usize,
+    ) -> Self {
+        BeamSearchOptions::new()
+            .beam_size(beam_size)
+            .beam_cut_threshold(beam_cut_threshold)
+            .collapse_repeats(collapse_repeats)
+            .blank_id(blank_id)
+    }
+
+    /// A view of `network_output` with time on the outer axis, according to `time_axis`.
+    fn time_major<'a, D: Data>(
+        &self,
+        network_output: &'a ArrayBase<D, Ix2>,
+    ) -> ArrayView2<'a, D::Elem> {
+        let view = network_output.view();
+        if self.time_axis == 1 {
+            view.reversed_axes()
+        } else {
+            view
+        }
+    }
+
+    /// Whether `column` is `blank_id` or one of `extra_blank_ids`.
+    fn is_blank(&self, column: usize) -> bool {
+        column == self.blank_id || self.extra_blank_ids.binary_search(&column).is_ok()
+    }
+
+    /// Whether the label in `column` can follow the label in column `prev` (`None` at the start
+    /// of a labelling), according to `allowed_transitions`.
+    fn allows_transition(&self, prev: Option<usize>, column: usize) -> bool {
+        match &self.allowed_transitions {
+            Some(allowed) => allowed[(prev.unwrap_or(self.blank_id), column)],
+            None => true,
+        }
+    }
+
+    /// The probability of a blank at a timestep where `prob(column)` is the probability of each
+    /// column. The blank columns are added up in increasing order after `blank_id`.
+    fn blank_prob<P: Probability>(&self, prob: impl Fn(usize) -> P) -> P {
+        let mut total = prob(self.blank_id);
+        for &column in &self.extra_blank_ids {
+            if column != self.blank_id {
+                total += prob(column);
+            }
+        }
+        total
+    }
+}
+
+/// Check the arguments shared by the beam search functions.
+///
+/// `beam_cut_threshold` is used instead of the one in `options`, so that it can be given with the
+/// same precision as `network_output`.
+fn check_beam_args<T, D>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    beam_cut_threshold: T,
+    from_logspace: bool,
+) -> Result<(), SearchError>
+where
+    T: Float,
+    D: Data,
+    D::Elem: Input,
+{
+    if options.time_axis > 1 {
+        return Err(SearchError::InvalidTimeAxis);
+    }
+    if alphabet.is_empty() || alphabet.len() != network_output.shape()[1] {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    if options.blank_id >= alphabet.len()
+        || options
+            .extra_blank_ids
+            .iter()
+            .any(|&id| id >= alphabet.len())
+    {
+        return Err(SearchError::InvalidBlankId);
+    }
+    if options.beam_size == 0 {
+        return Err(SearchError::InvalidBeamSize);
+    }
+    if options.max_symbols_per_frame == Some(0) {
+        return Err(SearchError::InvalidMaxSymbolsPerFrame);
+    }
+    if !options.blank_bias.is_finite() {
+        return Err(SearchError::InvalidBlankBias);
+    }
+    if let Some(beam_cut_relative) = options.beam_cut_relative {
+        if beam_cut_relative.is_nan() || beam_cut_relative < 0.0 {
+            return Err(SearchError::InvalidBeamCutRelative);
+        }
+    }
+    if !(0.0..=1.0).contains(&options.repeat_label_floor) {
+        return Err(SearchError::InvalidRepeatLabelFloor);
+    }
+    if options
+        .max_length
+        .map_or(false, |max| max < options.min_length)
+    {
+        return Err(SearchError::InvalidLengthRange);
+    }
+    if let Some(allowed) = &options.allowed_transitions {
+        if allowed.dim() != (alphabet.len(), alphabet.len()) {
+            return Err(SearchError::InvalidTransitions);
+        }
+    }
+    if let Some(label_prior) = &options.label_prior {
+        let valid_prior = label_prior.len() == alphabet.len()
+            && label_prior
+                .iter()
+                .enumerate()
+                .all(|(column, &x)| options.is_blank(column) || (x.is_finite() && x > 0.0))
+            && options.label_prior_weight.is_finite();
+        if !valid_prior {
+            return Err(SearchError::InvalidLabelPrior);
+        }
+    }
+    check_beam_cut_threshold(beam_cut_threshold, alphabet.len(), from_logspace)?;
+    if options.check_finite {
+        for (row, pr) in network_output.outer_iter().enumerate() {
+            if let Some(col) = first_non_finite(pr.iter()) {
+                return Err(SearchError::IncomparableValues { row, col });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that `beam_cut_threshold` is at least 0.0 and less than `1/alphabet_len` (or, if
+/// `from_logspace`, is less than the log of that).
+///
+/// The upper bound is exclusive: with a threshold of `1/alphabet_len`, a row where every label
+/// is equally likely would prune every extension of the beam.
+fn check_beam_cut_threshold<T: Float>(
+    beam_cut_threshold: T,
+    alphabet_len: usize,
+    from_logspace: bool,
+) -> Result<(), SearchError> {
+    let max_beam_cut = T::one() / T::from(alphabet_len).unwrap();
+    let valid_beam_cut = if from_logspace {
+        beam_cut_threshold < max_beam_cut.ln()
+    } else {
+        (T::zero()..max_beam_cut).contains(&beam_cut_threshold)
+    };
+    if valid_beam_cut {
+        Ok(())
+    } else {
+        Err(SearchError::InvalidBeamCutThreshold)
+    }
+}
+
+/// Check the `beam_size` and `beam_cut_threshold` of a search that does not take
+/// `BeamSearchOptions` (the CRF and duplex searches), in the same way as `check_beam_args`.
+pub(crate) fn check_beam_size_and_threshold(
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    alphabet_len: usize,
+) -> Result<(), SearchError> {
+    if beam_size == 0 {
+        return Err(SearchError::InvalidBeamSize);
+    }
+    check_beam_cut_threshold(beam_cut_threshold, alphabet_len, false)
+}
+
+/// A hook into the beam search, to change the probabilities of the labellings it is choosing
+/// between at each timestep (see `beam_search_hooked`).
+///
+/// This is the way to try out rescoring that the searches do not have built in, such as fusion
+/// with a language model that keeps state of its own.
+pub trait BeamHook {
+    /// Rescore the candidates for the beam after the row of the network output at `step`.
+    ///
+    /// `beam` has the node of each candidate's labelling in `tree`, and its probability, which
+    /// can be changed. The changes to the nodes are ignored.
+    fn rescore(&mut self, step: usize, tree: &SuffixTree<usize>, beam: &mut [(i32, f32)]);
+}
+
+/// What the search knows about which labellings are likely, beyond the network output.
+#[derive(Default)]
+struct Guidance<'a> {
+    lexicon: Option<LexiconGuide<'a>>,
+    /// A language model, and the weight of its scores.
+    language_model: Option<(&'a dyn LanguageModel, f32)>,
+    /// Windows of timesteps, and the column of a label each labelling must emit in that window
+    /// (see `beam_search_anchored`).
+    anchors: &'a [(Range<usize>, usize)],
+    /// A hook that rescores the candidates for the beam at each timestep.
+    hook: Option<RefCell<&'a mut dyn BeamHook>>,
+}
+
+impl Guidance<'_> {
+    /// Let the hook, if there is one, rescore the candidates in `beam` after the row at `step`.
+    ///
+    /// A candidate's new probability is split between its paths that end in a label and in a
+    /// blank in the same proportions as before (or all put on the blank, if it had none).
+    /// `rescored` is scratch space for the hook's view of the beam.
+    fn rescore<P: Probability>(
+        &self,
+        step: usize,
+        suffix_tree: &SuffixTree<usize>,
+        beam: &mut [SearchPoint<P>],
+        rescored: &mut Vec<(i32, f32)>,
+    ) {
+        let hook = match &self.hook {
+            Some(hook) => hook,
+            None => return,
+        };
+        rescored.clear();
+        rescored.extend(
+            beam.iter()
+                .map(|x| (x.node, x.probability().to_f64() as f32)),
+        );
+        hook.borrow_mut().rescore(step, suffix_tree, rescored);
+        for (x, &(_, prob)) in beam.iter_mut().zip(rescored.iter()) {
+            let old = x.probability().to_f64();
+            if old > 0.0 {
+                let scale = P::from_f64(f64::from(prob) / old);
+                x.label_prob = x.label_prob * scale;
+                x.gap_prob = x.gap_prob * scale;
+            } else {
+                x.label_prob = P::zero();
+                x.gap_prob = P::from_f64(f64::from(prob));
+            }
+        }
+    }
+
+    /// The factor to multiply the probability of emitting the label in `column` after the
+    /// labelling of `node` by.
+    ///
+    /// `context` is scratch space for building the language model context.
+    fn label_weight<P: Probability>(
+        &self,
+        suffix_tree: &SuffixTree<usize>,
+        node: i32,
+        column: usize,
+        blank_id: usize,
+        context: &mut Vec<usize>,
+    ) -> P {
+        let (language_model, weight) = match self.language_model {
+            Some(language_model) => language_model,
+            None => return P::one(),
+        };
+        context.clear();
+        if node != ROOT_NODE {
+            let max_context = language_model.max_context().unwrap_or(usize::MAX);
+            context.extend(
+                suffix_tree
+                    .iter_from_no_data(node)
+                    .take(max_context)
+                    .map(|label| label_column(label, blank_id)),
+            );
+            context.reverse();
+        }
+        P::from_ln(weight * language_model.score(context, column))
+    }
+
+    /// The state of a search point after it emits the label in `column`, or `None` if the label
+    /// cannot be emitted.
+    fn next_state(&self, state: usize, column: usize) -> Option<usize> {
+        match &self.lexicon {
+            Some(lexicon) => lexicon.next_state(state, column),
+            None => Some(state),
+        }
+    }
+
+    /// Whether the labelling of `node` emits the label of each anchor whose window is `due`, at a
+    /// timepoint in that window.
+    fn meets_anchors<F: Fn(&Range<usize>) -> bool>(
+        &self,
+        suffix_tree: &SuffixTree<usize>,
+        node: i32,
+        blank_id: usize,
+        due: F,
+    ) -> bool {
+        self.anchors
+            .iter()
+            .filter(|(timesteps, _)| due(timesteps))
+            .all(|(timesteps, column)| {
+                node != ROOT_NODE
+                    && suffix_tree.iter_from(node).any(|(label, time)| {
+                        label_column(label, blank_id) == *column && timesteps.contains(time)
+                    })
+            })
+    }
+}
+
+/// Limits the labellings a search can produce to words from a lexicon, separated by the label in
+/// column `separator`.
+struct LexiconGuide<'a> {
+    lexicon: &'a Lexicon,
+    alphabet: &'a [String],
+    separator: usize,
+}
+
+impl LexiconGuide<'_> {
+    /// The lexicon node after emitting the label in `column` from `node`, if that can still be
+    /// part of a sequence of words.
+    fn next_state(&self, node: usize, column: usize) -> Option<usize> {
+        if column == self.separator {
+            if self.lexicon.is_word(node) {
+                Some(Lexicon::ROOT)
+            } else {
+                None
+            }
+        } else {
+            self.lexicon.walk(node, &self.alphabet[column])
+        }
+    }
+}
+
+/// The labelling tree and beams of a beam search, kept together so their storage can be reused.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct BeamState<P> {
+    suffix_tree: SuffixTree<usize>,
+    beam: Vec<SearchPoint<P>>,
+    next_beam: Vec<SearchPoint<P>>,
+    /// If set, the probability of each node (offset by one, so the root is first) at the last
+    /// timestep it was a candidate for the beam.
+    node_probs: Option<Vec<P>>,
+    /// If set, a row for each timestep so far, giving the probability of the paths in the beam
+    /// (after it was cut down) that take each column of the network output.
+    marginals: Option<Vec2D<P>>,
+    /// If set, the beam at each timestep so far, and how the paths moved between them.
+    lattice: Option<LatticeRecord<P>>,
+    /// If set, how full the beam got and how much was pruned from it. `tree_size` is left at 0.
+    stats: Option<DecodeStats>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    scratch: Scratch<P>,
+}
+
+/// Storage for `BeamState::advance` to reuse from one row to the next.
+struct Scratch<P> {
+    /// The probabilities of the row.
+    probs: Vec<P>,
+    /// The columns of the row the beam extends with.
+    columns: Vec<usize>,
+    /// The labellings of two search points with the same probability, to break the tie.
+    tied_a: Vec<usize>,
+    tied_b: Vec<usize>,
+    /// The factor for emitting the label of each column from `label_prior`, if it is set.
+    prior: Vec<P>,
+    /// The nodes and probabilities of the beam, for a `BeamHook` to rescore.
+    rescored: Vec<(i32, f32)>,
+}
+
+impl<P> Default for Scratch<P> {
+    fn default() -> Self {
+        Scratch {
+            probs: Vec::new(),
+            columns: Vec::new(),
+            tied_a: Vec::new(),
+            tied_b: Vec::new(),
+            prior: Vec::new(),
+            rescored: Vec::new(),
+        }
+    }
+}
+
+/// The states and arcs of a `Lattice`, as recorded by `BeamState::advance`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct LatticeRecord<P> {
+    /// The timestep, node and probability of each state.
+    states: Vec<(usize, i32, P)>,
+    /// The source state, target state and probability of each arc.
+    arcs: Vec<(usize, usize, P)>,
+    /// The index in `states` of the first entry of the current beam (the rest follow in order).
+    beam_start: usize,
+}
+
+impl<P: Probability> BeamState<P> {
+    fn new() -> Self {
+        BeamState {
+            suffix_tree: SuffixTree::new(0),
+            beam: Vec::new(),
+            next_beam: Vec::new(),
+            node_probs: None,
+            marginals: None,
+            lattice: None,
+            stats: None,
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Go back to the start of a search over `alphabet_size` (non-blank) labels, keeping the
+    /// storage allocated so far.
+    fn reset(&mut self, alphabet_size: usize) {
+        if self.suffix_tree.alphabet_size() == alphabet_size {
+            self.suffix_tree.clear();
+        } else {
+            let growth = self.suffix_tree.growth();
+            self.suffix_tree = SuffixTree::new(alphabet_size);
+            self.suffix_tree.set_growth(growth);
+        }
+        self.beam.clear();
+        self.beam.push(SearchPoint {
+            node: ROOT_NODE,
+            state: 0,
+            gap_prob: P::one(),
+            label_prob: P::zero(),
+            run_length: 0,
+            length: 0,
+        });
+        self.next_beam.clear();
+        if let Some(node_probs) = &mut self.node_probs {
+            node_probs.clear();
+            node_probs.push(P::one());
+        }
+        if let Some(marginals) = &mut self.marginals {
+            marginals.clear();
+        }
+        if let Some(lattice) = &mut self.lattice {
+            lattice.states.clear();
+            lattice.states.push((0, ROOT_NODE, P::one()));
+            lattice.arcs.clear();
+            lattice.beam_start = 0;
+        }
+        if let Some(stats) = &mut self.stats {
+            *stats = DecodeStats::default();
+        }
+    }
+}
+
+impl<P: Probability> BeamState<P> {
+    /// Continue the search over `network_output`, whose first row is timestep `first_timestep`.
+    ///
+    /// Each entry of `network_output` is converted to the probability representation `P` with
+    /// `to_prob`. The beam is left sorted by decreasing probability, and each entry refers to a
+    /// distinct node (and so a distinct labelling).
+    fn advance<E, D, F>(
+        &mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        first_timestep: usize,
+        options: &BeamSearchOptions,
+        guidance: &Guidance<'_>,
+        beam_cut_threshold: P,
+        to_prob: F,
+    ) -> Result<(), SearchError>
+    where
+        E: Input,
+        D: Data<Elem = E>,
+        F: Fn(E) -> P,
+    {
+        let BeamSearchOptions {
+            beam_size,
+            collapse,
+            blank_id,
+            max_symbols_per_frame,
+            blank_bias,
+            beam_cut_relative,
+            keep_best_on_empty,
+            tie_break,
+            check_normalized,
+            normalize_rows,
+            max_length,
+            repeat_label_floor,
+            merge_across_blank,
+            emit_at_first_row,
+            max_tree_nodes,
+            ..
+        } = *options;
+        let max_run_length = max_symbols_per_frame.unwrap_or(usize::MAX);
+        let max_length = max_length.unwrap_or(usize::MAX);
+        let relative_cut = beam_cut_relative.map(|x| P::from_ln(-x));
+        let blank_scale = P::from_ln(blank_bias);
+        let repeat_floor = P::from_ln(repeat_label_floor.ln());
+        let mut context = Vec::new();
+        // for the lattice: where each entry of the beam's extensions end in `next_beam`, and the
+        // extensions (as arcs to nodes rather than states)
+        let (mut source_ends, mut new_arcs) = (Vec::new(), Vec::new());
+        let BeamState {
+            suffix_tree,
+            beam,
+            next_beam,
+            node_probs,
+            marginals,
+            lattice,
+            stats,
+            scratch,
+        } = self;
+        // the number of columns that are not blanks
+        let label_columns = (0..network_output.ncols())
+            .filter(|&column| !options.is_blank(column))
+            .count();
+        let Scratch {
+            probs,
+            columns,
+            tied_a,
+            tied_b,
+            prior,
+            rescored,
+        } = scratch;
+        prior.clear();
+        if let Some(label_prior) = &options.label_prior {
+            let weight = options.label_prior_weight;
+            prior.extend(label_prior.iter().map(|&x| P::from_ln(weight * x.ln())));
+        }
+        for (idx, pr) in (first_timestep..).zip(network_output.outer_iter()) {
+            next_beam.clear();
+            // every search point extends with the same labels, so find them once per row
+            probs.clear();
+            probs.extend(pr.iter().map(|&x| to_prob(x)));
+            if check_normalized {
+                let sum = probs.iter().fold(0.0, |acc, x| acc + x.to_f64());
+                if (sum - 1.0).abs() > f64::from(ROW_SUM_TOLERANCE) {
+                    return Err(SearchError::RowSumMismatch {
+                        row: idx,
+                        sum: sum as f32,
+                    });
+                }
+            }
+            if normalize_rows {
+                let sum = probs.iter().fold(P::zero(), |acc, &x| acc + x);
+                if sum == P::zero() {
+                    return Err(SearchError::RowSumMismatch { row: idx, sum: 0.0 });
+                }
+                // a NaN or infinite sum makes the row NaN, which the sort below reports
+                let scale = P::from_f64(1.0 / sum.to_f64());
+                for x in probs.iter_mut() {
+                    *x = *x * scale;
+                }
+            }
+            columns.clear();
+            P::columns_at_least(probs, beam_cut_threshold, columns);
+            // the blank columns are all handled together, as one blank
+            columns.retain(|&column| !options.is_blank(column));
+            // the labels below the threshold, counted before any are ruled out at the first row
+            let mut labels_below_cut = label_columns - columns.len();
+            // the beam starts as if after a blank, so unless this is ruled out, paths can emit a
+            // label at the first row
+            let can_emit = emit_at_first_row || idx > 0;
+            if !can_emit {
+                columns.clear();
+            }
+            let pr_blank = options.blank_prob(|column| probs[column]) * blank_scale;
+            let mut cut = beam_cut_threshold;
+            // if the threshold would prune every extension of the beam, keep the best one instead
+            let keep_best = keep_best_on_empty && pr_blank <= cut && columns.is_empty();
+            if keep_best {
+                cut = P::zero();
+                labels_below_cut = 0;
+                if can_emit {
+                    columns.extend((0..probs.len()).filter(|&column| !options.is_blank(column)));
+                }
+            }
+            if let Some(max_tree_nodes) = max_tree_nodes {
+                // each extension of the beam with a label can add a node
+                let most_added = beam.len() * columns.len();
+                if suffix_tree.len() + most_added > max_tree_nodes {
+                    evict_nodes(suffix_tree, beam, node_probs, lattice, stats);
+                    if suffix_tree.len() + most_added > max_tree_nodes {
+                        return Err(SearchError::RanOutOfBeam { at_timestep: idx });
+                    }
+                }
+            }
+            if let Some(stats) = stats {
+                let below_cut = labels_below_cut + usize::from(pr_blank <= cut);
+                stats.threshold_pruned += below_cut * beam.len();
+            }
+
+            for &SearchPoint {
+                node,
+                label_prob,
+                gap_prob,
+                state,
+                run_length,
+                length,
+            } in beam.iter()
+            {
+                let tip_label = suffix_tree.label(node);
+                let tip_column = tip_label.map(|label| label_column(label, blank_id));
+                // add N to beam
+                if pr_blank > cut {
+                    next_beam.push(SearchPoint {
+                        node,
+                        state,
+                        label_prob: P::zero(),
+                        gap_prob: (label_prob + gap_prob) * pr_blank,
+                        run_length: 0,
+                        length,
+                    });
+                }
+
+                for &column in columns.iter() {
+                    let label = if column < blank_id {
+                        column
+                    } else {
+                        column - 1
+                    };
+                    let pr_b = probs[column];
+                    // emitting the label (rather than continuing it) is weighted by the prior
+                    let pr_emit = prior.get(column).map_or(pr_b, |&factor| pr_b * factor);
+
+                    if tip_column.map_or(false, |last| collapse.merges(last, column)) {
+                        let repeat_prob = label_prob
+                            * if pr_b < repeat_floor {
+                                repeat_floor
+                            } else {
+                                pr_b
+                            };
+                        if merge_across_blank {
+                            // the paths through a blank continue the last label too, rather than
+                            // adding it again
+                            next_beam.push(SearchPoint {
+                                node,
+                                label_prob: repeat_prob + gap_prob * pr_b,
+                                gap_prob: P::zero(),
+                                state,
+                                run_length,
+                                length,
+                            });
+                            continue;
+                        }
+                        next_beam.push(SearchPoint {
+                            node,
+                            label_prob: repeat_prob,
+                            gap_prob: P::zero(),
+                            state,
+                            run_length,
+                            length,
+                        });
+                        // labellings of `max_length` labels cannot grow any further
+                        if length >= max_length || !options.allows_transition(tip_column, column) {
+                            continue;
+                        }
+                        let next_state = match guidance.next_state(state, column) {
+                            Some(next_state) => next_state,
+                            None => continue,
+                        };
+                        let new_node_idx = suffix_tree.get_child(node, label).or_else(|| {
+                            if gap_prob > P::zero() {
+                                Some(suffix_tree.add_node(node, label, idx))
+                            } else {
+                                None
+                            }
+                        });
+
+                        if let Some(idx) = new_node_idx {
+                            let weight = guidance.label_weight(
+                                suffix_tree,
+                                node,
+                                column,
+                                blank_id,
+                                &mut context,
+                            );
+                            next_beam.push(SearchPoint {
+                                node: idx,
+                                state: next_state,
+                                label_prob: gap_prob * pr_emit * weight,
+                                gap_prob: P::zero(),
+                                run_length: 1,
+                                length: length + 1,
+                            });
+                        }
+                    } else {
+                        if length >= max_length || !options.allows_transition(tip_column, column) {
+                            continue;
+                        }
+                        let next_state = match guidance.next_state(state, column) {
+                            Some(next_state) => next_state,
+                            None => continue,
+                        };
+                        // paths that have already emitted too many labels in a row must go
+                        // through a blank first
+                        let prev_prob = if run_length < max_run_length {
+                            label_prob + gap_prob
+                        } else if gap_prob > P::zero() {
+                            gap_prob
+                        } else {
+                            continue;
+                        };
+                        let weight = guidance.label_weight(
+                            suffix_tree,
+                            node,
+                            column,
+                            blank_id,
+                            &mut context,
+                        );
+                        let new_node_idx = suffix_tree
+                            .get_child(node, label)
+                            .unwrap_or_else(|| suffix_tree.add_node(node, label, idx));
+
+                        next_beam.push(SearchPoint {
+                            node: new_node_idx,
+                            state: next_state,
+                            label_prob: prev_prob * pr_emit * weight,
+                            gap_prob: P::zero(),
+                            run_length: if gap_prob > P::zero() {
+                                1
+                            } else {
+                                run_length + 1
+                            },
+                            length: length + 1,
+                        });
+                    }
+                }
+                if lattice.is_some() {
+                    source_ends.push(next_beam.len());
+                }
+            }
+            if let Some(lattice) = lattice {
+                new_arcs.clear();
+                let mut start = 0;
+                for (source, &end) in source_ends.iter().enumerate() {
+                    for x in &next_beam[start..end] {
+                        if x.probability() > P::zero() {
+                            new_arcs.push((lattice.beam_start + source, x.node, x.probability()));
+                        }
+                    }
+                    start = end;
+                }
+                source_ends.clear();
+            }
+            core::mem::swap(beam, next_beam);
+
+            const DELETE_MARKER: i32 = i32::MIN;
+            // the sort is stable, so the paths of each labelling are added up in the same order
+            // (the order of the beam they came from, which is fully sorted) on every run
+            beam.sort_by_key(|x| x.node);
+            let mut last_key = DELETE_MARKER;
+            let mut last_key_pos = 0;
+            for i in 0..beam.len() {
+                let beam_item = beam[i];
+                if beam_item.node == last_key {
+                    let kept = &mut beam[last_key_pos];
+                    if beam_item.label_prob > P::zero()
+                        && (kept.label_prob == P::zero() || beam_item.run_length < kept.run_length)
+                    {
+                        kept.run_length = beam_item.run_length;
+                    }
+                    kept.label_prob += beam_item.label_prob;
+                    kept.gap_prob += beam_item.gap_prob;
+                    beam[i].node = DELETE_MARKER;
+                } else {
+                    last_key_pos = i;
+                    last_key = beam_item.node;
+                }
+            }
+
+            // labellings that have not met the anchors whose windows end at this row are pruned
+            beam.retain(|x| {
+                x.node != DELETE_MARKER
+                    && guidance.meets_anchors(suffix_tree, x.node, blank_id, |timesteps| {
+                        timesteps.end == idx + 1
+                    })
+            });
+            guidance.rescore(idx, suffix_tree, beam, rescored);
+            let mut has_nans = false;
+            beam.sort_unstable_by(|a, b| match b.probability().partial_cmp(&a.probability()) {
+                // the labels in the tree are the columns with the blank left out, so they are in
+                // the same order, and comparing them does not need the labellings built
+                Some(Ordering::Equal) => match tie_break {
+                    TieBreak::Lexicographic => suffix_tree.compare_labellings(a.node, b.node),
+                    TieBreak::Custom(_) => {
+                        labelling_columns(suffix_tree, a.node, blank_id, tied_a);
+                        labelling_columns(suffix_tree, b.node, blank_id, tied_b);
+                        tie_break.compare(tied_a, tied_b)
+                    }
+                },
+                Some(ordering) => ordering,
+                None => {
+                    has_nans = true;
+                    nan_order(&a.probability(), &b.probability())
+                }
+            });
+            if has_nans {
+                let col = first_non_finite(pr.iter()).unwrap_or(0);
+                return Err(SearchError::IncomparableValues { row: idx, col });
+            }
+            if let Some(node_probs) = node_probs {
+                node_probs.resize(suffix_tree.len() + 1, P::zero());
+                for x in beam.iter() {
+                    node_probs[(x.node + 1) as usize] = x.probability();
+                }
+            }
+            let mut keep = if keep_best { 1 } else { beam_size };
+            if let (Some(relative_cut), Some(best)) = (relative_cut, beam.first()) {
+                let cutoff = best.probability() * relative_cut;
+                if let Some(pos) = beam.iter().position(|x| x.probability() < cutoff) {
+                    keep = keep.min(pos);
+                }
+            }
+            if let Some(stats) = stats {
+                stats.beam_pruned += beam.len().saturating_sub(keep);
+                stats.beam_occupancy.push(keep.min(beam.len()));
+            }
+            beam.truncate(keep);
+            if beam.is_empty() {
+                // we've run out of beam (probably the threshold is too high)
+                return Err(SearchError::RanOutOfBeam { at_timestep: idx });
+            }
+            if let Some(lattice) = lattice {
+                let beam_start = lattice.states.len();
+                for x in beam.iter() {
+                    lattice.states.push((idx + 1, x.node, x.probability()));
+                }
+                // the blank and repeat extensions of an entry go to the same node, so add them up
+                new_arcs.sort_by_key(|&(source, node, _)| (source, node));
+                let mut last = None;
+                for &(source, node, prob) in &new_arcs {
+                    if last == Some((source, node)) {
+                        if let Some(arc) = lattice.arcs.last_mut() {
+                            arc.2 += prob;
+                        }
+                        continue;
+                    }
+                    last = Some((source, node));
+                    if let Some(pos) = beam.iter().position(|x| x.node == node) {
+                        lattice.arcs.push((source, beam_start + pos, prob));
+                    } else {
+                        // the target was cut from the beam, so this arc is not in the lattice
+                        last = None;
+                    }
+                }
+                lattice.beam_start = beam_start;
+            }
+            if let Some(marginals) = marginals {
+                marginals.add_row_with_value(P::zero());
+                for x in beam.iter() {
+                    marginals[(idx, blank_id)] += x.gap_prob;
+                    if let Some(label) = suffix_tree.label(x.node) {
+                        marginals[(idx, label_column(label, blank_id))] += x.label_prob;
+                    }
+                }
+            }
+
+            // Probability normalisation is removed because we want to
+            // output raw probability scores.
+            // NOTE: this provides a risk of float underflow as we are
+            // multiplying several numbers < 0.
+            // We can get round this by using logs and adding log(p) to scores
+            // but that would be slower.
+
+            // let top = beam[0].probability();
+            // for x in &mut beam {
+            //     x.label_prob /= top;
+            //     x.gap_prob /= top;
+            // }
+        }
+        Ok(())
+    }
+}
+
+/// Remove the nodes of the labelling tree that the search no longer needs, for
+/// `BeamSearchOptions::max_tree_nodes`.
+///
+/// The nodes still needed are those of the labellings in `beam`, and of the states of `lattice`.
+/// They are renumbered (see `SuffixTree::retain_ancestors`), and `node_probs` only keeps the
+/// probabilities of the nodes that are left.
+fn evict_nodes<P: Probability>(
+    suffix_tree: &mut SuffixTree<usize>,
+    beam: &mut [SearchPoint<P>],
+    node_probs: &mut Option<Vec<P>>,
+    lattice: &mut Option<LatticeRecord<P>>,
+    stats: &mut Option<DecodeStats>,
+) {
+    let old_len = suffix_tree.len();
+    let lattice_nodes = lattice
+        .iter()
+        .flat_map(|lattice| lattice.states.iter().map(|&(_, node, _)| node));
+    let new_index = suffix_tree.retain_ancestors(beam.iter().map(|x| x.node).chain(lattice_nodes));
+    let new_node = |node: i32| {
+        if node >= 0 {
+            new_index[node as usize].expect("the node was kept")
+        } else {
+            node
+        }
+    };
+    for x in beam.iter_mut() {
+        x.node = new_node(x.node);
+    }
+    if let Some(lattice) = lattice {
+        for state in &mut lattice.states {
+            state.1 = new_node(state.1);
+        }
+    }
+    if let Some(node_probs) = node_probs {
+        // the root is first, then each node in order
+        let mut old = 0;
+        node_probs.retain(|_| {
+            let keep = old == 0 || new_index[old - 1].is_some();
+            old += 1;
+            keep
+        });
+    }
+    if let Some(stats) = stats {
+        stats.evicted_nodes += old_len - suffix_tree.len();
+    }
+}
+
+impl<P> BeamState<P> {
+    /// Move the labellings of at least `min_length` labels to the front of the beam, keeping the
+    /// order of each part, so that the first is the best of them if there are any.
+    fn prefer_min_length(&mut self, min_length: usize) {
+        if min_length > 0 {
+            self.beam.sort_by_key(|x| x.length < min_length);
+        }
+    }
+
+    /// The entry of the beam to return: the best with at least `min_length` labels, or if there
+    /// are none, the best of any length.
+    fn best(&self, min_length: usize) -> &SearchPoint<P> {
+        self.beam
+            .iter()
+            .find(|x| x.length >= min_length)
+            .unwrap_or(&self.beam[0])
+    }
+}
+
+/// Run the beam search over `network_output`, leaving the labelling tree and the final beam in
+/// `state`.
+///
+/// Each entry of `network_output` is converted to the probability representation `P` with
+/// `to_prob`. The final beam is sorted by decreasing probability (after the labellings of at
+/// least `min_length` labels), and each entry refers to a distinct node (and so a distinct
+/// labelling).
+fn run_beam_search<P, E, D, F>(
+    state: &mut BeamState<P>,
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    guidance: &Guidance<'_>,
+    beam_cut_threshold: P,
+    to_prob: F,
+) -> Result<(), SearchError>
+where
+    P: Probability,
+    E: Input,
+    D: Data<Elem = E>,
+    F: Fn(E) -> P,
+{
+    // alphabet size minus the blank label
+    state.reset(alphabet.len() - 1);
+    state.advance(
+        network_output,
+        0,
+        options,
+        guidance,
+        beam_cut_threshold,
+        to_prob,
+    )?;
+    state.prefer_min_length(options.min_length);
+    Ok(())
+}
+
+/// Build the labelling ending at `node`, along with the final timepoint of each label.
+///
+/// The labelling is the concatenation of the tokens of `alphabet` for its labels, and there is one
+/// timepoint per label. A label whose token is the empty string still counts as a label (so it
+/// has a timepoint, and repeats of it are collapsed), but adds nothing to the string.
+fn labelling(
+    suffix_tree: &SuffixTree<usize>,
+    node: i32,
+    alphabet: &[String],
+    blank_id: usize,
+) -> (String, Vec<usize>) {
+    // the tree gives the labels from the last to the first
+    let mut path = Vec::new();
+    let mut tokens = Vec::new();
+    if node != ROOT_NODE {
+        for (label, &time) in suffix_tree.iter_from(node) {
+            path.push(time);
+            tokens.push(alphabet[label_column(label, blank_id)].as_str());
+        }
+    }
+    path.reverse();
+    (tokens.iter().rev().copied().collect(), path)
+}
+
+/// Perform a CTC beam search decode on an RNN output.
+///
+/// `network_output` can be any 2D array of probabilities (including an `ArrayView2`), with time
+/// on the outer axis and labels on the inner axis. `blank_id` gives the index of the blank label
+/// on the inner axis (usually 0).
+///
+/// Returns the decoded sequence, the final timepoint of each label and the probability of the
+/// chosen labelling relative to the others remaining in the beam.
+///
+/// Each entry of `alphabet` is the token for a label, which may be several characters long. The
+/// decoded sequence is the concatenation of the tokens of the emitted labels, and there is one
+/// timepoint per emitted label (not per character). A non-blank label whose token is empty is
+/// emitted like any other, so it has a timepoint, but it adds nothing to the sequence.
+///
+/// Labellings with exactly the same probability are ordered lexicographically by their columns of
+/// `network_output`, so the result is the same on every run and every platform that computes the
+/// same probabilities. `BeamSearchOptions::tie_break` can change this order.
+///
+/// A `network_output` with no rows, or an alphabet of only the blank label, decodes to an empty
+/// sequence with no timepoints and a probability of 1.0.
+///
+/// This is a shorthand for `beam_search_with`, using the given options.
+pub fn beam_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let options =
+        BeamSearchOptions::from_args(beam_size, beam_cut_threshold, collapse_repeats, blank_id);
+    beam_search_with(network_output, alphabet, &options)
+}
+
+/// Perform a CTC beam search decode on an RNN output, as configured by `options`.
+///
+/// `network_output` can be any 2D array of probabilities (including an `ArrayView2`), with time
+/// on the outer axis and labels on the inner axis.
+///
+/// Returns the decoded sequence, the final timepoint of each label and the probability of the
+/// chosen labelling relative to the others remaining in the beam.
+pub fn beam_search_with<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    Decoder::new(options.clone()).decode(network_output, alphabet)
+}
+
+/// Perform a CTC beam search decode on an RNN output, writing the result to `out` and
+/// `timepoints`.
+///
+/// This is the same search as `beam_search_with`, but the decoded sequence and the final
+/// timepoint of each label replace the contents of the buffers given, rather than being
+/// returned in new ones, and only the probability of the labelling is returned. See
+/// `Decoder::decode_into`, which also reuses the storage of the search.
+pub fn beam_search_into<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    out: &mut String,
+    timepoints: &mut Vec<usize>,
+) -> Result<f32, SearchError> {
+    Decoder::new(options.clone()).decode_into(network_output, alphabet, out, timepoints)
+}
+
+/// A CTC beam search decoder that can be reused for many decodes.
+///
+/// The decoder keeps the labelling tree and beams from one decode to the next, so decoding many
+/// network outputs with the same decoder only allocates when an output needs more storage than
+/// any before it. Each decode is independent of the ones before: the results are the same as
+/// for `beam_search_with`.
+///
+/// A decoder made with `with_capacity` allocates everything up front instead, and never allocates
+/// during a decode other than for the sequence and timepoints it returns (and `decode_into` and
+/// `decode_iter` do not return new ones).
+pub struct Decoder {
+    options: BeamSearchOptions,
+    state: BeamState<f32>,
+    /// For a decoder made with `with_capacity`, the alphabet length and the most rows it has
+    /// storage for.
+    capacity: Option<(usize, usize)>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new(BeamSearchOptions::default())
+    }
+}
+
+impl Decoder {
+    pub fn new(options: BeamSearchOptions) -> Self {
+        Decoder {
+            options,
+            state: BeamState::new(),
+            capacity: None,
+        }
+    }
+
+    /// A decoder with the storage for decoding network outputs of up to `max_timesteps` rows
+    /// with an alphabet of `alphabet_len` labels, allocated up front.
+    ///
+    /// This is for real-time use, where a decode must not allocate. Decoding a network output
+    /// with more rows or a different alphabet length fails with `SearchError::CapacityExceeded`
+    /// rather than allocating more storage.
+    ///
+    /// Each row adds at most `beam_size * (alphabet_len - 1)` nodes to the labelling tree, so
+    /// this allocates about `max_timesteps * beam_size * (alphabet_len - 1) * (4 * alphabet_len +
+    /// 32)` bytes for the tree, which is usually far more than a decode uses. The options should
+    /// not enable anything that needs more storage per row (`beam_search_with_marginals` and the
+    /// lattice are not available through a `Decoder` anyway).
+    pub fn with_capacity(
+        options: BeamSearchOptions,
+        alphabet_len: usize,
+        max_timesteps: usize,
+    ) -> Self {
+        let mut state = BeamState::new();
+        // alphabet size minus the blank label
+        let labels = alphabet_len.saturating_sub(1);
+        state.reset(labels);
+        state
+            .suffix_tree
+            .reserve(max_timesteps.saturating_mul(options.beam_size.saturating_mul(labels)));
+        // each search point extends with a blank, and each label (twice for a repeat)
+        let extensions = options
+            .beam_size
+            .saturating_mul(labels.saturating_mul(2).saturating_add(1));
+        state.beam.reserve(extensions);
+        state.next_beam.reserve(extensions);
+        let scratch = &mut state.scratch;
+        scratch.probs.reserve(alphabet_len);
+        scratch.columns.reserve(alphabet_len);
+        scratch.tied_a.reserve(max_timesteps);
+        scratch.tied_b.reserve(max_timesteps);
+        scratch.prior.reserve(alphabet_len);
+        Decoder {
+            options,
+            state,
+            capacity: Some((alphabet_len, max_timesteps)),
+        }
+    }
+
+    /// A decoder with storage for a labelling tree of `expected_nodes` nodes allocated up front,
+    /// for network outputs with an alphabet of `alphabet_len` labels.
+    ///
+    /// Unlike `with_capacity`, this does not limit the decodes: the tree grows (as set by
+    /// `set_growth`) if a decode needs more nodes. It just avoids growing it part way through the
+    /// decodes that do not. Each row of a network output adds at most `beam_size *
+    /// (alphabet_len - 1)` nodes, but a decode usually adds about `beam_size` per row (see the
+    /// `tree` module), so `2 * beam_size` times the rows of the longest expected network output
+    /// is usually plenty. Decoding an output with a different alphabet length replaces the
+    /// storage.
+    pub fn with_capacity_hint(
+        options: BeamSearchOptions,
+        alphabet_len: usize,
+        expected_nodes: usize,
+    ) -> Self {
+        let mut state = BeamState::new();
+        // alphabet size minus the blank label
+        state.reset(alphabet_len.saturating_sub(1));
+        state.suffix_tree.reserve(expected_nodes);
+        Decoder {
+            options,
+            state,
+            capacity: None,
+        }
+    }
+
+    /// Set how the labelling tree grows when a decode needs more nodes than it has storage for.
+    ///
+    /// The default, `Growth::Double`, takes the least time overall. `Growth::Step` keeps the
+    /// storage closer to what the decodes need, at the cost of growing more often. A decoder made
+    /// with `with_capacity` never grows its tree, so this has no effect on it.
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.state.suffix_tree.set_growth(growth);
+    }
+
+    /// The options every decode uses.
+    pub fn options(&self) -> &BeamSearchOptions {
+        &self.options
+    }
+
+    /// Perform a CTC beam search decode on an RNN output.
+    ///
+    /// Returns the decoded sequence, the final timepoint of each label and the probability of
+    /// the chosen labelling relative to the others remaining in the beam.
+    pub fn decode<D: Data<Elem = f32>>(
+        &mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &[String],
+    ) -> Result<(String, Vec<usize>, f32), SearchError> {
+        self.search(network_output, alphabet)?;
+        let BeamState {
+            suffix_tree, beam, ..
+        } = &self.state;
+
+        // The probabilities of the beam paths are summed (using normalize_denominator)
+        // and used to divide the best path's probability to normalize the outputted
+        // probabilities to range within [0,1]. This fixes the problem of outputting
+        // very low probability values (like 1e-5) which is not easily interpretable
+        // as confidence scores, which is one of the main use cases of this value.
+        let mut normalize_denominator: f32 = 0.00;
+        for x in beam {
+            normalize_denominator += x.probability()
+        }
+        let (sequence, path) =
+            labelling(suffix_tree, beam[0].node, alphabet, self.options.blank_id);
+        Ok((
+            sequence,
+            path,
+            beam[0].probability() / normalize_denominator,
+        ))
+    }
+
+    /// Perform a CTC beam search decode on an RNN output, writing the result to `out` and
+    /// `timepoints`.
+    ///
+    /// This is the same as `decode`, but the buffers are cleared and the decoded sequence and
+    /// the final timepoint of each label written to them, and only the probability of the
+    /// labelling is returned. Clearing a buffer keeps its capacity, so a buffer only grows when a
+    /// result is longer than any it has held, and is never shrunk or reallocated for a shorter
+    /// one. Reusing the same buffers for every decode (with a decoder made with `with_capacity`)
+    /// therefore stops allocating once they have grown to the longest result. If the decode
+    /// fails, the buffers are left as they were.
+    pub fn decode_into<D: Data<Elem = f32>>(
+        &mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &[String],
+        out: &mut String,
+        timepoints: &mut Vec<usize>,
+    ) -> Result<f32, SearchError> {
+        let (labels, probability) = self.decode_iter(network_output, alphabet)?;
+        out.clear();
+        timepoints.clear();
+        for (token, time) in labels {
+            out.push_str(token);
+            timepoints.push(time);
+        }
+        Ok(probability)
+    }
+
+    /// Perform a CTC beam search decode on an RNN output, returning the labels one at a time.
+    ///
+    /// This is the same as `decode`, but rather than building the sequence and timepoints, it
+    /// returns an iterator over the token (from `alphabet`) and timepoint of each label of the
+    /// chosen labelling, in order, which borrows them from the decoder. Along with it is the
+    /// probability of the labelling relative to the others remaining in the beam.
+    ///
+    /// The labelling tree links each label to the one before it, so the iterator needs the nodes
+    /// of the labelling in a list to go through them from the first. That list is kept with the
+    /// decoder, and for a decoder made with `with_capacity` it is allocated up front like the rest
+    /// of its storage, so this does not allocate at all.
+    pub fn decode_iter<'a, D: Data<Elem = f32>>(
+        &'a mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &'a [String],
+    ) -> Result<(LabelIter<'a>, f32), SearchError> {
+        self.search(network_output, alphabet)?;
+        let BeamState {
+            suffix_tree,
+            beam,
+            scratch,
+            ..
+        } = &mut self.state;
+
+        let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+        // the tie breaking storage is free once the search is over
+        let nodes = &mut scratch.tied_a;
+        nodes.clear();
+        let mut node = beam[0].node;
+        while let Some(parent) = suffix_tree.parent(node) {
+            nodes.push(node as usize);
+            node = parent;
+        }
+        let labels = LabelIter {
+            suffix_tree,
+            alphabet,
+            blank_id: self.options.blank_id,
+            nodes: nodes.iter().rev(),
+        };
+        Ok((labels, beam[0].probability() / normalize_denominator))
+    }
+
+    /// Run the search over `network_output`, leaving the final beam in the state.
+    fn search<D: Data<Elem = f32>>(
+        &mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &[String],
+    ) -> Result<(), SearchError> {
+        let options = &self.options;
+        let network_output = &options.time_major(network_output);
+        check_beam_args(
+            network_output,
+            alphabet,
+            options,
+            options.beam_cut_threshold,
+            false,
+        )?;
+        if let Some((alphabet_len, max_timesteps)) = self.capacity {
+            if alphabet.len() != alphabet_len || network_output.nrows() > max_timesteps {
+                return Err(SearchError::CapacityExceeded);
+            }
+        }
+        run_beam_search(
+            &mut self.state,
+            network_output,
+            alphabet,
+            options,
+            &Guidance::default(),
+            options.beam_cut_threshold,
+            |x| x,
+        )
+    }
+}
+
+/// The token and timepoint of each label of a labelling, in order, returned by
+/// `Decoder::decode_iter`.
+pub struct LabelIter<'a> {
+    suffix_tree: &'a SuffixTree<usize>,
+    alphabet: &'a [String],
+    blank_id: usize,
+    /// The nodes of the labelling, from the first label.
+    nodes: Rev<slice::Iter<'a, usize>>,
+}
+
+impl<'a> Iterator for LabelIter<'a> {
+    type Item = (&'a str, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = *self.nodes.next()? as i32;
+        let label = self.suffix_tree.label(node)?;
+        let &time = self.suffix_tree.get_data_ref(node)?;
+        Some((
+            self.alphabet[label_column(label, self.blank_id)].as_str(),
+            time,
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.nodes.size_hint()
+    }
+}
+
+impl ExactSizeIterator for LabelIter<'_> {}
+
+/// A beam search that can be paused part way through a network output and continued later.
+///
+/// Feeding the rows of a network output to `advance` in any number of pieces gives the same
+/// result as decoding it in one go with `beam_search_with`, as long as every call uses the same
+/// alphabet and options. With the `serde` feature, the state can be serialized (along with the
+/// labelling tree) so that a long decode can be checkpointed and resumed somewhere else.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BeamSearchState {
+    state: BeamState<f32>,
+    timestep: usize,
+}
+
+impl BeamSearchState {
+    /// The state at the start of a search over `alphabet`.
+    pub fn new(alphabet: &[String]) -> Self {
+        let mut state = BeamState::new();
+        // alphabet size minus the blank label
+        state.reset(alphabet.len().saturating_sub(1));
+        BeamSearchState { state, timestep: 0 }
+    }
+
+    /// The number of rows of network output searched so far.
+    pub fn timestep(&self) -> usize {
+        self.timestep
+    }
+
+    /// Continue the search over the next rows of network output.
+    ///
+    /// The timepoints in the result (and in any error) count from the start of the search, not
+    /// the start of `network_output`. If this returns an error, the state should be discarded.
+    pub fn advance<D: Data<Elem = f32>>(
+        &mut self,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &[String],
+        options: &BeamSearchOptions,
+    ) -> Result<(), SearchError> {
+        let network_output = &options.time_major(network_output);
+        check_beam_args(
+            network_output,
+            alphabet,
+            options,
+            options.beam_cut_threshold,
+            false,
+        )?;
+        if alphabet.len() - 1 != self.state.suffix_tree.alphabet_size() {
+            return Err(SearchError::AlphabetMismatch);
+        }
+        self.state.advance(
+            network_output,
+            self.timestep,
+            options,
+            &Guidance::default(),
+            options.beam_cut_threshold,
+            |x| x,
+        )?;
+        self.timestep += network_output.shape()[0];
+        Ok(())
+    }
+
+    /// The best labelling so far: the decoded sequence, the final timepoint of each label and
+    /// the probability of the labelling relative to the others remaining in the beam.
+    pub fn result(
+        &self,
+        alphabet: &[String],
+        options: &BeamSearchOptions,
+    ) -> (String, Vec<usize>, f32) {
+        let beam = &self.state.beam;
+        let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+        // the beam is only reordered for `min_length` at the end of a search, and this state can
+        // still be advanced
+        let best = self.state.best(options.min_length);
+        let (sequence, path) = labelling(
+            &self.state.suffix_tree,
+            best.node,
+            alphabet,
+            options.blank_id,
+        );
+        (sequence, path, best.probability() / normalize_denominator)
+    }
+}
+
+/// Perform the search of `beam_search_with` `every` rows at a time, calling `between` with the
+/// state after each group of rows (including the last).
+///
+/// The search stops early if `between` returns false. Returns the state, and whether every row
+/// was searched.
+pub(crate) fn search_in_steps<D, F>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    every: usize,
+    mut between: F,
+) -> Result<(BeamSearchState, bool), SearchError>
+where
+    D: Data<Elem = f32>,
+    F: FnMut(&BeamSearchState) -> bool,
+{
+    if every == 0 {
+        return Err(SearchError::InvalidInterval);
+    }
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamSearchState::new(alphabet);
+    let n_rows = network_output.nrows();
+    for rows in network_output.axis_chunks_iter(Axis(0), every) {
+        state.state.advance(
+            &rows,
+            state.timestep,
+            options,
+            &Guidance::default(),
+            options.beam_cut_threshold,
+            |x| x,
+        )?;
+        state.timestep += rows.nrows();
+        if !between(&state) {
+            break;
+        }
+    }
+    let complete = state.timestep == n_rows;
+    Ok((state, complete))
+}
+
+/// Perform a CTC beam search decode on an RNN output, reporting progress as it goes.
+///
+/// This is the same search as `beam_search_with`, and returns the same result. After every
+/// `every` rows of `network_output` (and after the last row), `progress` is called with the number
+/// of rows searched so far and the best labelling at that point, so that a caller can show
+/// progress through a long read. The labelling can still change as later rows are searched.
+///
+/// Building the labelling for each call takes time proportional to its length, so calling
+/// `progress` often on a long read can slow the search down noticeably: `every` should be large
+/// enough that there are at most a few hundred calls (for example, 10,000 rows). It must be at
+/// least 1.
+pub fn beam_search_with_progress<D, F>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    every: usize,
+    mut progress: F,
+) -> Result<(String, Vec<usize>, f32), SearchError>
+where
+    D: Data<Elem = f32>,
+    F: FnMut(usize, &str),
+{
+    let (state, _) = search_in_steps(network_output, alphabet, options, every, |state| {
+        let (sequence, _, _) = state.result(alphabet, options);
+        progress(state.timestep, &sequence);
+        true
+    })?;
+    Ok(state.result(alphabet, options))
+}
+
+/// Perform a CTC beam search decode on an RNN output that can be stopped part way through.
+///
+/// This is the same search as `beam_search_with`, but after every `every` rows of
+/// `network_output` it checks `cancel`, and if it has been set (from another thread, for example
+/// when a deadline has passed), it stops there. `every` must be at least 1; checking the flag is
+/// cheap, so it can be small.
+///
+/// Returns the decoded sequence, the final timepoint of each label and the probability of the
+/// labelling relative to the others remaining in the beam, as `beam_search_with` does, and whether
+/// the search was stopped early. A search that was stopped returns the best labelling of the rows
+/// it searched, so it is a valid labelling of the start of `network_output`.
+pub fn beam_search_cancellable<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    every: usize,
+    cancel: &AtomicBool,
+) -> Result<(String, Vec<usize>, f32, bool), SearchError> {
+    let (state, complete) = search_in_steps(network_output, alphabet, options, every, |_| {
+        !cancel.load(AtomicOrdering::Relaxed)
+    })?;
+    let (sequence, path, prob) = state.result(alphabet, options);
+    Ok((sequence, path, prob, !complete))
+}
+
+/// A beam search that is fed its network output one row at a time.
+///
+/// This is for decoding network output as it is produced: `push_row` advances the search by one
+/// timestep, `best` gives the best labelling so far, and `finish` gives the same result that
+/// `beam_search` would for all the rows pushed.
+pub struct StreamingDecoder {
+    alphabet: Vec<String>,
+    options: BeamSearchOptions,
+    state: BeamSearchState,
+}
+
+impl StreamingDecoder {
+    /// A decoder for `alphabet`, with the blank label first and repeats collapsed.
+    pub fn new(
+        alphabet: &[String],
+        beam_size: usize,
+        beam_cut_threshold: f32,
+    ) -> Result<Self, SearchError> {
+        let options = BeamSearchOptions::new()
+            .beam_size(beam_size)
+            .beam_cut_threshold(beam_cut_threshold);
+        StreamingDecoder::with_options(alphabet, options)
+    }
+
+    /// A decoder for `alphabet`, as configured by `options`.
+    pub fn with_options(
+        alphabet: &[String],
+        options: BeamSearchOptions,
+    ) -> Result<Self, SearchError> {
+        // check the arguments now rather than on the first row
+        let no_rows = Array2::<f32>::zeros((0, alphabet.len()));
+        check_beam_args(
+            &no_rows,
+            alphabet,
+            &options,
+            options.beam_cut_threshold,
+            false,
+        )?;
+        Ok(StreamingDecoder {
+            alphabet: alphabet.to_vec(),
+            options,
+            state: BeamSearchState::new(alphabet),
+        })
+    }
+
+    /// The number of rows pushed so far.
+    pub fn timestep(&self) -> usize {
+        self.state.timestep()
+    }
+
+    /// Reserve storage for at least `additional` more nodes in the labelling tree.
+    ///
+    /// For real-time use, reserving enough for the whole stream up front keeps the tree from
+    /// growing (and so the time `push_row` takes from jumping) part way through it. Each row
+    /// usually adds about `beam_size` nodes (see the `tree` module).
+    pub fn reserve_nodes(&mut self, additional: usize) {
+        self.state.state.suffix_tree.reserve(additional);
+    }
+
+    /// Set how the labelling tree grows when it runs out of storage (see `Decoder::set_growth`).
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.state.state.suffix_tree.set_growth(growth);
+    }
+
+    /// Advance the search by one timestep.
+    ///
+    /// If this returns an error, the decoder should be discarded.
+    pub fn push_row(&mut self, row: ArrayView1<f32>) -> Result<(), SearchError> {
+        self.state.advance(
+            &row.insert_axis(Axis(self.options.time_axis)),
+            &self.alphabet,
+            &self.options,
+        )
+    }
+
+    /// The best labelling of the rows pushed so far: the decoded sequence and the final
+    /// timepoint of each label.
+    ///
+    /// Later rows can still change this, including the timepoints of labels it shares with the
+    /// final labelling.
+    pub fn best(&self) -> (String, Vec<usize>) {
+        let (sequence, path, _) = self.state.result(&self.alphabet, &self.options);
+        (sequence, path)
+    }
+
+    /// Finish the search, returning the decoded sequence, the final timepoint of each label and
+    /// the probability of the chosen labelling relative to the others remaining in the beam.
+    pub fn finish(self) -> (String, Vec<usize>, f32) {
+        self.state.result(&self.alphabet, &self.options)
+    }
+}
+
+/// The state of a beam search at the end of a decode, returned by `beam_search_debug`.
+///
+/// The tree holds every labelling the search explored, not just those left in the beam. The data
+/// of each node is the timestep the node was added at. Probabilities are the raw (unnormalised)
+/// probabilities the search accumulated for each labelling.
+pub struct BeamSearchDebug {
+    suffix_tree: SuffixTree<usize>,
+    beam: Vec<SearchPoint>,
+    node_probs: Vec<f32>,
+    alphabet: Vec<String>,
+    blank_id: usize,
+}
+
+impl BeamSearchDebug {
+    /// The tree of labellings the search explored.
+    ///
+    /// Tree labels leave out the blank, so label `l` is `alphabet[l]` for `l < blank_id` and
+    /// `alphabet[l + 1]` otherwise. Use `labelling` to get the sequence for a node.
+    pub fn tree(&self) -> &SuffixTree<usize> {
+        &self.suffix_tree
+    }
+
+    /// The nodes left in the final beam and their probabilities, most probable first.
+    pub fn beam(&self) -> impl Iterator<Item = (i32, f32)> + '_ {
+        self.beam.iter().map(|x| (x.node, x.probability()))
+    }
+
+    /// The probability of `node` at the last timestep it was a candidate for the beam (before
+    /// the beam was cut down to `beam_size`), or `None` if `node` is not in the tree.
+    pub fn probability(&self, node: i32) -> Option<f32> {
+        if node < ROOT_NODE {
+            return None;
+        }
+        self.node_probs.get((node + 1) as usize).copied()
+    }
+
+    /// The decoded sequence for `node`, along with the timepoint each of its labels was added at.
+    pub fn labelling(&self, node: i32) -> (String, Vec<usize>) {
+        labelling(&self.suffix_tree, node, &self.alphabet, self.blank_id)
+    }
+}
+
+/// Perform a CTC beam search decode on an RNN output, keeping the search tree for inspection.
+///
+/// This is the same search as `beam_search_with`, and returns the same sequence, timepoints and
+/// probability, followed by the state of the search at the end.
+pub fn beam_search_debug<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32, BeamSearchDebug), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    state.node_probs = Some(Vec::new());
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree,
+        beam,
+        node_probs,
+        ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    let prob = beam[0].probability() / normalize_denominator;
+    let debug = BeamSearchDebug {
+        suffix_tree,
+        beam,
+        node_probs: node_probs.unwrap_or_default(),
+        alphabet: alphabet.to_vec(),
+        blank_id: options.blank_id,
+    };
+    Ok((sequence, path, prob, debug))
+}
+
+/// A state of a `Lattice`: a labelling in the beam at one timestep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LatticeState {
+    /// The number of rows of network output searched so far, from 0 (before the first row) to
+    /// the length of the network output.
+    pub timestep: usize,
+    /// The labelling's node in `Lattice::tree`.
+    pub node: i32,
+    /// The natural log of the summed probability of the labelling's paths.
+    pub score: f32,
+}
+
+/// An arc of a `Lattice`, from a state at one timestep to a state at the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LatticeArc {
+    /// The index of the source state.
+    pub from: usize,
+    /// The index of the target state.
+    pub to: usize,
+    /// The column of `network_output` of the label emitted along the arc, or `None` if the
+    /// labelling stays the same (the paths take a blank, or repeat the last label).
+    pub label: Option<usize>,
+    /// The natural log of the probability of the paths that go from the source to the target
+    /// along the arc.
+    pub score: f32,
+}
+
+/// The states a beam search kept and how its paths moved between them, returned by
+/// `beam_search_lattice`.
+///
+/// There is a state for each entry of the beam at each timestep, including the empty labelling at
+/// timestep 0 (which is always state 0). The states are in order of timestep, and then (like the
+/// beam) by decreasing score. There is an arc from each state to each state at the next timestep
+/// that some of its paths extended to, in order of source state and then target node. Paths that
+/// were cut from the beam are left out, so each state's probability is the sum of the
+/// probabilities of the arcs into it (up to rounding).
+///
+/// With the `serde` feature, the lattice can be serialized, for example to JSON with
+/// `serde_json`, for use with other tools.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Lattice {
+    states: Vec<LatticeState>,
+    arcs: Vec<LatticeArc>,
+    suffix_tree: SuffixTree<usize>,
+    alphabet: Vec<String>,
+    blank_id: usize,
+}
+
+impl Lattice {
+    /// The states, indexed by the `from` and `to` of each arc.
+    pub fn states(&self) -> &[LatticeState] {
+        &self.states
+    }
+
+    /// The arcs.
+    pub fn arcs(&self) -> &[LatticeArc] {
+        &self.arcs
+    }
+
+    /// The indices of the states at the last timestep (the final beam).
+    pub fn final_states(&self) -> Range<usize> {
+        let last = self.states.last().map_or(0, |x| x.timestep);
+        let first = self
+            .states
+            .iter()
+            .position(|x| x.timestep == last)
+            .unwrap_or(0);
+        first..self.states.len()
+    }
+
+    /// The tree of labellings the search explored. As for `BeamSearchDebug::tree`, its labels
+    /// leave out the blank.
+    pub fn tree(&self) -> &SuffixTree<usize> {
+        &self.suffix_tree
+    }
+
+    /// The decoded sequence of the labelling of `state`, along with the timepoint each of its
+    /// labels was added at.
+    pub fn labelling(&self, state: usize) -> (String, Vec<usize>) {
+        labelling(
+            &self.suffix_tree,
+            self.states[state].node,
+            &self.alphabet,
+            self.blank_id,
+        )
+    }
+
+    /// Write the lattice in the text format of OpenFst's `fstcompile`.
+    ///
+    /// The start state is state 0, and the final states are those at the last timestep. Each arc
+    /// has the same input and output label: 0 (epsilon) if no label is emitted along it, and the
+    /// column of `network_output` plus one otherwise. The weight of an arc is the negative natural
+    /// log of the fraction of its source state's probability that goes along it, and final states
+    /// have a weight of 0, so the lattice can be compiled with the default (tropical) semiring.
+    pub fn to_openfst_text(&self) -> String {
+        let mut text = String::new();
+        for arc in &self.arcs {
+            let label = arc.label.map_or(0, |column| column + 1);
+            let weight = self.states[arc.from].score - arc.score;
+            text.push_str(&format!(
+                "{} {} {} {} {}\n",
+                arc.from, arc.to, label, label, weight
+            ));
+        }
+        for state in self.final_states() {
+            text.push_str(&format!("{} 0\n", state));
+        }
+        text
+    }
+}
+
+/// Perform a CTC beam search decode on an RNN output, returning a lattice of the states it kept.
+///
+/// This is the same search as `beam_search_scored` (so the first final state of the lattice is the
+/// labelling that returns), but it records the beam at every timestep, and how the paths of each
+/// entry moved on to the next beam. See `Lattice` for the format. This is intended for rescoring
+/// the hypotheses with other models: it takes memory proportional to the length of
+/// `network_output` times `beam_size`, and recording it slows the search down.
+pub fn beam_search_lattice<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<Lattice, SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::<LogSpace>::new();
+    state.lattice = Some(LatticeRecord {
+        states: Vec::new(),
+        arcs: Vec::new(),
+        beam_start: 0,
+    });
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        LogSpace::new(options.beam_cut_threshold),
+        LogSpace::new,
+    )?;
+    let BeamState {
+        suffix_tree,
+        lattice,
+        ..
+    } = state;
+    let LatticeRecord { states, arcs, .. } = lattice.expect("the lattice was recorded");
+
+    let states = states
+        .into_iter()
+        .map(|(timestep, node, prob)| LatticeState {
+            timestep,
+            node,
+            score: prob.ln(),
+        })
+        .collect::<Vec<_>>();
+    let arcs = arcs
+        .into_iter()
+        .map(|(from, to, prob)| {
+            let label = if states[from].node == states[to].node {
+                None
+            } else {
+                suffix_tree
+                    .label(states[to].node)
+                    .map(|label| label_column(label, options.blank_id))
+            };
+            LatticeArc {
+                from,
+                to,
+                label,
+                score: prob.ln(),
+            }
+        })
+        .collect();
+    Ok(Lattice {
+        states,
+        arcs,
+        suffix_tree,
+        alphabet: alphabet.to_vec(),
+        blank_id: options.blank_id,
+    })
+}
+
+/// How a beam search used its beam, returned by `beam_search_with_stats`.
+///
+/// If `beam_occupancy` is often below `beam_size`, the beam is bigger than it needs to be (or
+/// `beam_cut_threshold` is pruning most extensions). If `beam_pruned` is large, `beam_size` is what
+/// limits the search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DecodeStats {
+    /// For each row of the network output, the number of labellings left in the beam after it was
+    /// cut down to `beam_size`.
+    pub beam_occupancy: Vec<usize>,
+    /// The number of labellings cut from the beam (by `beam_size` or `beam_cut_relative`), over
+    /// all the rows.
+    pub beam_pruned: usize,
+    /// The number of extensions of the beam (with a label, or the blank) that were not made
+    /// because their probability was below `beam_cut_threshold`, over all the rows.
+    pub threshold_pruned: usize,
+    /// The number of nodes in the labelling tree at the end of the search, not counting the
+    /// root. Unless nodes were evicted, the tree only grows, so this is also its largest size.
+    pub tree_size: usize,
+    /// The number of nodes evicted from the labelling tree to keep it within
+    /// `BeamSearchOptions::max_tree_nodes`, over all the rows.
+    pub evicted_nodes: usize,
+}
+
+/// Perform a CTC beam search decode on an RNN output, also returning how the beam was used.
+///
+/// This is the same search as `beam_search_with`, and returns the same sequence, timepoints and
+/// probability, followed by the statistics of the search. Collecting them adds very little to the
+/// time the search takes.
+pub fn beam_search_with_stats<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32, DecodeStats), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    state.stats = Some(DecodeStats::default());
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree,
+        beam,
+        stats,
+        ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    let prob = beam[0].probability() / normalize_denominator;
+    let stats = DecodeStats {
+        tree_size: suffix_tree.len(),
+        ..stats.expect("the stats were collected")
+    };
+    Ok((sequence, path, prob, stats))
+}
+
+/// Perform a CTC beam search decode on a batch of RNN outputs.
+///
+/// `network_output` has the batch on the outer axis, then time, then labels. Each item of the
+/// batch is decoded as by `beam_search`, and the results are returned in batch order. The first
+/// item that fails to decode gives the error for the whole batch.
+///
+/// With the `rayon` feature, the items are decoded in parallel on the current rayon thread pool
+/// (run this inside `ThreadPool::install` to limit the number of threads used). Each item still
+/// gets its own search tree, so the results are the same as decoding them one at a time.
+///
+/// Rows of zeros are not treated specially, so zero padding at the end of shorter items will
+/// change their results: with a positive `beam_cut_threshold` it makes the search run out of
+/// beam, and otherwise every path ends up with zero probability. Pad with rows that give the
+/// blank label a probability of 1.0 instead, as these leave every labelling unchanged.
+pub fn beam_search_batch<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix3>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<Vec<(String, Vec<usize>, f32)>, SearchError> {
+    #[cfg(feature = "rayon")]
+    use ndarray::parallel::prelude::*;
+
+    #[cfg(feature = "rayon")]
+    let items = network_output.axis_iter(Axis(0)).into_par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let items = network_output.outer_iter();
+
+    let options =
+        BeamSearchOptions::from_args(beam_size, beam_cut_threshold, collapse_repeats, blank_id);
+    #[cfg(feature = "rayon")]
+    let results = items.map_init(
+        || Decoder::new(options.clone()),
+        |decoder, item| decoder.decode(&item, alphabet),
+    );
+    #[cfg(not(feature = "rayon"))]
+    let results = {
+        let mut decoder = Decoder::new(options);
+        items.map(move |item| decoder.decode(&item, alphabet))
+    };
+    results.collect()
+}
+
+/// Perform a CTC beam search decode on each of a sequence of RNN outputs, as they are needed.
+///
+/// The network outputs are taken from `reads` one at a time, as the returned iterator is advanced,
+/// and each is decoded as by `beam_search_with` and then dropped, so however many there are, only
+/// one is held at once. The decodes share one `Decoder`, which keeps its storage from one to the
+/// next. A read that fails to decode gives an error in its place, and the reads after it are
+/// still decoded.
+pub fn decode_all<'a, I, D>(
+    reads: I,
+    alphabet: &'a [String],
+    options: &BeamSearchOptions,
+) -> impl Iterator<Item = Result<(String, Vec<usize>, f32), SearchError>> + 'a
+where
+    I: IntoIterator<Item = ArrayBase<D, Ix2>>,
+    I::IntoIter: 'a,
+    D: Data<Elem = f32>,
+{
+    let mut decoder = Decoder::new(options.clone());
+    reads
+        .into_iter()
+        .map(move |read| decoder.decode(&read, alphabet))
+}
+
+/// Perform a CTC beam search decode on an RNN output, returning up to `n` labellings.
+///
+/// This is the same search as `beam_search`, but rather than just the best labelling, it returns
+/// the `n` most probable distinct labellings left in the beam (or fewer, if the beam has less than
+/// `n` entries). Each entry is the decoded sequence, the final timepoint of each label and the
+/// probability of that labelling relative to the others remaining in the beam. The entries are
+/// sorted by decreasing probability.
+pub fn beam_search_nbest<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+    n: usize,
+) -> Result<Vec<(String, Vec<usize>, f32)>, SearchError> {
+    let options =
+        BeamSearchOptions::from_args(beam_size, beam_cut_threshold, collapse_repeats, blank_id);
+    check_beam_args(
+        network_output,
+        alphabet,
+        &options,
+        beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        &options,
+        &Guidance::default(),
+        beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    Ok(beam
+        .iter()
+        .take(n)
+        .map(|x| {
+            let (sequence, path) = labelling(&suffix_tree, x.node, alphabet, blank_id);
+            (sequence, path, x.probability() / normalize_denominator)
+        })
+        .collect())
+}
+
+/// Perform a CTC beam search decode on an RNN output, with a quality score for each character.
+///
+/// This is the same search as `beam_search`, but also returns, for each character of the decoded
+/// sequence, the probability `network_output` gives to its label at the timepoint returned for
+/// that label. Labels that are more than one character long give the same score to each of their
+/// characters, so the scores always line up with the characters of the sequence.
+pub fn beam_search_with_qualities<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<(String, Vec<usize>, Vec<f32>), SearchError> {
+    let options =
+        BeamSearchOptions::from_args(beam_size, beam_cut_threshold, collapse_repeats, blank_id);
+    let (sequence, path, _, qualities) =
+        beam_search_qualities_with(network_output, alphabet, &options)?;
+    Ok((sequence, path, qualities))
+}
+
+/// Perform a CTC beam search decode on an RNN output, with a quality score for each character.
+///
+/// This is `beam_search_with_qualities` with the parameters given by `options`. It returns the
+/// decoded sequence, the final timepoint of each label and the probability of the labelling
+/// relative to the others remaining in the beam (all as for `beam_search_with`), followed by the
+/// quality score of each character.
+pub fn beam_search_qualities_with<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32, Vec<f32>), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let mut qualities = Vec::new();
+    if beam[0].node != ROOT_NODE {
+        for (label, &time) in suffix_tree.iter_from(beam[0].node) {
+            let column = label_column(label, options.blank_id);
+            let scale = options.row_scale(time, network_output.row(time))?;
+            let prob = network_output[(time, column)] * scale;
+            qualities.extend(alphabet[column].chars().map(|_| prob));
+        }
+    }
+    qualities.reverse();
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+        qualities,
+    ))
+}
+
+/// Perform a CTC beam search decode on an RNN output that is already in log space.
+///
+/// This is the same search as `beam_search`, but each entry of `network_output` is taken to be a
+/// natural-log probability (such as the output of a log-softmax layer), and `beam_cut_threshold`
+/// is a natural-log probability too: it must be less than `ln(1/len(alphabet))`. Path
+/// probabilities are accumulated in log space, so long sequences do not underflow. Note that
+/// with the `fastexp` feature, adding probabilities in log space uses the same approximation of
+/// `exp` as the duplex search.
+///
+/// Returns the decoded sequence, the final timepoint of each label and the natural log of the
+/// probability of the chosen labelling relative to the others remaining in the beam.
+pub fn beam_search_log<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let options =
+        BeamSearchOptions::from_args(beam_size, beam_cut_threshold, collapse_repeats, blank_id);
+    check_beam_args(network_output, alphabet, &options, beam_cut_threshold, true)?;
+    log_beam_search(
+        network_output,
+        alphabet,
+        &options,
+        LogSpace::from_ln(beam_cut_threshold),
+    )
+}
+
+/// Perform a CTC beam search decode on an RNN output that is already in log space, as configured
+/// by `options`.
+///
+/// Each entry of `network_output` is taken to be a natural-log probability, as for
+/// `beam_search_log`. Unlike there, the options' `beam_cut_threshold` is still a probability (so
+/// the default of 0.0 is no threshold): set it with `BeamSearchOptions::beam_cut_threshold_log`
+/// to give it as a natural log.
+///
+/// Returns the decoded sequence, the final timepoint of each label and the natural log of the
+/// probability of the chosen labelling relative to the others remaining in the beam.
+pub fn beam_search_log_with<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    log_beam_search(
+        network_output,
+        alphabet,
+        options,
+        LogSpace::new(options.beam_cut_threshold),
+    )
+}
+
+/// The beam search of `beam_search_log` and `beam_search_log_with`, once the arguments have been
+/// checked.
+fn log_beam_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    beam_cut_threshold: LogSpace,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let mut state = BeamState::<LogSpace>::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        beam_cut_threshold,
+        LogSpace::from_ln,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let mut normalize_denominator = LogSpace::zero();
+    for x in &beam {
+        normalize_denominator += x.probability();
+    }
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability().ln() - normalize_denominator.ln(),
+    ))
+}
+
+/// How much the `fastexp` approximation changed the results of decoding some reads.
+///
+/// See `compare_fastexp`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FastExpReport {
+    /// The number of reads decoded.
+    pub reads: usize,
+    /// The number of reads whose labelling was different with the approximation.
+    pub changed: usize,
+    /// The largest difference, as a natural log, between the probabilities of the labellings
+    /// found with and without the approximation, over the reads whose labelling did not change.
+    pub max_log_prob_deviation: f32,
+}
+
+impl FastExpReport {
+    /// The fraction of the reads whose labelling was different with the approximation.
+    pub fn changed_fraction(&self) -> f32 {
+        if self.reads == 0 {
+            0.0
+        } else {
+            self.changed as f32 / self.reads as f32
+        }
+    }
+}
+
+/// Measure how much the `fastexp` approximation changes the results of the log space searches.
+///
+/// Each of `reads` (with probabilities as for `beam_search`) is decoded in log space twice: once
+/// the way `beam_search_log` and the duplex search add probabilities, and once using the standard
+/// library's `exp`. Without the `fastexp` feature these are the same, so nothing changes.
+/// Running this over a representative set of reads shows whether the speedup is worth it.
+pub fn compare_fastexp<D: Data<Elem = f32>>(
+    reads: &[ArrayBase<D, Ix2>],
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<FastExpReport, SearchError> {
+    /// The labelling and the natural log of its probability relative to the rest of the beam.
+    fn decode<X: Exp, D: Data<Elem = f32>>(
+        state: &mut BeamState<LogSpace<X>>,
+        network_output: &ArrayBase<D, Ix2>,
+        alphabet: &[String],
+        options: &BeamSearchOptions,
+    ) -> Result<(String, f32), SearchError> {
+        run_beam_search(
+            state,
+            network_output,
+            alphabet,
+            options,
+            &Guidance::default(),
+            LogSpace::new(options.beam_cut_threshold),
+            LogSpace::new,
+        )?;
+        let mut normalize_denominator = LogSpace::zero();
+        for x in &state.beam {
+            normalize_denominator += x.probability();
+        }
+        let (sequence, _) = labelling(
+            &state.suffix_tree,
+            state.beam[0].node,
+            alphabet,
+            options.blank_id,
+        );
+        Ok((
+            sequence,
+            state.beam[0].probability().ln() - normalize_denominator.ln(),
+        ))
+    }
+
+    let mut report = FastExpReport {
+        reads: reads.len(),
+        changed: 0,
+        max_log_prob_deviation: 0.0,
+    };
+    let mut fast_state = BeamState::<LogSpace<DefaultExp>>::new();
+    let mut exact_state = BeamState::<LogSpace<StdExp>>::new();
+    for network_output in reads {
+        check_beam_args(
+            network_output,
+            alphabet,
+            options,
+            options.beam_cut_threshold,
+            false,
+        )?;
+        let (fast, fast_prob) = decode(&mut fast_state, network_output, alphabet, options)?;
+        let (exact, exact_prob) = decode(&mut exact_state, network_output, alphabet, options)?;
+        if fast == exact {
+            let deviation = (fast_prob - exact_prob).abs();
+            report.max_log_prob_deviation = report.max_log_prob_deviation.max(deviation);
+        } else {
+            report.changed += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Perform a CTC beam search decode on an RNN output, returning the score of the chosen labelling.
+///
+/// This takes the same arguments as `beam_search_with`, and returns the decoded sequence, the
+/// final timepoint of each label and the natural log of the summed probability of the paths that
+/// give the chosen labelling. Unlike the probability returned by `beam_search_with`, this is not
+/// relative to the other labellings in the beam, so it can be compared between reads. It is
+/// always at most 0.0, and gets lower as reads get longer.
+///
+/// The sum only covers the paths the search kept: those through labellings that were dropped from
+/// the beam, or through entries below `beam_cut_threshold`, are left out. With a beam large enough
+/// to keep every labelling and no threshold, it is the full probability of the labelling.
+///
+/// The probabilities are added up in log space (like `beam_search_log`) so that they do not
+/// underflow on long reads. With the `fastexp` feature, this can occasionally choose a different
+/// labelling to `beam_search_with` where two labellings are almost equally likely.
+pub fn beam_search_scored<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let BeamState {
+        suffix_tree, beam, ..
+    } = scored_search(network_output, alphabet, options)?;
+
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((sequence, path, beam[0].probability().ln()))
+}
+
+/// Perform a CTC beam search decode on an RNN output, returning every labelling whose score is
+/// close to the best.
+///
+/// This is the same search as `beam_search_scored`, but returns each labelling left in the beam
+/// whose score is at most `delta` below the chosen labelling's, rather than only the best. Each
+/// entry is the decoded sequence, the final timepoint of each label and the score (the natural log
+/// of the summed probability of the labelling's paths). The entries are distinct labellings,
+/// sorted by decreasing score, and the first is always the one `beam_search_scored` returns. With
+/// a `delta` of 0.0, only the labellings that tie with the best are returned.
+///
+/// The labellings come from the final beam, so there are at most `beam_size` of them, and a large
+/// `delta` just returns the whole beam. With `BeamSearchOptions::min_length`, only the labellings
+/// of at least that many labels are returned, unless the beam has none.
+pub fn beam_search_within<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    delta: f32,
+) -> Result<Vec<(String, Vec<usize>, f32)>, SearchError> {
+    if delta.is_nan() || delta < 0.0 {
+        return Err(SearchError::InvalidDelta);
+    }
+    let BeamState {
+        suffix_tree, beam, ..
+    } = scored_search(network_output, alphabet, options)?;
+
+    let best = beam[0].probability().ln();
+    // the labellings shorter than `min_length` are at the back of the beam, and only count when
+    // there are no others
+    let min_length = if beam[0].length >= options.min_length {
+        options.min_length
+    } else {
+        0
+    };
+    Ok(beam
+        .iter()
+        .map(|x| (x, x.probability().ln()))
+        .take_while(|&(x, score)| x.length >= min_length && best - score <= delta)
+        .map(|(x, score)| {
+            let (sequence, path) = labelling(&suffix_tree, x.node, alphabet, options.blank_id);
+            (sequence, path, score)
+        })
+        .collect())
+}
+
+/// Run the search of `beam_search_scored`, returning the final state.
+fn scored_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<BeamState<LogSpace>, SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        LogSpace::new(options.beam_cut_threshold),
+        LogSpace::new,
+    )?;
+    Ok(state)
+}
+
+/// Perform a CTC beam search decode on an RNN output given in double precision.
+///
+/// This is the same search as `beam_search`, but `network_output` and `beam_cut_threshold` are
+/// `f64`, and path probabilities are accumulated as `f64` too.
+pub fn beam_search_f64<D: Data<Elem = f64>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    beam_size: usize,
+    beam_cut_threshold: f64,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<(String, Vec<usize>, f64), SearchError> {
+    let options = BeamSearchOptions::from_args(beam_size, 0.0, collapse_repeats, blank_id);
+    check_beam_args(
+        network_output,
+        alphabet,
+        &options,
+        beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        &options,
+        &Guidance::default(),
+        beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let normalize_denominator: f64 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Perform a CTC beam search decode on a quantized RNN output.
+///
+/// Each entry `x` of `network_output` stands for the probability `(x - zero_point) * scale`. The
+/// entries are dequantized as the search reaches them, so there is no need to make a dequantized
+/// copy of `network_output` first. The beam cut threshold in `options` applies to the dequantized
+/// probabilities. Otherwise, this is the same search as `beam_search_with`.
+///
+/// `scale` must be finite and greater than 0.0.
+pub fn beam_search_quantized<D: Data<Elem = u8>>(
+    network_output: &ArrayBase<D, Ix2>,
+    scale: f32,
+    zero_point: i32,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(SearchError::InvalidScale);
+    }
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        // in i64, as `x - zero_point` overflows an i32 for a zero point near i32::MIN
+        |x| (i64::from(x) - i64::from(zero_point)) as f32 * scale,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Perform a CTC beam search decode on an RNN output, limited to a sequence of words.
+///
+/// This is the same search as `beam_search_with`, except that labellings are only extended
+/// while they can still be split into words from `lexicon`, separated by the label in column
+/// `separator` of `alphabet` (so, for example, the separator can be emitted only at the end of a
+/// word). The result is the most probable labelling left in the beam that does not end part way
+/// through a word, or just the most probable one if they all do.
+pub fn beam_search_lexicon<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    lexicon: &Lexicon,
+    separator: usize,
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    if separator >= alphabet.len() || options.is_blank(separator) {
+        return Err(SearchError::InvalidSeparator);
+    }
+    let guidance = Guidance {
+        lexicon: Some(LexiconGuide {
+            lexicon,
+            alphabet,
+            separator,
+        }),
+        ..Guidance::default()
+    };
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &guidance,
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let best = beam
+        .iter()
+        .find(|x| x.state == Lexicon::ROOT || lexicon.is_word(x.state))
+        .unwrap_or(&beam[0]);
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, best.node, alphabet, options.blank_id);
+    Ok((sequence, path, best.probability() / normalize_denominator))
+}
+
+/// Perform a CTC beam search decode on an RNN output, with labels pinned to windows of time.
+///
+/// This is the same search as `beam_search_with`, except that each anchor `(timesteps, column)`
+/// of `anchors` requires the labelling to emit the label in `column` at a timepoint in the window
+/// `timesteps` (the timepoints are the ones returned with the labelling). At the last row of each
+/// window, the labellings that have not done so are pruned from the beam, before it is cut down
+/// to `beam_size`, so that the beam is filled with labellings that meet the anchors so far.
+/// Windows that run past the last row are checked at the end of the search. One emission can meet
+/// several anchors, if they are for the same label and their windows overlap.
+///
+/// If no labelling in the beam meets the anchors due at a row, which is always the case for
+/// anchors that cannot all be met (for example, anchors for different labels with the same
+/// window of one timestep, or a window that starts after the last row), the search fails with
+/// `SearchError::RanOutOfBeam` at that row (or at the number of rows, for the check at the end).
+/// An anchor with an empty window, or a column that is past the end of `alphabet` or a blank,
+/// fails with `SearchError::InvalidAnchor`.
+pub fn beam_search_anchored<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    anchors: &[(Range<usize>, usize)],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    if anchors.iter().any(|(timesteps, column)| {
+        timesteps.is_empty() || *column >= alphabet.len() || options.is_blank(*column)
+    }) {
+        return Err(SearchError::InvalidAnchor);
+    }
+    let guidance = Guidance {
+        anchors,
+        ..Guidance::default()
+    };
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &guidance,
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree,
+        mut beam,
+        ..
+    } = state;
+
+    let n_rows = network_output.nrows();
+    beam.retain(|x| {
+        guidance.meets_anchors(&suffix_tree, x.node, options.blank_id, |timesteps| {
+            timesteps.end > n_rows
+        })
+    });
+    if beam.is_empty() {
+        return Err(SearchError::RanOutOfBeam {
+            at_timestep: n_rows,
+        });
+    }
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Perform a CTC beam search decode on an RNN output, biased by a language model.
+///
+/// This is the same search as `beam_search_with`, but each time a path emits a label (not a blank
+/// or a collapsed repeat), `weight * language_model.score(prefix, label)` is added to the log
+/// probability of the path, where `prefix` is the labelling before that label. A `weight` of 0.0
+/// gives the same result as `beam_search_with`.
+///
+/// The returned probability is relative to the others remaining in the beam, using the combined
+/// scores.
+pub fn beam_search_lm<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    language_model: &dyn LanguageModel,
+    weight: f32,
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let guidance = Guidance {
+        language_model: Some((language_model, weight)),
+        ..Guidance::default()
+    };
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &guidance,
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Perform a CTC beam search decode on an RNN output, letting `hook` rescore the labellings at
+/// each timestep.
+///
+/// This is the same search as `beam_search_with`, but after each row of `network_output`, once
+/// the beam has been extended with the row and the paths that give the same labelling have been
+/// added up, `hook.rescore` is called with every candidate for the new beam. That is before they
+/// are sorted and cut down to `beam_size` (or by `beam_cut_relative`), so the rescored
+/// probabilities decide which of them are kept. Extensions the `beam_cut_threshold` rules out
+/// have already been left out, and so have labellings that miss an anchor.
+///
+/// A candidate's probability is carried into every labelling that extends it, so a change made
+/// at one step stays with the labelling from then on: a hook that scores each label once should
+/// do so at the step the label is added (the step its node holds in `tree`), as
+/// `beam_search_lm` does with its language model. Probabilities should not be made negative, and
+/// one made NaN fails the search with `SearchError::IncomparableValues`.
+///
+/// Nodes are only added to the tree, so a node means the same labelling at every step, unless
+/// `max_tree_nodes` is set: the tree is then renumbered when nodes are removed from it.
+///
+/// The returned probability is relative to the others remaining in the beam, using the
+/// rescored probabilities.
+pub fn beam_search_hooked<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+    hook: &mut dyn BeamHook,
+) -> Result<(String, Vec<usize>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let guidance = Guidance {
+        hook: Some(RefCell::new(hook)),
+        ..Guidance::default()
+    };
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &guidance,
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Find the most probable path through `network_output` that gives the labelling `columns`.
+///
+/// The states of the path alternate between blanks and the labels of `columns`, starting and
+/// ending with a blank, so state `2 * k + 1` is label `k`. Returns the state the path is in at
+/// each timestep, and the natural log of the probability of the path. If the labelling is
+/// impossible (for example because there are not enough rows for it), the probability is zero,
+/// and the path does not give the labelling.
+fn align<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    columns: &[usize],
+    options: &BeamSearchOptions,
+) -> (Vec<usize>, f32) {
+    let collapse = options.collapse;
+    // states alternate between blanks and the labels of `columns`, starting and ending with a
+    // blank
+    let n_states = 2 * columns.len() + 1;
+    // paths start on the first blank, or on the first label if they can emit at the first row
+    let first_states = if options.emit_at_first_row { 2 } else { 1 };
+    let mut scores = vec![f32::NEG_INFINITY; n_states];
+    let mut next_scores = vec![f32::NEG_INFINITY; n_states];
+    // the state each state at each timestep was reached from
+    let mut from = Vec::with_capacity(network_output.shape()[0] * n_states);
+
+    for (time, pr) in network_output.outer_iter().enumerate() {
+        let blank_score = options.blank_prob(|column| pr[column]).ln();
+        for s in 0..n_states {
+            let mut best = (f32::NEG_INFINITY, s);
+            if time == 0 {
+                if s < first_states {
+                    best.0 = 0.0;
+                }
+            } else {
+                // labels can only last more than one timestep if they continue themselves
+                if s % 2 == 0 || collapse.merges(columns[s / 2], columns[s / 2]) {
+                    best = (scores[s], s);
+                }
+                if s >= 1 && scores[s - 1] > best.0 {
+                    best = (scores[s - 1], s - 1);
+                }
+                if s % 2 == 1
+                    && s >= 3
+                    && !collapse.merges(columns[s / 2 - 1], columns[s / 2])
+                    && scores[s - 2] > best.0
+                {
+                    best = (scores[s - 2], s - 2);
+                }
+            }
+            next_scores[s] = best.0
+                + if s % 2 == 0 {
+                    blank_score
+                } else {
+                    pr[columns[s / 2]].ln()
+                };
+            from.push(best.1);
+        }
+        core::mem::swap(&mut scores, &mut next_scores);
+    }
+
+    let n_rows = network_output.shape()[0];
+    let mut path = vec![0; n_rows];
+    if n_rows == 0 {
+        let score = if columns.is_empty() {
+            0.0
+        } else {
+            f32::NEG_INFINITY
+        };
+        return (path, score);
+    }
+    let mut s = n_states - 1;
+    if n_states > 1 && scores[n_states - 2] > scores[s] {
+        s = n_states - 2;
+    }
+    let score = scores[s];
+    for time in (0..n_rows).rev() {
+        path[time] = s;
+        s = from[time * n_states + s];
+    }
+    (path, score)
+}
+
+/// The path of `align`, failing with `SearchError::UnalignableTarget` if it does not give the
+/// labelling `columns`.
+///
+/// As well as an impossible labelling, this catches a `network_output` with negative values in it,
+/// whose logs are NaN: the path is then not the most probable one, and may miss labels.
+fn align_path<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    columns: &[usize],
+    options: &BeamSearchOptions,
+) -> Result<Vec<usize>, SearchError> {
+    let (states, score) = align(network_output, columns, options);
+    if score > f32::NEG_INFINITY {
+        Ok(states)
+    } else {
+        Err(SearchError::UnalignableTarget)
+    }
+}
+
+/// The span of each label of the labelling `columns` in the most probable path through
+/// `network_output` that gives it (see `align`).
+///
+/// Labels that are not on the path are `None`, which only happens if the labelling is impossible.
+pub(crate) fn label_spans<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    columns: &[usize],
+    options: &BeamSearchOptions,
+) -> Vec<Option<Span>> {
+    let (states, _) = align(network_output, columns, options);
+    path_spans(&states, columns.len())
+}
+
+/// The span of each of `n_labels` labels in the path `states` (see `align`), or `None` for labels
+/// the path does not reach.
+fn path_spans(states: &[usize], n_labels: usize) -> Vec<Option<Span>> {
+    let mut spans: Vec<Option<Span>> = vec![None; n_labels];
+    for (time, &s) in states.iter().enumerate() {
+        if s % 2 == 1 {
+            let span = &mut spans[s / 2];
+            *span = Some((span.map_or(time, |x| x.0), time));
+        }
+    }
+    spans
+}
+
+/// The columns of the labels that make up `target`, matching the longest label token of
+/// `alphabet` at each point (blanks and empty tokens are never matched).
+fn target_columns(
+    target: &str,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<Vec<usize>, SearchError> {
+    let mut columns = Vec::new();
+    let mut rest = target;
+    while !rest.is_empty() {
+        let column = alphabet
+            .iter()
+            .enumerate()
+            .filter(|&(column, token)| {
+                !options.is_blank(column) && !token.is_empty() && rest.starts_with(token.as_str())
+            })
+            .max_by_key(|&(column, token)| (token.len(), Reverse(column)))
+            .map(|(column, _)| column)
+            .ok_or(SearchError::InvalidTarget)?;
+        rest = &rest[alphabet[column].len()..];
+        columns.push(column);
+    }
+    Ok(columns)
+}
+
+/// The natural log of the probability `network_output` gives the labelling `target`.
+///
+/// This is the exact CTC forward algorithm for a single labelling: it sums the probabilities of
+/// every path through `network_output` that gives `target`, with no beam and no approximation,
+/// taking time proportional to the rows of `network_output` times the labels of `target`. It is
+/// the probability a beam search estimates for each labelling it keeps, so it can show how far
+/// off those estimates are, or how likely a labelling the search did not return is.
+///
+/// `target` is split into labels by matching the longest token of `alphabet` at each point. Paths
+/// give labellings as in `beam_search_with` with the same `options` (so repeats are collapsed
+/// according to `BeamSearchOptions::collapse`, and `extra_blank_ids` count as blanks), but the
+/// options that only shape the search, such as the beam size and the beam cut threshold, are
+/// ignored. A labelling no path gives, for example because it has more labels than
+/// `network_output` has rows, scores negative infinity.
+pub fn score_labelling<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    target: &str,
+    options: &BeamSearchOptions,
+) -> Result<f32, SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let columns = target_columns(target, alphabet, options)?;
+    score_columns(network_output, &columns, options)
+}
+
+/// The natural log of the probability `network_output` (time-major) gives the labelling of
+/// `columns`, for `score_labelling`.
+fn score_columns(
+    network_output: &ArrayView2<f32>,
+    columns: &[usize],
+    options: &BeamSearchOptions,
+) -> Result<f32, SearchError> {
+    let collapse = options.collapse;
+    // states alternate between blanks and the labels of `columns`, starting and ending with a
+    // blank, as for `align`
+    let n_states = 2 * columns.len() + 1;
+    // paths start on the first blank, or on the first label if they can emit at the first row
+    let first_states = if options.emit_at_first_row { 2 } else { 1 };
+    let mut probs = vec![LogSpace::<StdExp>::zero(); n_states];
+    let mut next_probs = probs.clone();
+
+    for (time, pr) in network_output.outer_iter().enumerate() {
+        let scale = options.row_scale(time, pr)?;
+        let pr = |column: usize| pr[column] * scale;
+        let blank_prob = LogSpace::new(options.blank_prob(pr));
+        for s in 0..n_states {
+            let mut prob = LogSpace::zero();
+            if time == 0 {
+                if s < first_states {
+                    prob = LogSpace::one();
+                }
+            } else {
+                // labels can only last more than one timestep if they continue themselves
+                if s % 2 == 0 || collapse.merges(columns[s / 2], columns[s / 2]) {
+                    prob += probs[s];
+                }
+                if s >= 1 {
+                    prob += probs[s - 1];
+                }
+                if s % 2 == 1 && s >= 3 && !collapse.merges(columns[s / 2 - 1], columns[s / 2]) {
+                    prob += probs[s - 2];
+                }
+            }
+            next_probs[s] = prob
+                * if s % 2 == 0 {
+                    blank_prob
+                } else {
+                    LogSpace::new(pr(columns[s / 2]))
+                };
+        }
+        core::mem::swap(&mut probs, &mut next_probs);
+    }
+
+    if network_output.nrows() == 0 {
+        return Ok(if columns.is_empty() {
+            0.0
+        } else {
+            f32::NEG_INFINITY
+        });
+    }
+    let mut total = probs[n_states - 1];
+    if n_states > 1 {
+        total += probs[n_states - 2];
+    }
+    Ok(total.ln())
+}
+
+/// The most probable labelling of at most `max_len` labels, and the natural log of its
+/// probability, found by scoring every one of them with `score_labelling`.
+///
+/// This is a reference for testing the searches against: with the default options, and
+/// `max_len` at least the number of rows, it is the labelling a beam search with no pruning
+/// should find. The number of labellings grows exponentially with `max_len`, so it is only
+/// feasible for tiny inputs. Ties go to the shorter labelling, then the one whose labels come
+/// first in `alphabet`.
+#[cfg(test)]
+pub(crate) fn exact_search(
+    network_output: &Array2<f32>,
+    alphabet: &[String],
+    max_len: usize,
+) -> (String, f32) {
+    let options = BeamSearchOptions::default();
+    let network_output = &network_output.view();
+    let n_labels = alphabet.len() - 1;
+    let mut best = (
+        Vec::new(),
+        score_columns(network_output, &[], &options).unwrap(),
+    );
+    // the labellings of each length in turn, as the digits of a number in base `n_labels`
+    let mut labelling = Vec::new();
+    for len in 1..=max_len {
+        labelling.clear();
+        labelling.resize(len, 0);
+        loop {
+            let columns: Vec<usize> = labelling.iter().map(|label| label + 1).collect();
+            let score = score_columns(network_output, &columns, &options).unwrap();
+            if score > best.1 {
+                best = (columns, score);
+            }
+            match labelling.iter().rposition(|&label| label + 1 < n_labels) {
+                Some(digit) => {
+                    labelling[digit] += 1;
+                    labelling[digit + 1..].fill(0);
+                }
+                None => break,
+            }
+        }
+    }
+    let (columns, score) = best;
+    let sequence = columns
+        .iter()
+        .map(|&column| alphabet[column].as_str())
+        .collect();
+    (sequence, score)
+}
+
+/// Perform a CTC beam search decode on an RNN output, also returning a path through it.
+///
+/// This is the same search as `beam_search_with`, but rather than the probability of the
+/// labelling, it returns the column of `network_output` (blank or label) at each timestep of the
+/// most probable path that gives the chosen labelling. The search only tracks the probabilities
+/// of whole labellings, so this path is found afterwards by aligning the labelling with
+/// `network_output`. It is the most probable path for that labelling, but there may be more
+/// probable paths that give other labellings. The alignment uses `network_output` as it is, and
+/// does not take account of `blank_bias`.
+///
+/// The final timepoints of the labels come from the search, as for `beam_search_with`, and may
+/// not match the path.
+///
+/// Fails with `SearchError::UnalignableTarget` if no path with a probability above zero gives the
+/// chosen labelling. The search only chooses such a labelling when `network_output` is not made
+/// of probabilities (with negative values in it, say), or when every labelling has a probability
+/// of zero.
+pub fn beam_search_with_path<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, Vec<usize>), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let mut columns = Vec::new();
+    if beam[0].node != ROOT_NODE {
+        columns.extend(
+            suffix_tree
+                .iter_from_no_data(beam[0].node)
+                .map(|label| label_column(label, options.blank_id)),
+        );
+    }
+    columns.reverse();
+    let frames = align_path(network_output, &columns, options)?
+        .into_iter()
+        .map(|s| {
+            if s % 2 == 0 {
+                options.blank_id
+            } else {
+                columns[s / 2]
+            }
+        })
+        .collect();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((sequence, path, frames))
+}
+
+/// Perform a CTC beam search decode on an RNN output, also returning the label marginals.
+///
+/// This is the same search as `beam_search_with`, and returns the same sequence, timepoints and
+/// probability, followed by a `Vec2D` the same shape as `network_output`. Entry `(t, c)` is the
+/// share of the probability of the beam at timestep `t` (after it was cut down to size) in paths
+/// that take column `c` there, so each row sums to 1. Paths through any of the blank columns
+/// count towards `blank_id` (see `BeamSearchOptions::extra_blank_ids`). Unlike `network_output`,
+/// this only counts the labellings the search kept, so it shows where the search was uncertain.
+pub fn beam_search_with_marginals<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<usize>, f32, Vec2D<f32>), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    state.marginals = Some(Vec2D::new(alphabet.len()));
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree,
+        beam,
+        marginals,
+        ..
+    } = state;
+
+    let mut marginals = marginals.unwrap();
+    for row in marginals.rows_mut() {
+        let total: f32 = row.iter().sum();
+        for x in row {
+            *x /= total;
+        }
+    }
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, path) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        path,
+        beam[0].probability() / normalize_denominator,
+        marginals,
+    ))
+}
+
+/// The first and last timesteps (inclusive) of a label.
+pub type Span = (usize, usize);
+
+/// Perform a CTC beam search decode on an RNN output, returning the span of each label.
+///
+/// This is the same search as `beam_search_with`, but instead of one timepoint per label it
+/// returns the first and last timesteps (inclusive) of the label in the most probable path for
+/// the chosen labelling (see `beam_search_with_path`). So `start <= end`, both are rows of
+/// `network_output`, and the spans of successive labels do not overlap.
+///
+/// The timepoints from `beam_search_with` are where the search tree first reached each label,
+/// which may be on a different path, so they can be earlier than the starts. This fails where
+/// `beam_search_with_path` does.
+pub fn beam_search_with_spans<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<Span>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let mut columns = Vec::new();
+    if beam[0].node != ROOT_NODE {
+        columns.extend(
+            suffix_tree
+                .iter_from_no_data(beam[0].node)
+                .map(|label| label_column(label, options.blank_id)),
+        );
+    }
+    columns.reverse();
+    let states = align_path(network_output, &columns, options)?;
+    // every label of a possible labelling is on the path
+    let spans = path_spans(&states, columns.len())
+        .into_iter()
+        .collect::<Option<_>>()
+        .ok_or(SearchError::UnalignableTarget)?;
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, _) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        spans,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// A run of timesteps that a path spends on one label, or on blanks, returned by
+/// `beam_search_with_segments`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// The rows of `network_output` in the run.
+    pub timesteps: Range<usize>,
+    /// The index of the label in the labelling, or `None` for a run of blanks.
+    pub label: Option<usize>,
+}
+
+/// Perform a CTC beam search decode on an RNN output, splitting it into labelled and blank
+/// segments.
+///
+/// This is the same search as `beam_search_with`, and the segments come from the same path as
+/// the spans of `beam_search_with_spans` (the most probable path for the chosen labelling). They
+/// tile the whole network output: the first starts at row 0, each starts where the one before it
+/// ended, and the last ends at the number of rows, so there are none at all for a network output
+/// with no rows. Each label of the labelling has exactly one segment, in order, with its span, and
+/// the runs of blanks before, between and after them have segments of their own (a labelling with
+/// two labels that would collapse together always has one between them). Segments next to each
+/// other are never both blank. This fails where `beam_search_with_path` does.
+pub fn beam_search_with_segments<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    options: &BeamSearchOptions,
+) -> Result<(String, Vec<Segment>, f32), SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let mut state = BeamState::new();
+    run_beam_search(
+        &mut state,
+        network_output,
+        alphabet,
+        options,
+        &Guidance::default(),
+        options.beam_cut_threshold,
+        |x| x,
+    )?;
+    let BeamState {
+        suffix_tree, beam, ..
+    } = state;
+
+    let mut columns = Vec::new();
+    labelling_columns(&suffix_tree, beam[0].node, options.blank_id, &mut columns);
+    let states = align_path(network_output, &columns, options)?;
+    // state `2 * k + 1` is label `k`, and the even states are blanks (see `align`)
+    let mut segments: Vec<Segment> = Vec::new();
+    for (time, &s) in states.iter().enumerate() {
+        let label = if s % 2 == 1 { Some(s / 2) } else { None };
+        match segments.last_mut() {
+            Some(last) if last.label == label => last.timesteps.end = time + 1,
+            _ => segments.push(Segment {
+                timesteps: time..time + 1,
+                label,
+            }),
+        }
+    }
+
+    let normalize_denominator: f32 = beam.iter().map(|x| x.probability()).sum();
+    let (sequence, _) = labelling(&suffix_tree, beam[0].node, alphabet, options.blank_id);
+    Ok((
+        sequence,
+        segments,
+        beam[0].probability() / normalize_denominator,
+    ))
+}
+
+/// Align `network_output` with a known labelling, returning the span of each of its labels.
+///
+/// This is CTC forced alignment: the most probable path through `network_output` (the Viterbi
+/// path) that gives the labelling `reference`, found with no beam over every path that does.
+/// `reference` is split into labels as for `score_labelling`, and paths give labellings as in
+/// `beam_search_with` with the same `options`. The result has the first and last timesteps
+/// (inclusive) of each label, in order, as for `beam_search_with_spans`; for labels longer than
+/// one character, that is one span for the whole label.
+///
+/// Returns `SearchError::UnalignableTarget` if no path gives `reference`, because it needs more
+/// timesteps than `network_output` has (counting a blank between each pair of labels that would
+/// otherwise be collapsed), or because `network_output` gives one of the labels it needs a
+/// probability of zero.
+pub fn forced_alignment<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    reference: &str,
+    options: &BeamSearchOptions,
+) -> Result<Vec<Span>, SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let columns = target_columns(reference, alphabet, options)?;
+    let states = align_path(network_output, &columns, options)?;
+    // every label of a possible labelling is on the path
+    path_spans(&states, columns.len())
+        .into_iter()
+        .collect::<Option<_>>()
+        .ok_or(SearchError::UnalignableTarget)
+}
+
+/// The `k` most probable paths through `network_output` that give the labelling `target`, most
+/// probable first.
+///
+/// Where `forced_alignment` finds the single most probable path for a labelling, this also finds
+/// the next most probable ones, which show the other places the network could have put the same
+/// labels. Each path is the column of `network_output` (`blank_id` for a blank) at each timestep,
+/// as for `beam_search_with_path`, and comes with the natural log of its probability. The paths
+/// are all different, and there are fewer than `k` of them if fewer paths give `target` (none
+/// for a `k` of 0). `target` is split into labels, and paths give labellings, as for
+/// `forced_alignment`, and as there, `network_output` is used as it is.
+///
+/// This is a k-best Viterbi search over the same states as `forced_alignment`: rather than the
+/// best path into each state at each timestep, it keeps the best `k`. For a target of `n` labels
+/// and `t` timesteps, that takes time proportional to `t * n * k * log(k)`, and memory
+/// proportional to `t * n * k`, so `k` times what `forced_alignment` needs (and a little more
+/// for sorting).
+///
+/// Returns `SearchError::UnalignableTarget` if no path gives `target`, as for
+/// `forced_alignment`.
+pub fn top_paths_for<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    target: &str,
+    k: usize,
+    options: &BeamSearchOptions,
+) -> Result<Vec<(Vec<usize>, f32)>, SearchError> {
+    let network_output = &options.time_major(network_output);
+    check_beam_args(
+        network_output,
+        alphabet,
+        options,
+        options.beam_cut_threshold,
+        false,
+    )?;
+    let columns = target_columns(target, alphabet, options)?;
+    let collapse = options.collapse;
+    // the states of `align`: blanks and the labels of `columns` in turn, starting and ending with
+    // a blank
+    let n_states = 2 * columns.len() + 1;
+    let first_states = if options.emit_at_first_row { 2 } else { 1 };
+    // the best paths into each state at each timestep, best first, as the natural log of their
+    // probability and the state and rank (in that state's list) they came from
+    let mut ranked: Vec<Vec<(f32, usize, usize)>> =
+        Vec::with_capacity(network_output.nrows() * n_states);
+    let mut candidates = Vec::new();
+
+    for (time, pr) in network_output.outer_iter().enumerate() {
+        let blank_score = options.blank_prob(|column| pr[column]).ln();
+        let start = ranked.len();
+        for s in 0..n_states {
+            let score = if s % 2 == 0 {
+                blank_score
+            } else {
+                pr[columns[s / 2]].ln()
+            };
+            candidates.clear();
+            if time == 0 {
+                if s < first_states {
+                    candidates.push((score, s, 0));
+                }
+            } else {
+                let previous = &ranked[start - n_states..start];
+                let mut from = |state: usize| {
+                    candidates.extend(
+                        previous[state]
+                            .iter()
+                            .enumerate()
+                            .map(|(rank, &(prefix, _, _))| (prefix + score, state, rank)),
+                    );
+                };
+                // labels can only last more than one timestep if they continue themselves
+                if s % 2 == 0 || collapse.merges(columns[s / 2], columns[s / 2]) {
+                    from(s);
+                }
+                if s >= 1 {
+                    from(s - 1);
+                }
+                if s % 2 == 1 && s >= 3 && !collapse.merges(columns[s / 2 - 1], columns[s / 2]) {
+                    from(s - 2);
+                }
+            }
+            // impossible paths (and NaNs) are dropped, so the rest can be sorted
+            candidates.retain(|&(score, _, _)| score > f32::NEG_INFINITY);
+            candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+            candidates.truncate(k);
+            ranked.push(candidates.clone());
+        }
+    }
+
+    let n_rows = network_output.nrows();
+    if n_rows == 0 {
+        return if columns.is_empty() {
+            let mut paths = vec![(Vec::new(), 0.0)];
+            paths.truncate(k);
+            Ok(paths)
+        } else {
+            Err(SearchError::UnalignableTarget)
+        };
+    }
+    // paths end on the last label or the blank after it
+    let last = &ranked[(n_rows - 1) * n_states..];
+    candidates.clear();
+    for (s, ends) in last.iter().enumerate().skip(n_states.saturating_sub(2)) {
+        candidates.extend(
+            ends.iter()
+                .enumerate()
+                .map(|(rank, &(score, _, _))| (score, s, rank)),
+        );
+    }
+    if candidates.is_empty() && k > 0 {
+        return Err(SearchError::UnalignableTarget);
+    }
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    candidates.truncate(k);
+    Ok(candidates
+        .iter()
+        .map(|&(score, mut s, mut rank)| {
+            let mut path = vec![0; n_rows];
+            for time in (0..n_rows).rev() {
+                path[time] = if s % 2 == 0 {
+                    options.blank_id
+                } else {
+                    columns[s / 2]
+                };
+                let (_, from, from_rank) = ranked[time * n_states + s][rank];
+                s = from;
+                rank = from_rank;
+            }
+            (path, score)
+        })
+        .collect())
+}
+
+fn find_max(
+    acc: Option<(usize, f32)>,
+    elem_idx: usize,
+    elem_val: &f32,
+) -> FoldWhile<Option<(usize, f32)>> {
+    match acc {
+        Some((_, val)) => {
+            if *elem_val > val {
+                FoldWhile::Continue(Some((elem_idx, *elem_val)))
+            } else {
+                FoldWhile::Continue(acc)
+            }
+        }
+        None => FoldWhile::Continue(Some((elem_idx, *elem_val))),
+    }
+}
+
+pub fn viterbi_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    qstring: bool,
+    qscale: f32,
+    qbias: f32,
+    collapse_repeats: bool,
+    blank_id: usize,
+) -> Result<(String, Vec<usize>), SearchError> {
+    if alphabet.is_empty() || alphabet.len() != network_output.ncols() {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    if blank_id >= alphabet.len() {
+        return Err(SearchError::InvalidBlankId);
+    }
+
+    let mut path = Vec::new();
+    let mut quality = String::new();
+    let mut sequence = String::new();
+
+    let mut last_label = None;
+    let mut label_prob_count = 0;
+    let mut label_prob_total = 0.0;
+
+    for (idx, pr) in network_output.outer_iter().enumerate() {
+        let (label, prob) = match pr.as_slice() {
+            Some(values) => simd::argmax(values),
+            None => Zip::indexed(pr).fold_while(None, find_max).into_inner(),
+        }
+        .unwrap(); // only an empty row could give us None, and the alphabet is not empty
+
+        if label != blank_id && (!collapse_repeats || last_label != Some(label)) {
+            if label_prob_count > 0 {
+                quality.push(phred(
+                    label_prob_total / (label_prob_count as f32),
+                    qscale,
+                    qbias,
+                ));
+                label_prob_total = 0.0;
+                label_prob_count = 0;
+            }
+
+            sequence.push_str(&alphabet[label]);
+            path.push(idx);
+        }
+
+        if label != blank_id {
+            label_prob_total += prob;
+            label_prob_count += 1;
+        }
+
+        last_label = Some(label);
+    }
+
+    if label_prob_count > 0 {
+        quality.push(phred(
+            label_prob_total / (label_prob_count as f32),
+            qscale,
+            qbias,
+        ));
+    }
+
+    if qstring {
+        sequence.push_str(&quality);
+    }
+
+    Ok((sequence, path))
+}
+
+/// Perform a greedy (best path) CTC decode on an RNN output.
+///
+/// This takes the most probable label at each timepoint, collapses repeats and removes blanks,
+/// just like `viterbi_search` without a quality string. The timepoints are the first timepoint of
+/// each label, as for `beam_search`, so the two results can be compared directly.
+pub fn greedy_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+    blank_id: usize,
+) -> Result<(String, Vec<usize>), SearchError> {
+    viterbi_search(network_output, alphabet, false, 1.0, 0.0, true, blank_id)
+}
+
+/// The most probable column of `network_output` at each timepoint, with nothing removed.
+///
+/// This is the path `greedy_search` collapses, for seeing where a network places its blanks and
+/// repeats: it returns the token (from `alphabet`) of every column of the path, blanks included,
+/// concatenated by `frame_tokens`, along with the columns themselves. The path has one column
+/// per row of `network_output`, so with labels of one character each the string has one
+/// character per row, but with longer labels the columns are needed to split it up again.
+pub fn greedy_frames<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix2>,
+    alphabet: &[String],
+) -> Result<(String, Vec<usize>), SearchError> {
+    if alphabet.is_empty() || alphabet.len() != network_output.ncols() {
+        return Err(SearchError::AlphabetMismatch);
+    }
+    let frames: Vec<usize> = network_output
+        .outer_iter()
+        .map(|pr| {
+            match pr.as_slice() {
+                Some(values) => simd::argmax(values),
+                None => Zip::indexed(pr).fold_while(None, find_max).into_inner(),
+            }
+            .unwrap() // only an empty row could give us None, and the alphabet is not empty
+            .0
+        })
+        .collect();
+    Ok((frame_tokens(&frames, alphabet), frames))
+}
+
+/// The tokens of the columns of a path through a network output (such as the path of
+/// `beam_search_with_path` or `greedy_frames`), concatenated with nothing removed.
+///
+/// Every column gives its token, including the blank and each repeat, so this shows a path as
+/// the network saw it rather than the labelling it gives.
+pub fn frame_tokens(frames: &[usize], alphabet: &[String]) -> String {
+    frames
+        .iter()
+        .map(|&column| alphabet[column].as_str())
+        .collect()
+}
+
+pub fn crf_greedy_search<D: Data<Elem = f32>>(
+    network_output: &ArrayBase<D, Ix3>,
+    init_state: &ArrayBase<D, Ix1>,
+    alphabet: &[String],
+    qstring: bool,
+    qscale: f32,
+    qbias: f32,
+) -> Result<(String, Vec<usize>), SearchError> {
+    let (init, _) = check_crf_args(network_output, init_state, alphabet)?;
+    let n_state = network_output.shape()[1] as i32;
+    let n_base = network_output.shape()[2] as i32 - 1;
+
+    let mut path = Vec::new();
+    let mut quality = String::new();
+    let mut sequence = String::new();
+    let mut state = init as i32;
+
+    for (idx, pr) in network_output.axis_iter(Axis(0)).enumerate() {
+        let (label, prob) = max_index(pr.slice(s![state, ..]))
+            .ok_or_else(|| SearchError::incomparable_row(idx, pr.iter()))?;
+
+        if label > 0 {
+            path.push(idx);
+            sequence.push_str(&alphabet[label]);
+            quality.push(phred(prob, qscale, qbias));
+            state = (state * n_base) % n_state + (label as i32 - 1);
+        }
+    }
+
+    if qstring {
+        sequence.push_str(&quality);
+    }
+
+    Ok((sequence, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Lcg;
+    //use test::Bencher;
+
+    #[test]
+    fn crf_test_greedy() {
+        let alphabet = vec![
+            String::from("N"),
+            String::from("A"),
+            String::from("C"),
+            String::from("G"),
+            String::from("T"),
+        ];
+
+        let network_output = array![
+            [
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [1f32, 0.000, 0.000, 0.000, 0.000], // N 2
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+            ],
+            [
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.900, 0.000, 0.000], // C 2
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+            ],
+            [
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.700], // T 1
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+            ],
+            [
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [1f32, 0.000, 0.000, 0.000, 0.000], // N 3
+            ],
+            [
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.990, 0.000, 0.000, 0.000], // A 3
+            ],
+            [
+                [0f32, 0.900, 0.000, 0.000, 0.000], // A 0
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+            ],
+            [
+                [0f32, 0.000, 0.000, 0.999, 0.000], // G 0
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+                [0f32, 0.000, 0.000, 0.000, 0.000],
+            ],
+        ];
+        let init = array![0f32, 0., 1., 0., 0.];
+        let (sequence, path) =
+            crf_greedy_search(&network_output, &init, &alphabet, false, 1.0, 0.0).unwrap();
+
+        assert_eq!(sequence, "CTAAG");
+        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+
+        let (sequence, path) =
+            crf_greedy_search(&network_output, &init, &alphabet, true, 1.0, 0.0).unwrap();
+
+        assert_eq!(sequence, "CTAAG+&5+?");
+        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+
+        let beam_size = 5;
+        let beam_cut_threshold = 0.01;
+        let (sequence, path) = crf_beam_search(
+            &network_output,
+            &init,
+            &alphabet,
+            beam_size,
+            beam_cut_threshold,
+        )
+        .unwrap();
+
+        assert_eq!(sequence, "CTAAG");
+        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_phred_scores() {
+        let qbias = 0.0;
+        let qscale = 1.0;
+        assert_eq!('!', phred(0.0, qscale, qbias));
+        assert_eq!('$', phred(0.5, qscale, qbias));
+        assert_eq!('+', phred(1.0 - 1e-1, qscale, qbias));
+        assert_eq!('5', phred(1.0 - 1e-2, qscale, qbias));
+        assert_eq!('?', phred(1.0 - 1e-3, qscale, qbias));
+        assert_eq!('I', phred(1.0 - 1e-4, qscale, qbias));
+        assert_eq!('I', phred(1.0 - 1e-5, qscale, qbias));
+        assert_eq!('I', phred(1.0 - 1e-6, qscale, qbias));
+        assert_eq!('I', phred(1.0, qscale, qbias));
+
+        // scores past what Phred+33 can encode are clamped
+        assert_eq!('~', phred(1.0, 10.0, qbias));
+        assert_eq!('!', phred(0.5, qscale, -100.0));
+        assert_eq!('!', phred(f32::NAN, qscale, qbias));
+        assert_eq!('!', phred(0.5, f32::NAN, qbias));
+    }
+
+    #[test]
+    fn test_viterbi() {
+        let qbias = 0.0;
+        let qscale = 1.0;
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.0f32, 0.4, 0.6], // G
+            [0.0f32, 0.3, 0.7], // G
+            [0.3f32, 0.3, 0.4], // G
+            [0.4f32, 0.3, 0.3], // N
+            [0.4f32, 0.3, 0.3], // N
+            [0.3f32, 0.3, 0.4], // G
+            [0.1f32, 0.4, 0.5], // G
+            [0.1f32, 0.5, 0.4], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+        ];
+
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, false, qscale, qbias, true, 0).unwrap();
+        assert_eq!(seq, "GGAG");
+        assert_eq!(starts, vec![0, 5, 7, 9]);
+
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, true, qscale, qbias, true, 0).unwrap();
+        assert_eq!(seq, "GGAG%$$(");
+        assert_eq!(starts, vec![0, 5, 7, 9]);
+    }
+
+    #[test]
+    fn test_viterbi_blank_bounds() {
+        let qbias = 0.0;
+        let qscale = 1.0;
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.6f32, 0.2, 0.2], // N
+            [0.6f32, 0.2, 0.2], // N
+            [0.0f32, 0.4, 0.6], // G
+            [0.0f32, 0.3, 0.7], // G
+            [0.3f32, 0.3, 0.4], // G
+            [0.4f32, 0.3, 0.3], // N
+            [0.4f32, 0.3, 0.3], // N
+            [0.3f32, 0.3, 0.4], // G
+            [0.1f32, 0.4, 0.5], // G
+            [0.1f32, 0.5, 0.4], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.4f32, 0.3, 0.3], // N
+        ];
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, false, qscale, qbias, true, 0).unwrap();
+        assert_eq!(seq, "GGAG");
+        assert_eq!(starts, vec![2, 7, 9, 11]);
+
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, true, qscale, qbias, true, 0).unwrap();
+        assert_eq!(seq, "GGAG%$$(");
+        assert_eq!(starts, vec![2, 7, 9, 11]);
+
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, false, qscale, qbias, false, 0).unwrap();
+        assert_eq!(seq, "GGGGGAG");
+        assert_eq!(starts, vec![2, 3, 4, 7, 8, 9, 11]);
+
+        let (seq, starts) =
+            viterbi_search(&network_output, &alphabet, true, qscale, qbias, false, 0).unwrap();
+        assert_eq!(seq, "GGGGGAG%&##$$(");
+        assert_eq!(starts, vec![2, 3, 4, 7, 8, 9, 11]);
+
+        let (seq, _starts, _prob) =
+            beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "GAGAG");
+
+        let (seq, _starts, _prob) =
+            beam_search(&network_output, &alphabet, 5, 0.0, false, 0).unwrap();
+        assert_eq!(seq, "GGGAGAG");
+    }
+
+    #[test]
+    fn test_beam_search_view() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.0f32, 0.4, 0.6], // G
+            [0.0f32, 0.3, 0.7], // G
+            [0.3f32, 0.3, 0.4], // G
+            [0.4f32, 0.3, 0.3], // N
+            [0.4f32, 0.3, 0.3], // N
+            [0.3f32, 0.3, 0.4], // G
+            [0.1f32, 0.4, 0.5], // G
+            [0.1f32, 0.5, 0.4], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+        ];
+
+        let view = network_output.slice(s![2.., ..]);
+        let owned = view.to_owned();
+        let (seq, starts, prob) = beam_search(&view, &alphabet, 5, 0.0, true, 0).unwrap();
+        let (owned_seq, owned_starts, owned_prob) =
+            beam_search(&owned, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "GAG");
+        assert_eq!(seq, owned_seq);
+        assert_eq!(starts, owned_starts);
+        assert_eq!(prob, owned_prob);
+    }
+
+    #[test]
+    fn test_beam_search_invalid_args() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.0f32, 0.4, 0.6], [0.0f32, 0.3, 0.7]];
+
+        assert!(matches!(
+            beam_search(&network_output, &alphabet[..2], 5, 0.0, true, 0),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 0, 0.0, true, 0),
+            Err(SearchError::InvalidBeamSize)
+        ));
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 5, -0.1, true, 0),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 5, 0.5, true, 0),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+    }
+
+    /// The boundaries of `beam_size` and `beam_cut_threshold` for a three label alphabet, and
+    /// whether each should be accepted (or the name of the error it should give).
+    const BEAM_ARG_BOUNDARIES: &[(usize, f32, Result<(), &str>)] = &[
+        (0, 0.0, Err("InvalidBeamSize")),
+        (0, 0.5, Err("InvalidBeamSize")),
+        (1, 0.0, Ok(())),
+        (5, 0.0, Ok(())),
+        (5, -f32::MIN_POSITIVE, Err("InvalidBeamCutThreshold")),
+        (5, -0.1, Err("InvalidBeamCutThreshold")),
+        (5, 0.33, Ok(())),
+        (5, 1.0 / 3.0, Err("InvalidBeamCutThreshold")),
+        (5, 0.5, Err("InvalidBeamCutThreshold")),
+        (5, f32::NAN, Err("InvalidBeamCutThreshold")),
+    ];
+
+    /// The name of the error of `result`, if it has one.
+    fn error_name<T>(result: Result<T, SearchError>) -> Result<(), String> {
+        result.map(|_| ()).map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_crf_beam_search_invalid_shapes() {
+        use ndarray::Array3;
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = Array3::from_elem((2, 4, 3), 1.0f32 / 3.0);
+        let init_state = array![1.0f32, 0.0, 0.0, 0.0];
+
+        let result = crf_beam_search(&network_output, &init_state, &[], 5, 0.0);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+        let result = crf_beam_search(&network_output, &init_state, &alphabet[..2], 5, 0.0);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+        let no_labels = Array3::<f32>::zeros((2, 4, 0));
+        let result = crf_beam_search(&no_labels, &init_state, &alphabet, 5, 0.0);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+
+        let no_states = Array3::<f32>::zeros((2, 0, 3));
+        let result = crf_beam_search(&no_states, &init_state, &alphabet, 5, 0.0);
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        let result = crf_beam_search(&network_output, &array![], &alphabet, 5, 0.0);
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        let result = crf_beam_search(
+            &network_output,
+            &array![0.5f32, f32::NAN],
+            &alphabet,
+            5,
+            0.0,
+        );
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        // init_state may be longer than the state axis, as long as its most likely state is in it
+        let long_init = array![0.0f32, 0.0, 0.0, 0.0, 1.0];
+        let result = crf_beam_search(&network_output, &long_init, &alphabet, 5, 0.0);
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        let long_init = array![0.0f32, 1.0, 0.0, 0.0, 0.0];
+        assert!(crf_beam_search(&network_output, &long_init, &alphabet, 5, 0.0).is_ok());
+        // the states must be a multiple of the labels, or a label can move outside the matrix
+        let odd_states = Array3::from_elem((2, 3, 3), 1.0f32 / 3.0);
+        let result = crf_beam_search(&odd_states, &array![1.0f32, 0.0, 0.0], &alphabet, 5, 0.0);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+
+        // no rows is not an error: there is nothing to decode
+        let no_rows = Array3::<f32>::zeros((0, 4, 3));
+        let (seq, path) = crf_beam_search(&no_rows, &init_state, &alphabet, 5, 0.0).unwrap();
+        assert_eq!(seq, "");
+        assert!(path.is_empty());
+    }
+
+    #[test]
+    fn test_crf_greedy_search_invalid_input() {
+        use ndarray::{Array1, Array3};
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = Array3::from_elem((2, 4, 3), 1.0f32 / 3.0);
+        let init_state = array![1.0f32, 0.0, 0.0, 0.0];
+        let greedy = |output: &Array3<f32>, init: &Array1<f32>, alphabet: &[String]| {
+            crf_greedy_search(output, init, alphabet, false, 1.0, 0.0)
+        };
+
+        let result = greedy(&network_output, &init_state, &alphabet[..2]);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+        let odd_states = Array3::from_elem((2, 3, 3), 1.0f32 / 3.0);
+        let result = greedy(&odd_states, &array![1.0f32, 0.0, 0.0], &alphabet);
+        assert!(matches!(result, Err(SearchError::AlphabetMismatch)));
+        let result = greedy(
+            &network_output,
+            &array![0.0f32, 0.0, 0.0, 0.0, 1.0],
+            &alphabet,
+        );
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+        let result = greedy(&network_output, &array![], &alphabet);
+        assert!(matches!(result, Err(SearchError::InvalidInitState)));
+
+        let mut nan_row = network_output.clone();
+        nan_row.slice_mut(s![1, .., 2]).fill(f32::NAN);
+        let result = greedy(&nan_row, &init_state, &alphabet);
+        assert!(matches!(
+            result,
+            Err(SearchError::IncomparableValues { row: 1, col: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_beam_arg_boundaries() {
+        use ndarray::Array3;
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.3, 0.6], [0.7f32, 0.2, 0.1], [0.2f32, 0.5, 0.3]];
+        let crf_output = Array3::from_elem((2, 4, 3), 1.0f32 / 3.0);
+        let crf_init = array![1.0f32, 0.0, 0.0, 0.0];
+
+        for &(beam_size, threshold, expected) in BEAM_ARG_BOUNDARIES {
+            let expected = expected.map_err(String::from);
+            let case = format!("beam_size {}, threshold {}", beam_size, threshold);
+            let result = beam_search(&network_output, &alphabet, beam_size, threshold, true, 0);
+            assert_eq!(error_name(result), expected, "beam_search, {}", case);
+            let options = BeamSearchOptions::new()
+                .beam_size(beam_size)
+                .beam_cut_threshold(threshold);
+            let result = beam_search_with(&network_output, &alphabet, &options);
+            assert_eq!(error_name(result), expected, "beam_search_with, {}", case);
+            let result = beam_search_f64(
+                &network_output.mapv(f64::from),
+                &alphabet,
+                beam_size,
+                f64::from(threshold),
+                true,
+                0,
+            );
+            assert_eq!(error_name(result), expected, "beam_search_f64, {}", case);
+            let result = crf_beam_search(&crf_output, &crf_init, &alphabet, beam_size, threshold);
+            assert_eq!(error_name(result), expected, "crf_beam_search, {}", case);
+        }
+
+        // in log space, the threshold is only bounded above, by ln(1/3)
+        let log_output = network_output.mapv(f32::ln);
+        let max = (1.0f32 / 3.0).ln();
+        for &(threshold, expected) in &[
+            (f32::NEG_INFINITY, Ok(())),
+            (-5.0, Ok(())),
+            (-1.1, Ok(())),
+            (max, Err("InvalidBeamCutThreshold")),
+            (0.0, Err("InvalidBeamCutThreshold")),
+            (f32::NAN, Err("InvalidBeamCutThreshold")),
+        ] {
+            let result = beam_search_log(&log_output, &alphabet, 5, threshold, true, 0);
+            let expected = expected.map_err(String::from);
+            assert_eq!(error_name(result), expected, "threshold {}", threshold);
+        }
+    }
+
+    #[test]
+    fn test_beam_search_with() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.3, 0.6], [0.7f32, 0.2, 0.1], [0.2f32, 0.5, 0.3]];
+
+        let options = BeamSearchOptions::new();
+        assert_eq!(options.beam_size, 5);
+        assert_eq!(options.beam_cut_threshold, 0.0);
+        assert!(matches!(options.collapse, Collapse::Repeats));
+        assert_eq!(options.blank_id, 0);
+        assert!(!options.check_finite);
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options).unwrap(),
+            beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap()
+        );
+
+        let options = BeamSearchOptions::new()
+            .beam_size(2)
+            .beam_cut_threshold(0.2)
+            .collapse_repeats(false)
+            .blank_id(1);
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options).unwrap(),
+            beam_search(&network_output, &alphabet, 2, 0.2, false, 1).unwrap()
+        );
+
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options.clone().beam_size(0)),
+            Err(SearchError::InvalidBeamSize)
+        ));
+
+        let network_output = array![[0.1f32, 0.3, 0.6], [0.7f32, f32::NAN, 0.1]];
+        let options = BeamSearchOptions::new().check_finite(true);
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options),
+            Err(SearchError::IncomparableValues { row: 1, col: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_memory_order() {
+        use ndarray::ShapeBuilder;
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let c_order = random_reads(1, 50, 5, 3).pop().unwrap();
+        let mut f_order = Array2::zeros((50, 5).f());
+        f_order.assign(&c_order);
+        // a transposed view, with a step along the labels too
+        let mut wider = Array2::zeros((10, 50));
+        wider.slice_mut(s![..;2, ..]).assign(&c_order.t());
+        let transposed = wider.slice(s![..;2, ..]).reversed_axes();
+        // and one with the rows in reverse order
+        let mut reversed_rows = Array2::zeros((50, 5));
+        reversed_rows.assign(&c_order.slice(s![..;-1, ..]));
+        let reversed_view = reversed_rows.slice(s![..;-1, ..]);
+
+        let options = BeamSearchOptions::new().beam_size(10);
+        let expected_beam = beam_search_with(&c_order, &alphabet, &options).unwrap();
+        let expected_spans = beam_search_with_spans(&c_order, &alphabet, &options).unwrap();
+        let expected_viterbi = viterbi_search(&c_order, &alphabet, true, 1.0, 0.0, true, 0);
+        for view in &[f_order.view(), transposed, reversed_view] {
+            assert!(!view.is_standard_layout());
+            assert_eq!(view, &c_order);
+            assert_eq!(
+                beam_search_with(view, &alphabet, &options).unwrap(),
+                expected_beam
+            );
+            assert_eq!(
+                beam_search_with_spans(view, &alphabet, &options).unwrap(),
+                expected_spans
+            );
+            assert_eq!(
+                viterbi_search(view, &alphabet, true, 1.0, 0.0, true, 0).unwrap(),
+                expected_viterbi.clone().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_beam_search_with_progress() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let network_output = random_reads(1, 45, 5, 11).pop().unwrap();
+        let options = BeamSearchOptions::new().beam_size(10);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+
+        let mut calls = Vec::new();
+        let result =
+            beam_search_with_progress(&network_output, &alphabet, &options, 10, |timestep, seq| {
+                calls.push((timestep, seq.to_string()))
+            })
+            .unwrap();
+        assert_eq!(result, expected);
+        let timesteps: Vec<usize> = calls.iter().map(|x| x.0).collect();
+        assert_eq!(timesteps, vec![10, 20, 30, 40, 45]);
+        for (timestep, seq) in &calls {
+            let rows = network_output.slice(s![..*timestep, ..]);
+            assert_eq!(
+                seq,
+                &beam_search_with(&rows, &alphabet, &options).unwrap().0
+            );
+        }
+
+        assert!(matches!(
+            beam_search_with_progress(&network_output, &alphabet, &options, 0, |_, _| ()),
+            Err(SearchError::InvalidInterval)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_cancellable() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let network_output = random_reads(1, 45, 5, 11).pop().unwrap();
+        let options = BeamSearchOptions::new().beam_size(10);
+        let (seq, path, prob) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        assert_eq!(
+            beam_search_cancellable(&network_output, &alphabet, &options, 10, &cancel).unwrap(),
+            (seq, path, prob, false)
+        );
+
+        // a flag set before the search starts stops it at the first check
+        cancel.store(true, AtomicOrdering::Relaxed);
+        let (seq, path, prob, truncated) =
+            beam_search_cancellable(&network_output, &alphabet, &options, 10, &cancel).unwrap();
+        assert!(truncated);
+        let rows = network_output.slice(s![..10, ..]);
+        assert_eq!(
+            (seq, path, prob),
+            beam_search_with(&rows, &alphabet, &options).unwrap()
+        );
+        // a search stopped after its last row still searched every row
+        let (_, _, _, truncated) =
+            beam_search_cancellable(&rows, &alphabet, &options, 10, &cancel).unwrap();
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn test_beam_search_with_stats() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.2f32, 0.5, 0.3], [0.3f32, 0.6, 0.1]];
+        let options = BeamSearchOptions::new().beam_cut_threshold(0.25);
+        let (seq, path, prob, stats) =
+            beam_search_with_stats(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(
+            (seq, path, prob),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+        // the first row only extends with A and G, and the second extends A, G with A or a blank,
+        // giving A, G and GA
+        assert_eq!(
+            stats,
+            DecodeStats {
+                beam_occupancy: vec![2, 3],
+                beam_pruned: 0,
+                threshold_pruned: 3,
+                tree_size: 3,
+                evicted_nodes: 0,
+            }
+        );
+
+        let (_, _, _, stats) =
+            beam_search_with_stats(&network_output, &alphabet, &options.clone().beam_size(1))
+                .unwrap();
+        assert_eq!(
+            stats,
+            DecodeStats {
+                beam_occupancy: vec![1, 1],
+                beam_pruned: 1,
+                threshold_pruned: 2,
+                tree_size: 2,
+                evicted_nodes: 0,
+            }
+        );
+
+        // the labels that cannot be emitted at the first row were not pruned by the threshold:
+        // only G is, at both rows
+        let network_output = array![[0.4f32, 0.5, 0.1], [0.3f32, 0.6, 0.1]];
+        let late = options.emit_at_first_row(false);
+        let (_, _, _, stats) = beam_search_with_stats(&network_output, &alphabet, &late).unwrap();
+        assert_eq!(
+            stats,
+            DecodeStats {
+                beam_occupancy: vec![1, 2],
+                beam_pruned: 0,
+                threshold_pruned: 2,
+                tree_size: 1,
+                evicted_nodes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_beam_search_max_tree_nodes() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let options = BeamSearchOptions::new().beam_size(5);
+        for network_output in random_reads(3, 60, 5, 11) {
+            let (seq, _, prob) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+            // room for the labellings of the beam, which mostly share their prefixes, and 5
+            // extensions with 4 labels at each row
+            let capped = options.clone().max_tree_nodes(Some(150));
+            let (capped_seq, capped_path, capped_prob, stats) =
+                beam_search_with_stats(&network_output, &alphabet, &capped).unwrap();
+            assert_eq!(capped_seq, seq);
+            assert_eq!(capped_path.len(), seq.len());
+            assert!((capped_prob - prob).abs() < 1e-6);
+            assert!(stats.tree_size <= 150);
+            assert!(stats.evicted_nodes > 0);
+        }
+
+        let network_output = &random_reads(1, 60, 5, 11)[0];
+        assert!(matches!(
+            beam_search_with(
+                network_output,
+                &alphabet,
+                &options.clone().max_tree_nodes(Some(10))
+            ),
+            Err(SearchError::RanOutOfBeam { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decoder_with_capacity() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let options = BeamSearchOptions::new().beam_size(8);
+        let mut decoder = Decoder::with_capacity(options.clone(), 5, 40);
+        let capacities = |decoder: &Decoder| {
+            let state = &decoder.state;
+            (
+                state.suffix_tree.capacity(),
+                state.beam.capacity(),
+                state.next_beam.capacity(),
+                state.scratch.probs.capacity(),
+                state.scratch.columns.capacity(),
+                state.scratch.tied_a.capacity(),
+            )
+        };
+        let before = capacities(&decoder);
+        assert!(before.0 >= 40 * 8 * 4);
+
+        // every value is the same, so each row adds as many nodes as it can, and every
+        // labelling ties with the others
+        let uniform = Array2::from_elem((40, 5), 0.2f32);
+        for network_output in random_reads(3, 40, 5, 7).iter().chain(Some(&uniform)) {
+            assert_eq!(
+                decoder.decode(network_output, &alphabet).unwrap(),
+                beam_search_with(network_output, &alphabet, &options).unwrap()
+            );
+            assert_eq!(capacities(&decoder), before);
+        }
+
+        assert!(matches!(
+            decoder.decode(&Array2::from_elem((41, 5), 0.2f32), &alphabet),
+            Err(SearchError::CapacityExceeded)
+        ));
+        assert!(matches!(
+            decoder.decode(&Array2::from_elem((10, 4), 0.25f32), &alphabet[..4]),
+            Err(SearchError::CapacityExceeded)
+        ));
+        assert_eq!(capacities(&decoder), before);
+
+        for network_output in &random_reads(3, 40, 5, 9) {
+            let (sequence, path, prob) =
+                beam_search_with(network_output, &alphabet, &options).unwrap();
+            let (labels, iter_prob) = decoder.decode_iter(network_output, &alphabet).unwrap();
+            assert_eq!(labels.len(), path.len());
+            let (tokens, timepoints): (Vec<&str>, Vec<usize>) = labels.unzip();
+            assert_eq!(
+                (tokens.concat(), timepoints, iter_prob),
+                (sequence, path, prob)
+            );
+            assert_eq!(capacities(&decoder), before);
+        }
+    }
+
+    #[test]
+    fn test_beam_search_into() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let options = BeamSearchOptions::new().beam_size(8);
+        let mut decoder = Decoder::with_capacity(options.clone(), 5, 40);
+        let (mut out, mut timepoints) = (String::from("left over"), vec![7; 3]);
+        let reads = random_reads(2, 40, 5, 9);
+        let expected = beam_search_with(&reads[0], &alphabet, &options).unwrap();
+        let prob = beam_search_into(&reads[0], &alphabet, &options, &mut out, &mut timepoints);
+        assert_eq!((out.clone(), timepoints.clone(), prob.unwrap()), expected);
+
+        // a shorter result keeps the buffers as they are
+        let capacities = (out.capacity(), timepoints.capacity());
+        let (out_ptr, timepoints_ptr) = (out.as_ptr(), timepoints.as_ptr());
+        for network_output in [
+            &reads[1],
+            &reads[0],
+            &reads[1].slice(s![..10, ..]).to_owned(),
+        ] {
+            let expected = beam_search_with(network_output, &alphabet, &options).unwrap();
+            assert!(expected.1.len() <= timepoints.capacity());
+            let prob = decoder.decode_into(network_output, &alphabet, &mut out, &mut timepoints);
+            assert_eq!((out.clone(), timepoints.clone(), prob.unwrap()), expected);
+            assert_eq!((out.capacity(), timepoints.capacity()), capacities);
+            assert_eq!(
+                (out.as_ptr(), timepoints.as_ptr()),
+                (out_ptr, timepoints_ptr)
+            );
+        }
+
+        let before = (out.clone(), timepoints.clone());
+        assert!(matches!(
+            decoder.decode_into(
+                &Array2::from_elem((41, 5), 0.2f32),
+                &alphabet,
+                &mut out,
+                &mut timepoints
+            ),
+            Err(SearchError::CapacityExceeded)
+        ));
+        assert_eq!((out, timepoints), before);
+    }
+
+    #[test]
+    fn test_decoder_with_capacity_hint() {
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let options = BeamSearchOptions::new().beam_size(8);
+        let mut decoder = Decoder::with_capacity_hint(options.clone(), 5, 2 * 8 * 40);
+        let before = decoder.state.suffix_tree.capacity();
+        assert!(before >= 2 * 8 * 40);
+        for network_output in &random_reads(3, 40, 5, 7) {
+            assert_eq!(
+                decoder.decode(network_output, &alphabet).unwrap(),
+                beam_search_with(network_output, &alphabet, &options).unwrap()
+            );
+            assert_eq!(decoder.state.suffix_tree.capacity(), before);
+        }
+
+        // unlike with_capacity, a decode that needs more nodes grows the tree
+        decoder.set_growth(Growth::Step(100));
+        let long = &random_reads(1, 400, 5, 9)[0];
+        let (sequence, path, _) = decoder.decode(long, &alphabet).unwrap();
+        let expected = beam_search_with(long, &alphabet, &options).unwrap();
+        // the probabilities of a read this long underflow, so only these can be compared
+        assert_eq!((sequence, path), (expected.0, expected.1));
+        let tree = &decoder.state.suffix_tree;
+        assert!(tree.capacity() > before && tree.capacity() < tree.len() + 100);
+    }
+
+    #[test]
+    fn test_decoder_reuse() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let long_output = array![
+            [0.1f32, 0.3, 0.6],
+            [0.7f32, 0.2, 0.1],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.3, 0.4],
+            [0.1f32, 0.8, 0.1]
+        ];
+        let short_output = array![[0.2f32, 0.7, 0.1], [0.6f32, 0.1, 0.3]];
+
+        let mut decoder = Decoder::new(BeamSearchOptions::new().beam_size(10));
+        for network_output in &[&long_output, &short_output, &long_output] {
+            assert_eq!(
+                decoder.decode(*network_output, &alphabet).unwrap(),
+                beam_search(*network_output, &alphabet, 10, 0.0, true, 0).unwrap()
+            );
+        }
+
+        // a failed decode doesn't affect the next one
+        assert!(matches!(
+            decoder.decode(&short_output, &alphabet[..2]),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert_eq!(
+            decoder.decode(&short_output, &alphabet).unwrap(),
+            beam_search(&short_output, &alphabet, 10, 0.0, true, 0).unwrap()
+        );
+
+        // nor does a different alphabet size
+        let small_alphabet = vec![String::from("N"), String::from("A")];
+        let small_output = array![[0.2f32, 0.8], [0.9f32, 0.1], [0.3f32, 0.7]];
+        assert_eq!(
+            decoder.decode(&small_output, &small_alphabet).unwrap(),
+            beam_search(&small_output, &small_alphabet, 10, 0.0, true, 0).unwrap()
+        );
+        assert_eq!(
+            decoder.decode(&long_output, &alphabet).unwrap(),
+            beam_search(&long_output, &alphabet, 10, 0.0, true, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_beam_search_debug() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.3, 0.6], [0.7f32, 0.2, 0.1], [0.2f32, 0.5, 0.3]];
+        let options = BeamSearchOptions::new().beam_size(3);
+
+        let (sequence, path, prob, debug) =
+            beam_search_debug(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(
+            (sequence.clone(), path.clone(), prob),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+
+        let beam: Vec<(i32, f32)> = debug.beam().collect();
+        assert_eq!(beam.len(), 3);
+        assert_eq!(debug.labelling(beam[0].0), (sequence, path));
+        for &(node, prob) in &beam {
+            assert_eq!(debug.probability(node), Some(prob));
+        }
+        assert_eq!(debug.labelling(ROOT_NODE), (String::new(), vec![]));
+
+        let tree = debug.tree();
+        assert!(!tree.is_empty());
+        assert_eq!(tree.nodes().count(), tree.len());
+        assert_eq!(debug.probability(tree.len() as i32), None);
+        for node in tree.nodes() {
+            let parent = tree.parent(node).unwrap();
+            assert!(parent < node);
+            assert_eq!(
+                tree.get_child(parent, tree.label(node).unwrap()),
+                Some(node)
+            );
+            assert!(debug.probability(node).unwrap() > 0.0);
+
+            // a node's labelling is its parent's with one more label
+            let (sequence, path) = debug.labelling(node);
+            let (parent_sequence, parent_path) = debug.labelling(parent);
+            assert!(sequence.starts_with(&parent_sequence));
+            assert_eq!(sequence.len(), parent_sequence.len() + 1);
+            assert_eq!(&path[..parent_path.len()], &parent_path[..]);
+            assert_eq!(path.last(), tree.get_data_ref(node));
+        }
+    }
+
+    #[test]
+    fn test_beam_search_state_resume() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.3, 0.6],
+            [0.7f32, 0.2, 0.1],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.3, 0.4],
+            [0.1f32, 0.8, 0.1],
+            [0.6f32, 0.1, 0.3],
+            [0.2f32, 0.2, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(4);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+
+        for split in 0..=network_output.shape()[0] {
+            let mut state = BeamSearchState::new(&alphabet);
+            state
+                .advance(&network_output.slice(s![..split, ..]), &alphabet, &options)
+                .unwrap();
+            assert_eq!(state.timestep(), split);
+            state
+                .advance(&network_output.slice(s![split.., ..]), &alphabet, &options)
+                .unwrap();
+            assert_eq!(state.result(&alphabet, &options), expected);
+        }
+
+        let mut state = BeamSearchState::new(&alphabet);
+        assert_eq!(
+            state.result(&alphabet, &options),
+            (String::new(), vec![], 1.0)
+        );
+        assert!(matches!(
+            state.advance(&network_output.slice(s![.., ..2]), &alphabet[..2], &options),
+            Err(SearchError::AlphabetMismatch)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_beam_search_state_serde() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.3, 0.6],
+            [0.7f32, 0.2, 0.1],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.3, 0.4],
+            [0.1f32, 0.8, 0.1],
+            [0.6f32, 0.1, 0.3],
+            [0.2f32, 0.2, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(4);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+
+        for split in 0..=network_output.shape()[0] {
+            let mut state = BeamSearchState::new(&alphabet);
+            state
+                .advance(&network_output.slice(s![..split, ..]), &alphabet, &options)
+                .unwrap();
+            let checkpoint = serde_json::to_string(&state).unwrap();
+            let mut state: BeamSearchState = serde_json::from_str(&checkpoint).unwrap();
+            state
+                .advance(&network_output.slice(s![split.., ..]), &alphabet, &options)
+                .unwrap();
+            assert_eq!(state.result(&alphabet, &options), expected);
+        }
+    }
+
+    #[test]
+    fn test_streaming_decoder() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.3, 0.6],
+            [0.7f32, 0.2, 0.1],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.3, 0.4],
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.7, 0.2],
+            [0.6f32, 0.1, 0.3],
+            [0.2f32, 0.2, 0.6]
+        ];
+
+        let mut decoder = StreamingDecoder::new(&alphabet, 4, 0.0).unwrap();
+        assert_eq!(decoder.best(), (String::new(), vec![]));
+        for (idx, row) in network_output.outer_iter().enumerate() {
+            decoder.push_row(row).unwrap();
+            assert_eq!(decoder.timestep(), idx + 1);
+            let (sequence, path, _) = beam_search(
+                &network_output.slice(s![..=idx, ..]),
+                &alphabet,
+                4,
+                0.0,
+                true,
+                0,
+            )
+            .unwrap();
+            assert_eq!(decoder.best(), (sequence, path));
+        }
+        assert_eq!(
+            decoder.finish(),
+            beam_search(&network_output, &alphabet, 4, 0.0, true, 0).unwrap()
+        );
+
+        assert!(matches!(
+            StreamingDecoder::new(&alphabet, 0, 0.0),
+            Err(SearchError::InvalidBeamSize)
+        ));
+        assert!(matches!(
+            StreamingDecoder::new(&alphabet, 4, 0.5),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+        let mut decoder = StreamingDecoder::new(&alphabet, 4, 0.0).unwrap();
+        assert!(matches!(
+            decoder.push_row(network_output.slice(s![0, ..2])),
+            Err(SearchError::AlphabetMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_f64() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.3, 0.6],
+            [0.7f32, 0.2, 0.1],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.3, 0.4],
+            [0.1f32, 0.8, 0.1]
+        ];
+
+        let (seq, path, prob) = beam_search(&network_output, &alphabet, 5, 0.125, true, 0).unwrap();
+        let (seq_f64, path_f64, prob_f64) = beam_search_f64(
+            &network_output.mapv(f64::from),
+            &alphabet,
+            5,
+            0.125,
+            true,
+            0,
+        )
+        .unwrap();
+        assert_eq!(seq_f64, seq);
+        assert_eq!(path_f64, path);
+        assert!((prob_f64 - f64::from(prob)).abs() < 1e-6);
+
+        // these labellings differ by less than f32 can represent
+        let network_output = array![[0.0f64, 0.5 + 1e-10, 0.5 - 1e-10]];
+        let (seq, _, _) = beam_search_f64(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "A");
+        let network_output = array![[0.0f64, 0.5 - 1e-10, 0.5 + 1e-10]];
+        let (seq, _, _) = beam_search_f64(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "G");
+
+        assert!(matches!(
+            beam_search_f64(&network_output, &alphabet, 5, 1.0 / 3.0, true, 0),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+        assert!(matches!(
+            beam_search_f64(&network_output, &alphabet, 0, 0.0, true, 0),
+            Err(SearchError::InvalidBeamSize)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_quantized() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let quantized = array![
+            [30u8, 50, 80],
+            [90u8, 40, 30],
+            [40u8, 70, 50],
+            [50u8, 50, 60],
+            [30u8, 100, 30]
+        ];
+        let scale = 0.01;
+        let zero_point = 20;
+        let dequantized = quantized.mapv(|x| (i32::from(x) - zero_point) as f32 * scale);
+
+        for &threshold in &[0.0, 0.2] {
+            let options = BeamSearchOptions::new().beam_cut_threshold(threshold);
+            assert_eq!(
+                beam_search_quantized(&quantized, scale, zero_point, &alphabet, &options).unwrap(),
+                beam_search_with(&dequantized, &alphabet, &options).unwrap()
+            );
+        }
+
+        // a zero point this far from the entries does not overflow
+        let (scale, zero_point) = (1e-10, i32::MIN);
+        let dequantized = quantized.mapv(|x| (i64::from(x) - i64::from(zero_point)) as f32 * scale);
+        let options = BeamSearchOptions::new();
+        assert_eq!(
+            beam_search_quantized(&quantized, scale, zero_point, &alphabet, &options).unwrap(),
+            beam_search_with(&dequantized, &alphabet, &options).unwrap()
+        );
+
+        let options = BeamSearchOptions::new().beam_size(0);
+        assert!(matches!(
+            beam_search_quantized(&quantized, scale, zero_point, &alphabet, &options),
+            Err(SearchError::InvalidBeamSize)
+        ));
+        let options = BeamSearchOptions::new();
+        for &scale in &[0.0, -0.01, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                beam_search_quantized(&quantized, scale, zero_point, &alphabet, &options),
+                Err(SearchError::InvalidScale)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_max_symbols_per_frame() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.8, 0.1],
+            [0.1f32, 0.8, 0.1]
+        ];
+        let options = BeamSearchOptions::new()
+            .beam_size(10)
+            .collapse_repeats(false);
+
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AAAAAA");
+        let limited = options.clone().max_symbols_per_frame(Some(6));
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &limited).unwrap(),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+
+        // a blank is needed after every two labels
+        let limited = options.clone().max_symbols_per_frame(Some(2));
+        let (seq, path, _) = beam_search_with(&network_output, &alphabet, &limited).unwrap();
+        assert_eq!(seq, "AAAA");
+        assert_eq!(path, vec![0, 1, 3, 4]);
+
+        let limited = options.clone().max_symbols_per_frame(Some(1));
+        let (seq, path, _) = beam_search_with(&network_output, &alphabet, &limited).unwrap();
+        assert_eq!(seq, "AAA");
+        assert_eq!(path, vec![0, 2, 4]);
+
+        assert!(matches!(
+            beam_search_with(
+                &network_output,
+                &alphabet,
+                &options.max_symbols_per_frame(Some(0))
+            ),
+            Err(SearchError::InvalidMaxSymbolsPerFrame)
+        ));
+    }
+
+    #[test]
+    fn test_blank_bias() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.6f32, 0.3, 0.1],
+            [0.6f32, 0.1, 0.3],
+            [0.5f32, 0.4, 0.1],
+            [0.7f32, 0.1, 0.2],
+            [0.4f32, 0.3, 0.3]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+
+        for &bias in &[-2.0f32, -0.5, 0.5, 2.0] {
+            // the same as scaling the blank column
+            let mut scaled = network_output.clone();
+            scaled.column_mut(0).mapv_inplace(|x| x * bias.exp());
+            let biased = beam_search_with(
+                &network_output,
+                &alphabet,
+                &options.clone().blank_bias(bias),
+            )
+            .unwrap();
+            assert_eq!(
+                biased,
+                beam_search_with(&scaled, &alphabet, &options).unwrap()
+            );
+            if bias < 0.0 {
+                assert!(biased.0.len() >= seq.len());
+            } else {
+                assert!(biased.0.len() <= seq.len());
+            }
+        }
+
+        let (seq, _, _) = beam_search_with(
+            &network_output,
+            &alphabet,
+            &options.clone().blank_bias(-2.0),
+        )
+        .unwrap();
+        assert_eq!(seq, "AGAG");
+        let (seq, _, _) =
+            beam_search_with(&network_output, &alphabet, &options.clone().blank_bias(2.0)).unwrap();
+        assert_eq!(seq, "");
+
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options.blank_bias(f32::NAN)),
+            Err(SearchError::InvalidBlankBias)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_allowed_transitions() {
+        let alphabet: Vec<String> = "NACG".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.1f32, 0.8, 0.05, 0.05], [0.1f32, 0.05, 0.8, 0.05]];
+        let options = BeamSearchOptions::new();
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AC");
+
+        let mut allowed = Array2::from_elem((4, 4), true);
+        allowed[(1, 2)] = false;
+        let (seq, _, _) = beam_search_with(
+            &network_output,
+            &alphabet,
+            &options.clone().allowed_transitions(Some(allowed.clone())),
+        )
+        .unwrap();
+        assert_eq!(seq, "A");
+
+        // the blank row gives the labels a labelling can start with
+        allowed[(0, 1)] = false;
+        let (seq, _, _) = beam_search_with(
+            &network_output,
+            &alphabet,
+            &options.clone().allowed_transitions(Some(allowed)),
+        )
+        .unwrap();
+        assert_eq!(seq, "C");
+
+        assert!(matches!(
+            beam_search_with(
+                &network_output,
+                &alphabet,
+                &options.allowed_transitions(Some(Array2::from_elem((4, 3), true)))
+            ),
+            Err(SearchError::InvalidTransitions)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_length_limits() {
+        let alphabet: Vec<String> = "NAC".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.6f32, 0.1, 0.3]];
+        let options = BeamSearchOptions::new();
+        let search = |options: BeamSearchOptions| {
+            beam_search_with(&network_output, &alphabet, &options).map(|(seq, _, _)| seq)
+        };
+        assert_eq!(search(options.clone()).unwrap(), "A");
+        assert_eq!(search(options.clone().min_length(2)).unwrap(), "AC");
+        // if no labelling is long enough, the best one is returned
+        assert_eq!(search(options.clone().min_length(3)).unwrap(), "A");
+        assert!(matches!(
+            search(options.clone().min_length(2).max_length(Some(1))),
+            Err(SearchError::InvalidLengthRange)
+        ));
+
+        let network_output = array![[0.2f32, 0.7, 0.1], [0.1f32, 0.1, 0.8]];
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AC");
+        let (seq, _, _) =
+            beam_search_with(&network_output, &alphabet, &options.max_length(Some(1))).unwrap();
+        assert_eq!(seq, "C");
+    }
+
+    #[test]
+    fn test_beam_search_within_min_length() {
+        let alphabet: Vec<String> = "NAC".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.6f32, 0.1, 0.3], [0.5f32, 0.3, 0.2]];
+        let options = BeamSearchOptions::new().beam_size(8);
+        let within = |options: &BeamSearchOptions| {
+            beam_search_within(&network_output, &alphabet, options, 100.0)
+                .unwrap()
+                .into_iter()
+                .map(|(seq, _, _)| seq)
+                .collect::<Vec<_>>()
+        };
+        let all = within(&options);
+        assert!(all.iter().any(|seq| seq.len() < 2));
+
+        let long = within(&options.clone().min_length(2));
+        assert!(!long.is_empty());
+        assert!(long.iter().all(|seq| seq.len() >= 2), "{:?}", long);
+        let expected: Vec<String> = all.iter().filter(|seq| seq.len() >= 2).cloned().collect();
+        assert_eq!(long, expected);
+        let (best, _, _) =
+            beam_search_scored(&network_output, &alphabet, &options.clone().min_length(2)).unwrap();
+        assert_eq!(long[0], best);
+
+        // with none long enough, every labelling counts
+        assert_eq!(within(&options.min_length(4)), all);
+    }
+
+    #[test]
+    fn test_beam_search_repeat_label_floor() {
+        let alphabet: Vec<String> = "NA".chars().map(|x| x.to_string()).collect();
+        // a peaky model: two spikes of A with a blank-heavy timestep between them
+        let network_output = array![[0.02f32, 0.98], [0.7, 0.3], [0.02, 0.98]];
+        let options = BeamSearchOptions::new();
+        let search = |options: BeamSearchOptions| {
+            beam_search_with(&network_output, &alphabet, &options).map(|(seq, _, _)| seq)
+        };
+        assert_eq!(search(options.clone()).unwrap(), "AA");
+        // a floor below the probability the model gives to A changes nothing
+        assert_eq!(
+            search(options.clone().repeat_label_floor(0.2)).unwrap(),
+            "AA"
+        );
+        assert_eq!(
+            search(options.clone().repeat_label_floor(0.8)).unwrap(),
+            "A"
+        );
+
+        for floor in [-0.1, 1.5, f32::NAN] {
+            assert!(matches!(
+                search(options.clone().repeat_label_floor(floor)),
+                Err(SearchError::InvalidRepeatLabelFloor)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_beam_search_merge_across_blank() {
+        let alphabet: Vec<String> = "bAG".chars().map(|x| x.to_string()).collect();
+        let row = |label: char| match label {
+            'b' => [0.9f32, 0.05, 0.05],
+            'A' => [0.05, 0.9, 0.05],
+            _ => [0.05, 0.05, 0.9],
+        };
+        let search = |rows: &str, merge_across_blank: bool| {
+            let rows: Vec<[f32; 3]> = rows.chars().map(row).collect();
+            let network_output = Array2::from(rows);
+            let options = BeamSearchOptions::new().merge_across_blank(merge_across_blank);
+            beam_search_with(&network_output, &alphabet, &options)
+                .unwrap()
+                .0
+        };
+        for &(rows, standard, merged) in &[
+            ("AbA", "AA", "A"),
+            ("AbbA", "AA", "A"),
+            ("AAbAA", "AA", "A"),
+            ("AAA", "A", "A"),
+            ("AbGbA", "AGA", "AGA"),
+            ("bAbGGbbG", "AGG", "AG"),
+        ] {
+            assert_eq!(search(rows, false), standard, "{}", rows);
+            assert_eq!(search(rows, true), merged, "{}", rows);
+        }
+
+        // with one label, every path that emits it gives the labelling "A"
+        let alphabet: Vec<String> = "bA".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.3f32, 0.7], [0.6, 0.4], [0.2, 0.8]];
+        let options = BeamSearchOptions::new().merge_across_blank(true);
+        let (seq, _, prob) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "A");
+        assert!((prob - (1.0 - 0.3 * 0.6 * 0.2)).abs() < 1e-6);
+        // it has no effect without collapsing
+        let options = options.collapse_repeats(false);
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options).unwrap(),
+            beam_search(&network_output, &alphabet, 5, 0.0, false, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_beam_search_empty() {
+        let alphabet: Vec<String> = "NAG".chars().map(|x| x.to_string()).collect();
+        let network_output = Array2::<f32>::zeros((0, 3));
+        for &collapse_repeats in &[true, false] {
+            assert_eq!(
+                beam_search(&network_output, &alphabet, 5, 0.0, collapse_repeats, 0).unwrap(),
+                (String::new(), vec![], 1.0)
+            );
+        }
+        let options = BeamSearchOptions::new().time_axis(1);
+        assert_eq!(
+            beam_search_with(&network_output.t(), &alphabet, &options).unwrap(),
+            (String::new(), vec![], 1.0)
+        );
+        assert_eq!(
+            viterbi_search(&network_output, &alphabet, false, 1.0, 0.0, true, 0).unwrap(),
+            (String::new(), vec![])
+        );
+
+        // with only the blank label, there is nothing else to emit
+        let blank_only = vec![String::from("N")];
+        for network_output in &[Array2::<f32>::ones((4, 1)), Array2::zeros((0, 1))] {
+            assert_eq!(
+                beam_search(network_output, &blank_only, 5, 0.0, true, 0).unwrap(),
+                (String::new(), vec![], 1.0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_beam_search_time_axis() {
+        let alphabet: Vec<String> = "NAG".chars().map(|x| x.to_string()).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1],
+            [0.7, 0.2, 0.1],
+            [0.1, 0.1, 0.8],
+            [0.1, 0.8, 0.1],
+        ];
+        let options = BeamSearchOptions::new();
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(expected.0, "AGA");
+
+        let labels_major = network_output.t();
+        let options = options.time_axis(1);
+        assert_eq!(
+            beam_search_with(&labels_major, &alphabet, &options).unwrap(),
+            expected
+        );
+        // a contiguous copy with labels on the outer axis, as from a Fortran-ordered producer
+        assert_eq!(
+            beam_search_with(&labels_major.to_owned(), &alphabet, &options).unwrap(),
+            expected
+        );
+        let mut decoder = StreamingDecoder::with_options(&alphabet, options.clone()).unwrap();
+        for row in network_output.outer_iter() {
+            decoder.push_row(row).unwrap();
+        }
+        let (sequence, path, _) = decoder.finish();
+        assert_eq!((sequence, path), (expected.0, expected.1));
+
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options.time_axis(2)),
+            Err(SearchError::InvalidTimeAxis)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_lexicon() {
+        let alphabet: Vec<String> = "NACG|".chars().map(|x| x.to_string()).collect();
+        let network_output = array![
+            [0.1f32, 0.6, 0.1, 0.1, 0.1],
+            [0.1f32, 0.1, 0.2, 0.5, 0.1],
+            [0.1f32, 0.1, 0.1, 0.1, 0.6],
+            [0.1f32, 0.1, 0.1, 0.6, 0.1],
+            [0.1f32, 0.5, 0.1, 0.2, 0.2]
+        ];
+        let options = BeamSearchOptions::new().beam_size(20);
+
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AG|GA");
+
+        let lexicon = Lexicon::new(["AC", "GA"]);
+        let (seq, path, _) =
+            beam_search_lexicon(&network_output, &alphabet, &lexicon, 4, &options).unwrap();
+        assert_eq!(seq, "AC|GA");
+        assert_eq!(path, vec![0, 1, 2, 3, 4]);
+
+        // the lexicon can be reused
+        let (seq, _, _) = beam_search_lexicon(
+            &network_output.slice(s![..3, ..]),
+            &alphabet,
+            &lexicon,
+            4,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(seq, "AC|");
+
+        // without a separator label, this is a single word
+        let lexicon = Lexicon::new(["AGGA", "ACG"]);
+        let (seq, _, _) =
+            beam_search_lexicon(&network_output, &alphabet, &lexicon, 4, &options).unwrap();
+        assert!(lexicon.contains(&seq));
+
+        assert!(matches!(
+            beam_search_lexicon(&network_output, &alphabet, &lexicon, 0, &options),
+            Err(SearchError::InvalidSeparator)
+        ));
+        assert!(matches!(
+            beam_search_lexicon(&network_output, &alphabet, &lexicon, 5, &options),
+            Err(SearchError::InvalidSeparator)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_anchored() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+        let unanchored = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(
+            beam_search_anchored(&network_output, &alphabet, &[], &options).unwrap(),
+            unanchored
+        );
+        // the labelling already emits A in the first two timesteps, but the labellings that do not
+        // are pruned, so it is more probable relative to the rest of the beam
+        let (seq, path, prob) =
+            beam_search_anchored(&network_output, &alphabet, &[(0..2, 1)], &options).unwrap();
+        assert_eq!((seq, path), (unanchored.0.clone(), unanchored.1.clone()));
+        assert!(prob > unanchored.2);
+
+        let (seq, path, _) =
+            beam_search_anchored(&network_output, &alphabet, &[(1..2, 2)], &options).unwrap();
+        assert_eq!(seq, "AGAG");
+        assert_eq!(path, vec![0, 1, 2, 3]);
+        // a window past the last row is checked at the end
+        let (seq, path, _) =
+            beam_search_anchored(&network_output, &alphabet, &[(3..10, 1)], &options).unwrap();
+        assert_eq!(seq, "AAA");
+        assert_eq!(path, vec![0, 2, 4]);
+
+        // only one label can be emitted at each timestep
+        assert!(matches!(
+            beam_search_anchored(
+                &network_output,
+                &alphabet,
+                &[(1..2, 1), (1..2, 2)],
+                &options
+            ),
+            Err(SearchError::RanOutOfBeam { at_timestep: 1 })
+        ));
+        assert!(matches!(
+            beam_search_anchored(&network_output, &alphabet, &[(5..6, 1)], &options),
+            Err(SearchError::RanOutOfBeam { at_timestep: 5 })
+        ));
+        for anchor in [(2..2, 1), (0..1, 0), (0..1, 3)] {
+            assert!(matches!(
+                beam_search_anchored(&network_output, &alphabet, &[anchor], &options),
+                Err(SearchError::InvalidAnchor)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_beam_search_label_prior() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(expected.0, "AAG");
+        // the blank's entry is ignored
+        let flat = options.clone().label_prior(Some(vec![0.0, 1.0, 1.0]));
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &flat).unwrap(),
+            expected
+        );
+
+        // a prior favouring G over A keeps the labelling from emitting A twice
+        let prior = options.clone().label_prior(Some(vec![0.0, 0.3, 0.7]));
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &prior).unwrap();
+        assert_eq!(seq, "AG");
+        let (seq, _, _) = beam_search_with(
+            &network_output,
+            &alphabet,
+            &prior.clone().label_prior_weight(3.0),
+        )
+        .unwrap();
+        assert_eq!(seq, "G");
+        assert_eq!(
+            beam_search_with(
+                &network_output,
+                &alphabet,
+                &prior.clone().label_prior_weight(0.0)
+            )
+            .unwrap(),
+            expected
+        );
+        // the prior is added to the language model's scores
+        let model = PairModel { after: 1, next: 2 };
+        assert_eq!(
+            beam_search_lm(&network_output, &alphabet, &model, 0.0, &prior).unwrap(),
+            beam_search_with(&network_output, &alphabet, &prior).unwrap()
+        );
+
+        for (label_prior, weight) in [
+            (vec![1.0, 1.0], 1.0),
+            (vec![1.0, 0.0, 1.0], 1.0),
+            (vec![1.0, f32::NAN, 1.0], 1.0),
+            (vec![1.0, 1.0, 1.0], f32::INFINITY),
+        ] {
+            let options = options
+                .clone()
+                .label_prior(Some(label_prior))
+                .label_prior_weight(weight);
+            assert!(matches!(
+                beam_search_with(&network_output, &alphabet, &options),
+                Err(SearchError::InvalidLabelPrior)
+            ));
+        }
+    }
+
+    /// A language model that strongly prefers `next` to follow `after`.
+    struct PairModel {
+        after: usize,
+        next: usize,
+    }
+
+    impl LanguageModel for PairModel {
+        fn score(&self, context: &[usize], next: usize) -> f32 {
+            assert_ne!(next, 0, "language model queried on a blank");
+            if context.last() == Some(&self.after) && next == self.next {
+                0.0
+            } else {
+                -2.0
+            }
+        }
+
+        fn max_context(&self) -> Option<usize> {
+            Some(1)
+        }
+    }
+
+    #[test]
+    fn test_beam_search_lm() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+        let model = PairModel { after: 1, next: 2 };
+
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AAG");
+        assert_eq!(
+            beam_search_lm(&network_output, &alphabet, &model, 0.0, &options).unwrap(),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+        let (seq, _, _) =
+            beam_search_lm(&network_output, &alphabet, &model, 1.0, &options).unwrap();
+        assert_eq!(seq, "AG");
+    }
+
+    #[test]
+    fn test_beam_search_hooked() {
+        /// Records the steps and candidates it sees, and rules out labellings with a repeated `A`.
+        struct NoRepeats {
+            steps: Vec<usize>,
+            candidates: usize,
+        }
+
+        impl BeamHook for NoRepeats {
+            fn rescore(&mut self, step: usize, tree: &SuffixTree<usize>, beam: &mut [(i32, f32)]) {
+                self.steps.push(step);
+                self.candidates = self.candidates.max(beam.len());
+                for (node, prob) in beam {
+                    let parent = tree.parent(*node).and_then(|parent| tree.label(parent));
+                    if tree.label(*node) == Some(0) && parent == Some(0) {
+                        *prob = 0.0;
+                    }
+                }
+            }
+        }
+
+        /// Leaves the probabilities as they are.
+        struct Unchanged;
+
+        impl BeamHook for Unchanged {
+            fn rescore(&mut self, _: usize, _: &SuffixTree<usize>, _: &mut [(i32, f32)]) {}
+        }
+
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(2);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(expected.0, "AAG");
+        assert_eq!(
+            beam_search_hooked(&network_output, &alphabet, &options, &mut Unchanged).unwrap(),
+            expected
+        );
+
+        let mut hook = NoRepeats {
+            steps: Vec::new(),
+            candidates: 0,
+        };
+        let (seq, _, _) =
+            beam_search_hooked(&network_output, &alphabet, &options, &mut hook).unwrap();
+        assert_eq!(seq, "GAG");
+        assert_eq!(hook.steps, [0, 1, 2, 3, 4]);
+        // the hook sees the candidates before the beam is cut down to size
+        assert!(hook.candidates > 2);
+
+        struct Nan;
+
+        impl BeamHook for Nan {
+            fn rescore(&mut self, _: usize, _: &SuffixTree<usize>, beam: &mut [(i32, f32)]) {
+                beam[0].1 = f32::NAN;
+            }
+        }
+
+        assert!(matches!(
+            beam_search_hooked(&network_output, &alphabet, &options, &mut Nan),
+            Err(SearchError::IncomparableValues { row: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_beam_cut_relative() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+        let (_, _, _, debug) = beam_search_debug(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(debug.beam().count(), 10);
+
+        // a large enough cut makes no difference
+        let cut = options.clone().beam_cut_relative(Some(f32::INFINITY));
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &cut).unwrap(),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+
+        let cut = options.clone().beam_cut_relative(Some(1.0));
+        let (seq, _, _, debug) = beam_search_debug(&network_output, &alphabet, &cut).unwrap();
+        let beam: Vec<(i32, f32)> = debug.beam().collect();
+        assert!(beam.len() > 1 && beam.len() < 10);
+        for &(_, prob) in &beam {
+            assert!(prob.ln() >= beam[0].1.ln() - 1.0);
+        }
+        assert_eq!(seq, "AAG");
+
+        // the tighter of the two cuts wins
+        let cut = options.clone().beam_size(1).beam_cut_relative(Some(1.0));
+        let (_, _, _, debug) = beam_search_debug(&network_output, &alphabet, &cut).unwrap();
+        assert_eq!(debug.beam().count(), 1);
+
+        assert!(matches!(
+            beam_search_with(
+                &network_output,
+                &alphabet,
+                &options.beam_cut_relative(Some(-1.0))
+            ),
+            Err(SearchError::InvalidBeamCutRelative)
+        ));
+    }
+
+    /// Collapse a path through the network output into the columns of its labelling.
+    fn collapse_path(path: &[usize], blank_id: usize, collapse_repeats: bool) -> Vec<usize> {
+        let mut columns = Vec::new();
+        let mut last = blank_id;
+        for &column in path {
+            if column != blank_id && (column != last || !collapse_repeats) {
+                columns.push(column);
+            }
+            last = column;
+        }
+        columns
+    }
+
+    #[test]
+    fn test_score_labelling() {
+        let alphabet: Vec<String> = "NA".chars().map(|x| x.to_string()).collect();
+        let network_output = array![[0.4f32, 0.6], [0.3, 0.7]];
+        let options = BeamSearchOptions::new();
+        let score = |target: &str, options: &BeamSearchOptions| {
+            score_labelling(&network_output, &alphabet, target, options)
+                .unwrap()
+                .exp()
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+        // AA, AN and NA all give A
+        assert!(close(
+            score("A", &options),
+            0.6 * 0.7 + 0.6 * 0.3 + 0.4 * 0.7
+        ));
+        assert!(close(score("", &options), 0.4 * 0.3));
+        // AA would need a blank between them
+        assert_eq!(score("AA", &options), 0.0);
+
+        let options = options.collapse(Collapse::Never);
+        assert!(close(score("A", &options), 0.6 * 0.3 + 0.4 * 0.7));
+        assert!(close(score("AA", &options), 0.6 * 0.7));
+
+        // the longest token matches
+        let alphabet: Vec<String> = vec!["N".into(), "A".into(), "AC".into(), "C".into()];
+        let network_output = array![[0.1f32, 0.1, 0.7, 0.1], [0.1, 0.1, 0.1, 0.7]];
+        let options = BeamSearchOptions::new();
+        let score = |target: &str| score_labelling(&network_output, &alphabet, target, &options);
+        assert!(close(score("ACC").unwrap().exp(), 0.7 * 0.7));
+        assert!(matches!(score("G"), Err(SearchError::InvalidTarget)));
+        assert!(matches!(score("N"), Err(SearchError::InvalidTarget)));
+    }
+
+    #[test]
+    fn test_beam_search_with_path() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.1f32, 0.7, 0.2],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let options = BeamSearchOptions::new().beam_size(10);
+
+        let (seq, path, frames) =
+            beam_search_with_path(&network_output, &alphabet, &options).unwrap();
+        let (expected_seq, expected_path, _) =
+            beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, expected_seq);
+        assert_eq!(path, expected_path);
+        assert_eq!(seq, "AAG");
+        assert_eq!(frames, vec![1, 1, 0, 1, 0, 2]);
+
+        // the path always gives the labelling
+        for &collapse_repeats in &[true, false] {
+            for &blank_id in &[0, 2] {
+                let options = options
+                    .clone()
+                    .collapse_repeats(collapse_repeats)
+                    .blank_id(blank_id);
+                let (seq, _, frames) =
+                    beam_search_with_path(&network_output, &alphabet, &options).unwrap();
+                assert_eq!(frames.len(), network_output.shape()[0]);
+                let labelled: String = collapse_path(&frames, blank_id, collapse_repeats)
+                    .into_iter()
+                    .map(|column| alphabet[column].clone())
+                    .collect();
+                assert_eq!(labelled, seq);
+            }
+        }
+
+        let (seq, _, frames) =
+            beam_search_with_path(&network_output.slice(s![..0, ..]), &alphabet, &options).unwrap();
+        assert_eq!(seq, "");
+        assert!(frames.is_empty());
+    }
+
+    /// Softmax-like reads, computed with basic float operations only (in a fixed order) so they
+    /// are the same on every platform.
+    fn random_reads(n_reads: usize, n_rows: usize, n_labels: usize, seed: u32) -> Vec<Array2<f32>> {
+        let mut rng = Lcg(seed);
+        (0..n_reads)
+            .map(|_| {
+                let mut read = Array2::zeros((n_rows, n_labels));
+                for mut row in read.outer_iter_mut() {
+                    for x in row.iter_mut() {
+                        let u = rng.next_f32();
+                        *x = u * u * u + 0.01;
+                    }
+                    let total = row.iter().fold(0.0, |acc, x| acc + x);
+                    row /= total;
+                }
+                read
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_exact_search() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.7, 0.2, 0.1], [0.1, 0.1, 0.8]];
+        let (sequence, score) = exact_search(&network_output, &alphabet, 3);
+        assert_eq!(sequence, "AG");
+        assert_eq!(
+            score,
+            score_labelling(&network_output, &alphabet, "AG", &BeamSearchOptions::new()).unwrap()
+        );
+        assert_eq!(exact_search(&network_output, &alphabet, 0).0, "");
+
+        // with no pruning, the beam holds the exact probability of every labelling, so it finds
+        // the most probable one
+        let options = BeamSearchOptions::new()
+            .beam_size(100)
+            .beam_cut_threshold(0.0);
+        for network_output in random_reads(20, 4, 3, 13) {
+            let (expected, score) = exact_search(&network_output, &alphabet, 4);
+            let (sequence, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+            if sequence != expected {
+                // only a tie can pick another labelling
+                let other = score_labelling(&network_output, &alphabet, &sequence, &options);
+                assert!(
+                    (other.unwrap() - score).abs() < 1e-6,
+                    "{} {}",
+                    sequence,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_fastexp() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let reads = random_reads(10, 30, 3, 7);
+        let options = BeamSearchOptions::new().beam_size(10);
+        let report = compare_fastexp(&reads, &alphabet, &options).unwrap();
+        assert_eq!(report.reads, 10);
+        assert_eq!(report.changed, 0);
+        assert!(report.max_log_prob_deviation < 1e-4);
+        if cfg!(not(feature = "fastexp")) {
+            assert_eq!(report.max_log_prob_deviation, 0.0);
+        }
+
+        let empty: &[Array2<f32>] = &[];
+        assert_eq!(
+            compare_fastexp(empty, &alphabet, &options)
+                .unwrap()
+                .changed_fraction(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_multi_character_tokens() {
+        let alphabet: Vec<String> = ["", "AA", "T", "GGG"].iter().map(|&x| x.into()).collect();
+        let network_output = array![
+            [0.1f32, 0.7, 0.1, 0.1], // AA
+            [0.1f32, 0.7, 0.1, 0.1], // AA (collapsed)
+            [0.7f32, 0.1, 0.1, 0.1], // blank
+            [0.1f32, 0.7, 0.1, 0.1], // AA
+            [0.1f32, 0.1, 0.1, 0.7], // GGG
+            [0.1f32, 0.1, 0.7, 0.1], // T
+        ];
+        let (seq, path, _) = beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "AAAAGGGT");
+        assert_eq!(path, vec![0, 2, 4, 5]);
+        let (seq, path) =
+            viterbi_search(&network_output, &alphabet, false, 1.0, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "AAAAGGGT");
+        assert_eq!(path.len(), 4);
+
+        // tokens that are not palindromes keep their order
+        let alphabet: Vec<String> = ["", "AC", "T", "GGA"].iter().map(|&x| x.into()).collect();
+        let (seq, path, _) = beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "ACACGGAT");
+        assert_eq!(path.len(), 4);
+    }
+
+    #[test]
+    fn test_empty_tokens() {
+        let alphabet: Vec<String> = ["N", "", "T"].iter().map(|&x| x.into()).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1], // empty
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.8, 0.1], // empty
+            [0.1f32, 0.8, 0.1], // empty (collapsed)
+            [0.1f32, 0.1, 0.8], // T
+        ];
+        let (seq, path, _) = beam_search(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(seq, "T");
+        assert_eq!(path, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn test_forced_alignment() {
+        let alphabet: Vec<String> = "NAC".chars().map(|x| x.to_string()).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1],
+            [0.2, 0.7, 0.1],
+            [0.8, 0.1, 0.1],
+            [0.05, 0.05, 0.9],
+            [0.6, 0.2, 0.2],
+        ];
+        let options = BeamSearchOptions::new();
+        let align =
+            |reference: &str| forced_alignment(&network_output, &alphabet, reference, &options);
+        assert_eq!(align("AC").unwrap(), vec![(0, 1), (3, 3)]);
+        // not the best labelling, but the best path for this one
+        assert_eq!(align("CA").unwrap(), vec![(3, 3), (4, 4)]);
+        assert_eq!(align("").unwrap(), vec![]);
+        // a repeat needs a blank between, so AAAC needs 6 timesteps
+        assert_eq!(align("AACA").unwrap().len(), 4);
+        assert!(matches!(align("AAAC"), Err(SearchError::UnalignableTarget)));
+        assert!(matches!(align("G"), Err(SearchError::InvalidTarget)));
+
+        let network_output = array![[0.0f32, 1.0, 0.0], [1.0, 0.0, 0.0]];
+        assert!(matches!(
+            forced_alignment(&network_output, &alphabet, "C", &options),
+            Err(SearchError::UnalignableTarget)
+        ));
+    }
+
+    #[test]
+    fn test_top_paths_for() {
+        let alphabet: Vec<String> = "NAC".chars().map(|x| x.to_string()).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1],
+            [0.2, 0.7, 0.1],
+            [0.8, 0.1, 0.1],
+            [0.05, 0.05, 0.9],
+            [0.6, 0.2, 0.2],
+        ];
+        let options = BeamSearchOptions::new();
+        // every path through the five rows that gives AC, best first
+        let mut expected = Vec::new();
+        for n in 0..3usize.pow(5) {
+            let path: Vec<usize> = (0..5).map(|t| n / 3usize.pow(t) % 3).collect();
+            if collapse_path(&path, 0, true) == [1, 2] {
+                let score: f32 = (0..5).map(|t| network_output[(t, path[t])].ln()).sum();
+                expected.push((path, score));
+            }
+        }
+        expected.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        let paths = top_paths_for(&network_output, &alphabet, "AC", 4, &options).unwrap();
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[0].0, [1, 1, 0, 2, 0]);
+        for ((path, score), (_, expected)) in paths.iter().zip(&expected) {
+            assert_eq!(collapse_path(path, 0, true), [1, 2]);
+            assert!((score - expected).abs() < 1e-5);
+        }
+        // asking for more than there are gives them all, each once
+        let mut all = top_paths_for(&network_output, &alphabet, "AC", 1000, &options).unwrap();
+        assert_eq!(all.len(), expected.len());
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all.dedup_by(|a, b| a.0 == b.0);
+        assert_eq!(all.len(), expected.len());
+
+        assert_eq!(
+            top_paths_for(&network_output, &alphabet, "", 3, &options).unwrap(),
+            [(vec![0; 5], network_output.column(0).mapv(f32::ln).sum())]
+        );
+        assert!(top_paths_for(&network_output, &alphabet, "AC", 0, &options)
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            top_paths_for(&network_output, &alphabet, "AAAC", 3, &options),
+            Err(SearchError::UnalignableTarget)
+        ));
+    }
+
+    #[test]
+    fn test_beam_search_with_spans() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+        ];
+        let options = BeamSearchOptions::new();
+        let (seq, spans, prob) =
+            beam_search_with_spans(&network_output, &alphabet, &options).unwrap();
+        let (expected_seq, _, expected_prob) =
+            beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AGA");
+        assert_eq!(seq, expected_seq);
+        assert_eq!(prob, expected_prob);
+        assert_eq!(spans, vec![(0, 1), (3, 3), (4, 6)]);
+
+        // the spans are valid and in order, whatever the labelling
+        let network_output = array![
+            [0.3f32, 0.35, 0.35],
+            [0.4f32, 0.3, 0.3],
+            [0.2f32, 0.4, 0.4],
+            [0.3f32, 0.3, 0.4],
+            [0.5f32, 0.1, 0.4],
+        ];
+        for &collapse_repeats in &[true, false] {
+            let options = BeamSearchOptions::new()
+                .beam_size(2)
+                .collapse_repeats(collapse_repeats);
+            let (seq, spans, _) =
+                beam_search_with_spans(&network_output, &alphabet, &options).unwrap();
+            assert_eq!(spans.len(), seq.len());
+            for (i, &(start, end)) in spans.iter().enumerate() {
+                assert!(start <= end);
+                assert!(end < network_output.shape()[0]);
+                if i > 0 {
+                    assert!(spans[i - 1].1 < start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_beam_search_with_segments() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // blank
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.1, 0.8], // G
+        ];
+        let options = BeamSearchOptions::new();
+        let (seq, segments, prob) =
+            beam_search_with_segments(&network_output, &alphabet, &options).unwrap();
+        let (expected_seq, _, expected_prob) =
+            beam_search_with_spans(&network_output, &alphabet, &options).unwrap();
+        assert_eq!((seq.as_str(), prob), ("AAG", expected_prob));
+        assert_eq!(seq, expected_seq);
+        let segment = |timesteps, label| Segment { timesteps, label };
+        assert_eq!(
+            segments,
+            vec![
+                segment(0..1, None),
+                segment(1..3, Some(0)),
+                segment(3..5, None),
+                segment(5..6, Some(1)),
+                segment(6..7, Some(2)),
+            ]
+        );
+
+        // the labelled segments are the spans, and the segments tile the rows
+        let network_output = array![
+            [0.3f32, 0.35, 0.35],
+            [0.4f32, 0.3, 0.3],
+            [0.2f32, 0.4, 0.4],
+            [0.3f32, 0.3, 0.4],
+            [0.5f32, 0.1, 0.4],
+        ];
+        for &collapse_repeats in &[true, false] {
+            let options = BeamSearchOptions::new()
+                .beam_size(2)
+                .collapse_repeats(collapse_repeats);
+            let (_, segments, _) =
+                beam_search_with_segments(&network_output, &alphabet, &options).unwrap();
+            let (_, spans, _) =
+                beam_search_with_spans(&network_output, &alphabet, &options).unwrap();
+            let mut end = 0;
+            for (i, segment) in segments.iter().enumerate() {
+                assert_eq!(segment.timesteps.start, end);
+                assert!(!segment.timesteps.is_empty());
+                end = segment.timesteps.end;
+                if i > 0 {
+                    assert!(segment.label.is_some() || segments[i - 1].label.is_some());
+                }
+            }
+            assert_eq!(end, network_output.nrows());
+            let labelled: Vec<Span> = segments
+                .iter()
+                .filter(|x| x.label.is_some())
+                .map(|x| (x.timesteps.start, x.timesteps.end - 1))
+                .collect();
+            assert_eq!(labelled, spans);
+        }
+
+        let (seq, segments, _) =
+            beam_search_with_segments(&Array2::<f32>::zeros((0, 3)), &alphabet, &options).unwrap();
+        assert_eq!(seq, "");
+        assert!(segments.is_empty());
+    }
+
+    /// The probability of every labelling of `network_output`, found by summing over all paths.
+    fn labelling_probs(
+        network_output: &Array2<f32>,
+        collapse_repeats: bool,
+    ) -> std::collections::HashMap<Vec<usize>, f32> {
+        let (n_rows, n_cols) = network_output.dim();
+        let mut probs = std::collections::HashMap::new();
+        for code in 0..n_cols.pow(n_rows as u32) {
+            let path: Vec<usize> = (0..n_rows)
+                .map(|t| code / n_cols.pow(t as u32) % n_cols)
+                .collect();
+            let mut labelling = Vec::new();
+            let mut prob = 1.0;
+            for (t, &col) in path.iter().enumerate() {
+                prob *= network_output[(t, col)];
+                if col != 0 && (!collapse_repeats || t == 0 || path[t - 1] != col) {
+                    labelling.push(col);
+                }
+            }
+            *probs.entry(labelling).or_insert(0.0) += prob;
+        }
+        probs
+    }
+
+    #[test]
+    fn test_collapse_repeats_probabilities() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.6, 0.1],
+            [0.4f32, 0.3, 0.3],
+            [0.1f32, 0.2, 0.7],
+            [0.3f32, 0.4, 0.3],
+        ];
+        for &collapse_repeats in &[true, false] {
+            let probs = labelling_probs(&network_output, collapse_repeats);
+            // a beam big enough to hold every labelling makes the search exact
+            let options = BeamSearchOptions::new()
+                .beam_size(probs.len())
+                .collapse_repeats(collapse_repeats);
+            let (seq, _, prob, debug) =
+                beam_search_debug(&network_output, &alphabet, &options).unwrap();
+            let (best, &best_prob) = probs
+                .iter()
+                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+                .unwrap();
+            let best: String = best.iter().map(|&col| alphabet[col].as_str()).collect();
+            assert_eq!(seq, best);
+            assert!((prob - best_prob).abs() < 1e-5);
+
+            // each labelling has its own entry, with the summed probability of its paths
+            assert_eq!(debug.beam().count(), probs.len());
+            for (node, node_prob) in debug.beam() {
+                let (labelling, _) = debug.labelling(node);
+                let labelling: Vec<usize> = labelling
+                    .chars()
+                    .map(|c| if c == 'A' { 1 } else { 2 })
+                    .collect();
+                assert!((node_prob - probs[&labelling]).abs() < 1e-5);
+            }
+        }
+        let (seq, _, _) = beam_search(&network_output, &alphabet, 50, 0.0, false, 0).unwrap();
+        assert_eq!(seq, "AAGA");
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let alphabet: Vec<String> = "NACGT".chars().map(|c| c.to_string()).collect();
+        let read = random_reads(1, 60, 5, 42).pop().unwrap();
+        let options = BeamSearchOptions::new()
+            .beam_size(16)
+            .beam_cut_threshold(0.01);
+        let result = beam_search_with(&read, &alphabet, &options).unwrap();
+        assert_eq!(
+            result,
+            beam_search_with(&read, &alphabet, &options).unwrap()
+        );
+        // only multiplications and additions in a fixed order go into these, so they are the
+        // same on every platform (CI checks Linux and macOS)
+        let (seq, path, prob) = result;
+        assert_eq!(seq, "CAGTGTCAGCGCAGATCGACTCGAGCGCGATAGACTACA");
+        assert_eq!(
+            path,
+            vec![
+                0, 1, 2, 5, 6, 8, 9, 10, 11, 12, 13, 14, 17, 18, 19, 20, 22, 23, 25, 26, 28, 30,
+                32, 34, 36, 37, 38, 39, 41, 44, 46, 47, 48, 49, 51, 52, 53, 54, 57
+            ]
+        );
+        assert_eq!(prob.to_bits(), 1_038_704_540);
+    }
+
+    #[test]
+    fn test_extra_blank_ids() {
+        let reads = random_reads(5, 40, 4, 3);
+        let merged_alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        // padding in the last column, or between the labels
+        for &(padding, alphabet) in &[(3, ["N", "A", "G", "P"]), (1, ["N", "P", "A", "G"])] {
+            let alphabet: Vec<String> = alphabet.iter().map(|x| x.to_string()).collect();
+            let options = BeamSearchOptions::new()
+                .beam_size(8)
+                .extra_blank_ids(&[padding]);
+            for read in &reads {
+                let mut merged = Array2::zeros((read.nrows(), 3));
+                for (mut row, pr) in merged.outer_iter_mut().zip(read.outer_iter()) {
+                    let labels: Vec<f32> =
+                        (1..4).filter(|&c| c != padding).map(|c| pr[c]).collect();
+                    row[0] = pr[0] + pr[padding];
+                    row[1] = labels[0];
+                    row[2] = labels[1];
+                }
+                let expected = beam_search_with(
+                    &merged,
+                    &merged_alphabet,
+                    &options.clone().extra_blank_ids(&[]),
+                )
+                .unwrap();
+                assert_eq!(
+                    beam_search_with(read, &alphabet, &options).unwrap(),
+                    expected
+                );
+                let (seq, _, frames) = beam_search_with_path(read, &alphabet, &options).unwrap();
+                assert_eq!(seq, expected.0);
+                assert!(frames.iter().all(|&column| column != padding));
+            }
+        }
+
+        // moving between the blanks emits nothing, and separates repeats
+        let alphabet: Vec<String> = ["N", "A", "P"].iter().map(|x| x.to_string()).collect();
+        let network_output = array![
+            [0.0f32, 1.0, 0.0],
+            [0.0f32, 0.0, 1.0],
+            [1.0f32, 0.0, 0.0],
+            [0.0f32, 1.0, 0.0],
+        ];
+        let options = BeamSearchOptions::new()
+            .beam_cut_threshold(0.1)
+            .extra_blank_ids(&[2, 2]);
+        let (seq, path, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AA");
+        assert_eq!(path, vec![0, 3]);
+
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options.extra_blank_ids(&[3])),
+            Err(SearchError::InvalidBlankId)
+        ));
+    }
+
+    #[test]
+    fn test_tie_break() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        // A, AG, G and GA all have probability 0.25
+        let network_output = array![[0.0f32, 0.5, 0.5], [0.0f32, 0.5, 0.5]];
+        for beam_size in 1..5 {
+            let options = BeamSearchOptions::new().beam_size(beam_size);
+            let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+            assert_eq!(seq, "A");
+            let (seq, _, _) =
+                beam_search_log(&network_output, &alphabet, beam_size, -5.0, true, 0).unwrap();
+            assert_eq!(seq, "A");
+        }
+
+        let options = BeamSearchOptions::new().tie_break(TieBreak::Custom(|a, b| b.cmp(a)));
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "GA");
+        // labellings the comparator finds equal are still ordered lexicographically
+        let options = BeamSearchOptions::new()
+            .tie_break(TieBreak::Custom(|a, b| b.len().cmp(&a.len())))
+            .beam_size(1);
+        let (seq, _, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AG");
+
+        // columns are compared, not tokens
+        let alphabet = vec![String::from("N"), String::from("G"), String::from("A")];
+        let (seq, _, _) =
+            beam_search_with(&network_output, &alphabet, &BeamSearchOptions::new()).unwrap();
+        assert_eq!(seq, "G");
+
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let uniform = Array2::from_elem((2, 5), 0.2f32);
+        let survivors = |network_output: ArrayView2<f32>, beam_size| {
+            beam_search_nbest(
+                &network_output,
+                &alphabet,
+                beam_size,
+                0.0,
+                true,
+                0,
+                beam_size,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(seq, _, _)| seq)
+            .collect::<Vec<_>>()
+        };
+        // after one row, the empty labelling and each label are all equally likely, and the
+        // first in lexicographic order survive
+        assert_eq!(survivors(uniform.slice(s![..1, ..]), 3), ["", "A", "C"]);
+        // T was cut from the beam after the first row, so after the second it is less likely
+        // than A, C and G, and ties with the empty labelling and every labelling of two labels
+        assert_eq!(survivors(uniform.view(), 4), ["A", "C", "G", ""]);
+    }
+
+    #[test]
+    fn test_beam_search_repeatable() {
+        // nothing in the search is random, so the result only depends on the inputs, even where
+        // many labellings are tied
+        let alphabet: Vec<String> = "NACGT".chars().map(String::from).collect();
+        let network_output = Array2::from_shape_fn((40, 5), |(row, col)| {
+            if row % 3 == 0 {
+                0.2f32
+            } else {
+                [0.1f32, 0.3, 0.1, 0.4, 0.1][(row + col) % 5]
+            }
+        });
+        let options = BeamSearchOptions::new().beam_size(16);
+        let expected = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        let mut decoder = Decoder::new(options.clone());
+        for _ in 0..100 {
+            let result = beam_search_with(&network_output, &alphabet, &options).unwrap();
+            assert_eq!(result.0, expected.0);
+            assert_eq!(result.1, expected.1);
+            assert_eq!(result.2.to_bits(), expected.2.to_bits());
+            let result = decoder.decode(&network_output, &alphabet).unwrap();
+            assert_eq!(result.0, expected.0);
+            assert_eq!(result.2.to_bits(), expected.2.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_collapse() {
+        let alphabet: Vec<String> = "NAa".chars().map(String::from).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1], // A
+            [0.1f32, 0.1, 0.8], // a
+            [0.1f32, 0.1, 0.8], // a
+            [0.8f32, 0.1, 0.1], // blank
+            [0.1f32, 0.1, 0.8], // a
+        ];
+        let search = |collapse| {
+            let options = BeamSearchOptions::new().collapse(collapse);
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        };
+        assert_eq!(search(Collapse::Repeats).0, "Aaa");
+        assert_eq!(search(Collapse::Never).0, "Aaaa");
+        assert_eq!(
+            search(Collapse::Custom(|a, b| a == b)),
+            search(Collapse::Repeats)
+        );
+        assert_eq!(
+            search(Collapse::Custom(|_, _| false)),
+            search(Collapse::Never)
+        );
+
+        // a continues an A straight before it (and itself), but not after a blank
+        let (seq, _, _) = search(Collapse::Custom(|a, b| a == b || (a == 1 && b == 2)));
+        assert_eq!(seq, "Aa");
+    }
+
+    #[test]
+    fn test_keep_best_on_empty() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.8, 0.1], // A
+            [0.2f32, 0.3, 0.3], // nothing passes the threshold (not normalised)
+            [0.1f32, 0.1, 0.8], // G
+        ];
+        let options = BeamSearchOptions::new().beam_cut_threshold(0.32);
+        assert!(matches!(
+            beam_search_with(&network_output, &alphabet, &options),
+            Err(SearchError::RanOutOfBeam { at_timestep: 1 })
+        ));
+
+        let options = options.keep_best_on_empty(true);
+        let (seq, path, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AG");
+        assert_eq!(path, vec![0, 1]);
+
+        // rows that do have candidates are unaffected
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.1f32, 0.1, 0.8]];
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options).unwrap(),
+            beam_search_with(
+                &network_output,
+                &alphabet,
+                &options.keep_best_on_empty(false)
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_beam_search_with_marginals() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.5f32, 0.3, 0.2], [0.1f32, 0.1, 0.8],];
+        let (seq, path, prob, marginals) =
+            beam_search_with_marginals(&network_output, &alphabet, &BeamSearchOptions::new())
+                .unwrap();
+        assert_eq!(
+            (seq, path, prob),
+            beam_search_with(&network_output, &alphabet, &BeamSearchOptions::new()).unwrap()
+        );
+        assert_eq!(marginals.inner_size(), 3);
+        assert_eq!(marginals.outer_size(), 3);
+        for row in marginals.rows() {
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+        // nothing is pruned at the first timestep, so the marginals are the network output
+        for col in 0..3 {
+            assert!((marginals[(0, col)] - network_output[(0, col)]).abs() < 1e-6);
+        }
+
+        // with a beam of one, only the paths of one labelling are counted, which leaves out the
+        // paths through blank and G at the first timestep
+        let options = BeamSearchOptions::new().beam_size(1);
+        let (seq, _, _, marginals) =
+            beam_search_with_marginals(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(seq, "AG");
+        assert!((marginals[(0, 1)] - 1.0).abs() < 1e-6);
+        assert_eq!(marginals[(0, 2)], 0.0);
+    }
+
+    #[test]
+    fn test_beam_search_nbest() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.3, 0.6], [0.7f32, 0.2, 0.1], [0.2f32, 0.5, 0.3]];
+
+        let (best_seq, best_path, best_prob) =
+            beam_search(&network_output, &alphabet, 10, 0.0, true, 0).unwrap();
+        let nbest = beam_search_nbest(&network_output, &alphabet, 10, 0.0, true, 0, 3).unwrap();
+        assert_eq!(nbest.len(), 3);
+        assert_eq!(nbest[0], (best_seq, best_path, best_prob));
+        for pair in nbest.windows(2) {
+            assert!(pair[0].2 >= pair[1].2);
+            assert_ne!(pair[0].0, pair[1].0);
+        }
+
+        // there are only a handful of labellings with a beam this small
+        let nbest = beam_search_nbest(&network_output, &alphabet, 2, 0.0, true, 0, 10).unwrap();
+        assert_eq!(nbest.len(), 2);
+    }
+
+    #[test]
+    fn test_to_fastq() {
+        assert_eq!(
+            to_fastq("read_1", "ACG", &[0.9, 0.999, 1.0]),
+            "@read_1\nACG\n+\n+?I\n"
+        );
+        assert_eq!(to_fastq("read_2", "", &[]), "@read_2\n\n+\n\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_to_fastq_length_mismatch() {
+        to_fastq("read_1", "ACG", &[0.9, 0.9]);
+    }
+
+    #[test]
+    fn test_beam_search_with_qualities() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("GG")];
+        let network_output = array![
+            [0.1f32, 0.3, 0.6],   // GG
+            [0.8f32, 0.1, 0.1],   // N
+            [0.1f32, 0.7, 0.2],   // A
+            [0.9f32, 0.05, 0.05], // N
+        ];
+
+        let (seq, path, qualities) =
+            beam_search_with_qualities(&network_output, &alphabet, 5, 0.2, true, 0).unwrap();
+        assert_eq!(seq, "GGA");
+        assert_eq!(path, vec![0, 2]);
+        assert_eq!(qualities, vec![0.6, 0.6, 0.7]);
+
+        // with labels on the outer axis
+        let options = BeamSearchOptions::new()
+            .beam_cut_threshold(0.2)
+            .time_axis(1);
+        let (seq, path, prob, qualities) =
+            beam_search_qualities_with(&network_output.t(), &alphabet, &options).unwrap();
+        assert_eq!((seq.as_str(), path), ("GGA", vec![0, 2]));
+        assert_eq!(qualities, vec![0.6, 0.6, 0.7]);
+        let (_, _, expected) =
+            beam_search_with(&network_output, &alphabet, &options.time_axis(0)).unwrap();
+        assert_eq!(prob, expected);
+    }
+
+    #[test]
+    fn test_beam_search_log() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.1, 0.8], // G
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
+        ];
+        let log_output = network_output.mapv(f32::ln);
+
+        for &collapse_repeats in &[true, false] {
+            let (seq, starts, prob) =
+                beam_search(&network_output, &alphabet, 5, 0.0, collapse_repeats, 0).unwrap();
+            let (log_seq, log_starts, log_prob) = beam_search_log(
+                &log_output,
+                &alphabet,
+                5,
+                f32::NEG_INFINITY,
+                collapse_repeats,
+                0,
+            )
+            .unwrap();
+            assert_eq!(seq, log_seq);
+            assert_eq!(starts, log_starts);
+            assert!((prob.ln() - log_prob).abs() < 1e-3);
+        }
+
+        assert!(matches!(
+            beam_search_log(&log_output, &alphabet, 5, 0.0, true, 0),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+    }
 
     #[test]
-    fn crf_test_greedy() {
-        let alphabet = vec![
-            String::from("N"),
-            String::from("A"),
-            String::from("C"),
-            String::from("G"),
-            String::from("T"),
+    fn test_beam_search_log_with() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.1, 0.8],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.8, 0.1],
+            [0.7f32, 0.1, 0.2],
+            [0.1f32, 0.1, 0.8],
         ];
+        let log_output = network_output.mapv(f32::ln);
+
+        for &ln_threshold in &[f32::NEG_INFINITY, -2.0, -1.4] {
+            let expected =
+                beam_search_log(&log_output, &alphabet, 5, ln_threshold, true, 0).unwrap();
+            let options = BeamSearchOptions::new().beam_cut_threshold_log(ln_threshold);
+            let result = beam_search_log_with(&log_output, &alphabet, &options).unwrap();
+            assert_eq!(result.0, expected.0);
+            assert_eq!(result.1, expected.1);
+            assert!((result.2 - expected.2).abs() < 1e-6);
+            // the same threshold cuts the same entries of the probabilities
+            let (sequence, path, _) =
+                beam_search_with(&network_output, &alphabet, &options).unwrap();
+            assert_eq!((sequence, path), (expected.0, expected.1));
+        }
+
+        // the default threshold is no threshold, and the rest of the options apply
+        let options = BeamSearchOptions::new().time_axis(1);
+        let (sequence, _, _) = beam_search_log_with(&log_output.t(), &alphabet, &options).unwrap();
+        assert_eq!(sequence, "GAG");
+
+        // ln(1/3) is about -1.0986
+        for &ln_threshold in &[-1.0f32, 0.0] {
+            let options = BeamSearchOptions::new().beam_cut_threshold_log(ln_threshold);
+            assert!(matches!(
+                beam_search_log_with(&log_output, &alphabet, &options),
+                Err(SearchError::InvalidBeamCutThreshold)
+            ));
+        }
+    }
 
+    #[test]
+    fn test_beam_search_scored() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
         let network_output = array![
-            [
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [1f32, 0.000, 0.000, 0.000, 0.000], // N 2
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-            ],
-            [
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.900, 0.000, 0.000], // C 2
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-            ],
-            [
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.700], // T 1
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-            ],
-            [
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [1f32, 0.000, 0.000, 0.000, 0.000], // N 3
-            ],
-            [
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.990, 0.000, 0.000, 0.000], // A 3
-            ],
-            [
-                [0f32, 0.900, 0.000, 0.000, 0.000], // A 0
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-            ],
-            [
-                [0f32, 0.000, 0.000, 0.999, 0.000], // G 0
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-                [0f32, 0.000, 0.000, 0.000, 0.000],
-            ],
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.6, 0.1],
+            [0.4f32, 0.3, 0.3],
+            [0.1f32, 0.2, 0.7],
         ];
-        let init = array![0f32, 0., 1., 0., 0.];
-        let (sequence, path) =
-            crf_greedy_search(&network_output, &init, &alphabet, false, 1.0, 0.0).unwrap();
+        let probs = labelling_probs(&network_output, true);
+        let (labelling, best) = probs
+            .iter()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+        // a beam this big keeps every labelling, so the score is the full probability
+        let options = BeamSearchOptions::new().beam_size(100);
+        let (seq, path, score) = beam_search_scored(&network_output, &alphabet, &options).unwrap();
+        let (expected_seq, expected_path, _) =
+            beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!((&seq, &path), (&expected_seq, &expected_path));
+        let best_seq: String = labelling
+            .iter()
+            .map(|&col| alphabet[col].as_str())
+            .collect();
+        assert_eq!(seq, best_seq);
+        assert!((score - best.ln()).abs() < 1e-4);
 
-        assert_eq!(sequence, "CTAAG");
-        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+        // a smaller beam only counts the paths it kept
+        let options = BeamSearchOptions::new().beam_size(1);
+        let (_, _, small_score) = beam_search_scored(&network_output, &alphabet, &options).unwrap();
+        assert!(small_score < score);
 
-        let (sequence, path) =
-            crf_greedy_search(&network_output, &init, &alphabet, true, 1.0, 0.0).unwrap();
+        // long reads do not underflow
+        let long_output = Array2::from_elem((2000, 3), 1.0f32 / 3.0);
+        let (_, _, score) =
+            beam_search_scored(&long_output, &alphabet, &BeamSearchOptions::new()).unwrap();
+        // far below the smallest f32, which is about exp(-103)
+        assert!(score.is_finite() && score < -200.0);
+    }
 
-        assert_eq!(sequence, "CTAAG+&5+?");
-        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+    #[test]
+    fn test_beam_search_within() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.6, 0.1],
+            [0.4f32, 0.3, 0.3],
+            [0.1f32, 0.2, 0.7],
+        ];
+        let options = BeamSearchOptions::new().beam_size(100);
+        let best = beam_search_scored(&network_output, &alphabet, &options).unwrap();
+        let within = beam_search_within(&network_output, &alphabet, &options, 0.0).unwrap();
+        assert_eq!(within, vec![best.clone()]);
 
-        let beam_size = 5;
-        let beam_cut_threshold = 0.01;
-        let (sequence, path) = crf_beam_search(
-            &network_output,
-            &init,
-            &alphabet,
-            beam_size,
-            beam_cut_threshold,
-        )
-        .unwrap();
+        // the beam keeps every labelling, so this gets every one within 1.0 of the best
+        let mut expected: Vec<(String, f32)> = labelling_probs(&network_output, true)
+            .into_iter()
+            .map(|(labelling, prob)| {
+                let seq = labelling
+                    .iter()
+                    .map(|&col| alphabet[col].as_str())
+                    .collect();
+                (seq, prob.ln())
+            })
+            .filter(|(_, score)| best.2 - score <= 1.0)
+            .collect();
+        expected.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let within = beam_search_within(&network_output, &alphabet, &options, 1.0).unwrap();
+        assert!(expected.len() > 1);
+        assert_eq!(within.len(), expected.len());
+        assert_eq!(within[0], best);
+        for ((seq, _, score), (expected_seq, expected_score)) in within.iter().zip(&expected) {
+            assert_eq!(seq, expected_seq);
+            assert!((score - expected_score).abs() < 1e-4);
+        }
 
-        assert_eq!(sequence, "CTAAG");
-        assert_eq!(path, vec![1, 2, 4, 5, 6]);
+        // a delta of 0.0 still returns ties
+        let network_output = array![[1.0f32 / 3.0, 1.0 / 3.0, 1.0 / 3.0]];
+        let within = beam_search_within(&network_output, &alphabet, &options, 0.0).unwrap();
+        let mut seqs: Vec<&str> = within.iter().map(|x| x.0.as_str()).collect();
+        seqs.sort_unstable();
+        assert_eq!(seqs, vec!["", "A", "G"]);
+
+        for &delta in &[-1.0, f32::NAN] {
+            assert!(matches!(
+                beam_search_within(&network_output, &alphabet, &options, delta),
+                Err(SearchError::InvalidDelta)
+            ));
+        }
     }
 
     #[test]
-    fn test_phred_scores() {
-        let qbias = 0.0;
-        let qscale = 1.0;
-        assert_eq!('!', phred(0.0, qscale, qbias));
-        assert_eq!('$', phred(0.5, qscale, qbias));
-        assert_eq!('+', phred(1.0 - 1e-1, qscale, qbias));
-        assert_eq!('5', phred(1.0 - 1e-2, qscale, qbias));
-        assert_eq!('?', phred(1.0 - 1e-3, qscale, qbias));
-        assert_eq!('I', phred(1.0 - 1e-4, qscale, qbias));
-        assert_eq!('I', phred(1.0 - 1e-5, qscale, qbias));
-        assert_eq!('I', phred(1.0 - 1e-6, qscale, qbias));
-        assert_eq!('I', phred(1.0, qscale, qbias));
+    fn test_beam_search_lattice() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.2f32, 0.5, 0.3],
+            [0.3f32, 0.6, 0.1],
+            [0.4f32, 0.3, 0.3],
+            [0.1f32, 0.2, 0.7],
+        ];
+        let options = BeamSearchOptions::new().beam_size(3);
+        let lattice = beam_search_lattice(&network_output, &alphabet, &options).unwrap();
+        let states = lattice.states();
+        assert_eq!(states[0].timestep, 0);
+        assert_eq!(states[0].node, ROOT_NODE);
+
+        // the final beam is the one the scored search ends with
+        let (seq, path, score) = beam_search_scored(&network_output, &alphabet, &options).unwrap();
+        let finals = lattice.final_states();
+        assert_eq!(finals.len(), 3);
+        assert!(finals.clone().all(|i| states[i].timestep == 4));
+        assert_eq!(lattice.labelling(finals.start), (seq, path));
+        assert!((states[finals.start].score - score).abs() < 1e-6);
+
+        for (to, state) in states.iter().enumerate().skip(1) {
+            let mut incoming = 0.0;
+            for arc in lattice.arcs().iter().filter(|arc| arc.to == to) {
+                let from = &states[arc.from];
+                assert_eq!(from.timestep + 1, state.timestep);
+                let (from_seq, _) = lattice.labelling(arc.from);
+                let (to_seq, _) = lattice.labelling(to);
+                match arc.label {
+                    Some(column) => assert_eq!(from_seq + &alphabet[column], to_seq),
+                    None => assert_eq!(from_seq, to_seq),
+                }
+                assert!(arc.score <= from.score);
+                incoming += arc.score.exp();
+            }
+            assert!((incoming.ln() - state.score).abs() < 1e-4);
+        }
+
+        let text = lattice.to_openfst_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), lattice.arcs().len() + finals.len());
+        assert!(lines[0].starts_with("0 "));
+        assert_eq!(lines[lines.len() - 1], format!("{} 0", states.len() - 1));
     }
 
     #[test]
-    fn test_viterbi() {
-        let qbias = 0.0;
-        let qscale = 1.0;
+    fn test_blank_id() {
         let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
         let network_output = array![
             [0.0f32, 0.4, 0.6], // G
@@ -548,63 +7333,482 @@ mod tests {
             [0.8f32, 0.1, 0.1], // N
             [0.1f32, 0.1, 0.8], // G
         ];
+        // the same output and alphabet with the blank moved to the end
+        let blank_last_alphabet = vec![String::from("A"), String::from("G"), String::from("N")];
+        let blank_last_output = network_output.select(Axis(1), &[1, 2, 0]);
 
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, false, qscale, qbias, true).unwrap();
-        assert_eq!(seq, "GGAG");
-        assert_eq!(starts, vec![0, 5, 7, 9]);
+        for &collapse_repeats in &[true, false] {
+            let expected =
+                beam_search(&network_output, &alphabet, 5, 0.0, collapse_repeats, 0).unwrap();
+            let result = beam_search(
+                &blank_last_output,
+                &blank_last_alphabet,
+                5,
+                0.0,
+                collapse_repeats,
+                2,
+            )
+            .unwrap();
+            assert_eq!(expected, result);
 
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, true, qscale, qbias, true).unwrap();
-        assert_eq!(seq, "GGAG%$$(");
-        assert_eq!(starts, vec![0, 5, 7, 9]);
+            let expected = viterbi_search(
+                &network_output,
+                &alphabet,
+                true,
+                1.0,
+                0.0,
+                collapse_repeats,
+                0,
+            )
+            .unwrap();
+            let result = viterbi_search(
+                &blank_last_output,
+                &blank_last_alphabet,
+                true,
+                1.0,
+                0.0,
+                collapse_repeats,
+                2,
+            )
+            .unwrap();
+            assert_eq!(expected, result);
+        }
+
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 5, 0.0, true, 3),
+            Err(SearchError::InvalidBlankId)
+        ));
     }
 
     #[test]
-    fn test_viterbi_blank_bounds() {
-        let qbias = 0.0;
-        let qscale = 1.0;
+    fn test_greedy_search() {
         let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
         let network_output = array![
-            [0.6f32, 0.2, 0.2], // N
-            [0.6f32, 0.2, 0.2], // N
-            [0.0f32, 0.4, 0.6], // G
-            [0.0f32, 0.3, 0.7], // G
-            [0.3f32, 0.3, 0.4], // G
-            [0.4f32, 0.3, 0.3], // N
-            [0.4f32, 0.3, 0.3], // N
-            [0.3f32, 0.3, 0.4], // G
-            [0.1f32, 0.4, 0.5], // G
-            [0.1f32, 0.5, 0.4], // A
+            [0.1f32, 0.1, 0.8], // G
+            [0.1f32, 0.1, 0.8], // G
             [0.8f32, 0.1, 0.1], // N
             [0.1f32, 0.1, 0.8], // G
-            [0.4f32, 0.3, 0.3], // N
+            [0.1f32, 0.8, 0.1], // A
+            [0.8f32, 0.1, 0.1], // N
+            [0.1f32, 0.8, 0.1], // A
         ];
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, false, qscale, qbias, true).unwrap();
-        assert_eq!(seq, "GGAG");
-        assert_eq!(starts, vec![2, 7, 9, 11]);
 
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, true, qscale, qbias, true).unwrap();
-        assert_eq!(seq, "GGAG%$$(");
-        assert_eq!(starts, vec![2, 7, 9, 11]);
+        let (seq, path) = greedy_search(&network_output, &alphabet, 0).unwrap();
+        assert_eq!(seq, "GGAA");
+        assert_eq!(path, vec![0, 3, 4, 6]);
 
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, false, qscale, qbias, false).unwrap();
-        assert_eq!(seq, "GGGGGAG");
-        assert_eq!(starts, vec![2, 3, 4, 7, 8, 9, 11]);
+        let (beam_seq, beam_path, _prob) =
+            beam_search(&network_output, &alphabet, 1, 0.2, true, 0).unwrap();
+        assert_eq!(seq, beam_seq);
+        assert_eq!(path, beam_path);
+    }
 
-        let (seq, starts) =
-            viterbi_search(&network_output, &alphabet, true, qscale, qbias, false).unwrap();
-        assert_eq!(seq, "GGGGGAG%&##$$(");
-        assert_eq!(starts, vec![2, 3, 4, 7, 8, 9, 11]);
+    #[test]
+    fn test_beam_search_batch() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [
+                [0.1f32, 0.1, 0.8], // G
+                [0.8f32, 0.1, 0.1], // N
+                [0.1f32, 0.8, 0.1], // A
+            ],
+            [
+                [0.1f32, 0.8, 0.1], // A
+                [0.1f32, 0.1, 0.8], // G
+                [1.0f32, 0.0, 0.0], // N (padding)
+            ],
+        ];
 
-        let (seq, _starts) = beam_search(&network_output, &alphabet, 5, 0.0, true).unwrap();
-        assert_eq!(seq, "GAGAG");
+        let results = beam_search_batch(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(results.len(), 2);
+        for (item, result) in network_output.outer_iter().zip(&results) {
+            assert_eq!(
+                result,
+                &beam_search(&item, &alphabet, 5, 0.0, true, 0).unwrap()
+            );
+        }
+        assert_eq!(results[0].0, "GA");
+        assert_eq!(results[1].0, "AG");
+    }
 
-        let (seq, _starts) = beam_search(&network_output, &alphabet, 5, 0.0, false).unwrap();
-        assert_eq!(seq, "GGGAGAG");
+    #[test]
+    fn test_decode_all() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let reads = [
+            array![[0.1f32, 0.1, 0.8], [0.8, 0.1, 0.1], [0.1, 0.8, 0.1]],
+            array![[0.1f32, 0.8, 0.1]],
+            array![[0.1f32, 0.8, 0.1], [0.1, 0.1, 0.1]],
+            Array2::zeros((0, 3)),
+            array![[0.1f32, 0.8, 0.1], [0.1, 0.1, 0.8]],
+        ];
+        let options = BeamSearchOptions::new().beam_cut_threshold(0.2);
+        let expected: Vec<_> = reads
+            .iter()
+            .map(|read| beam_search_with(read, &alphabet, &options))
+            .collect();
+
+        // reads are taken one at a time, as the results are
+        let mut taken = 0;
+        let mut results = decode_all(
+            reads.iter().cloned().inspect(|_| taken += 1),
+            &alphabet,
+            &options,
+        );
+        let first = results.next().unwrap().unwrap();
+        assert_eq!(first.0, "GA");
+        let rest: Vec<_> = results.collect();
+        assert_eq!(taken, reads.len());
+        assert_eq!(rest.len(), reads.len() - 1);
+        for (result, expected) in Some(Ok(first)).into_iter().chain(rest).zip(expected) {
+            assert_eq!(format!("{:?}", result), format!("{:?}", expected));
+        }
+
+        // views can be decoded as well as owned arrays
+        let batch = array![[[0.1f32, 0.8, 0.1], [0.1, 0.1, 0.8]]];
+        let results: Vec<_> = decode_all(batch.outer_iter(), &alphabet, &options).collect();
+        assert_eq!(results[0].as_ref().unwrap().0, "AG");
+    }
+
+    #[test]
+    fn test_beam_search_batch_order() {
+        use ndarray::Array3;
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        // each item has a different (peaky) labelling
+        let network_output =
+            Array3::from_shape_fn(
+                (64, 20, 3),
+                |(b, t, l)| {
+                    if (b + t) % 3 == l {
+                        0.8f32
+                    } else {
+                        0.1f32
+                    }
+                },
+            );
+
+        let results = beam_search_batch(&network_output, &alphabet, 5, 0.0, true, 0).unwrap();
+        assert_eq!(results.len(), 64);
+        for (item, result) in network_output.outer_iter().zip(&results) {
+            assert_eq!(
+                result,
+                &beam_search(&item, &alphabet, 5, 0.0, true, 0).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_search_error_context() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![[0.1f32, 0.1, 0.8], [0.8f32, 0.1, 0.1], [0.1f32, 0.1, 0.1],];
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 5, 0.2, true, 0),
+            Err(SearchError::RanOutOfBeam { at_timestep: 2 })
+        ));
+
+        let network_output = array![
+            [0.1f32, 0.1, 0.8],
+            [0.8f32, 0.1, f32::NAN],
+            [0.1f32, 0.1, 0.8],
+        ];
+        assert!(matches!(
+            beam_search(&network_output, &alphabet, 5, 0.0, true, 0),
+            Err(SearchError::IncomparableValues { row: 1, col: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_arbitrary_values() {
+        // the values the fuzz target is most likely to find trouble with
+        const VALUES: [f32; 12] = [
+            0.0,
+            -0.0,
+            0.5,
+            1.0,
+            2.0,
+            -1.0,
+            1e-40, // subnormal
+            f32::MIN_POSITIVE,
+            f32::MAX,
+            f32::NAN,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+        ];
+        let alphabet: Vec<String> = "NAGC".chars().map(String::from).collect();
+        let mut rng = Lcg(5);
+        let mut next = || rng.next_u32() as usize;
+        for _ in 0..2000 {
+            let (n_rows, n_labels) = (next() % 5, 1 + next() % 4);
+            let network_output =
+                Array2::from_shape_simple_fn((n_rows, n_labels), || VALUES[next() % VALUES.len()]);
+            let alphabet = &alphabet[..n_labels];
+            let options = BeamSearchOptions::new()
+                .beam_size([1, 3, 100][next() % 3])
+                .collapse_repeats(next() % 2 == 0);
+            // none of these may panic, whatever the result
+            let result = beam_search_with(&network_output, alphabet, &options);
+            if let Ok((_, path, _)) = result {
+                assert!(path.iter().all(|&time| time < n_rows));
+            }
+            let _ = beam_search_with_spans(&network_output, alphabet, &options);
+            let _ = beam_search_with_segments(&network_output, alphabet, &options);
+            let _ = beam_search_qualities_with(&network_output, alphabet, &options);
+            let _ = score_labelling(&network_output, alphabet, "AG", &options);
+            let _ = viterbi_search(&network_output, alphabet, true, 1.0, 0.0, true, 0);
+        }
+
+        // a negative blank probability leaves the chosen labelling with no path to align
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output = array![[-1.0f32, 0.6, 0.4]];
+        let options = BeamSearchOptions::new();
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &options)
+                .unwrap()
+                .0,
+            "A"
+        );
+        assert!(matches!(
+            beam_search_with_spans(&network_output, &alphabet, &options),
+            Err(SearchError::UnalignableTarget)
+        ));
+        assert!(matches!(
+            beam_search_with_segments(&network_output, &alphabet, &options),
+            Err(SearchError::UnalignableTarget)
+        ));
+
+        assert_eq!(
+            viterbi_search(&Array2::zeros((0, 3)), &alphabet, true, 1.0, 0.0, true, 0).unwrap(),
+            (String::new(), vec![])
+        );
+        assert!(matches!(
+            viterbi_search(&Array2::zeros((2, 2)), &alphabet, true, 1.0, 0.0, true, 0),
+            Err(SearchError::AlphabetMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_check_finite() {
+        let mut network_output = ndarray::Array2::from_elem((10, 5), 0.2f32);
+        assert!(check_finite(&network_output).is_ok());
+
+        network_output[(6, 3)] = f32::NAN;
+        assert!(matches!(
+            check_finite(&network_output),
+            Err(SearchError::IncomparableValues { row: 6, col: 3 })
+        ));
+
+        network_output[(4, 1)] = f32::INFINITY;
+        assert!(matches!(
+            check_finite(&network_output),
+            Err(SearchError::IncomparableValues { row: 4, col: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_validate_inputs() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let mut network_output = array![[0.2f32, 0.5, 0.3], [0.3f32, 0.6, 0.1]];
+        assert!(validate_inputs(&network_output, &alphabet, 5, 0.0).is_ok());
+        assert!(matches!(
+            validate_inputs(&network_output, &alphabet[..2], 5, 0.0),
+            Err(SearchError::AlphabetMismatch)
+        ));
+        assert!(matches!(
+            validate_inputs(&network_output, &alphabet, 0, 0.0),
+            Err(SearchError::InvalidBeamSize)
+        ));
+        assert!(matches!(
+            validate_inputs(&network_output, &alphabet, 5, 0.5),
+            Err(SearchError::InvalidBeamCutThreshold)
+        ));
+
+        network_output[(1, 2)] = 0.1005;
+        assert!(validate_inputs(&network_output, &alphabet, 5, 0.0).is_ok());
+        network_output[(1, 2)] = 0.2;
+        match validate_inputs(&network_output, &alphabet, 5, 0.0) {
+            Err(SearchError::RowSumMismatch { row, sum }) => {
+                assert_eq!(row, 1);
+                assert!((sum - 1.1).abs() < 1e-6);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        // the search itself does not mind
+        assert!(beam_search(&network_output, &alphabet, 5, 0.0, true, 0).is_ok());
+
+        network_output[(1, 0)] = f32::NAN;
+        assert!(matches!(
+            validate_inputs(&network_output, &alphabet, 5, 0.0),
+            Err(SearchError::IncomparableValues { row: 1, col: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_softmax_rows() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let mut logits = array![
+            [0.0f32, 1.0, 2.0],
+            [1000.0, 1001.0, 1002.0],
+            [-5.0, 3.0, 0.0]
+        ];
+        logits[(2, 0)] = f32::NEG_INFINITY;
+        let mut network_output = logits.clone();
+        softmax_rows(&mut network_output);
+        assert!(network_output.iter().all(|x| x.is_finite()));
+        // subtracting the maximum gives a shifted row the same softmax
+        assert_eq!(network_output.row(0), network_output.row(1));
+        let sum: f32 = [0.0f32, 1.0, 2.0].iter().map(|x| x.exp()).sum();
+        for (x, &l) in network_output.row(0).iter().zip(logits.row(0)) {
+            assert!((x - l.exp() / sum).abs() < 1e-5);
+        }
+        assert_eq!(network_output[(2, 0)], 0.0);
+        assert!(validate_inputs(&network_output, &alphabet, 5, 0.0).is_ok());
+
+        network_output[(0, 1)] = f32::NAN;
+        softmax_rows(&mut network_output);
+        assert!(network_output.row(0).iter().all(|x| x.is_nan()));
+    }
+
+    #[test]
+    fn test_check_normalized() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let mut network_output = array![[0.1f32, 0.8, 0.1], [0.5, 0.4, 0.1], [0.2, 0.2, 0.6]];
+        let options = BeamSearchOptions::new().check_normalized(true);
+        assert!(beam_search_with(&network_output, &alphabet, &options).is_ok());
+
+        // logits that have not been through a softmax
+        network_output[(1, 0)] = 2.5;
+        match beam_search_with(&network_output, &alphabet, &options) {
+            Err(SearchError::RowSumMismatch { row, sum }) => {
+                assert_eq!(row, 1);
+                assert!((sum - 3.0).abs() < 1e-6);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(beam_search_with(&network_output, &alphabet, &BeamSearchOptions::new()).is_ok());
+    }
+
+    #[test]
+    fn test_greedy_frames() {
+        let alphabet: Vec<String> = "NAG".chars().map(String::from).collect();
+        let network_output = array![
+            [0.1f32, 0.8, 0.1],
+            [0.1, 0.8, 0.1],
+            [0.7, 0.2, 0.1],
+            [0.1, 0.1, 0.8],
+            [0.1, 0.1, 0.8]
+        ];
+        let (frames, path) = greedy_frames(&network_output, &alphabet).unwrap();
+        assert_eq!(frames, "AANGG");
+        assert_eq!(path, [1, 1, 0, 2, 2]);
+        assert_eq!(
+            greedy_search(&network_output, &alphabet, 0).unwrap().0,
+            "AG"
+        );
+        // the columns split up labels of several characters
+        let long: Vec<String> = vec!["-".into(), "AC".into(), "G".into()];
+        let (frames, path) = greedy_frames(&network_output, &long).unwrap();
+        assert_eq!(frames, "ACAC-GG");
+        assert_eq!(frame_tokens(&path[..2], &long), "ACAC");
+        assert_eq!(
+            greedy_frames(&network_output.slice(s![..;2, ..]), &alphabet).unwrap(),
+            (String::from("ANG"), vec![1, 0, 2])
+        );
+        assert!(matches!(
+            greedy_frames(&network_output, &alphabet[..2]),
+            Err(SearchError::AlphabetMismatch)
+        ));
+
+        let options = BeamSearchOptions::new();
+        let (_, _, path) = beam_search_with_path(&network_output, &alphabet, &options).unwrap();
+        assert_eq!(frame_tokens(&path, &alphabet), "AANGG");
+    }
+
+    #[test]
+    fn test_emit_at_first_row() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let options = BeamSearchOptions::new().beam_size(5);
+        let late = options.clone().emit_at_first_row(false);
+
+        let network_output = array![[0.1f32, 0.8, 0.1]];
+        let (sequence, path, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!((sequence.as_str(), path.as_slice()), ("A", &[0][..]));
+        let (sequence, path, _) = beam_search_with(&network_output, &alphabet, &late).unwrap();
+        assert_eq!((sequence.as_str(), path.as_slice()), ("", &[][..]));
+        assert!(
+            (score_labelling(&network_output, &alphabet, "A", &options).unwrap() - 0.8f32.ln())
+                .abs()
+                < 1e-6
+        );
+        assert_eq!(
+            score_labelling(&network_output, &alphabet, "A", &late).unwrap(),
+            f32::NEG_INFINITY
+        );
+
+        // the label is taken from the second row instead
+        let network_output = array![[0.1f32, 0.8, 0.1], [0.1, 0.8, 0.1], [0.8, 0.1, 0.1]];
+        let (sequence, path, _) = beam_search_with(&network_output, &alphabet, &options).unwrap();
+        assert_eq!((sequence.as_str(), path.as_slice()), ("A", &[0][..]));
+        let (sequence, path, _) = beam_search_with(&network_output, &alphabet, &late).unwrap();
+        assert_eq!((sequence.as_str(), path.as_slice()), ("A", &[1][..]));
+        let (_, spans, _) = beam_search_with_spans(&network_output, &alphabet, &late).unwrap();
+        assert_eq!(spans, [(1, 1)]);
+        assert_eq!(
+            forced_alignment(&network_output, &alphabet, "A", &late).unwrap(),
+            [(1, 1)]
+        );
+
+        // a search resumed after the first row can emit straight away
+        let mut state = BeamSearchState::new(&alphabet);
+        for row in network_output.outer_iter() {
+            state
+                .advance(&row.insert_axis(Axis(0)), &alphabet, &late)
+                .unwrap();
+        }
+        assert_eq!(
+            state.result(&alphabet, &late),
+            beam_search_with(&network_output, &alphabet, &late).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_normalize_rows() {
+        let alphabet = vec![String::from("N"), String::from("A"), String::from("G")];
+        let network_output = array![
+            [0.1f32, 0.6, 0.3],
+            [0.6f32, 0.2, 0.2],
+            [0.1f32, 0.6, 0.3],
+            [0.5f32, 0.3, 0.2],
+            [0.1f32, 0.3, 0.6]
+        ];
+        let doubled = &network_output * 2.0;
+        // the threshold applies to the divided values, so it cuts the same extensions
+        let options = BeamSearchOptions::new()
+            .beam_size(3)
+            .beam_cut_threshold(0.25);
+        let normalized = options.clone().normalize_rows(true);
+        assert_eq!(
+            beam_search_with(&doubled, &alphabet, &normalized).unwrap(),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+        assert_eq!(
+            beam_search_qualities_with(&doubled, &alphabet, &normalized).unwrap(),
+            beam_search_qualities_with(&network_output, &alphabet, &options).unwrap()
+        );
+        assert_eq!(
+            score_labelling(&doubled, &alphabet, "AG", &normalized).unwrap(),
+            score_labelling(&network_output, &alphabet, "AG", &options).unwrap()
+        );
+        // and rows that already sum to 1.0 are left as they are
+        assert_eq!(
+            beam_search_with(&network_output, &alphabet, &normalized).unwrap(),
+            beam_search_with(&network_output, &alphabet, &options).unwrap()
+        );
+
+        let mut zeros = doubled;
+        zeros.row_mut(2).fill(0.0);
+        assert!(matches!(
+            beam_search_with(&zeros, &alphabet, &normalized),
+            Err(SearchError::RowSumMismatch { row: 2, .. })
+        ));
     }
 
     /*
@@ -620,7 +7824,7 @@ mod tests {
             (_, 0) => 1.0f32,
             (_, _) => 0.0f32,
         });
-        b.iter(|| viterbi_search(&network_output, &alphabet, false, qscale, qbias, true));
+        b.iter(|| viterbi_search(&network_output, &alphabet, false, qscale, qbias, true, 0));
     }
 
     // This one changes label at every data point, so result contruction has the maximum possible
@@ -638,7 +7842,7 @@ mod tests {
             (n, 2) if n % 2 != 0 => 0.0f32,
             _ => 0.0f32,
         });
-        b.iter(|| viterbi_search(&network_output, &alphabet, false, qscale, qbias, true));
+        b.iter(|| viterbi_search(&network_output, &alphabet, false, qscale, qbias, true, 0));
     }
      */
 }
@@ -0,0 +1,347 @@
+//! Scans over the label axis of a row of network output, and sums of log probabilities over it.
+//!
+//! With the `simd` feature on x86_64, rows of at least `MIN_SIMD_LEN` values are scanned four at a
+//! time with SSE (which every x86_64 processor has, although targets such as
+//! `x86_64-unknown-none` leave it off). Otherwise, and for shorter rows, the scalar versions are
+//! used. Both give exactly the same results.
+//!
+//! `log_add` is used by the duplex search, for all the labellings it adds to the tree at a row of
+//! the first output at once. With the `simd` feature on x86_64, it adds at least `MIN_SIMD_SUMS`
+//! values four at a time, approximating `exp` and `ln_1p` with polynomials, so its sums can then
+//! differ from those of `LogSpace` addition by at most `LOG_ADD_TOLERANCE` (as a natural log).
+
+use crate::logspace::LogSpace;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// The shortest row worth scanning with SIMD instructions.
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+const MIN_SIMD_LEN: usize = 16;
+
+/// The fewest sums worth doing with SIMD instructions.
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+const MIN_SIMD_SUMS: usize = 2;
+
+/// Push the index of each value in `values` that is not less than `threshold` onto `columns`, in
+/// increasing order.
+///
+/// NaNs are never less than `threshold`, so their indices are included.
+pub fn columns_at_least(values: &[f32], threshold: f32, columns: &mut Vec<usize>) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        if values.len() >= MIN_SIMD_LEN {
+            return sse::columns_at_least(values, threshold, columns);
+        }
+    }
+    scalar_columns_at_least(values, threshold, columns)
+}
+
+/// The index and value of the first largest value in `values`, or `None` if it is empty.
+///
+/// A value only replaces the current largest if it compares greater, so NaNs are skipped, unless
+/// the first value is NaN.
+pub fn argmax(values: &[f32]) -> Option<(usize, f32)> {
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        if values.len() >= MIN_SIMD_LEN {
+            return sse::argmax(values);
+        }
+    }
+    scalar_argmax(values)
+}
+
+/// The most a sum from `log_add` can differ from the exact value, as a natural log, beyond
+/// rounding to the nearest `f32` (for sums that are at most 1, as probabilities are).
+#[cfg(test)]
+pub const LOG_ADD_TOLERANCE: f32 = 2e-6;
+
+/// Set `out[i]` to the sum of the probabilities whose natural logs are `a[i]` and `b[i]`, for each
+/// `i`.
+///
+/// The slices must all be the same length. Without SIMD, this is `LogSpace` addition.
+pub fn log_add(a: &[f32], b: &[f32], out: &mut [f32]) {
+    assert!(a.len() == out.len() && b.len() == out.len());
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        if out.len() >= MIN_SIMD_SUMS {
+            return sse::log_add(a, b, out);
+        }
+    }
+    scalar_log_add(a, b, out)
+}
+
+pub fn scalar_log_add(a: &[f32], b: &[f32], out: &mut [f32]) {
+    for ((&a, &b), out) in a.iter().zip(b).zip(out) {
+        let sum: LogSpace = LogSpace::from_ln(a) + LogSpace::from_ln(b);
+        *out = sum.ln();
+    }
+}
+
+pub fn scalar_columns_at_least(values: &[f32], threshold: f32, columns: &mut Vec<usize>) {
+    for (column, x) in values.iter().enumerate() {
+        if x.partial_cmp(&threshold) != Some(Ordering::Less) {
+            columns.push(column);
+        }
+    }
+}
+
+pub fn scalar_argmax(values: &[f32]) -> Option<(usize, f32)> {
+    let mut iter = values.iter().copied().enumerate();
+    let first = iter.next()?;
+    Some(iter.fold(first, |acc, x| if x.1 > acc.1 { x } else { acc }))
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "sse2"))]
+mod sse {
+    use alloc::vec::Vec;
+    use core::arch::x86_64::*;
+
+    /// `exp` of each lane of `x`, all of which are at most 0 (or NaN).
+    ///
+    /// This is the Cephes single precision approximation, with `x` rounded to the nearest integer
+    /// multiple `n` of ln 2 plus a remainder, whose exp is a degree 6 polynomial. Below -87 the
+    /// result would be subnormal, so those lanes give exp(-87) instead, which is too small to
+    /// change a sum of probabilities.
+    ///
+    /// # Safety
+    ///
+    /// Needs SSE2, which every x86_64 processor has.
+    #[inline]
+    unsafe fn exp(x: __m128) -> __m128 {
+        // `_mm_max_ps` returns its second argument for NaNs, so they are kept
+        let x = _mm_max_ps(_mm_set1_ps(-87.0), x);
+        let n = _mm_cvtps_epi32(_mm_mul_ps(x, _mm_set1_ps(core::f32::consts::LOG2_E)));
+        let nf = _mm_cvtepi32_ps(n);
+        // x - n * ln 2, with ln 2 split in two so the first product is exact
+        let r = _mm_sub_ps(x, _mm_mul_ps(nf, _mm_set1_ps(0.693_359_4)));
+        let r = _mm_sub_ps(r, _mm_mul_ps(nf, _mm_set1_ps(-2.121_944_4e-4)));
+        let mut y = _mm_set1_ps(1.987_569_1e-4);
+        for &coeff in &[
+            1.398_199_9e-3,
+            8.333_452e-3,
+            4.166_579_6e-2,
+            1.666_666_5e-1,
+            0.5,
+        ] {
+            y = _mm_add_ps(_mm_mul_ps(y, r), _mm_set1_ps(coeff));
+        }
+        let y = _mm_add_ps(
+            _mm_add_ps(_mm_mul_ps(_mm_mul_ps(y, r), r), r),
+            _mm_set1_ps(1.0),
+        );
+        // 2^n, built from its exponent bits (n is at least -126, so it is a normal float)
+        let scale = _mm_castsi128_ps(_mm_slli_epi32(_mm_add_epi32(n, _mm_set1_epi32(127)), 23));
+        _mm_mul_ps(y, scale)
+    }
+
+    /// `ln_1p` of each lane of `x`, all of which are between 0 and 1 (or NaN).
+    ///
+    /// With `s = x / (2 + x)`, which is at most 1/3, ln(1 + x) = 2 atanh(s), whose series is
+    /// summed to the s^11 term. The rest of the series adds less than 1e-7.
+    ///
+    /// # Safety
+    ///
+    /// Needs SSE2, which every x86_64 processor has.
+    #[inline]
+    unsafe fn ln_1p(x: __m128) -> __m128 {
+        let s = _mm_div_ps(x, _mm_add_ps(x, _mm_set1_ps(2.0)));
+        let s2 = _mm_mul_ps(s, s);
+        let mut y = _mm_set1_ps(1.0 / 11.0);
+        for &coeff in &[1.0 / 9.0, 1.0 / 7.0, 1.0 / 5.0, 1.0 / 3.0, 1.0] {
+            y = _mm_add_ps(_mm_mul_ps(y, s2), _mm_set1_ps(coeff));
+        }
+        _mm_mul_ps(_mm_add_ps(s, s), y)
+    }
+
+    /// `mask ? a : b`, lane by lane, for a mask from a comparison.
+    #[inline]
+    unsafe fn select(mask: __m128, a: __m128, b: __m128) -> __m128 {
+        _mm_or_ps(_mm_and_ps(mask, a), _mm_andnot_ps(mask, b))
+    }
+
+    pub fn log_add(a: &[f32], b: &[f32], out: &mut [f32]) {
+        // a partial chunk is padded out to four values, lane by lane (copying a few values with
+        // `copy_from_slice` is a slow call to memcpy)
+        let lane = |values: &[f32], i: usize| values.get(i).copied().unwrap_or(0.0);
+        for ((a, b), out) in a.chunks(4).zip(b.chunks(4)).zip(out.chunks_mut(4)) {
+            let mut sum4 = [0.0; 4];
+            // SAFETY: SSE2 is part of the x86_64 baseline, and the store is to an array of four
+            // values.
+            unsafe {
+                let a = _mm_setr_ps(lane(a, 0), lane(a, 1), lane(a, 2), lane(a, 3));
+                let b = _mm_setr_ps(lane(b, 0), lane(b, 1), lane(b, 2), lane(b, 3));
+                // order the operands as `LogSpace` addition does, so a NaN in `a` ends up in `big`
+                let a_le_b = _mm_cmple_ps(a, b);
+                let big = select(a_le_b, b, a);
+                let small = select(a_le_b, a, b);
+                let sum = _mm_add_ps(big, ln_1p(exp(_mm_sub_ps(small, big))));
+                // adding a zero probability changes nothing (and -inf - -inf would be NaN)
+                let small_is_zero = _mm_cmpeq_ps(small, _mm_set1_ps(f32::NEG_INFINITY));
+                _mm_storeu_ps(sum4.as_mut_ptr(), select(small_is_zero, big, sum));
+            }
+            for (out, &sum) in out.iter_mut().zip(&sum4) {
+                *out = sum;
+            }
+        }
+    }
+
+    pub fn columns_at_least(values: &[f32], threshold: f32, columns: &mut Vec<usize>) {
+        let chunks = values.chunks_exact(4);
+        let rest = chunks.remainder();
+        // SAFETY: SSE is part of the x86_64 baseline, and each load reads exactly the four values
+        // of a chunk.
+        unsafe {
+            let threshold4 = _mm_set1_ps(threshold);
+            for (idx, chunk) in chunks.enumerate() {
+                // "not less than" is also true for NaNs, like the scalar comparison
+                let mut mask =
+                    _mm_movemask_ps(_mm_cmpnlt_ps(_mm_loadu_ps(chunk.as_ptr()), threshold4));
+                while mask != 0 {
+                    columns.push(idx * 4 + mask.trailing_zeros() as usize);
+                    mask &= mask - 1;
+                }
+            }
+        }
+        let offset = values.len() - rest.len();
+        let start = columns.len();
+        super::scalar_columns_at_least(rest, threshold, columns);
+        for column in &mut columns[start..] {
+            *column += offset;
+        }
+    }
+
+    pub fn argmax(values: &[f32]) -> Option<(usize, f32)> {
+        if values.len() < 4 {
+            return super::scalar_argmax(values);
+        }
+        let chunks = values.chunks_exact(4);
+        let rest = chunks.remainder();
+        // SAFETY: as for `columns_at_least`.
+        let (max, has_nans) = unsafe {
+            let mut max4 = _mm_loadu_ps(values.as_ptr());
+            let mut nans4 = _mm_setzero_ps();
+            for chunk in chunks {
+                let x = _mm_loadu_ps(chunk.as_ptr());
+                nans4 = _mm_or_ps(nans4, _mm_cmpunord_ps(x, x));
+                max4 = _mm_max_ps(max4, x);
+            }
+            let mut lanes = [0.0; 4];
+            _mm_storeu_ps(lanes.as_mut_ptr(), max4);
+            let max = lanes
+                .iter()
+                .chain(rest)
+                .copied()
+                .fold(f32::NEG_INFINITY, f32::max);
+            (max, _mm_movemask_ps(nans4) != 0)
+        };
+        // `_mm_max_ps` does not order NaNs the way the scalar scan does
+        if has_nans || rest.iter().any(|x| x.is_nan()) {
+            return super::scalar_argmax(values);
+        }
+        values
+            .iter()
+            .position(|&x| x == max)
+            .map(|idx| (idx, values[idx]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Lcg;
+
+    /// Softmax-like rows over 256 labels.
+    fn rows(n_rows: usize, n_labels: usize) -> Vec<Vec<f32>> {
+        let mut rng = Lcg(12345);
+        (0..n_rows)
+            .map(|_| {
+                let row: Vec<f32> = (0..n_labels).map(|_| rng.next_f32().powi(8)).collect();
+                let total: f32 = row.iter().sum();
+                row.into_iter().map(|x| x / total).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scans_match_scalar() {
+        let mut rows = rows(64, 256);
+        rows.push(vec![0.5; 256]);
+        rows.push(vec![f32::NAN; 37]);
+        let mut with_nans = rows[0].clone();
+        with_nans[3] = f32::NAN;
+        with_nans[200] = f32::NAN;
+        rows.push(with_nans);
+        for len in 0..20 {
+            rows.push(rows[1][..len].to_vec());
+        }
+
+        let (mut columns, mut expected) = (Vec::new(), Vec::new());
+        for row in &rows {
+            for &threshold in &[0.0, 0.001, 0.01, 0.5] {
+                columns.clear();
+                expected.clear();
+                columns_at_least(row, threshold, &mut columns);
+                scalar_columns_at_least(row, threshold, &mut expected);
+                assert_eq!(columns, expected);
+            }
+            let expected = scalar_argmax(row);
+            let actual = argmax(row);
+            assert_eq!(actual.map(|x| x.0), expected.map(|x| x.0));
+            assert_eq!(
+                actual.map(|x| x.1.to_bits()),
+                expected.map(|x| x.1.to_bits())
+            );
+        }
+    }
+
+    #[test]
+    fn test_log_add() {
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        for i in 0..=400 {
+            for &x in &[0.0, -1.0, -20.0, -300.0] {
+                a.push(-(i as f32) * 0.25 + x);
+                b.push(x);
+            }
+        }
+        for &(x, y) in &[
+            (f32::NEG_INFINITY, -1.0),
+            (-1.0, f32::NEG_INFINITY),
+            (f32::NEG_INFINITY, f32::NEG_INFINITY),
+            (-1.0, -200.0),
+        ] {
+            a.push(x);
+            b.push(y);
+        }
+        let mut sums = vec![0.0; a.len()];
+        let mut expected = vec![0.0; a.len()];
+        log_add(&a, &b, &mut sums);
+        scalar_log_add(&a, &b, &mut expected);
+        for i in 0..a.len() {
+            let exact = (f64::from(a[i]).exp() + f64::from(b[i]).exp()).ln() as f32;
+            if exact == f32::NEG_INFINITY {
+                assert_eq!(sums[i], exact);
+                assert_eq!(expected[i], exact);
+                continue;
+            }
+            let tolerance = LOG_ADD_TOLERANCE + exact.abs() * f32::EPSILON;
+            assert!((sums[i] - exact).abs() <= tolerance, "{} {}", a[i], b[i]);
+            assert!(
+                (expected[i] - exact).abs() <= tolerance,
+                "{} {}",
+                a[i],
+                b[i]
+            );
+        }
+        // the same for each value, however they are split up (a lone value is added on its own)
+        for len in 2..9 {
+            let mut short = vec![0.0; len];
+            log_add(&a[3..3 + len], &b[3..3 + len], &mut short);
+            assert_eq!(short, sums[3..3 + len]);
+        }
+
+        let nans = [f32::NAN, -1.0, f32::NAN];
+        log_add(&nans, &[-1.0, f32::NAN, f32::NEG_INFINITY], &mut sums[..3]);
+        assert!(sums[0].is_nan() && sums[2].is_nan());
+    }
+}
@@ -0,0 +1,21 @@
+//! The random number generator the tests (and the benchmarks) make their inputs with.
+//!
+//! It is a linear congruential generator, computed with basic integer and float operations only,
+//! so a seed gives the same values on every platform, and the results pinned by the tests hold
+//! everywhere.
+
+/// A linear congruential generator, started from its seed.
+pub(crate) struct Lcg(pub(crate) u32);
+
+impl Lcg {
+    /// The next value, below `1 << 24`.
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        self.0 >> 8
+    }
+
+    /// The next value, between 0.0 and 1.0.
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        self.next_u32() as f32 / (1 << 24) as f32
+    }
+}
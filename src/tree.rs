@@ -1,7 +1,28 @@
+//! The tree of labellings built up by the beam searches.
+//!
+//! Each node of a `SuffixTree` other than the root holds one label, and stands for the labelling
+//! made by reading the labels from the node up to the root. Nodes are identified by `i32`
+//! indices: the root is `ROOT_NODE`, and the other nodes are numbered from 0 in the order they
+//! were added.
+//!
+//! A tree only grows during a search. Each row of a network output adds at most one node for
+//! each label (other than the blank) that each entry of the beam is extended with, so at most
+//! `beam_size * (len(alphabet) - 1)` nodes per row. Most extensions fall out of the beam, and
+//! many reach labellings that are already in the tree, so a search usually adds about one node
+//! per row for each entry of the beam: a few times fewer than the most it can. Reserving that
+//! much up front (see `SuffixTree::with_capacity`) means the tree rarely has to grow part way
+//! through a search; `Growth` sets how it grows when it does.
+
 use crate::vec2d::Vec2D;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// An element in a possible labelling.
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct LabelNode<T> {
     /// The index into the alphabet of this label.
     ///
@@ -9,16 +30,42 @@ struct LabelNode<T> {
     label: usize,
     /// The index of the parent LabelNode.
     parent: i32,
+    /// The number of labels in the labelling, counting this one.
+    depth: u32,
+    /// The parent, or an ancestor further up, so that any ancestor can be reached in a number of
+    /// steps logarithmic in the depth (see `SuffixTree::ancestor_at_depth`).
+    jump: i32,
     /// Extra data attached to the node
     data: T,
 }
 
+/// How a `SuffixTree` grows its storage when it is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Growth {
+    /// Double the storage, as a `Vec` does. Growing takes time proportional to the size of the
+    /// tree, but happens less and less often as the tree gets bigger.
+    Double,
+    /// Add storage for this many more nodes (at least one). The tree never holds more than this
+    /// much unused storage, but every growth copies the whole tree, so a small step grows slowly.
+    Step(usize),
+}
+
+impl Default for Growth {
+    fn default() -> Self {
+        Growth::Double
+    }
+}
+
 /// A tree of labelling suffixes (partial labellings pinned to the end of the network output).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SuffixTree<T> {
     // Invariants:
     //
     // nodes[i].parent < nodes.len() for all i
     // nodes[i].parent < 0 => nodes[i].parent == ROOT_NODE for all i
+    // nodes[i].depth == depth(nodes[i].parent) + 1, where the depth of ROOT_NODE is 0
+    // nodes[i].jump is nodes[i].parent or one of its ancestors, picked by depth alone
     // children[i][j]] < nodes.len() for all i, j
     //
     // nodes.len() == children.len()
@@ -41,8 +88,12 @@ pub struct SuffixTree<T> {
     // or parent. In order to keep `nodes` and `children` in line (so they could be zipped), we
     // store the root's children here.
     root_children: Vec<i32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    growth: Growth,
 }
 
+/// An iterator over the labels (and data) from a node up to the root, returned by
+/// `SuffixTree::iter_from`.
 pub struct SuffixTreeIter<'a, T> {
     nodes: &'a Vec<LabelNode<T>>,
     next: i32,
@@ -64,6 +115,8 @@ impl<'a, T> Iterator for SuffixTreeIter<'a, T> {
     }
 }
 
+/// An iterator over the labels from a node up to the root, returned by
+/// `SuffixTree::iter_from_no_data`.
 pub struct SuffixTreeIterNoData<'a, T> {
     nodes: &'a Vec<LabelNode<T>>,
     next: i32,
@@ -85,23 +138,90 @@ impl<'a, T> Iterator for SuffixTreeIterNoData<'a, T> {
     }
 }
 
+/// The index of the root node, which stands for the empty labelling.
 pub const ROOT_NODE: i32 = -1;
 
+/// The position of a (non-root) node in the tree.
 #[derive(Clone, Copy, Debug)]
 pub struct NodeInfo {
+    /// The parent node, which may be `ROOT_NODE`.
     pub parent: i32,
+    /// The label the node adds to its parent's labelling.
     pub label: usize,
 }
 
 impl<T> SuffixTree<T> {
+    /// An empty tree (just the root) for labels in `0..alphabet_size`.
     pub fn new(alphabet_size: usize) -> Self {
         Self {
             nodes: Vec::new(),
             children: Vec2D::new(alphabet_size),
             root_children: vec![-1; alphabet_size],
+            growth: Growth::Double,
+        }
+    }
+
+    /// An empty tree with storage for `capacity` nodes (not counting the root).
+    pub fn with_capacity(alphabet_size: usize, capacity: usize) -> Self {
+        let mut tree = Self::new(alphabet_size);
+        tree.reserve(capacity);
+        tree
+    }
+
+    /// How the tree grows its storage when it is full.
+    pub fn growth(&self) -> Growth {
+        self.growth
+    }
+
+    /// Set how the tree grows its storage when it is full. This does not change the storage
+    /// already allocated.
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.growth = growth;
+    }
+
+    /// The number of labels a node can have as children.
+    pub fn alphabet_size(&self) -> usize {
+        self.root_children.len()
+    }
+
+    /// Remove every node except the root, keeping the allocated storage.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.children.clear();
+        for child in &mut self.root_children {
+            *child = -1;
         }
     }
 
+    /// Reserve storage for at least `additional` more nodes.
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+        self.children.reserve(additional);
+    }
+
+    /// The number of nodes (not counting the root) the tree can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity().min(self.children.row_capacity())
+    }
+
+    /// The number of nodes in the tree, not counting the root.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the tree has no nodes other than the root.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Every node in the tree other than the root, in the order they were added.
+    ///
+    /// A node is always added after its parent, so parents come before their children.
+    pub fn nodes(&self) -> impl Iterator<Item = i32> {
+        0..self.nodes.len() as i32
+    }
+
+    /// The label of `node`, or `None` for the root.
     pub fn label(&self, node: i32) -> Option<usize> {
         if node >= 0 {
             Some(self.nodes[node as usize].label)
@@ -110,6 +230,12 @@ impl<T> SuffixTree<T> {
         }
     }
 
+    /// The parent of `node`, or `None` for the root.
+    pub fn parent(&self, node: i32) -> Option<i32> {
+        self.info(node).map(|info| info.parent)
+    }
+
+    /// The parent and label of `node`, or `None` for the root.
     pub fn info(&self, node: i32) -> Option<NodeInfo> {
         if node >= 0 {
             let node = &self.nodes[node as usize];
@@ -122,11 +248,39 @@ impl<T> SuffixTree<T> {
         }
     }
 
+    /// Add a child of `parent` with the given label and data, returning the new node.
+    ///
+    /// Panics if `parent` already has a child with that label.
     pub fn add_node(&mut self, parent: i32, label: usize, data: T) -> i32 {
         assert!(label < self.root_children.len());
-        assert!(self.nodes.len() < (i32::max_value() as usize));
+        assert!(self.nodes.len() < (i32::MAX as usize));
+
+        if let Growth::Step(step) = self.growth {
+            if self.nodes.len() >= self.capacity() {
+                let additional = step.max(1);
+                self.nodes.reserve_exact(additional);
+                self.children.reserve_exact(additional);
+            }
+        }
 
         let new_node_idx = self.nodes.len() as i32;
+        // The jumps form a skew-binary ladder (as in Myers' random access lists): a node jumps to
+        // where its parent's jump jumps, when the parent's jump and that one are as far apart as
+        // the parent and its jump, and otherwise to its parent. Any ancestor is then at most a
+        // logarithmic number of jumps and parent steps away.
+        let (depth, jump) = if parent >= 0 {
+            let parent_node = &self.nodes[parent as usize];
+            let jump = parent_node.jump;
+            if parent_node.depth - self.depth(jump)
+                == self.depth(jump) - self.depth(self.jump(jump))
+            {
+                (parent_node.depth + 1, self.jump(jump))
+            } else {
+                (parent_node.depth + 1, parent)
+            }
+        } else {
+            (1, ROOT_NODE)
+        };
         if parent == ROOT_NODE {
             assert_eq!(self.root_children[label], -1);
             self.root_children[label] = new_node_idx;
@@ -138,12 +292,65 @@ impl<T> SuffixTree<T> {
         self.nodes.push(LabelNode {
             label,
             parent,
+            depth,
+            jump,
             data,
         });
         self.children.add_row_with_value(-1);
         new_node_idx
     }
 
+    /// Remove every node that is not one of `nodes` or an ancestor of one, keeping the storage.
+    ///
+    /// This frees up the tree once the labellings of the other nodes are no longer needed. The
+    /// nodes that are left keep their labels and data, and are renumbered from 0 in the same
+    /// order as before, so parents still come before their children. Returns the new index of
+    /// each old node, or `None` for the nodes that were removed.
+    pub fn retain_ancestors<I: IntoIterator<Item = i32>>(&mut self, nodes: I) -> Vec<Option<i32>> {
+        // mark the nodes to keep, then number them in order
+        let mut new_index = vec![None; self.nodes.len()];
+        for mut node in nodes {
+            while node >= 0 && new_index[node as usize].is_none() {
+                new_index[node as usize] = Some(0);
+                node = self.nodes[node as usize].parent;
+            }
+        }
+        let mut kept = 0;
+        for idx in new_index.iter_mut().flatten() {
+            *idx = kept;
+            kept += 1;
+        }
+
+        // a removed child is no child at all
+        let new_child = |child: i32| {
+            if child >= 0 {
+                new_index[child as usize].unwrap_or(-1)
+            } else {
+                child
+            }
+        };
+        // each kept node moves down to a place that is free or its own
+        for (old, &new) in new_index.iter().enumerate() {
+            if let Some(new) = new {
+                let new = new as usize;
+                self.nodes.swap(new, old);
+                self.nodes[new].parent = new_child(self.nodes[new].parent);
+                // the ancestors of a kept node are kept, at the same depths
+                self.nodes[new].jump = new_child(self.nodes[new].jump);
+                for label in 0..self.root_children.len() {
+                    self.children[(new, label)] = new_child(self.children[(old, label)]);
+                }
+            }
+        }
+        self.nodes.truncate(kept as usize);
+        self.children.truncate(kept as usize);
+        for child in &mut self.root_children {
+            *child = new_child(*child);
+        }
+        new_index
+    }
+
+    /// The child of `node` with the given label, if it has one.
     pub fn get_child(&self, node: i32, label: usize) -> Option<i32> {
         if node == ROOT_NODE {
             let idx = self.root_children[label];
@@ -160,6 +367,7 @@ impl<T> SuffixTree<T> {
         None
     }
 
+    /// The data attached to `node`, or `None` for the root.
     pub fn get_data_ref(&self, node: i32) -> Option<&T> {
         if node >= 0 && (node as usize) < self.nodes.len() {
             Some(&self.nodes[node as usize].data)
@@ -168,6 +376,7 @@ impl<T> SuffixTree<T> {
         }
     }
 
+    /// The data attached to `node`, or `None` for the root.
     pub fn get_data_ref_mut(&mut self, node: i32) -> Option<&mut T> {
         if node >= 0 && (node as usize) < self.nodes.len() {
             Some(&mut self.nodes[node as usize].data)
@@ -176,7 +385,9 @@ impl<T> SuffixTree<T> {
         }
     }
 
-    pub fn iter_from_no_data(&self, node: i32) -> SuffixTreeIterNoData<T> {
+    /// The labels of `node` and each of its ancestors, from `node` up to (but not including) the
+    /// root. This is the labelling of `node` in reverse.
+    pub fn iter_from_no_data(&self, node: i32) -> SuffixTreeIterNoData<'_, T> {
         assert!((node as usize) < self.nodes.len());
         SuffixTreeIterNoData {
             nodes: &self.nodes,
@@ -184,18 +395,177 @@ impl<T> SuffixTree<T> {
         }
     }
 
-    pub fn iter_from(&self, node: i32) -> SuffixTreeIter<T> {
+    /// The labels and data of `node` and each of its ancestors, from `node` up to (but not
+    /// including) the root.
+    pub fn iter_from(&self, node: i32) -> SuffixTreeIter<'_, T> {
         assert!((node as usize) < self.nodes.len());
         SuffixTreeIter {
             nodes: &self.nodes,
             next: node,
         }
     }
+
+    /// Order the labellings of nodes `a` and `b` lexicographically by their labels, from the
+    /// first, so a labelling comes before any labelling it is a prefix of.
+    ///
+    /// The labellings only differ below their nearest common ancestor, so this finds the children
+    /// of it that `a` and `b` descend from and compares their labels, rather than building the
+    /// labellings. With the jumps each node keeps, that takes time logarithmic in the length of
+    /// the labellings, however long ago they went their separate ways.
+    pub fn compare_labellings(&self, a: i32, b: i32) -> Ordering {
+        let (depth_a, depth_b) = (self.depth(a), self.depth(b));
+        let depth = depth_a.min(depth_b);
+        let (mut a, mut b) = (
+            self.ancestor_at_depth(a, depth),
+            self.ancestor_at_depth(b, depth),
+        );
+        if a == b {
+            // one is an ancestor (a prefix) of the other, or they are the same node
+            return depth_a.cmp(&depth_b);
+        }
+        // nodes at the same depth jump to the same depth, so take the jumps that still land on
+        // different nodes (below the common ancestor), and parent steps otherwise
+        while self.nodes[a as usize].parent != self.nodes[b as usize].parent {
+            let (jump_a, jump_b) = (self.jump(a), self.jump(b));
+            if jump_a != jump_b {
+                a = jump_a;
+                b = jump_b;
+            } else {
+                a = self.nodes[a as usize].parent;
+                b = self.nodes[b as usize].parent;
+            }
+        }
+        self.nodes[a as usize]
+            .label
+            .cmp(&self.nodes[b as usize].label)
+    }
+
+    /// The number of labels in the labelling of `node`: 0 for the root.
+    fn depth(&self, node: i32) -> u32 {
+        if node >= 0 {
+            self.nodes[node as usize].depth
+        } else {
+            0
+        }
+    }
+
+    /// Where `node` jumps to: the root jumps to itself.
+    fn jump(&self, node: i32) -> i32 {
+        if node >= 0 {
+            self.nodes[node as usize].jump
+        } else {
+            ROOT_NODE
+        }
+    }
+
+    /// The ancestor of `node` (or `node` itself) with a labelling of `depth` labels, which must be
+    /// no more than the labels of `node`.
+    fn ancestor_at_depth(&self, node: i32, depth: u32) -> i32 {
+        let mut node = node;
+        while self.depth(node) > depth {
+            let jump = self.jump(node);
+            node = if self.depth(jump) >= depth {
+                jump
+            } else {
+                self.nodes[node as usize].parent
+            };
+        }
+        node
+    }
+}
+
+/// A prefix tree of words, for limiting a search to labellings made up of those words.
+///
+/// Nodes are identified by `usize` indices, with the root (the empty prefix) at `Lexicon::ROOT`.
+/// Each other node is a prefix of at least one word. A lexicon does not depend on the alphabet of
+/// the search, so the same one can be used for many searches.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Lexicon {
+    // children[n] holds the edges from node n, as (character, child node) pairs
+    children: Vec<Vec<(char, usize)>>,
+    is_word: Vec<bool>,
+}
+
+impl Lexicon {
+    /// The node for the empty prefix.
+    pub const ROOT: usize = 0;
+
+    /// A lexicon containing `words`.
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut lexicon = Lexicon {
+            children: vec![Vec::new()],
+            is_word: vec![false],
+        };
+        for word in words {
+            lexicon.insert(word.as_ref());
+        }
+        lexicon
+    }
+
+    /// Add `word` to the lexicon.
+    pub fn insert(&mut self, word: &str) {
+        let mut node = Lexicon::ROOT;
+        for c in word.chars() {
+            node = match self.child(node, c) {
+                Some(child) => child,
+                None => {
+                    let child = self.children.len();
+                    self.children.push(Vec::new());
+                    self.is_word.push(false);
+                    self.children[node].push((c, child));
+                    child
+                }
+            };
+        }
+        self.is_word[node] = true;
+    }
+
+    /// The number of nodes in the trie, including the root.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Whether the lexicon has no words.
+    pub fn is_empty(&self) -> bool {
+        !self.is_word.iter().any(|&x| x)
+    }
+
+    /// The node for the prefix made by adding `c` to the prefix of `node`, if any word has that
+    /// prefix.
+    pub fn child(&self, node: usize, c: char) -> Option<usize> {
+        self.children[node]
+            .iter()
+            .find(|&&(edge, _)| edge == c)
+            .map(|&(_, child)| child)
+    }
+
+    /// The node for the prefix made by adding `s` to the prefix of `node`, if any word has that
+    /// prefix.
+    pub fn walk(&self, node: usize, s: &str) -> Option<usize> {
+        s.chars().try_fold(node, |node, c| self.child(node, c))
+    }
+
+    /// Whether the prefix of `node` is a whole word.
+    pub fn is_word(&self, node: usize) -> bool {
+        self.is_word[node]
+    }
+
+    /// Whether `word` is in the lexicon.
+    pub fn contains(&self, word: &str) -> bool {
+        self.walk(Lexicon::ROOT, word)
+            .map_or(false, |node| self.is_word(node))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::Lcg;
 
     #[test]
     fn test_tree_assembly() {
@@ -267,4 +637,187 @@ mod tests {
             tree.iter_from(4).map(|(x, &y)| (x, y)).collect();
         assert_eq!(ancestor_label_and_data, vec![(1, 104), (1, 103), (0, 100)]);
     }
+
+    #[test]
+    fn test_tree_retain_ancestors() {
+        let mut tree = SuffixTree::new(2);
+        assert_eq!(tree.add_node(-1, 0, 100), 0);
+        assert_eq!(tree.add_node(-1, 1, 101), 1);
+        assert_eq!(tree.add_node(0, 0, 102), 2);
+        assert_eq!(tree.add_node(0, 1, 103), 3);
+        assert_eq!(tree.add_node(3, 1, 104), 4);
+        assert_eq!(tree.add_node(1, 0, 105), 5);
+
+        // keeping 4 keeps 3 and 0 too, and 5 keeps 1
+        let new_index = tree.retain_ancestors(vec![4, 5, ROOT_NODE]);
+        assert_eq!(
+            new_index,
+            vec![Some(0), Some(1), None, Some(2), Some(3), Some(4)]
+        );
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.get_child(-1, 0), Some(0));
+        assert_eq!(tree.get_child(-1, 1), Some(1));
+        assert_eq!(tree.get_child(0, 0), None);
+        assert_eq!(tree.get_child(0, 1), Some(2));
+        assert_eq!(tree.get_child(2, 1), Some(3));
+        assert_eq!(tree.get_child(1, 0), Some(4));
+        let ancestors: Vec<(usize, i32)> = tree.iter_from(3).map(|(x, &y)| (x, y)).collect();
+        assert_eq!(ancestors, vec![(1, 104), (1, 103), (0, 100)]);
+        assert_eq!(tree.parent(4), Some(1));
+        assert_eq!(tree.get_data_ref(4), Some(&105));
+
+        // the tree carries on growing from there
+        assert_eq!(tree.add_node(0, 0, 106), 5);
+        assert_eq!(tree.get_child(0, 0), Some(5));
+
+        assert_eq!(tree.retain_ancestors(vec![]), vec![None; 6]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.get_child(-1, 0), None);
+        assert_eq!(tree.get_child(-1, 1), None);
+    }
+
+    #[test]
+    fn test_tree_compare_labellings() {
+        let mut tree = SuffixTree::new(3);
+        // nodes added out of depth order, as a search adds them
+        assert_eq!(tree.add_node(-1, 1, ()), 0); // 1
+        assert_eq!(tree.add_node(0, 2, ()), 1); // 12
+        assert_eq!(tree.add_node(-1, 0, ()), 2); // 0
+        assert_eq!(tree.add_node(1, 0, ()), 3); // 120
+        assert_eq!(tree.add_node(0, 0, ()), 4); // 10
+        assert_eq!(tree.add_node(2, 2, ()), 5); // 02
+        assert_eq!(tree.add_node(4, 2, ()), 6); // 102
+
+        fn labelling(tree: &SuffixTree<()>, node: i32) -> Vec<usize> {
+            let mut labels: Vec<usize> = if node == ROOT_NODE {
+                Vec::new()
+            } else {
+                tree.iter_from_no_data(node).collect()
+            };
+            labels.reverse();
+            labels
+        }
+        for a in -1..7 {
+            for b in -1..7 {
+                assert_eq!(
+                    tree.compare_labellings(a, b),
+                    labelling(&tree, a).cmp(&labelling(&tree, b)),
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+
+        // long labellings that part at every depth, as a beam's do over a long network output,
+        // and the same once the tree is cut down to some of them
+        let mut tree = SuffixTree::new(3);
+        let mut rng = Lcg(9);
+        let mut tips = vec![ROOT_NODE];
+        for _ in 0..3000 {
+            let tip = rng.next_u32() as usize % tips.len();
+            let label = rng.next_u32() as usize % 3;
+            tips[tip] = match tree.get_child(tips[tip], label) {
+                Some(child) => child,
+                None => tree.add_node(tips[tip], label, ()),
+            };
+            if rng.next_u32() % 50 == 0 {
+                tips.push(tips[tip]);
+            }
+        }
+        for &keep in &[false, true] {
+            if keep {
+                let new_index = tree.retain_ancestors(tips.iter().copied().step_by(2));
+                tips = tips
+                    .iter()
+                    .filter_map(|&tip| {
+                        if tip < 0 {
+                            Some(tip)
+                        } else {
+                            new_index[tip as usize]
+                        }
+                    })
+                    .collect();
+            }
+            let nodes: Vec<i32> = tips
+                .iter()
+                .copied()
+                .chain(tree.nodes().step_by(97))
+                .collect();
+            for &a in &nodes {
+                for &b in &nodes {
+                    assert_eq!(
+                        tree.compare_labellings(a, b),
+                        labelling(&tree, a).cmp(&labelling(&tree, b)),
+                        "{} {}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_clear() {
+        let mut tree = SuffixTree::new(2);
+        assert_eq!(tree.add_node(-1, 0, 100), 0);
+        assert_eq!(tree.add_node(0, 1, 101), 1);
+        assert_eq!(tree.add_node(-1, 1, 102), 2);
+
+        tree.clear();
+        assert_eq!(tree.alphabet_size(), 2);
+        assert_eq!(tree.get_child(-1, 0), None);
+        assert_eq!(tree.get_child(-1, 1), None);
+        assert_eq!(tree.get_data_ref(0), None);
+
+        assert_eq!(tree.add_node(-1, 1, 103), 0);
+        assert_eq!(tree.get_child(-1, 1), Some(0));
+        assert_eq!(tree.get_child(0, 0), None);
+        assert_eq!(tree.get_child(0, 1), None);
+        assert_eq!(tree.get_data_ref(0), Some(&103));
+    }
+
+    #[test]
+    fn test_tree_growth() {
+        let tree = SuffixTree::<i32>::with_capacity(2, 3);
+        assert!(tree.capacity() >= 3);
+        assert_eq!(tree.growth(), Growth::Double);
+
+        let mut tree = SuffixTree::new(2);
+        tree.set_growth(Growth::Step(2));
+        let mut parent = ROOT_NODE;
+        for i in 0..5 {
+            parent = tree.add_node(parent, 0, i);
+            // grown two nodes at a time
+            assert_eq!(tree.capacity(), (tree.len() + 1) / 2 * 2);
+        }
+        assert_eq!(tree.iter_from_no_data(parent).count(), 5);
+    }
+
+    #[test]
+    fn test_lexicon() {
+        let lexicon = Lexicon::new(["AC", "ACGT", "GA"]);
+        assert!(!lexicon.is_empty());
+        assert_eq!(lexicon.len(), 7);
+        assert!(lexicon.contains("AC"));
+        assert!(lexicon.contains("ACGT"));
+        assert!(lexicon.contains("GA"));
+        assert!(!lexicon.contains("A"));
+        assert!(!lexicon.contains("ACG"));
+        assert!(!lexicon.contains("GAT"));
+        assert!(!lexicon.contains(""));
+
+        let a = lexicon.child(Lexicon::ROOT, 'A').unwrap();
+        assert!(!lexicon.is_word(a));
+        assert_eq!(lexicon.child(a, 'G'), None);
+        let ac = lexicon.walk(Lexicon::ROOT, "AC").unwrap();
+        assert_eq!(lexicon.child(a, 'C'), Some(ac));
+        assert!(lexicon.is_word(ac));
+        assert_eq!(lexicon.walk(ac, ""), Some(ac));
+        assert!(lexicon.is_word(lexicon.walk(ac, "GT").unwrap()));
+        assert_eq!(lexicon.walk(ac, "GA"), None);
+
+        assert!(Lexicon::new(Vec::<String>::new()).is_empty());
+    }
 }
@@ -1,7 +1,18 @@
-use std::iter;
-use std::ops::{Index, IndexMut};
+use alloc::vec::Vec;
+use core::iter::{Skip, StepBy};
+use core::ops::{Index, IndexMut};
+use core::slice::{ChunksExact, ChunksExactMut, Iter};
+use ndarray::ArrayView2;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// A 2D vector that can grow along one dimension.
+///
+/// The values are stored in row-major order: row `outer` is the `inner_size` values starting at
+/// `outer * inner_size`. Indexing with `(outer, inner)` panics if either index is out of range;
+/// `get` and `get_mut` return `None` instead.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Vec2D<T> {
     vec: Vec<T>,
     inner_size: usize,
@@ -14,15 +25,147 @@ impl<T> Vec2D<T> {
             inner_size,
         }
     }
+
+    /// Remove all the rows, keeping the allocated storage.
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+
+    /// Reserve storage for at least `additional` more rows.
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional * self.inner_size);
+    }
+
+    /// Reserve storage for exactly `additional` more rows (see `Vec::reserve_exact`).
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.vec.reserve_exact(additional * self.inner_size);
+    }
+
+    /// The number of rows the storage can hold without reallocating.
+    pub fn row_capacity(&self) -> usize {
+        // rows of length 0 take no storage
+        self.vec
+            .capacity()
+            .checked_div(self.inner_size)
+            .unwrap_or(usize::MAX)
+    }
+
+    /// The length of each row.
+    pub fn inner_size(&self) -> usize {
+        self.inner_size
+    }
+
+    /// The number of rows.
+    pub fn outer_size(&self) -> usize {
+        self.vec.len().checked_div(self.inner_size).unwrap_or(0)
+    }
+
+    /// The value at `(outer, inner)`, or `None` if either index is out of range.
+    pub fn get(&self, outer: usize, inner: usize) -> Option<&T> {
+        if inner < self.inner_size {
+            self.vec.get(outer * self.inner_size + inner)
+        } else {
+            None
+        }
+    }
+
+    /// The value at `(outer, inner)`, or `None` if either index is out of range.
+    pub fn get_mut(&mut self, outer: usize, inner: usize) -> Option<&mut T> {
+        if inner < self.inner_size {
+            self.vec.get_mut(outer * self.inner_size + inner)
+        } else {
+            None
+        }
+    }
+
+    /// All the values, row by row.
+    pub fn as_slice(&self) -> &[T] {
+        &self.vec
+    }
+
+    /// All the values, row by row.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.vec
+    }
+
+    /// The values in row `outer`.
+    ///
+    /// Panics if `outer` is out of range.
+    pub fn row(&self, outer: usize) -> &[T] {
+        let start = outer * self.inner_size;
+        &self.vec[start..start + self.inner_size]
+    }
+
+    /// The values in row `outer`.
+    ///
+    /// Panics if `outer` is out of range.
+    pub fn row_mut(&mut self, outer: usize) -> &mut [T] {
+        let start = outer * self.inner_size;
+        &mut self.vec[start..start + self.inner_size]
+    }
+
+    /// The rows, in order.
+    pub fn rows(&self) -> ChunksExact<'_, T> {
+        // there are no values when `inner_size` is 0, so any chunk size gives no rows
+        self.vec.chunks_exact(self.inner_size.max(1))
+    }
+
+    /// The rows, in order.
+    pub fn rows_mut(&mut self) -> ChunksExactMut<'_, T> {
+        self.vec.chunks_exact_mut(self.inner_size.max(1))
+    }
+
+    /// The values in column `inner`, from the first row to the last.
+    ///
+    /// Panics if `inner` is out of range.
+    pub fn col(&self, inner: usize) -> StepBy<Skip<Iter<'_, T>>> {
+        assert!(inner < self.inner_size, "inner index out of range");
+        self.vec.iter().skip(inner).step_by(self.inner_size)
+    }
+
+    /// The columns, in order.
+    pub fn cols(&self) -> impl Iterator<Item = StepBy<Skip<Iter<'_, T>>>> + '_ {
+        (0..self.inner_size).map(move |inner| self.col(inner))
+    }
+
+    /// A view of the values as an `outer_size` x `inner_size` array, without copying them.
+    pub fn as_array_view(&self) -> ArrayView2<'_, T> {
+        ArrayView2::from_shape((self.outer_size(), self.inner_size), &self.vec)
+            .expect("the values are a whole number of rows")
+    }
 }
 
 impl<T> Vec2D<T>
 where
     T: Clone,
 {
+    /// Copy `rows` rows of `cols` values each from `data`, which is in row-major order.
+    ///
+    /// Panics if `data` does not have `rows * cols` values.
+    pub fn from_slice(data: &[T], rows: usize, cols: usize) -> Self {
+        assert_eq!(data.len(), rows * cols, "data must have rows * cols values");
+        Self {
+            vec: data.to_vec(),
+            inner_size: cols,
+        }
+    }
+
+    /// Copy the values of `array`, whose rows become the rows of the `Vec2D`.
+    pub fn from_ndarray(array: ArrayView2<'_, T>) -> Self {
+        Self {
+            vec: array.iter().cloned().collect(),
+            inner_size: array.ncols(),
+        }
+    }
+
+    /// Keep the first `rows` rows and drop the rest, keeping the allocated storage.
+    pub fn truncate(&mut self, rows: usize) {
+        self.vec.truncate(rows * self.inner_size);
+    }
+
     pub fn add_row_with_value(&mut self, value: T) {
-        self.vec.reserve(self.inner_size);
-        self.vec.extend(iter::repeat(value).take(self.inner_size))
+        let new_len = self.vec.len() + self.inner_size;
+        self.vec.resize(new_len, value);
     }
 }
 
@@ -30,12 +173,75 @@ impl<T> Index<(usize, usize)> for Vec2D<T> {
     type Output = T;
 
     fn index(&self, (outer, inner): (usize, usize)) -> &Self::Output {
+        assert!(inner < self.inner_size, "inner index out of range");
         &self.vec[outer * self.inner_size + inner]
     }
 }
 
 impl<T> IndexMut<(usize, usize)> for Vec2D<T> {
     fn index_mut(&mut self, (outer, inner): (usize, usize)) -> &mut Self::Output {
+        assert!(inner < self.inner_size, "inner index out of range");
         &mut self.vec[outer * self.inner_size + inner]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let array = array![[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        let mut v = Vec2D::from_ndarray(array.view());
+        assert_eq!(v.inner_size(), 3);
+        assert_eq!(v.outer_size(), 2);
+        assert_eq!(v.as_array_view(), array);
+        assert_eq!(v.as_slice(), &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(v, Vec2D::from_slice(v.as_slice(), 2, 3));
+        // a transposed view is copied in its logical order
+        assert_eq!(Vec2D::from_ndarray(array.t()).as_array_view(), array.t());
+
+        v.as_mut_slice()[4] = 7.0;
+        assert_eq!(v[(1, 1)], 7.0);
+        v[(0, 2)] = 8.0;
+        assert_eq!(v.get(0, 2), Some(&8.0));
+        assert_eq!(v.get(0, 3), None);
+        assert_eq!(v.get(2, 0), None);
+        assert_eq!(v.get_mut(1, 3), None);
+    }
+
+    #[test]
+    fn test_rows_and_cols() {
+        let mut v = Vec2D::from_slice(&[1, 2, 3, 4, 5, 6], 2, 3);
+        let sums: Vec<i32> = v.rows().map(|row| row.iter().sum()).collect();
+        assert_eq!(sums, vec![6, 15]);
+        assert_eq!(v.row(1), &[4, 5, 6]);
+        let cols: Vec<Vec<i32>> = v.cols().map(|col| col.copied().collect()).collect();
+        assert_eq!(cols, vec![vec![1, 4], vec![2, 5], vec![3, 6]]);
+
+        for row in v.rows_mut() {
+            row[0] = 0;
+        }
+        v.row_mut(1)[2] = 7;
+        assert_eq!(v.col(0).copied().collect::<Vec<_>>(), vec![0, 0]);
+        assert_eq!(v.row(1), &[0, 5, 7]);
+
+        let mut empty = Vec2D::<i32>::new(0);
+        empty.add_row_with_value(0);
+        assert_eq!(empty.rows().count(), 0);
+        assert_eq!(empty.cols().count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_past_row_end() {
+        let v = Vec2D::from_slice(&[1, 2, 3, 4], 2, 2);
+        let _ = v[(0, 2)];
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_slice_wrong_length() {
+        Vec2D::from_slice(&[1, 2, 3], 2, 2);
+    }
+}
@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+
+import numpy as np
+from unittest import TestCase, main
+from fast_ctc_decode import viterbi_search
+
+
+class TestViterbiSearch(TestCase):
+
+    def setUp(self):
+        self.alphabet = "NAG"
+
+    def test_viterbi_search(self):
+        network_output = np.array([
+            [0.1, 0.8, 0.1],
+            [0.8, 0.1, 0.1],
+            [0.1, 0.1, 0.8],
+        ], dtype=np.float32)
+        seq, path = viterbi_search(network_output, self.alphabet)
+        self.assertEqual(seq, "AG")
+        self.assertEqual(list(path), [0, 2])
+
+    def test_no_rows(self):
+        # a read with no rows decodes to nothing, as it does in Rust
+        network_output = np.zeros((0, len(self.alphabet)), dtype=np.float32)
+        seq, path = viterbi_search(network_output, self.alphabet)
+        self.assertEqual(seq, "")
+        self.assertEqual(len(path), 0)
+
+    def test_alphabet_mismatch(self):
+        network_output = np.zeros((0, 2), dtype=np.float32)
+        with self.assertRaises(ValueError):
+            viterbi_search(network_output, self.alphabet)
+
+
+if __name__ == '__main__':
+    main()